@@ -0,0 +1,33 @@
+use std::process::Command;
+
+fn main() {
+    // the build id in the module info frame, so two builds of the same
+    // version can be told apart
+    println!("cargo:rustc-env=OXCC_GIT_HASH={}", git_hash());
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+}
+
+/// Short hash of the checked out commit, with a `-dirty` suffix if the tree
+/// has uncommitted changes, "unknown" outside a git checkout
+fn git_hash() -> String {
+    let hash = Command::new("git")
+        .args(&["rev-parse", "--short=8", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string());
+
+    let dirty = Command::new("git")
+        .args(&["diff-index", "--quiet", "HEAD", "--"])
+        .status()
+        .map(|status| !status.success())
+        .unwrap_or(false);
+
+    match hash {
+        Some(hash) if dirty => format!("{}-dirty", hash),
+        Some(hash) => hash,
+        None => "unknown".to_string(),
+    }
+}
@@ -0,0 +1,177 @@
+//! Bit-banged SPI master, transmit only
+//!
+//! Drives a DAC from spare GPIO when the SPI peripheral is shared with other
+//! devices. Only SPI mode 0 is implemented (the MCP4922 `MODE`): MOSI is set
+//! while SCK is low and sampled by the device on the rising edge.
+
+#[cfg(not(test))]
+use cortex_m::asm;
+use dac_mcp4922::{Mcp4922, NoLdac};
+use embedded_hal::blocking::spi::Write;
+use embedded_hal::digital::OutputPin;
+
+/// Core clock cycles SCK is held in each state by default, well over the
+/// MCP4922 25 ns minimum SCK high and low times at 216 MHz
+pub const DEFAULT_HALF_PERIOD_CYCLES: u32 = 16;
+
+/// Writes to GPIO can't fail
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Error {}
+
+pub struct BitBangSpi<SCK, MOSI> {
+    sck: SCK,
+    mosi: MOSI,
+    half_period_cycles: u32,
+}
+
+impl<SCK, MOSI> BitBangSpi<SCK, MOSI>
+where
+    SCK: OutputPin,
+    MOSI: OutputPin,
+{
+    pub fn new(mut sck: SCK, mosi: MOSI) -> Self {
+        // mode 0 idles low
+        sck.set_low();
+
+        BitBangSpi {
+            sck,
+            mosi,
+            half_period_cycles: DEFAULT_HALF_PERIOD_CYCLES,
+        }
+    }
+
+    /// Sets the core clock cycles SCK is held high and low, defaults to
+    /// DEFAULT_HALF_PERIOD_CYCLES
+    pub fn set_half_period_cycles(&mut self, cycles: u32) {
+        self.half_period_cycles = cycles;
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        // MSB first
+        for bit in (0..8).rev() {
+            if (byte >> bit) & 1 == 1 {
+                self.mosi.set_high();
+            } else {
+                self.mosi.set_low();
+            }
+            self.hold();
+
+            self.sck.set_high();
+            self.hold();
+
+            self.sck.set_low();
+        }
+    }
+
+    // the host test build has no core clock to delay on
+    fn hold(&self) {
+        #[cfg(not(test))]
+        asm::delay(self.half_period_cycles);
+    }
+}
+
+impl<SCK, MOSI> Write<u8> for BitBangSpi<SCK, MOSI>
+where
+    SCK: OutputPin,
+    MOSI: OutputPin,
+{
+    type Error = Error;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Error> {
+        for byte in words {
+            self.write_byte(*byte);
+        }
+
+        Ok(())
+    }
+}
+
+impl<SCK, MOSI, CS> Mcp4922<BitBangSpi<SCK, MOSI>, CS, NoLdac>
+where
+    SCK: OutputPin,
+    MOSI: OutputPin,
+    CS: OutputPin,
+{
+    /// Drives the DAC over GPIO instead of an SPI peripheral
+    pub fn new_bit_banged(sck: SCK, mosi: MOSI, cs: CS) -> Self {
+        Mcp4922::new(BitBangSpi::new(sck, mosi), cs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dac_mcp4922::{Channel, DacOutput};
+    use sim_board::{SimPin, SimSpi};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Default)]
+    struct Wire {
+        sck: bool,
+        mosi: bool,
+        // MOSI sampled on each rising SCK edge
+        bits: Vec<bool>,
+    }
+
+    struct MockSck(Rc<RefCell<Wire>>);
+
+    impl OutputPin for MockSck {
+        fn set_low(&mut self) {
+            self.0.borrow_mut().sck = false;
+        }
+        fn set_high(&mut self) {
+            let mut wire = self.0.borrow_mut();
+            if !wire.sck {
+                let mosi = wire.mosi;
+                wire.bits.push(mosi);
+            }
+            wire.sck = true;
+        }
+    }
+
+    struct MockMosi(Rc<RefCell<Wire>>);
+
+    impl OutputPin for MockMosi {
+        fn set_low(&mut self) {
+            self.0.borrow_mut().mosi = false;
+        }
+        fn set_high(&mut self) {
+            self.0.borrow_mut().mosi = true;
+        }
+    }
+
+    // the sampled bits as 16 bit words, MSB first
+    fn words(bits: &[bool]) -> Vec<u16> {
+        bits.chunks(16)
+            .map(|word| word.iter().fold(0, |acc, &bit| (acc << 1) | u16::from(bit)))
+            .collect()
+    }
+
+    #[test]
+    fn bit_banged_dac_writes_the_same_words_as_the_spi_peripheral() {
+        let wire = Rc::new(RefCell::new(Wire::default()));
+        let mut bit_banged =
+            Mcp4922::new_bit_banged(MockSck(wire.clone()), MockMosi(wire.clone()), SimPin::new());
+        let mut peripheral = Mcp4922::new(SimSpi::new(), SimPin::new());
+
+        let outputs = [
+            (0x000, Channel::ChannelA),
+            (0xABC, Channel::ChannelB),
+            (0xFFF, Channel::ChannelA),
+            (0x555, Channel::ChannelB),
+        ];
+        for &(value, channel) in outputs.iter() {
+            assert!(bit_banged.output(DacOutput::clamp(value), channel).is_ok());
+            assert!(peripheral.output(DacOutput::clamp(value), channel).is_ok());
+        }
+
+        let expected: Vec<u16> = (0..outputs.len())
+            .rev()
+            .map(|back| peripheral.spi_mut().word(back).unwrap())
+            .collect();
+        assert_eq!(words(&wire.borrow().bits), expected);
+        // mode 0, SCK is left idling low
+        assert!(!wire.borrow().sck);
+    }
+}
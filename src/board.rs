@@ -1,25 +1,41 @@
 use config;
+use core::mem;
+use core::ptr;
 use cortex_m;
-use dac_mcp4922::Mcp4922;
 use dac_mcp4922::MODE as DAC_MODE;
+use dac_mcp4922::{self, Channel, Mcp4922, SpiConfig, SpiConfigError};
 use dual_signal::HighLowReader;
-use nucleo_f767zi::debug_console::DebugConsole;
+use embedded_hal::blocking::spi;
+use embedded_hal::digital::OutputPin;
+use embedded_hal::spi::{Mode, Phase, Polarity};
+use fault_log::{FaultLog, FaultLogRecords, FaultLogStorage};
 use nucleo_f767zi::hal::adc::{Adc, AdcChannel, AdcPrescaler, AdcSampleTime};
 use nucleo_f767zi::hal::can::Can;
 use nucleo_f767zi::hal::iwdg::{Iwdg, IwdgConfig, WatchdogTimeout};
 use nucleo_f767zi::hal::prelude::*;
-use nucleo_f767zi::hal::rcc::ResetConditions;
+use nucleo_f767zi::hal::rcc::{Clocks, ResetConditions};
 use nucleo_f767zi::hal::serial::Serial;
 use nucleo_f767zi::hal::spi::Spi;
 use nucleo_f767zi::hal::stm32f7x7;
-use nucleo_f767zi::hal::stm32f7x7::{ADC1, ADC2, ADC3, IWDG};
+use nucleo_f767zi::hal::stm32f7x7::{ADC1, ADC2, ADC3, IWDG, PWR, RCC, SPI1, SPI2, SPI3};
 use nucleo_f767zi::led::{Color, Leds};
 use nucleo_f767zi::UserButtonPin;
-use vehicle::FAULT_HYSTERESIS;
+use override_source::OverrideSwitch;
+use signal_range::{LearnedRangeStorage, LearnedRanges, RangeSignal, SignalRange};
+use spoof_enable_sense::SpoofEnableSense;
+use system_clock::{self, StartupGrace, SystemClock};
 
 pub use types::*;
 
-pub const CAN_PUBLISH_HZ: u32 = 50;
+#[cfg(any(test, feature = "sim"))]
+pub use sim_board::{
+    SimBrakePedalPositionSensor as BrakePedalPositionSensor,
+    SimSensor as AcceleratorPositionSensor, SimSensor as TorqueSensor,
+};
+
+/// Independent watchdog timeout, the control loop must call
+/// `Board::pet_watchdog` more often than this or the MCU is reset
+pub const WATCHDOG_TIMEOUT: WatchdogTimeout = WatchdogTimeout::Wdto50ms;
 
 // TODO
 // We need to decide on a desired ADC sample time.
@@ -46,13 +62,32 @@ pub const ADC_SAMPLE_TIME: AdcSampleTime = AdcSampleTime::Cycles480;
 // single read with large Cycles480 sample time?
 pub const DAC_SAMPLE_AVERAGE_COUNT: u32 = 20;
 
+/// SPI clock shared by the brake, throttle and steering DACs [Hz]
+pub const DAC_SPI_CLOCK_HZ: u32 = 1_000_000;
+
+// VREFINT_CAL, the factory ADC reading of VREFINT at VDDA = 3.3 V
+const VREFINT_CAL_ADDR: usize = 0x1FF0_F44A;
+const VREFINT_CAL_VDDA: f32 = 3.3;
+
+// backup SRAM, 4 KB
+const BKPSRAM_BASE: usize = 0x4002_4000;
+const BACKUP_REGULATOR_READY_MAX_POLLS: u32 = 100_000;
+
+// clocks the DAC SPIs were configured with, kept for `drive_outputs_safe`
+// to rebuild them
+static mut DAC_SPI_CLOCKS: Option<Clocks> = None;
+
 pub struct FullBoard {
     pub debug_console: DebugConsole,
     pub leds: Leds,
     pub user_button: UserButtonPin,
-    pub can_publish_timer: CanPublishTimer,
     pub wdg: Iwdg<IWDG>,
+    fault_log: FaultLog,
+    learned_ranges: LearnedRanges,
     pub reset_conditions: ResetConditions,
+    /// Result of checking the DAC SPI mode and clock, an error is logged
+    pub dac_spi_config: Result<(), SpiConfigError>,
+    pub system_clock: SystemClock,
     control_can: ControlCan,
     obd_can: ObdCan,
     brake_pedal_position_sensor: BrakePedalPositionSensor,
@@ -64,22 +99,32 @@ pub struct FullBoard {
     brake_pins: BrakePins,
     throttle_pins: ThrottlePins,
     steering_pins: SteeringPins,
-    brake_grounded_fault_timer: BrakeGroundedFaultTimer,
-    brake_override_fault_timer: BrakeOverrideFaultTimer,
-    throttle_grounded_fault_timer: ThrottleGroundedFaultTimer,
-    throttle_override_fault_timer: ThrottleOverrideFaultTimer,
-    steering_grounded_fault_timer: SteeringGroundedFaultTimer,
 }
 
 pub struct Board {
     pub leds: Leds,
     pub user_button: UserButtonPin,
-    pub wdg: Iwdg<IWDG>,
+    wdg: Iwdg<IWDG>,
+    fault_log: FaultLog,
+    learned_ranges: LearnedRanges,
     pub reset_conditions: ResetConditions,
+    pub dac_spi_config: Result<(), SpiConfigError>,
+    pub system_clock: SystemClock,
+    startup_grace: StartupGrace,
 }
 
+// the sim builds have no board peripherals to bring up
+#[cfg(not(any(test, feature = "sim")))]
 impl FullBoard {
     pub fn new() -> Self {
+        FullBoard::new_with_can_bitrates(config::CONTROL_CAN_BITRATE, config::OBD_CAN_BITRATE)
+    }
+
+    /// CAN bit timings are computed from the APB1 clock for the given bus speeds
+    pub fn new_with_can_bitrates(
+        control_can_bitrate: config::CanBitrate,
+        obd_can_bitrate: config::CanBitrate,
+    ) -> Self {
         // read the RCC reset condition flags before anything else
         let reset_conditions = ResetConditions::read_and_clear();
 
@@ -93,20 +138,38 @@ impl FullBoard {
             .SCB
             .enable_dcache(&mut core_peripherals.CPUID);
 
+        let (fault_log_storage, learned_range_storage) =
+            enable_backup_sram(&peripherals.RCC, &peripherals.PWR);
+        let fault_log = FaultLog::new(fault_log_storage);
+        let learned_ranges = LearnedRanges::new(learned_range_storage);
+
         let mut flash = peripherals.FLASH.constrain();
         let mut rcc = peripherals.RCC.constrain();
         let mut c_adc = peripherals.C_ADC;
 
+        // VREFINT on ADC1 channel 17, for measuring VDDA
+        c_adc.ccr.modify(|_, w| w.tsvrefe().set_bit());
+
         let mut gpiob = peripherals.GPIOB.split(&mut rcc.ahb1);
         let mut gpioa = peripherals.GPIOA.split(&mut rcc.ahb1);
         let mut gpioc = peripherals.GPIOC.split(&mut rcc.ahb1);
         let mut gpiod = peripherals.GPIOD.split(&mut rcc.ahb1);
+        let mut gpioe = peripherals.GPIOE.split(&mut rcc.ahb1);
         let mut gpiof = peripherals.GPIOF.split(&mut rcc.ahb1);
 
         let brake_pins = BrakePins {
             spoof_enable: gpiod
                 .pd12
                 .into_push_pull_output(&mut gpiod.moder, &mut gpiod.otyper),
+            spoof_enable_sense: if config::SPOOF_ENABLE_SENSE_INSTALLED {
+                Some(SpoofEnableSense::new(
+                    gpioe
+                        .pe2
+                        .into_pull_down_input(&mut gpioe.moder, &mut gpioe.pupdr),
+                ))
+            } else {
+                None
+            },
             brake_light_enable: gpiod
                 .pd13
                 .into_push_pull_output(&mut gpiod.moder, &mut gpiod.otyper),
@@ -116,6 +179,19 @@ impl FullBoard {
             pedal_pos_sensor_low: gpioc
                 .pc0
                 .into_analog_input(&mut gpioc.moder, &mut gpioc.pupdr),
+            override_switch: if config::BRAKE_OVERRIDE_SWITCH_INSTALLED {
+                Some(OverrideSwitch::new(
+                    gpiod
+                        .pd14
+                        .into_pull_up_input(&mut gpiod.moder, &mut gpiod.pupdr),
+                ))
+            } else {
+                None
+            },
+            #[cfg(feature = "brake-pressure-loop")]
+            pressure_sensor: gpioa
+                .pa0
+                .into_analog_input(&mut gpioa.moder, &mut gpioa.pupdr),
         };
 
         // TODO - move these once DAC impl is ready
@@ -130,6 +206,15 @@ impl FullBoard {
             spoof_enable: gpiod
                 .pd10
                 .into_push_pull_output(&mut gpiod.moder, &mut gpiod.otyper),
+            spoof_enable_sense: if config::SPOOF_ENABLE_SENSE_INSTALLED {
+                Some(SpoofEnableSense::new(
+                    gpioe
+                        .pe4
+                        .into_pull_down_input(&mut gpioe.moder, &mut gpioe.pupdr),
+                ))
+            } else {
+                None
+            },
             accel_pos_sensor_high: gpioc
                 .pc3
                 .into_analog_input(&mut gpioc.moder, &mut gpioc.pupdr),
@@ -152,6 +237,15 @@ impl FullBoard {
             spoof_enable: gpiod
                 .pd11
                 .into_push_pull_output(&mut gpiod.moder, &mut gpiod.otyper),
+            spoof_enable_sense: if config::SPOOF_ENABLE_SENSE_INSTALLED {
+                Some(SpoofEnableSense::new(
+                    gpioe
+                        .pe5
+                        .into_pull_down_input(&mut gpioe.moder, &mut gpioe.pupdr),
+                ))
+            } else {
+                None
+            },
             torque_sensor_high: gpiof
                 .pf5
                 .into_analog_input(&mut gpiof.moder, &mut gpiof.pupdr),
@@ -196,6 +290,10 @@ impl FullBoard {
         // configure maximum clock frequency at 200 MHz
         let clocks = rcc.cfgr.freeze_max(&mut flash.acr);
 
+        unsafe {
+            DAC_SPI_CLOCKS = Some(clocks);
+        }
+
         let mut leds = Leds::new(led_r, led_g, led_b);
         for led in leds.iter_mut() {
             led.off();
@@ -216,24 +314,29 @@ impl FullBoard {
         /* loopback/silent mode can be used for testing */
         /* &CanConfig { loopback_mode: true, silent_mode: true,
          * ..CanConfig::default() }, */
+        let control_can_timing = config::can_bit_timing(control_can_bitrate, clocks.pclk1().0)
+            .expect("No control CAN bit timing for the APB1 clock");
         let control_can = Can::can1(
             peripherals.CAN1,
             (can1_tx, can1_rx),
             &mut rcc.apb1,
-            &config::CONTROL_CAN_CONFIG,
+            &config::can_config(control_can_timing),
         ).expect("Failed to configure control CAN (CAN1)");
 
+        let obd_can_timing = config::can_bit_timing(obd_can_bitrate, clocks.pclk1().0)
+            .expect("No OBD CAN bit timing for the APB1 clock");
         let obd_can = Can::can2(
             peripherals.CAN2,
             (can2_tx, can2_rx),
             &mut rcc.apb1,
-            &config::OBD_CAN_CONFIG,
+            &config::can_config(obd_can_timing),
         ).expect("Failed to configure OBD CAN (CAN2)");
 
         // apply control CAN filters
-        for filter in &config::gather_control_can_filters() {
+        let control_can_filters = config::gather_control_can_filters(config::can_id_offset());
+        for index in 0..control_can_filters.len() {
             control_can
-                .configure_filter(&filter)
+                .configure_filter(&control_can_filters.filter(index))
                 .expect("Failed to configure control CAN filter");
         }
 
@@ -248,7 +351,7 @@ impl FullBoard {
             peripherals.SPI1,
             (brake_sck, brake_miso, brake_mosi),
             DAC_MODE,
-            1.mhz().into(),
+            DAC_SPI_CLOCK_HZ.hz().into(),
             clocks,
             &mut rcc.apb2,
         );
@@ -257,7 +360,7 @@ impl FullBoard {
             peripherals.SPI2,
             (throttle_sck, throttle_miso, throttle_mosi),
             DAC_MODE,
-            1.mhz().into(),
+            DAC_SPI_CLOCK_HZ.hz().into(),
             clocks,
             &mut rcc.apb1,
         );
@@ -266,28 +369,35 @@ impl FullBoard {
             peripherals.SPI3,
             (steering_sck, steering_miso, steering_mosi),
             DAC_MODE,
-            1.mhz().into(),
+            DAC_SPI_CLOCK_HZ.hz().into(),
             clocks,
             &mut rcc.apb1,
         );
 
+        // read back from the registers the HAL wrote, checked before the
+        // first DAC write
+        let dac_spi_config = [
+            read_spi_config(unsafe { &*SPI1::ptr() }, clocks.pclk2().0),
+            read_spi_config(unsafe { &*SPI2::ptr() }, clocks.pclk1().0),
+            read_spi_config(unsafe { &*SPI3::ptr() }, clocks.pclk1().0),
+        ]
+        .iter()
+        .map(dac_mcp4922::check_spi_config)
+        .find(|result| result.is_err())
+        .unwrap_or(Ok(()));
+
         FullBoard {
             debug_console: DebugConsole::new(serial),
             leds,
             user_button: gpioc
                 .pc13
                 .into_pull_down_input(&mut gpioc.moder, &mut gpioc.pupdr),
-            can_publish_timer: CanPublishTimer::tim2(
-                peripherals.TIM2,
-                CAN_PUBLISH_HZ.hz(),
-                clocks,
-                &mut rcc.apb1,
-            ),
-            wdg: Iwdg::new(
-                peripherals.IWDG,
-                IwdgConfig::from(WatchdogTimeout::Wdto50ms),
-            ),
+            wdg: Iwdg::new(peripherals.IWDG, IwdgConfig::from(WATCHDOG_TIMEOUT)),
+            fault_log,
+            learned_ranges,
             reset_conditions,
+            dac_spi_config,
+            system_clock: SystemClock::new(core_peripherals.SYST, clocks),
             control_can,
             obd_can,
             brake_pedal_position_sensor: BrakePedalPositionSensor {
@@ -305,39 +415,11 @@ impl FullBoard {
             brake_pins,
             throttle_pins,
             steering_pins,
-            brake_grounded_fault_timer: BrakeGroundedFaultTimer::tim3(
-                peripherals.TIM3,
-                (1000 / FAULT_HYSTERESIS).hz(),
-                clocks,
-                &mut rcc.apb1,
-            ),
-            brake_override_fault_timer: BrakeOverrideFaultTimer::tim4(
-                peripherals.TIM4,
-                (1000 / FAULT_HYSTERESIS).hz(),
-                clocks,
-                &mut rcc.apb1,
-            ),
-            throttle_grounded_fault_timer: ThrottleGroundedFaultTimer::tim5(
-                peripherals.TIM5,
-                (1000 / FAULT_HYSTERESIS).hz(),
-                clocks,
-                &mut rcc.apb1,
-            ),
-            throttle_override_fault_timer: ThrottleOverrideFaultTimer::tim6(
-                peripherals.TIM6,
-                (1000 / FAULT_HYSTERESIS).hz(),
-                clocks,
-                &mut rcc.apb1,
-            ),
-            steering_grounded_fault_timer: SteeringGroundedFaultTimer::tim7(
-                peripherals.TIM7,
-                (1000 / FAULT_HYSTERESIS).hz(),
-                clocks,
-                &mut rcc.apb1,
-            ),
         }
     }
+}
 
+impl FullBoard {
     pub fn split_components(
         self,
     ) -> (
@@ -345,19 +427,13 @@ impl FullBoard {
         BrakeDac,
         BrakePins,
         BrakePedalPositionSensor,
-        BrakeGroundedFaultTimer,
-        BrakeOverrideFaultTimer,
         AcceleratorPositionSensor,
         ThrottleDac,
         ThrottlePins,
-        ThrottleGroundedFaultTimer,
-        ThrottleOverrideFaultTimer,
         TorqueSensor,
         SteeringDac,
         SteeringPins,
-        SteeringGroundedFaultTimer,
         DebugConsole,
-        CanPublishTimer,
         ControlCan,
         ObdCan,
     ) {
@@ -365,9 +441,12 @@ impl FullBoard {
             debug_console,
             leds,
             user_button,
-            can_publish_timer,
             wdg,
+            fault_log,
+            learned_ranges,
             reset_conditions,
+            dac_spi_config,
+            system_clock,
             control_can,
             obd_can,
             brake_pedal_position_sensor,
@@ -379,35 +458,29 @@ impl FullBoard {
             brake_pins,
             throttle_pins,
             steering_pins,
-            brake_grounded_fault_timer,
-            brake_override_fault_timer,
-            throttle_grounded_fault_timer,
-            throttle_override_fault_timer,
-            steering_grounded_fault_timer,
         } = self;
         (
             Board {
                 leds,
                 user_button,
                 wdg,
+                fault_log,
+                learned_ranges,
                 reset_conditions,
+                dac_spi_config,
+                system_clock,
+                startup_grace: StartupGrace::new(config::STARTUP_FAULT_GRACE_MS),
             },
             brake_dac,
             brake_pins,
             brake_pedal_position_sensor,
-            brake_grounded_fault_timer,
-            brake_override_fault_timer,
             accelerator_position_sensor,
             throttle_dac,
             throttle_pins,
-            throttle_grounded_fault_timer,
-            throttle_override_fault_timer,
             torque_sensor,
             steering_dac,
             steering_pins,
-            steering_grounded_fault_timer,
             debug_console,
-            can_publish_timer,
             control_can,
             obd_can,
         )
@@ -415,30 +488,167 @@ impl FullBoard {
 }
 
 impl Board {
+    /// Refreshes the independent watchdog, called once per control loop
+    /// iteration. A hung loop stops petting and the reset brings every
+    /// module back up disabled.
+    pub fn pet_watchdog(&mut self) {
+        self.wdg.refresh();
+    }
+
+    /// True until STARTUP_FAULT_GRACE_MS after the system clock started,
+    /// never again once it has passed
+    pub fn in_startup_grace_period(&mut self) -> bool {
+        self.startup_grace.is_active()
+    }
+
     pub fn user_button(&mut self) -> bool {
         self.user_button.is_high()
     }
+
+    /// Records a fault event in the backup SRAM, timestamped with the
+    /// system clock
+    pub fn store_fault(&mut self, fault_origin_id: u32, dtcs: u8, extended_dtcs: u8) {
+        self.fault_log.store(
+            fault_origin_id,
+            dtcs,
+            extended_dtcs,
+            system_clock::get_timestamp_ms(),
+        );
+    }
+
+    /// Fault events stored before and since the last reset, oldest first
+    pub fn read_fault_log(&self) -> FaultLogRecords {
+        self.fault_log.records()
+    }
+
+    pub fn clear_fault_log(&mut self) {
+        self.fault_log.clear();
+    }
+
+    /// Keeps a learned signal range in the backup SRAM
+    pub fn store_learned_range(&mut self, signal: RangeSignal, range: SignalRange) {
+        self.learned_ranges.store(signal, range);
+    }
+
+    /// The range learned for `signal` before or since the last reset
+    pub fn learned_range(&self, signal: RangeSignal) -> Option<SignalRange> {
+        self.learned_ranges.get(signal)
+    }
+
+    pub fn clear_learned_ranges(&mut self) {
+        self.learned_ranges.clear();
+    }
+}
+
+// enables write access to the backup SRAM and its retention regulator
+fn enable_backup_sram(
+    rcc: &RCC,
+    pwr: &PWR,
+) -> (
+    &'static mut FaultLogStorage,
+    &'static mut LearnedRangeStorage,
+) {
+    rcc.apb1enr.modify(|_, w| w.pwren().set_bit());
+    pwr.cr1.modify(|_, w| w.dbp().set_bit());
+    rcc.ahb1enr.modify(|_, w| w.bkpsramen().set_bit());
+
+    // keeps the contents on VBAT, it takes a moment to become ready
+    pwr.csr1.modify(|_, w| w.bre().set_bit());
+    for _ in 0..BACKUP_REGULATOR_READY_MAX_POLLS {
+        if pwr.csr1.read().brr().bit_is_set() {
+            break;
+        }
+    }
+
+    // the fault log is at the start, the learned ranges follow it, nothing
+    // else uses the backup SRAM
+    let learned_ranges_base = BKPSRAM_BASE + mem::size_of::<FaultLogStorage>();
+    unsafe {
+        (
+            &mut *(BKPSRAM_BASE as *mut FaultLogStorage),
+            &mut *(learned_ranges_base as *mut LearnedRangeStorage),
+        )
+    }
+}
+
+/// Analog signals read by the brake module's ADC
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AdcSignal {
+    BrakePedalPositionHigh,
+    BrakePedalPositionLow,
+    #[cfg(feature = "brake-pressure-loop")]
+    BrakePressure,
+    InternalReference,
 }
 
 // brake module owns ADC1
+#[cfg(not(any(test, feature = "sim")))]
 pub struct BrakePedalPositionSensor {
     adc1: Adc<ADC1>,
 }
 
+#[cfg(not(any(test, feature = "sim")))]
+impl BrakePedalPositionSensor {
+    pub fn read_signal(&self, signal: AdcSignal) -> u16 {
+        let channel = match signal {
+            AdcSignal::BrakePedalPositionHigh => AdcChannel::Adc123In3,
+            AdcSignal::BrakePedalPositionLow => AdcChannel::Adc123In10,
+            #[cfg(feature = "brake-pressure-loop")]
+            AdcSignal::BrakePressure => AdcChannel::Adc123In0,
+            AdcSignal::InternalReference => AdcChannel::Adc1In17,
+        };
+
+        self.adc1.read(channel, ADC_SAMPLE_TIME)
+    }
+
+    /// Measures the analog supply (VDDA) through the internal reference
+    /// [volts]
+    pub fn read_vdda(&self) -> f32 {
+        vdda_from_vrefint(self.read_signal(AdcSignal::InternalReference))
+    }
+}
+
+/// VDDA measured from the internal reference
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SupplyVoltage {
+    /// [volts]
+    pub vdda: f32,
+    /// VDDA has been outside VDDA_NOMINAL +/- VDDA_TOLERANCE for longer than
+    /// the fault debounce, and not back inside it for as long since
+    pub out_of_tolerance: bool,
+}
+
+/// VDDA [volts] for a raw VREFINT reading, scaled by the factory calibration
+///
+/// A zero reading gives 0 V, which is always out of tolerance.
+pub fn vdda_from_vrefint(vrefint: u16) -> f32 {
+    if vrefint == 0 {
+        return 0.0;
+    }
+
+    // read-only system memory, programmed at the factory
+    let vrefint_cal = unsafe { ptr::read_volatile(VREFINT_CAL_ADDR as *const u16) };
+
+    VREFINT_CAL_VDDA * f32::from(vrefint_cal) / f32::from(vrefint)
+}
+
+#[cfg(not(any(test, feature = "sim")))]
 impl HighLowReader for BrakePedalPositionSensor {
     fn read_high(&self) -> u16 {
-        self.adc1.read(AdcChannel::Adc123In3, ADC_SAMPLE_TIME)
+        self.read_signal(AdcSignal::BrakePedalPositionHigh)
     }
     fn read_low(&self) -> u16 {
-        self.adc1.read(AdcChannel::Adc123In10, ADC_SAMPLE_TIME)
+        self.read_signal(AdcSignal::BrakePedalPositionLow)
     }
 }
 
 // throttle module owns ADC2
+#[cfg(not(any(test, feature = "sim")))]
 pub struct AcceleratorPositionSensor {
     adc2: Adc<ADC2>,
 }
 
+#[cfg(not(any(test, feature = "sim")))]
 impl HighLowReader for AcceleratorPositionSensor {
     fn read_high(&self) -> u16 {
         self.adc2.read(AdcChannel::Adc123In13, ADC_SAMPLE_TIME)
@@ -449,10 +659,12 @@ impl HighLowReader for AcceleratorPositionSensor {
 }
 
 // steering module owns ADC3
+#[cfg(not(any(test, feature = "sim")))]
 pub struct TorqueSensor {
     adc3: Adc<ADC3>,
 }
 
+#[cfg(not(any(test, feature = "sim")))]
 impl HighLowReader for TorqueSensor {
     fn read_high(&self) -> u16 {
         self.adc3.read(AdcChannel::Adc3In15, ADC_SAMPLE_TIME)
@@ -462,6 +674,32 @@ impl HighLowReader for TorqueSensor {
     }
 }
 
+/// Reads the mode, clock and frame format an SPI peripheral is configured with
+fn read_spi_config(spi: &stm32f7x7::spi1::RegisterBlock, pclk_hz: u32) -> SpiConfig {
+    let cr1 = spi.cr1.read();
+    let cr2 = spi.cr2.read();
+
+    SpiConfig {
+        mode: Mode {
+            polarity: if cr1.cpol().bit_is_set() {
+                Polarity::IdleHigh
+            } else {
+                Polarity::IdleLow
+            },
+            phase: if cr1.cpha().bit_is_set() {
+                Phase::CaptureOnSecondTransition
+            } else {
+                Phase::CaptureOnFirstTransition
+            },
+        },
+        // BR divides the peripheral clock by 2^(BR + 1)
+        clock_hz: pclk_hz >> (u32::from(cr1.br().bits()) + 1),
+        // DS holds the frame size minus one
+        frame_bits: cr2.ds().bits() + 1,
+        lsb_first: cr1.lsbfirst().bit_is_set(),
+    }
+}
+
 pub fn hard_fault_indicator() {
     cortex_m::interrupt::free(|_cs| unsafe {
         let peripherals = stm32f7x7::Peripherals::steal();
@@ -482,3 +720,116 @@ pub fn hard_fault_indicator() {
         leds[Color::Red].on();
     });
 }
+
+/// Drives every spoof enable low and shuts down both channels of each DAC,
+/// for the panic handler
+///
+/// Steals the peripherals, so it must only be called once the control loop
+/// will never run again. The GPIO ports and DAC SPIs are reconfigured from
+/// scratch, whatever state the panic left them in. Before `FullBoard::new`
+/// has configured the SPIs only the enables are driven.
+#[cfg(not(any(test, feature = "sim")))]
+pub fn drive_outputs_safe() {
+    cortex_m::interrupt::free(|_cs| unsafe {
+        let peripherals = stm32f7x7::Peripherals::steal();
+        let mut rcc = peripherals.RCC.constrain();
+        let mut gpioa = peripherals.GPIOA.split(&mut rcc.ahb1);
+        let mut gpiob = peripherals.GPIOB.split(&mut rcc.ahb1);
+        let mut gpioc = peripherals.GPIOC.split(&mut rcc.ahb1);
+        let mut gpiod = peripherals.GPIOD.split(&mut rcc.ahb1);
+
+        // with the enables low the relays give the vehicle back its own
+        // sensor signals, whatever the DACs output
+        let mut brake_spoof_enable: BrakeSpoofEnablePin = gpiod
+            .pd12
+            .into_push_pull_output(&mut gpiod.moder, &mut gpiod.otyper);
+        let mut throttle_spoof_enable: ThrottleSpoofEnablePin = gpiod
+            .pd10
+            .into_push_pull_output(&mut gpiod.moder, &mut gpiod.otyper);
+        let mut steering_spoof_enable: SteeringSpoofEnablePin = gpiod
+            .pd11
+            .into_push_pull_output(&mut gpiod.moder, &mut gpiod.otyper);
+        brake_spoof_enable.set_low();
+        throttle_spoof_enable.set_low();
+        steering_spoof_enable.set_low();
+
+        let clocks = match DAC_SPI_CLOCKS {
+            Some(clocks) => clocks,
+            None => return,
+        };
+
+        let brake_spi: BrakeSpi = Spi::spi1(
+            peripherals.SPI1,
+            (
+                gpioa.pa5.into_af5(&mut gpioa.moder, &mut gpioa.afrl),
+                gpioa.pa6.into_af5(&mut gpioa.moder, &mut gpioa.afrl),
+                gpioa.pa7.into_af5(&mut gpioa.moder, &mut gpioa.afrl),
+            ),
+            DAC_MODE,
+            DAC_SPI_CLOCK_HZ.hz().into(),
+            clocks,
+            &mut rcc.apb2,
+        );
+        let mut brake_dac: BrakeDac = Mcp4922::new(
+            brake_spi,
+            gpioa
+                .pa4
+                .into_push_pull_output(&mut gpioa.moder, &mut gpioa.otyper),
+        );
+        shutdown_dac(&mut brake_dac);
+
+        let throttle_spi: ThrottleSpi = Spi::spi2(
+            peripherals.SPI2,
+            (
+                gpiob.pb10.into_af5(&mut gpiob.moder, &mut gpiob.afrh),
+                gpioc.pc2.into_af5(&mut gpioc.moder, &mut gpioc.afrl),
+                gpiob.pb15.into_af5(&mut gpiob.moder, &mut gpiob.afrh),
+            ),
+            DAC_MODE,
+            DAC_SPI_CLOCK_HZ.hz().into(),
+            clocks,
+            &mut rcc.apb1,
+        );
+        let mut throttle_dac: ThrottleDac = Mcp4922::new(
+            throttle_spi,
+            gpiob
+                .pb4
+                .into_push_pull_output(&mut gpiob.moder, &mut gpiob.otyper),
+        );
+        shutdown_dac(&mut throttle_dac);
+
+        let steering_spi: SteeringSpi = Spi::spi3(
+            peripherals.SPI3,
+            (
+                gpioc.pc10.into_af5(&mut gpioc.moder, &mut gpioc.afrh),
+                gpioc.pc11.into_af5(&mut gpioc.moder, &mut gpioc.afrh),
+                gpioc.pc12.into_af5(&mut gpioc.moder, &mut gpioc.afrh),
+            ),
+            DAC_MODE,
+            DAC_SPI_CLOCK_HZ.hz().into(),
+            clocks,
+            &mut rcc.apb1,
+        );
+        let mut steering_dac: SteeringDac = Mcp4922::new(
+            steering_spi,
+            gpioa
+                .pa15
+                .into_push_pull_output(&mut gpioa.moder, &mut gpioa.otyper),
+        );
+        shutdown_dac(&mut steering_dac);
+    });
+}
+
+// a shut down channel's output is pulled to ground through the device's
+// 500 kOhm resistor, errors are ignored as there's nothing left to report
+// them to
+#[cfg(not(any(test, feature = "sim")))]
+fn shutdown_dac<SPI, CS, E>(dac: &mut Mcp4922<SPI, CS>)
+where
+    SPI: spi::Write<u8, Error = E>,
+    CS: OutputPin,
+{
+    for channel in Channel::both().iter() {
+        let _ = dac.shutdown(*channel);
+    }
+}
@@ -1,204 +1,839 @@
 // https://github.com/jonlamb-gh/oscc/tree/devel/firmware/brake/kia_soul_ev_niro
 
 use super::types::*;
-use board::BrakePedalPositionSensor;
+#[cfg(feature = "brake-pressure-loop")]
+use board::AdcSignal;
+use board::{BrakePedalPositionSensor, SupplyVoltage};
 use brake_can_protocol::*;
-use core::fmt::Write;
-use dac_mcp4922::DacOutput;
+use can_health::CanHealth;
+use config::{CanIdOffset, COMMAND_REJECT_MARGIN, VDDA_NOMINAL, VDDA_TOLERANCE};
+use control_ack_can_protocol::*;
+use control_module::{ControlModule, ControlState, ReportPublisher};
+use core::marker::PhantomData;
+use dac_mcp4922::{Channel, DacOutput};
 use dtc::DtcBitfield;
 use dual_signal::DualSignal;
+use emergency_stop_can_protocol::*;
 use fault_can_protocol::*;
-use fault_condition::FaultCondition;
-use nucleo_f767zi::debug_console::DebugConsole;
-use nucleo_f767zi::hal::can::CanFrame;
+use fault_condition::{self, FaultCondition};
+use log::{Level, Module};
+use module_info_can_protocol::*;
+use nucleo_f767zi::hal::can::{CanError, CanFrame, DataFrame};
 use nucleo_f767zi::hal::prelude::*;
 use num;
+use oscc_crc;
 use oscc_magic_byte::*;
-use oxcc_error::OxccError;
-use ranges;
+use oscc_protocol_version::OSCC_PROTOCOL_VERSION;
+use override_source::{OverrideSource, ThresholdOverride};
+use oxcc_error::{BrakeModuleError, DacError, OxccError};
+use param_can_protocol::*;
+#[cfg(feature = "brake-pressure-loop")]
+use pi_controller::PiController;
+use signal_range::SignalRange;
+use spoof_enable_sense::EnableReadback;
+use spoof_signal_pair::{ramp_toward, SignalMapping, SpoofSignalPair};
+use system_clock;
+use units::Volts;
 use vehicle::*;
 
 struct BrakeControlState<DTCS: DtcBitfield> {
-    enabled: bool,
-    operator_override: bool,
+    /// `Faulted` is left by a clear faults frame, or by the next enable frame
+    /// after a command timeout
+    state: ControlState,
     dtcs: DTCS,
+    /// DTCs beyond the bits of `dtcs`, see OSCC_BRAKE_EXTENDED_DTC_*
+    extended_dtcs: u8,
+    /// The spoof enable relay sense input disagrees with the enable pin,
+    /// control is not enabled while it does
+    spoof_enable_mismatch: bool,
+    last_command_ms: u32,
+    /// When control was last enabled or disabled, None until the first change
+    last_transition_ms: Option<u32>,
+    brake_light_high_threshold: u16,
+    brake_light_low_threshold: u16,
+    brake_light_hysteresis: u16,
+    brake_light_on: bool,
+    override_threshold: u16,
+    signal_mapping: SignalMapping,
+    /// Last (high, low) spoof values written, None until the DAC is initialized
+    last_spoof: Option<(u16, u16)>,
+    /// Last commanded position, after clamping, echoed in the brake report
+    last_commanded_position: f32,
+    /// Hold a command received while disabled and apply it on enable
+    buffer_commands_before_enable: bool,
+    /// (pedal command, rx timestamp) of the command received while disabled
+    pending_command: Option<(f32, u32)>,
+    /// Low-pass filter coefficient applied to brake commands, None disables it
+    command_filter_alpha: Option<f32>,
+    filtered_command: Option<f32>,
+    disable_ramp_active: bool,
+    /// (high, low) spoof values a buffered command applied on enable is
+    /// ramping towards, None once reached
+    enable_ramp_target: Option<(u16, u16)>,
+    dac_healthy: bool,
+    /// VDDA went out of tolerance and has not been back in tolerance for
+    /// FAULT_HYSTERESIS since
+    supply_voltage_faulted: bool,
+    /// A command or spoof output was clamped since the last brake report
+    output_clamped: bool,
+    /// A rejected brake frame set its DTC, the fault report is yet to be
+    /// returned by `check_rejected_command`
+    rejected_command_fault: bool,
+    /// Brake pressure the pressure loop is driving towards [steps]
+    #[cfg(feature = "brake-pressure-loop")]
+    pressure_setpoint: Option<f32>,
+    /// Previous brake pressure sensor reading, for the rate check [steps]
+    #[cfg(feature = "brake-pressure-loop")]
+    last_pressure: Option<u16>,
+    /// Share of the commanded braking requested from regen, 0.0..1.0
+    #[cfg(feature = "brake-regen-blending")]
+    regen_request: f32,
+    /// Receive time of the last regen status reporting regen available,
+    /// None once one reports it unavailable [ms]
+    #[cfg(feature = "brake-regen-blending")]
+    regen_available_ms: Option<u32>,
+    /// Filtered brake command before the regen blending, the brake lights
+    /// follow it
+    #[cfg(feature = "brake-regen-blending")]
+    total_command: f32,
 }
 
 impl<DTCS> BrakeControlState<DTCS>
 where
     DTCS: DtcBitfield,
 {
-    pub const fn new(dtcs: DTCS) -> Self {
+    pub fn new<V: Vehicle>(dtcs: DTCS) -> Self {
         BrakeControlState {
-            enabled: false,
-            operator_override: false,
+            state: ControlState::Disabled,
             dtcs,
+            extended_dtcs: 0,
+            spoof_enable_mismatch: false,
+            last_command_ms: 0,
+            last_transition_ms: None,
+            brake_light_high_threshold: V::BRAKE_LIGHT_SPOOF_HIGH_THRESHOLD,
+            brake_light_low_threshold: V::BRAKE_LIGHT_SPOOF_LOW_THRESHOLD,
+            brake_light_hysteresis: V::BRAKE_LIGHT_SPOOF_HYSTERESIS,
+            brake_light_on: false,
+            override_threshold: V::BRAKE_PEDAL_OVERRIDE_THRESHOLD,
+            signal_mapping: SignalMapping::HighToALowToB,
+            last_spoof: None,
+            last_commanded_position: 0.0,
+            buffer_commands_before_enable: false,
+            pending_command: None,
+            command_filter_alpha: None,
+            filtered_command: None,
+            disable_ramp_active: false,
+            enable_ramp_target: None,
+            dac_healthy: true,
+            supply_voltage_faulted: false,
+            output_clamped: false,
+            rejected_command_fault: false,
+            #[cfg(feature = "brake-pressure-loop")]
+            pressure_setpoint: None,
+            #[cfg(feature = "brake-pressure-loop")]
+            last_pressure: None,
+            #[cfg(feature = "brake-regen-blending")]
+            regen_request: 0.0,
+            #[cfg(feature = "brake-regen-blending")]
+            regen_available_ms: None,
+            #[cfg(feature = "brake-regen-blending")]
+            total_command: 0.0,
         }
     }
 }
 
-pub struct BrakeModule {
+/// Read-only copy of the brake control state
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BrakeStateSnapshot {
+    pub control_state: ControlState,
+    pub enabled: bool,
+    pub operator_override: bool,
+    pub dtcs: u8,
+    /// False if the last DAC write failed or a verified write read back wrong
+    pub dac_healthy: bool,
+    pub commanded_position: f32,
+    /// A command or spoof output was clamped since the last brake report
+    pub output_clamped: bool,
+}
+
+pub struct BrakeModule<V: Vehicle = KiaSoulEvNiro> {
     brake_pedal_position: DualSignal<BrakePedalPositionSensor>,
     control_state: BrakeControlState<u8>,
-    grounded_fault_state: FaultCondition<BrakeGroundedFaultTimer>,
-    operator_override_state: FaultCondition<BrakeOverrideFaultTimer>,
+    grounded_fault_state: FaultCondition,
+    short_high_fault_state: FaultCondition,
+    operator_override_state: FaultCondition,
+    override_release_state: FaultCondition,
+    supply_voltage_fault_state: FaultCondition,
+    supply_voltage_recovery_state: FaultCondition,
+    #[cfg(feature = "brake-pressure-loop")]
+    pressure_fault_state: FaultCondition,
     brake_report: OsccBrakeReport,
+    brake_heartbeat: OsccBrakeHeartbeat,
+    #[cfg(feature = "brake-regen-blending")]
+    brake_regen_request: OsccBrakeRegenRequest,
     fault_report: OsccFaultReport,
+    module_info: OsccModuleInfo,
+    module_info_requested: bool,
+    control_ack: Option<OsccControlAck>,
+    param_value: Option<OsccParamValue>,
+    can_id_offset: CanIdOffset,
     brake_dac: BrakeDac,
     brake_pins: BrakePins,
+    #[cfg(feature = "brake-pressure-loop")]
+    pressure_loop: PiController,
+    vehicle: PhantomData<V>,
 }
 
-pub struct UnpreparedBrakeModule {
-    brake_module: BrakeModule,
+pub struct UnpreparedBrakeModule<V: Vehicle = KiaSoulEvNiro> {
+    brake_module: BrakeModule<V>,
 }
 
-impl UnpreparedBrakeModule {
+impl<V: Vehicle> UnpreparedBrakeModule<V> {
     pub fn new(
         brake_dac: BrakeDac,
         brake_pins: BrakePins,
         brake_pedal_position_sensor: BrakePedalPositionSensor,
-        grounded_fault_timer: BrakeGroundedFaultTimer,
-        override_timer: BrakeOverrideFaultTimer,
     ) -> Self {
+        let mut brake_pedal_position = DualSignal::new(0, 0, brake_pedal_position_sensor);
+        brake_pedal_position.set_high_low_ratio(V::BRAKE_PEDAL_POSITION_SENSOR_HIGH_LOW_RATIO);
+
         UnpreparedBrakeModule {
             brake_module: BrakeModule {
-                brake_pedal_position: DualSignal::new(0, 0, brake_pedal_position_sensor),
-                control_state: BrakeControlState::new(u8::default()),
-                grounded_fault_state: FaultCondition::new(grounded_fault_timer),
-                operator_override_state: FaultCondition::new(override_timer),
+                brake_pedal_position,
+                control_state: BrakeControlState::new::<V>(u8::default()),
+                grounded_fault_state: FaultCondition::with_hysteresis(V::SENSOR_FAULT_HYSTERESIS),
+                short_high_fault_state: FaultCondition::with_hysteresis(V::SENSOR_FAULT_HYSTERESIS),
+                operator_override_state: FaultCondition::with_hysteresis(
+                    V::OPERATOR_OVERRIDE_HYSTERESIS,
+                ),
+                override_release_state: FaultCondition::with_hysteresis(
+                    V::OPERATOR_OVERRIDE_RELEASE_DEBOUNCE,
+                ),
+                supply_voltage_fault_state: FaultCondition::with_hysteresis(V::FAULT_HYSTERESIS),
+                supply_voltage_recovery_state: FaultCondition::with_hysteresis(V::FAULT_HYSTERESIS),
+                #[cfg(feature = "brake-pressure-loop")]
+                pressure_fault_state: FaultCondition::with_hysteresis(V::SENSOR_FAULT_HYSTERESIS),
                 brake_report: OsccBrakeReport::new(),
+                brake_heartbeat: OsccBrakeHeartbeat::new(),
+                #[cfg(feature = "brake-regen-blending")]
+                brake_regen_request: OsccBrakeRegenRequest::new(),
                 fault_report: OsccFaultReport {
                     fault_origin_id: FAULT_ORIGIN_BRAKE,
                     dtcs: 0,
+                    extended_dtcs: 0,
                 },
+                module_info: OsccModuleInfo::new(FAULT_ORIGIN_BRAKE),
+                module_info_requested: false,
+                control_ack: None,
+                param_value: None,
+                can_id_offset: CanIdOffset::none(),
                 brake_dac,
                 brake_pins,
+                #[cfg(feature = "brake-pressure-loop")]
+                pressure_loop: PiController::new(
+                    V::BRAKE_PRESSURE_LOOP_KP,
+                    V::BRAKE_PRESSURE_LOOP_KI,
+                    V::MINIMUM_BRAKE_COMMAND,
+                    V::MAXIMUM_BRAKE_COMMAND,
+                ),
+                vehicle: PhantomData,
             },
         }
     }
 
-    pub fn prepare_module(self) -> BrakeModule {
+    /// Releases the spoof and brake light outputs and sets the DAC to the
+    /// released pedal spoof values
+    pub fn prepare_module(self, debug_console: &mut DebugConsole) -> BrakeModule<V> {
         let mut brake_module = self.brake_module;
         brake_module.brake_pins.spoof_enable.set_low();
         brake_module.brake_pins.brake_light_enable.set_low();
+
+        let released = SpoofSignalPair::from_volts(
+            Volts(V::brake_position_to_volts_high(V::MINIMUM_BRAKE_COMMAND)),
+            Volts(V::brake_position_to_volts_low(V::MINIMUM_BRAKE_COMMAND)),
+            &V::BRAKE_SPOOF_SIGNAL_CALIBRATION,
+            brake_module.brake_dac.steps_per_volt(),
+        );
+
+        brake_module.brake_dac.wake(Channel::ChannelA);
+        brake_module.brake_dac.wake(Channel::ChannelB);
+
+        if let Err(e) = brake_module.write_spoof(released.high(), released.low()) {
+            log!(
+                debug_console,
+                Module::Brake,
+                Level::Error,
+                "Brake DAC initialization failed: {:?}",
+                e
+            );
+        }
+
         brake_module
     }
 }
 
-impl BrakeModule {
-    pub fn disable_control(&mut self, debug_console: &mut DebugConsole) -> Result<(), OxccError> {
-        if self.control_state.enabled {
-            self.brake_pedal_position.prevent_signal_discontinuity();
+impl<V: Vehicle> BrakeModule<V> {
+    /// Starts ramping the spoof outputs back to the sensor values, spoofing
+    /// stays enabled until `step_disable_ramp` completes the ramp. An active
+    /// override or fault is kept.
+    pub fn disable_control(
+        &mut self,
+        debug_console: &mut DebugConsole,
+    ) -> Result<(), BrakeModuleError> {
+        self.control_state.pending_command = None;
+
+        if self.control_state.state == ControlState::Enabled {
+            self.transition_to(ControlState::Disabled, debug_console)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn control_state(&self) -> ControlState {
+        self.control_state.state
+    }
+
+    /// Moves the control state machine to `next`, all enable and disable
+    /// side effects happen here. Returns false, leaving the state unchanged,
+    /// if `next` can't be reached from the current state.
+    ///
+    /// Leaving `Enabled` starts the disable ramp, entering it expects the DAC
+    /// to already match the sensor.
+    fn transition_to(
+        &mut self,
+        next: ControlState,
+        debug_console: &mut DebugConsole,
+    ) -> Result<bool, BrakeModuleError> {
+        let previous = self.control_state.state;
+
+        if previous == next {
+            return Ok(true);
+        }
 
-            let result = self.brake_dac.output_ab(
-                DacOutput::clamp(self.brake_pedal_position.low()),
-                DacOutput::clamp(self.brake_pedal_position.high()),
+        if !previous.can_transition_to(next) {
+            log!(
+                debug_console,
+                Module::Brake,
+                Level::Warn,
+                "Rejected brake control transition {:?} -> {:?}",
+                previous,
+                next
             );
+            return Ok(false);
+        }
 
-            // even if we've encountered an error, we can still disable
-            self.brake_pins.spoof_enable.set_low();
-            self.brake_pins.brake_light_enable.set_low();
-            self.control_state.enabled = false;
-            writeln!(debug_console, "Brake control disabled");
+        self.control_state.state = next;
+        VEHICLE_STATE.set_brake_operator_override(next == ControlState::OverrideActive);
 
-            return if let Err(e) = result {
-                Err(OxccError::from(e))
-            } else {
-                Ok(())
-            };
+        log!(
+            debug_console,
+            Module::Brake,
+            Level::Info,
+            "Brake control {:?} -> {:?}",
+            previous,
+            next
+        );
+
+        if next == ControlState::Enabled {
+            let now = system_clock::get_timestamp_ms();
+            self.brake_pins.spoof_enable.set_high();
+            self.control_state.last_transition_ms = Some(now);
+            // the outputs already match the sensor, nothing left to ramp
+            self.control_state.disable_ramp_active = false;
+            // start the command timeout from the moment control is enabled
+            self.control_state.last_command_ms = now;
+        } else {
+            self.control_state.pending_command = None;
+            self.control_state.enable_ramp_target = None;
+
+            if previous == ControlState::Enabled {
+                self.control_state.last_transition_ms = Some(system_clock::get_timestamp_ms());
+                self.control_state.disable_ramp_active = true;
+                self.control_state.filtered_command = None;
+                self.reset_pressure_loop();
+                #[cfg(feature = "brake-regen-blending")]
+                {
+                    self.control_state.regen_request = 0.0;
+                    self.control_state.total_command = V::MINIMUM_BRAKE_COMMAND;
+                }
+                self.step_disable_ramp(debug_console)?;
+            }
         }
 
+        Ok(true)
+    }
+
+    /// Disables control for a command timeout or an absurd command detected
+    /// by this module, the spoof outputs ramp back to the sensor values
+    fn fault(&mut self, debug_console: &mut DebugConsole) -> Result<(), BrakeModuleError> {
+        self.transition_to(ControlState::Faulted, debug_console)?;
+        Ok(())
+    }
+
+    /// Disables control for a sensor, relay, CAN bus or supply fault, the
+    /// spoof outputs can't be trusted so spoofing is released immediately
+    /// rather than ramped
+    fn hard_fault(&mut self, debug_console: &mut DebugConsole) -> Result<(), BrakeModuleError> {
+        self.transition_to(ControlState::Faulted, debug_console)?;
+        self.release_spoof();
         Ok(())
     }
 
-    fn enable_control(&mut self, debug_console: &mut DebugConsole) -> Result<(), OxccError> {
-        if !self.control_state.enabled && !self.control_state.operator_override {
-            self.brake_pedal_position.prevent_signal_discontinuity();
+    // hands the pedal signals back to the vehicle
+    fn release_spoof(&mut self) {
+        self.brake_pins.spoof_enable.set_low();
+        self.set_brake_light(false);
+        self.control_state.disable_ramp_active = false;
+    }
+
+    /// Moves the spoof outputs at most BRAKE_DISABLE_RAMP_MAX_DELTA towards
+    /// the sensor values, called every loop. Spoofing is released once the
+    /// outputs match the sensor, or immediately if the DAC write fails.
+    pub fn step_disable_ramp(
+        &mut self,
+        debug_console: &mut DebugConsole,
+    ) -> Result<(), BrakeModuleError> {
+        if !self.control_state.disable_ramp_active {
+            return Ok(());
+        }
+
+        self.brake_pedal_position.prevent_signal_discontinuity();
 
-            let result = self.brake_dac.output_ab(
-                DacOutput::clamp(self.brake_pedal_position.low()),
-                DacOutput::clamp(self.brake_pedal_position.high()),
+        let target_high = self.brake_pedal_position.high();
+        let target_low = self.brake_pedal_position.low();
+
+        let (output_high, output_low) = match self.control_state.last_spoof {
+            Some((last_high, last_low)) => (
+                ramp_toward(last_high, target_high, V::BRAKE_DISABLE_RAMP_MAX_DELTA),
+                ramp_toward(last_low, target_low, V::BRAKE_DISABLE_RAMP_MAX_DELTA),
+            ),
+            None => (target_high, target_low),
+        };
+
+        let result = self.write_spoof(output_high, output_low);
+
+        // even if we've encountered an error, we can still disable
+        if result.is_err() || ((output_high == target_high) && (output_low == target_low)) {
+            self.release_spoof();
+        }
+
+        if let Err(e) = result {
+            log!(
+                debug_console,
+                Module::Brake,
+                Level::Error,
+                "Brake DAC write failed: {:?}",
+                e
             );
+            Err(BrakeModuleError::from(e))
+        } else {
+            Ok(())
+        }
+    }
 
-            return if let Err(e) = result {
-                Err(OxccError::from(e))
-            } else {
-                self.brake_pins.spoof_enable.set_high();
-                self.control_state.enabled = true;
-                writeln!(debug_console, "Brake control enabled");
-                Ok(())
-            };
+    /// Moves the spoof outputs at most BRAKE_DISABLE_RAMP_MAX_DELTA towards
+    /// the target of a buffered command applied on enable, called every
+    /// loop. Commands received meanwhile move the target.
+    pub fn step_enable_ramp(
+        &mut self,
+        debug_console: &mut DebugConsole,
+    ) -> Result<(), BrakeModuleError> {
+        let (target_high, target_low) = match self.control_state.enable_ramp_target {
+            Some(target) => target,
+            None => return Ok(()),
+        };
+
+        let (output_high, output_low) = match self.control_state.last_spoof {
+            Some((last_high, last_low)) => (
+                ramp_toward(last_high, target_high, V::BRAKE_DISABLE_RAMP_MAX_DELTA),
+                ramp_toward(last_low, target_low, V::BRAKE_DISABLE_RAMP_MAX_DELTA),
+            ),
+            None => (target_high, target_low),
+        };
+
+        if let Err(e) = self.write_spoof(output_high, output_low) {
+            log!(
+                debug_console,
+                Module::Brake,
+                Level::Error,
+                "Brake DAC write failed: {:?}",
+                e
+            );
+            return Err(BrakeModuleError::from(e));
+        }
+
+        if (output_high == target_high) && (output_low == target_low) {
+            self.control_state.enable_ramp_target = None;
         }
 
         Ok(())
     }
 
-    fn update_brake(
-        &mut self,
-        spoof_command_high: u16,
-        spoof_command_low: u16,
-    ) -> Result<(), OxccError> {
-        if self.control_state.enabled {
-            let spoof_high = BrakeSpoofHighSignal::clamp(spoof_command_high);
-            let spoof_low = BrakeSpoofLowSignal::clamp(spoof_command_low);
-
-            if (spoof_high.val() > &BRAKE_LIGHT_SPOOF_HIGH_THRESHOLD)
-                || (spoof_low.val() > &BRAKE_LIGHT_SPOOF_LOW_THRESHOLD)
-            {
-                self.brake_pins.brake_light_enable.set_high();
-            } else {
-                self.brake_pins.brake_light_enable.set_low();
+    /// True until BRAKE_MIN_CONTROL_DWELL_IN_MSEC has passed since control was
+    /// last enabled or disabled
+    fn is_within_control_dwell(&self) -> bool {
+        match self.control_state.last_transition_ms {
+            Some(transition_ms) => !system_clock::is_timeout(
+                transition_ms,
+                system_clock::get_timestamp_ms(),
+                V::BRAKE_MIN_CONTROL_DWELL_IN_MSEC,
+            ),
+            None => false,
+        }
+    }
+
+    /// Time since the last brake command frame was received, or since control
+    /// was enabled if that is more recent [ms]
+    pub fn time_since_last_command_ms(&self) -> u32 {
+        system_clock::get_timestamp_ms().wrapping_sub(self.control_state.last_command_ms)
+    }
+
+    fn enable_control(&mut self, debug_console: &mut DebugConsole) -> Result<(), BrakeModuleError> {
+        if SYSTEM_FAULT_STATE.is_latched() {
+            log!(
+                debug_console,
+                Module::Brake,
+                Level::Warn,
+                "Brake control not enabled, system fault latched"
+            );
+            return Err(BrakeModuleError::NotReady);
+        }
+
+        if EMERGENCY_STOP_STATE.is_latched() {
+            log!(
+                debug_console,
+                Module::Brake,
+                Level::Warn,
+                "Brake control not enabled, emergency stop latched"
+            );
+            return Err(BrakeModuleError::NotReady);
+        }
+
+        if self.control_state.spoof_enable_mismatch {
+            log!(
+                debug_console,
+                Module::Brake,
+                Level::Warn,
+                "Brake control not enabled, spoof enable relay stuck"
+            );
+            return Err(BrakeModuleError::NotReady);
+        }
+
+        if self.control_state.state == ControlState::Enabled {
+            return Ok(());
+        }
+
+        self.recover_from_command_timeout(debug_console)?;
+
+        if !self
+            .control_state
+            .state
+            .can_transition_to(ControlState::Enabled)
+        {
+            log!(
+                debug_console,
+                Module::Brake,
+                Level::Warn,
+                "Brake control not enabled, {:?}",
+                self.control_state.state
+            );
+            return Err(BrakeModuleError::NotReady);
+        }
+
+        // NACKed by process_rx_frame, the controller retries once the dwell
+        // has passed rather than being enabled later without asking again
+        if self.is_within_control_dwell() {
+            log!(
+                debug_console,
+                Module::Brake,
+                Level::Warn,
+                "Brake control not enabled, disabled too recently"
+            );
+            return Err(BrakeModuleError::NotReady);
+        }
+
+        self.brake_pedal_position.prevent_signal_discontinuity();
+
+        let high = self.brake_pedal_position.high();
+        let low = self.brake_pedal_position.low();
+
+        if let Err(e) = self.write_spoof(high, low) {
+            // refuse to enable if the DAC can't be set to match the sensor
+            log!(
+                debug_console,
+                Module::Brake,
+                Level::Error,
+                "Brake DAC write failed, control not enabled: {:?}",
+                e
+            );
+            Err(BrakeModuleError::from(e))
+        } else {
+            self.transition_to(ControlState::Enabled, debug_console)?;
+            self.apply_pending_command()
+        }
+    }
+
+    fn update_brake(&mut self, spoof: &SpoofSignalPair) -> Result<(), BrakeModuleError> {
+        if self.control_state.state == ControlState::Enabled {
+            if spoof.was_clamped() {
+                self.control_state.output_clamped = true;
             }
 
-            // TODO - revisit this, enforce high->A, low->B
-            self.brake_dac
-                .output_ab(ranges::coerce(spoof_high), ranges::coerce(spoof_low))?;
+            let brake_light_on = self.brake_light_should_be_on(spoof);
+            // regen can meet the whole command with the friction brake
+            // released, the lights follow the total command
+            #[cfg(feature = "brake-regen-blending")]
+            let brake_light_on = brake_light_on || {
+                let total_spoof = self.position_spoof(self.control_state.total_command);
+                self.brake_light_should_be_on(&total_spoof)
+            };
+            self.set_brake_light(brake_light_on);
+
+            if self.control_state.enable_ramp_target.is_some() {
+                // step_enable_ramp gets there
+                self.control_state.enable_ramp_target = Some((spoof.high(), spoof.low()));
+            } else {
+                self.write_spoof(spoof.high(), spoof.low())?;
+            }
         }
 
         Ok(())
     }
 
+    /// Writes the high and low spoof values to the DAC channels selected by
+    /// the signal mapping, every brake DAC write goes through here
+    fn write_spoof(&mut self, spoof_high: u16, spoof_low: u16) -> Result<(), DacError> {
+        let (output_a, output_b) = self
+            .control_state
+            .signal_mapping
+            .route(DacOutput::clamp(spoof_high), DacOutput::clamp(spoof_low));
+
+        let result = self.brake_dac.output_ab(output_a, output_b);
+        self.control_state.dac_healthy = result.is_ok();
+
+        if result.is_ok() {
+            self.control_state.last_spoof = Some((spoof_high, spoof_low));
+        }
+
+        result
+    }
+
+    // once on, the lights stay on until both spoof values drop the
+    // hysteresis below their thresholds
+    fn brake_light_should_be_on(&self, spoof: &SpoofSignalPair) -> bool {
+        let (high_threshold, low_threshold) = if self.control_state.brake_light_on {
+            (
+                self.control_state
+                    .brake_light_high_threshold
+                    .saturating_sub(self.control_state.brake_light_hysteresis),
+                self.control_state
+                    .brake_light_low_threshold
+                    .saturating_sub(self.control_state.brake_light_hysteresis),
+            )
+        } else {
+            (
+                self.control_state.brake_light_high_threshold,
+                self.control_state.brake_light_low_threshold,
+            )
+        };
+
+        (spoof.high() > high_threshold) || (spoof.low() > low_threshold)
+    }
+
+    fn set_brake_light(&mut self, on: bool) {
+        if on {
+            self.brake_pins.brake_light_enable.set_high();
+        } else {
+            self.brake_pins.brake_light_enable.set_low();
+        }
+        self.control_state.brake_light_on = on;
+        VEHICLE_STATE.set_brake_applied(on);
+    }
+
+    /// Spoof values above which the brake lights are turned on,
+    /// defaults to BRAKE_LIGHT_SPOOF_HIGH_THRESHOLD and BRAKE_LIGHT_SPOOF_LOW_THRESHOLD
+    fn set_brake_light_thresholds(&mut self, high: u16, low: u16) {
+        self.control_state.brake_light_high_threshold = high;
+        self.control_state.brake_light_low_threshold = low;
+    }
+
+    /// Amount both spoof values must drop below their thresholds before the
+    /// brake lights turn off, defaults to BRAKE_LIGHT_SPOOF_HYSTERESIS
+    fn set_brake_light_hysteresis(&mut self, hysteresis: u16) {
+        self.control_state.brake_light_hysteresis = hysteresis;
+    }
+
+    pub fn brake_light_hysteresis(&self) -> u16 {
+        self.control_state.brake_light_hysteresis
+    }
+
+    /// Returns the (high, low) brake light spoof thresholds
+    pub fn brake_light_thresholds(&self) -> (u16, u16) {
+        (
+            self.control_state.brake_light_high_threshold,
+            self.control_state.brake_light_low_threshold,
+        )
+    }
+
+    /// Selects which DAC channel each spoof signal is written to, defaults
+    /// to `SignalMapping::HighToALowToB`
+    pub fn set_signal_mapping(&mut self, mapping: SignalMapping) {
+        self.control_state.signal_mapping = mapping;
+    }
+
+    pub fn signal_mapping(&self) -> SignalMapping {
+        self.control_state.signal_mapping
+    }
+
+    /// Pedal position average at or above which the operator is considered
+    /// to be overriding, defaults to BRAKE_PEDAL_OVERRIDE_THRESHOLD
+    fn set_override_threshold(&mut self, threshold: u16) {
+        self.control_state.override_threshold = threshold;
+    }
+
+    pub fn override_threshold(&self) -> u16 {
+        self.control_state.override_threshold
+    }
+
+    /// When set, the last command received while control is disabled is
+    /// applied as soon as control is enabled instead of being dropped.
+    /// Disabled by default.
+    pub fn set_buffer_commands_before_enable(&mut self, buffer: bool) {
+        self.control_state.buffer_commands_before_enable = buffer;
+        self.control_state.pending_command = None;
+    }
+
+    /// Enables a first-order low-pass filter on brake commands, `alpha` is
+    /// clamped to 0.0..1.0 where 1.0 passes commands through unchanged.
+    /// None (the default) disables the filter.
+    fn set_command_filter(&mut self, alpha: Option<f32>) {
+        self.control_state.command_filter_alpha = alpha.map(|a| num::clamp(a, 0.0, 1.0));
+        self.control_state.filtered_command = None;
+    }
+
+    pub fn command_filter(&self) -> Option<f32> {
+        self.control_state.command_filter_alpha
+    }
+
+    /// Applies the command filter, starting from a released brake after
+    /// control is enabled
+    fn filter_command(&mut self, position: f32) -> f32 {
+        if let Some(alpha) = self.control_state.command_filter_alpha {
+            let previous = self
+                .control_state
+                .filtered_command
+                .unwrap_or(V::MINIMUM_BRAKE_COMMAND);
+            let filtered = previous + (alpha * (position - previous));
+            self.control_state.filtered_command = Some(filtered);
+            filtered
+        } else {
+            position
+        }
+    }
+
     pub fn check_for_faults(
         &mut self,
         debug_console: &mut DebugConsole,
-    ) -> Result<Option<&OsccFaultReport>, OxccError> {
-        if !self.control_state.enabled && !self.control_state.dtcs.are_any_set() {
+    ) -> Result<Option<&OsccFaultReport>, BrakeModuleError> {
+        self.brake_pedal_position.update();
+
+        let brake_pedal_position_average = self.brake_pedal_position.average();
+
+        let pedal_override = ThresholdOverride {
+            average: brake_pedal_position_average,
+            threshold: self.control_state.override_threshold.into(),
+        }
+        .is_override_requested();
+
+        // the throttle interlock follows the pedal in every control state
+        VEHICLE_STATE.set_brake_pedal_pressed(pedal_override);
+
+        if (self.control_state.state != ControlState::Enabled)
+            && !self.control_state.dtcs.are_any_set()
+        {
             // Assumes this module already went through the proper transition into a faulted
             // and disabled state, and we do not want to double-report a possible duplicate
             // fault.
             return Ok(None);
         }
 
-        self.brake_pedal_position.update();
+        let switch_override = self.brake_pins.override_switch.is_override_requested();
 
-        let brake_pedal_position_average = self.brake_pedal_position.average();
+        let operator_overridden: bool = self
+            .operator_override_state
+            .condition_exceeded_duration(pedal_override || switch_override);
 
-        let operator_overridden: bool = self.operator_override_state.condition_exceeded_duration(
-            brake_pedal_position_average >= BRAKE_PEDAL_OVERRIDE_THRESHOLD.into(),
-        );
+        let override_released: bool = self
+            .override_release_state
+            .condition_exceeded_duration(!pedal_override && !switch_override);
 
-        let inputs_grounded: bool = self
+        let inputs_faulted: bool = self
             .grounded_fault_state
-            .check_voltage_grounded(&self.brake_pedal_position);
+            .check_voltage_grounded_or_mismatched(
+                &self.brake_pedal_position,
+                V::BRAKE_PEDAL_POSITION_SENSOR_MISMATCH_TOLERANCE,
+            );
+
+        let inputs_shorted_high: bool = self.short_high_fault_state.check_voltage_high(
+            &self.brake_pedal_position,
+            V::BRAKE_PEDAL_POSITION_SENSOR_SHORT_HIGH_THRESHOLD,
+        );
 
-        // sensor pins tied to ground - a value of zero indicates disconnection
-        if inputs_grounded {
-            self.disable_control(debug_console)?;
+        if inputs_shorted_high {
+            self.hard_fault(debug_console)?;
 
             self.control_state
                 .dtcs
-                .set(OSCC_BRAKE_DTC_INVALID_SENSOR_VAL);
+                .set(OSCC_BRAKE_DTC_SENSOR_SHORT_HIGH);
 
             self.update_fault_report();
 
-            writeln!(
+            log!(
                 debug_console,
-                "Bad value read from brake pedal position sensor"
+                Module::Brake,
+                Level::Error,
+                "Brake pedal position sensor shorted high"
             );
 
             Ok(Some(&self.fault_report))
-        } else if operator_overridden && !self.control_state.operator_override {
-            self.disable_control(debug_console)?;
+        } else if inputs_faulted {
+            self.hard_fault(debug_console)?;
+
+            // sensor pins tied to ground - a value of zero indicates disconnection,
+            // or reading outside the learned range
+            if fault_condition::is_signal_invalid(&self.brake_pedal_position) {
+                self.control_state
+                    .dtcs
+                    .set(OSCC_BRAKE_DTC_INVALID_SENSOR_VAL);
+
+                log!(
+                    debug_console,
+                    Module::Brake,
+                    Level::Error,
+                    "Bad value read from brake pedal position sensor"
+                );
+            } else {
+                self.control_state.dtcs.set(OSCC_BRAKE_DTC_SENSOR_MISMATCH);
+
+                log!(
+                    debug_console,
+                    Module::Brake,
+                    Level::Error,
+                    "Brake pedal position sensor high/low mismatch"
+                );
+            }
+
+            self.update_fault_report();
+
+            Ok(Some(&self.fault_report))
+        } else if operator_overridden
+            && self
+                .control_state
+                .state
+                .can_transition_to(ControlState::OverrideActive)
+            && (self.control_state.state != ControlState::OverrideActive)
+        {
+            self.transition_to(ControlState::OverrideActive, debug_console)?;
 
             self.control_state
                 .dtcs
@@ -206,95 +841,2225 @@ impl BrakeModule {
 
             self.update_fault_report();
 
-            self.control_state.operator_override = true;
-
-            writeln!(debug_console, "Brake operator override");
+            log!(
+                debug_console,
+                Module::Brake,
+                Level::Warn,
+                "Brake operator override, pedal {} switch {}",
+                pedal_override,
+                switch_override
+            );
 
             Ok(Some(&self.fault_report))
         } else {
-            self.control_state.dtcs.clear_all();
-            self.control_state.operator_override = false;
+            // event DTCs (invalid CRC, command timeout, protocol mismatch) are
+            // not tied to a sensor condition, so they stay latched until a
+            // clear faults frame
+            self.control_state
+                .dtcs
+                .clear(OSCC_BRAKE_DTC_INVALID_SENSOR_VAL);
+            self.control_state
+                .dtcs
+                .clear(OSCC_BRAKE_DTC_SENSOR_MISMATCH);
+            self.control_state
+                .dtcs
+                .clear(OSCC_BRAKE_DTC_SENSOR_SHORT_HIGH);
+            if self.control_state.state != ControlState::OverrideActive {
+                self.control_state
+                    .dtcs
+                    .clear(OSCC_BRAKE_DTC_OPERATOR_OVERRIDE);
+            } else if override_released {
+                // held until the inputs have stayed released for the debounce
+                self.control_state
+                    .dtcs
+                    .clear(OSCC_BRAKE_DTC_OPERATOR_OVERRIDE);
+                self.transition_to(ControlState::Disabled, debug_console)?;
+            }
             Ok(None)
         }
     }
 
-    fn update_fault_report(&mut self) {
-        self.fault_report.dtcs = self.control_state.dtcs;
-    }
+    /// A command timeout is soft, the controller only stopped talking, so a
+    /// `Faulted` state with no DTC beyond the soft ones is left for
+    /// `Disabled` by the next enable frame instead of needing a clear faults
+    /// frame first
+    fn recover_from_command_timeout(
+        &mut self,
+        debug_console: &mut DebugConsole,
+    ) -> Result<(), BrakeModuleError> {
+        let only_soft_dtcs = (self.control_state.dtcs & !soft_dtc_mask(FAULT_ORIGIN_BRAKE)) == 0
+            && self.control_state.extended_dtcs == 0;
 
-    pub fn supply_brake_report(&mut self) -> &OsccBrakeReport {
-        self.brake_report.enabled = self.control_state.enabled;
-        self.brake_report.operator_override = self.control_state.operator_override;
-        self.brake_report.dtcs = self.control_state.dtcs;
-        &self.brake_report
+        if self.control_state.state == ControlState::Faulted
+            && self
+                .control_state
+                .dtcs
+                .check(OSCC_BRAKE_DTC_COMMAND_TIMEOUT)
+            && only_soft_dtcs
+            && self.control_state.dac_healthy
+        {
+            self.control_state
+                .dtcs
+                .clear(OSCC_BRAKE_DTC_COMMAND_TIMEOUT);
+            self.transition_to(ControlState::Disabled, debug_console)?;
+            self.update_fault_report();
+        }
+
+        Ok(())
     }
 
-    pub fn process_rx_frame(
+    /// Disables control if no brake command has arrived within
+    /// BRAKE_COMMAND_TIMEOUT_IN_MSEC while enabled
+    pub fn check_command_timeout(
         &mut self,
-        can_frame: &CanFrame,
         debug_console: &mut DebugConsole,
-    ) -> Result<(), OxccError> {
-        if let CanFrame::DataFrame(ref frame) = can_frame {
-            let id: u32 = frame.id().into();
-            let data = frame.data();
+    ) -> Result<Option<&OsccFaultReport>, BrakeModuleError> {
+        if self.control_state.state != ControlState::Enabled {
+            return Ok(None);
+        }
 
-            if (data[0] == OSCC_MAGIC_BYTE_0) && (data[1] == OSCC_MAGIC_BYTE_1) {
-                if id == OSCC_BRAKE_ENABLE_CAN_ID.into() {
-                    self.enable_control(debug_console)?;
-                } else if id == OSCC_BRAKE_DISABLE_CAN_ID.into() {
-                    self.disable_control(debug_console)?;
-                } else if id == OSCC_BRAKE_COMMAND_CAN_ID.into() {
-                    self.process_brake_command(&OsccBrakeCommand::from(frame))?;
-                } else if id == OSCC_FAULT_REPORT_CAN_ID.into() {
-                    self.process_fault_report(&OsccFaultReport::from(frame), debug_console)?;
-                }
-            }
+        let command_timed_out = system_clock::is_timeout(
+            self.control_state.last_command_ms,
+            system_clock::get_timestamp_ms(),
+            V::BRAKE_COMMAND_TIMEOUT_IN_MSEC,
+        );
+
+        if command_timed_out {
+            self.fault(debug_console)?;
+
+            self.control_state.dtcs.set(OSCC_BRAKE_DTC_COMMAND_TIMEOUT);
+
+            self.update_fault_report();
+
+            log!(
+                debug_console,
+                Module::Brake,
+                Level::Error,
+                "Brake command timeout"
+            );
+
+            Ok(Some(&self.fault_report))
+        } else {
+            Ok(None)
         }
+    }
 
-        Ok(())
+    /// The fault report once after a rejected brake frame set its DTC, the
+    /// frame is rejected while it is processed. Only an absurd command also
+    /// disables control.
+    pub fn check_rejected_command(&mut self) -> Option<&OsccFaultReport> {
+        if !self.control_state.rejected_command_fault {
+            return None;
+        }
+
+        self.control_state.rejected_command_fault = false;
+        Some(&self.fault_report)
     }
 
-    fn process_fault_report(
+    /// Forces a DTC as if the fault had been detected, for exercising the
+    /// fault pipeline on hardware-in-the-loop rigs
+    #[cfg(feature = "fault-injection")]
+    pub fn inject_fault(
         &mut self,
-        fault_report: &OsccFaultReport,
+        dtc: u8,
         debug_console: &mut DebugConsole,
-    ) -> Result<(), OxccError> {
-        writeln!(
+    ) -> Result<&OsccFaultReport, BrakeModuleError> {
+        self.hard_fault(debug_console)?;
+
+        self.control_state.dtcs.set(dtc);
+
+        self.update_fault_report();
+
+        log!(
             debug_console,
-            "Fault report received from: {} DTCs: {}",
-            fault_report.fault_origin_id, fault_report.dtcs
+            Module::Brake,
+            Level::Warn,
+            "Injected brake DTC {}",
+            dtc
         );
 
-        self.disable_control(debug_console)
+        Ok(&self.fault_report)
     }
 
-    fn process_brake_command(&mut self, command: &OsccBrakeCommand) -> Result<(), OxccError> {
-        let clamped_position = num::clamp(
-            command.pedal_command,
-            MINIMUM_BRAKE_COMMAND,
-            MAXIMUM_BRAKE_COMMAND,
-        );
+    /// Disables control and sets the spoof enable mismatch extended DTC when
+    /// the spoof enable relay sense input disagrees with the enable pin, see
+    /// `config::SPOOF_ENABLE_SENSE_INSTALLED`. The DTC clears once they agree
+    /// again.
+    pub fn check_spoof_enable(
+        &mut self,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, BrakeModuleError> {
+        // the enable pin stays high until the disable ramp completes
+        let commanded = (self.control_state.state == ControlState::Enabled)
+            || self.control_state.disable_ramp_active;
+        let mismatch = self.brake_pins.spoof_enable_sense.is_stuck(commanded);
+        self.control_state.spoof_enable_mismatch = mismatch;
 
-        let spoof_voltage_low: f32 = num::clamp(
-            brake_position_to_volts_low(clamped_position),
-            BRAKE_SPOOF_LOW_SIGNAL_VOLTAGE_MIN,
-            BRAKE_SPOOF_LOW_SIGNAL_VOLTAGE_MAX,
-        );
+        let dtc_set = self
+            .control_state
+            .extended_dtcs
+            .check(OSCC_BRAKE_EXTENDED_DTC_SPOOF_ENABLE_MISMATCH);
 
-        let spoof_voltage_high: f32 = num::clamp(
-            brake_position_to_volts_high(clamped_position),
-            BRAKE_SPOOF_HIGH_SIGNAL_VOLTAGE_MIN,
-            BRAKE_SPOOF_HIGH_SIGNAL_VOLTAGE_MAX,
-        );
+        if !mismatch {
+            if dtc_set {
+                self.control_state
+                    .extended_dtcs
+                    .clear(OSCC_BRAKE_EXTENDED_DTC_SPOOF_ENABLE_MISMATCH);
+                self.update_fault_report();
+            }
+
+            return Ok(None);
+        }
+
+        if dtc_set {
+            return Ok(None);
+        }
 
-        let spoof_value_low = (STEPS_PER_VOLT * spoof_voltage_low) as u16;
-        let spoof_value_high = (STEPS_PER_VOLT * spoof_voltage_high) as u16;
+        self.hard_fault(debug_console)?;
 
-        self.update_brake(spoof_value_high, spoof_value_low)
+        self.control_state
+            .extended_dtcs
+            .set(OSCC_BRAKE_EXTENDED_DTC_SPOOF_ENABLE_MISMATCH);
+
+        self.update_fault_report();
+
+        log!(
+            debug_console,
+            Module::Brake,
+            Level::Error,
+            "Brake spoof enable relay stuck {}",
+            if commanded { "open" } else { "closed" }
+        );
+
+        Ok(Some(&self.fault_report))
     }
-}
 
-trait HighLowReader {
-    fn read_high(&self) -> u16;
+    /// Disables control and sets the DAC output mismatch extended DTC once
+    /// the verified brake DAC writes keep reading back wrong, see
+    /// `Mcp4922::set_output_verifier`
+    pub fn check_dac_output(
+        &mut self,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, BrakeModuleError> {
+        if !self.brake_dac.has_verification_fault()
+            || self
+                .control_state
+                .extended_dtcs
+                .check(OSCC_BRAKE_EXTENDED_DTC_DAC_OUTPUT_MISMATCH)
+        {
+            return Ok(None);
+        }
+
+        self.hard_fault(debug_console)?;
+
+        self.control_state
+            .extended_dtcs
+            .set(OSCC_BRAKE_EXTENDED_DTC_DAC_OUTPUT_MISMATCH);
+
+        self.update_fault_report();
+
+        log!(
+            debug_console,
+            Module::Brake,
+            Level::Error,
+            "Brake DAC output mismatch, {} verified writes read back wrong",
+            self.brake_dac.verification_failures()
+        );
+
+        Ok(Some(&self.fault_report))
+    }
+
+    /// Disables control and sets the CAN bus DTC when the control CAN bus is
+    /// bus-off, the DTC clears once the bus is error-active again
+    ///
+    /// Error-passive alone is not a fault, a node with no other node on the
+    /// bus goes error-passive from missing acknowledgments.
+    pub fn check_can_health(
+        &mut self,
+        can_health: &CanHealth,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, BrakeModuleError> {
+        if can_health.is_error_active() {
+            if self.control_state.dtcs.check(OSCC_BRAKE_DTC_CAN_BUS_FAULT) {
+                self.control_state.dtcs.clear(OSCC_BRAKE_DTC_CAN_BUS_FAULT);
+                self.update_fault_report();
+            }
+
+            return Ok(None);
+        }
+
+        if !can_health.bus_off || self.control_state.dtcs.check(OSCC_BRAKE_DTC_CAN_BUS_FAULT) {
+            return Ok(None);
+        }
+
+        self.hard_fault(debug_console)?;
+
+        self.control_state.dtcs.set(OSCC_BRAKE_DTC_CAN_BUS_FAULT);
+
+        self.update_fault_report();
+
+        log!(
+            debug_console,
+            Module::Brake,
+            Level::Error,
+            "Control CAN bus-off, TEC {} REC {}",
+            can_health.transmit_error_count,
+            can_health.receive_error_count
+        );
+
+        Ok(Some(&self.fault_report))
+    }
+
+    /// Measures VDDA through the internal reference on ADC1, out of
+    /// tolerance once it has been outside VDDA_NOMINAL +/- VDDA_TOLERANCE for
+    /// the fault debounce. Every module checks the measurement, see
+    /// `check_supply_voltage`.
+    pub fn measure_supply_voltage(&mut self) -> SupplyVoltage {
+        let vdda = self.brake_pedal_position.reader().read_vdda();
+
+        let out_of_range =
+            (vdda < (VDDA_NOMINAL - VDDA_TOLERANCE)) || (vdda > (VDDA_NOMINAL + VDDA_TOLERANCE));
+
+        let out_of_tolerance = self
+            .supply_voltage_fault_state
+            .condition_exceeded_duration(out_of_range);
+
+        let recovered = self
+            .supply_voltage_recovery_state
+            .condition_exceeded_duration(!out_of_range);
+
+        if out_of_tolerance {
+            self.control_state.supply_voltage_faulted = true;
+        } else if recovered {
+            self.control_state.supply_voltage_faulted = false;
+        }
+
+        SupplyVoltage {
+            vdda,
+            out_of_tolerance: self.control_state.supply_voltage_faulted,
+        }
+    }
+
+    /// Disables control and sets the supply voltage extended DTC when VDDA
+    /// is out of tolerance, the DTC clears once VDDA is back in tolerance
+    pub fn check_supply_voltage(
+        &mut self,
+        supply_voltage: &SupplyVoltage,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, BrakeModuleError> {
+        let dtc_set = self
+            .control_state
+            .extended_dtcs
+            .check(OSCC_BRAKE_EXTENDED_DTC_SUPPLY_VOLTAGE);
+
+        if !supply_voltage.out_of_tolerance {
+            if dtc_set {
+                self.control_state
+                    .extended_dtcs
+                    .clear(OSCC_BRAKE_EXTENDED_DTC_SUPPLY_VOLTAGE);
+                self.update_fault_report();
+            }
+
+            return Ok(None);
+        }
+
+        if dtc_set {
+            return Ok(None);
+        }
+
+        self.hard_fault(debug_console)?;
+
+        self.control_state
+            .extended_dtcs
+            .set(OSCC_BRAKE_EXTENDED_DTC_SUPPLY_VOLTAGE);
+
+        self.update_fault_report();
+
+        log!(
+            debug_console,
+            Module::Brake,
+            Level::Error,
+            "Brake supply voltage fault, VDDA {} V",
+            supply_voltage.vdda
+        );
+
+        Ok(Some(&self.fault_report))
+    }
+
+    /// Starts learning the brake pedal position sensor operating range for a
+    /// guided calibration, the pedal is swept end to end until
+    /// `finish_range_learning`. Returns false while control is enabled.
+    pub fn start_range_learning(&mut self) -> bool {
+        if self.control_state.state == ControlState::Enabled {
+            return false;
+        }
+
+        self.brake_pedal_position.start_range_learning();
+        true
+    }
+
+    /// Samples the sensor while learning, called every FAULT_CHECK_PERIOD_MS
+    pub fn step_range_learning(&mut self) {
+        if self.brake_pedal_position.is_learning_range()
+            && (self.control_state.state != ControlState::Enabled)
+        {
+            self.brake_pedal_position.update();
+        }
+    }
+
+    /// Makes the learned range the operating range used by the sensor
+    /// fault checks, see `DualSignal::finish_range_learning`
+    pub fn finish_range_learning(&mut self) -> Option<SignalRange> {
+        self.brake_pedal_position.finish_range_learning()
+    }
+
+    /// Sets a previously learned operating range, None disables the range
+    /// check
+    pub fn set_operating_range(&mut self, range: Option<SignalRange>) {
+        self.brake_pedal_position.set_operating_range(range);
+    }
+
+    fn update_fault_report(&mut self) {
+        self.fault_report.dtcs = self.control_state.dtcs;
+        self.fault_report.extended_dtcs = self.control_state.extended_dtcs;
+    }
+
+    pub fn state(&self) -> BrakeStateSnapshot {
+        BrakeStateSnapshot {
+            control_state: self.control_state.state,
+            enabled: self.control_state.state == ControlState::Enabled,
+            operator_override: self.control_state.state == ControlState::OverrideActive,
+            dtcs: self.control_state.dtcs,
+            dac_healthy: self.control_state.dac_healthy && !self.brake_dac.has_verification_fault(),
+            commanded_position: self.control_state.last_commanded_position,
+            output_clamped: self.control_state.output_clamped,
+        }
+    }
+
+    /// Clears the clamped flag, it covers one report period
+    pub fn supply_brake_report(&mut self) -> &OsccBrakeReport {
+        let state = self.state();
+        self.brake_report.enabled = state.enabled;
+        self.brake_report.operator_override = state.operator_override;
+        self.brake_report.dtcs = state.dtcs;
+        self.brake_report.dac_healthy = state.dac_healthy;
+        self.brake_report.commanded_position = state.commanded_position;
+        self.brake_report.output_clamped = state.output_clamped;
+        self.control_state.output_clamped = false;
+        &self.brake_report
+    }
+
+    #[cfg(feature = "brake-regen-blending")]
+    pub fn supply_brake_regen_request(&mut self) -> &OsccBrakeRegenRequest {
+        self.brake_regen_request.regen_request = self.control_state.regen_request;
+        &self.brake_regen_request
+    }
+
+    /// Each call advances the heartbeat sequence counter
+    pub fn supply_brake_heartbeat(&mut self) -> &OsccBrakeHeartbeat {
+        let state = self.state();
+        self.brake_heartbeat.sequence = self.brake_heartbeat.sequence.wrapping_add(1);
+        self.brake_heartbeat.enabled = state.enabled;
+        self.brake_heartbeat.operator_override = state.operator_override;
+        &self.brake_heartbeat
+    }
+
+    /// The acknowledgement of the last enable or disable frame, once
+    pub fn supply_control_ack(&mut self) -> Option<OsccControlAck> {
+        self.control_ack.take()
+    }
+
+    // queues the acknowledgement of an enable or disable frame, carrying the
+    // resulting state
+    fn acknowledge(&mut self, request: ControlRequest) {
+        self.control_ack = Some(OsccControlAck {
+            module_origin_id: FAULT_ORIGIN_BRAKE,
+            request,
+            enabled: self.control_state.state == ControlState::Enabled,
+            dtcs: self.control_state.dtcs,
+        });
+    }
+
+    /// The module info, once after each module info request
+    pub fn supply_module_info(&mut self) -> Option<&OsccModuleInfo> {
+        if self.module_info_requested {
+            self.module_info_requested = false;
+            Some(&self.module_info)
+        } else {
+            None
+        }
+    }
+
+    /// The answer to the last parameter write or read, once
+    pub fn supply_param_value(&mut self) -> Option<OsccParamValue> {
+        self.param_value.take()
+    }
+
+    /// The current fault report, DTCs cleared since the last detection
+    /// included
+    pub fn supply_fault_report(&mut self) -> &OsccFaultReport {
+        &self.fault_report
+    }
+
+    // answers a parameter write (`raw` set) or read, parameters owned by
+    // another module are ignored
+    fn process_param_request(
+        &mut self,
+        param_id: u8,
+        raw: Option<u16>,
+        debug_console: &mut DebugConsole,
+    ) {
+        if self.param(param_id).is_none() {
+            return;
+        }
+
+        let status = match raw {
+            Some(_) if self.control_state.state == ControlState::Enabled => {
+                ParamStatus::RefusedWhileEnabled
+            }
+            Some(raw) => match (OsccParamWrite { param_id, raw }).value() {
+                Some(value) => self.write_param(param_id, value),
+                None => ParamStatus::InvalidValue,
+            },
+            None => ParamStatus::Ok,
+        };
+
+        // a refused write answers with the value still in effect
+        if let Some(current) = self.param(param_id) {
+            if let Some(raw) = raw {
+                if status == ParamStatus::Ok {
+                    log!(
+                        debug_console,
+                        Module::Brake,
+                        Level::Info,
+                        "Brake parameter 0x{:02X} set to {}",
+                        param_id,
+                        current
+                    );
+                } else {
+                    log!(
+                        debug_console,
+                        Module::Brake,
+                        Level::Warn,
+                        "Brake parameter 0x{:02X} write of raw value {} refused: {:?}",
+                        param_id,
+                        raw,
+                        status
+                    );
+                }
+            }
+
+            self.param_value = Some(OsccParamValue {
+                param_id,
+                status,
+                value: current,
+            });
+        }
+    }
+
+    // the value of one of this module's parameters, None for parameters
+    // owned by another module
+    fn param(&self, param_id: u8) -> Option<f32> {
+        let (light_high, light_low) = self.brake_light_thresholds();
+
+        match param_id {
+            OSCC_PARAM_BRAKE_OVERRIDE_THRESHOLD => Some(f32::from(self.override_threshold())),
+            OSCC_PARAM_BRAKE_LIGHT_HIGH_THRESHOLD => Some(f32::from(light_high)),
+            OSCC_PARAM_BRAKE_LIGHT_LOW_THRESHOLD => Some(f32::from(light_low)),
+            OSCC_PARAM_BRAKE_LIGHT_HYSTERESIS => Some(f32::from(self.brake_light_hysteresis())),
+            OSCC_PARAM_BRAKE_COMMAND_FILTER_ALPHA => Some(self.command_filter().unwrap_or(1.0)),
+            #[cfg(feature = "brake-pressure-loop")]
+            OSCC_PARAM_BRAKE_PRESSURE_LOOP_KP => Some(self.pressure_loop_gains().0),
+            #[cfg(feature = "brake-pressure-loop")]
+            OSCC_PARAM_BRAKE_PRESSURE_LOOP_KI => Some(self.pressure_loop_gains().1),
+            _ => None,
+        }
+    }
+
+    // applies a written parameter, the caller checks this module owns it and
+    // `value` is within the parameter's range. Integer parameters have one
+    // raw step per unit so their value is whole.
+    fn write_param(&mut self, param_id: u8, value: f32) -> ParamStatus {
+        let (light_high, light_low) = self.brake_light_thresholds();
+
+        match param_id {
+            OSCC_PARAM_BRAKE_OVERRIDE_THRESHOLD => self.set_override_threshold(value as u16),
+            OSCC_PARAM_BRAKE_LIGHT_HIGH_THRESHOLD => {
+                self.set_brake_light_thresholds(value as u16, light_low)
+            }
+            OSCC_PARAM_BRAKE_LIGHT_LOW_THRESHOLD => {
+                self.set_brake_light_thresholds(light_high, value as u16)
+            }
+            OSCC_PARAM_BRAKE_LIGHT_HYSTERESIS => self.set_brake_light_hysteresis(value as u16),
+            OSCC_PARAM_BRAKE_COMMAND_FILTER_ALPHA if value < 1.0 => {
+                self.set_command_filter(Some(value))
+            }
+            OSCC_PARAM_BRAKE_COMMAND_FILTER_ALPHA => self.set_command_filter(None),
+            #[cfg(feature = "brake-pressure-loop")]
+            OSCC_PARAM_BRAKE_PRESSURE_LOOP_KP => {
+                let (_, ki) = self.pressure_loop_gains();
+                self.set_pressure_loop_gains(value, ki)
+            }
+            #[cfg(feature = "brake-pressure-loop")]
+            OSCC_PARAM_BRAKE_PRESSURE_LOOP_KI => {
+                let (kp, _) = self.pressure_loop_gains();
+                self.set_pressure_loop_gains(kp, value)
+            }
+            _ => return ParamStatus::InvalidValue,
+        }
+
+        ParamStatus::Ok
+    }
+
+    /// Handles the OSCC frames at IDs shifted by `can_id_offset`, see
+    /// `config::CanIdOffset`
+    pub fn set_can_id_offset(&mut self, can_id_offset: CanIdOffset) {
+        self.can_id_offset = can_id_offset;
+    }
+
+    /// `rx_timestamp_ms` is the system clock time the frame was received
+    ///
+    /// A dropped brake frame returns `MalformedFrame` and a refused enable
+    /// `NotReady`, both are logged here.
+    pub fn process_rx_frame(
+        &mut self,
+        can_frame: &CanFrame,
+        rx_timestamp_ms: u32,
+        debug_console: &mut DebugConsole,
+    ) -> Result<(), BrakeModuleError> {
+        if let CanFrame::DataFrame(ref frame) = can_frame {
+            // compared against the standard OSCC IDs from here on
+            let id = match self.can_id_offset.base_id(frame.id().into()) {
+                Some(id) => id,
+                None => return Ok(()),
+            };
+            let data = frame.data();
+
+            if has_oscc_magic(data) {
+                // disable and fault frames are always honored, only frames that
+                // could actuate the vehicle, or allow it to be actuated again,
+                // are required to pass the CRC check
+                let crc_valid = oscc_crc::is_crc_valid(data);
+
+                if id == OSCC_BRAKE_ENABLE_CAN_ID.into() {
+                    if crc_valid {
+                        let result = self.enable_control(debug_console);
+                        self.acknowledge(ControlRequest::Enable);
+                        result?;
+                    } else {
+                        self.reject_invalid_crc_frame(id, debug_console)?;
+                    }
+                } else if id == OSCC_BRAKE_DISABLE_CAN_ID.into() {
+                    let result = self.disable_control(debug_console);
+                    self.acknowledge(ControlRequest::Disable);
+                    result?;
+                } else if id == OSCC_ALL_DISABLE_CAN_ID.into() {
+                    self.emergency_stop(debug_console)?;
+                } else if id == OSCC_ALL_DISABLE_CLEAR_CAN_ID.into() {
+                    if crc_valid {
+                        EMERGENCY_STOP_STATE.clear();
+                    } else {
+                        self.reject_invalid_crc_frame(id, debug_console)?;
+                    }
+                } else if id == OSCC_BRAKE_COMMAND_CAN_ID.into() {
+                    match OsccBrakeCommand::from_frame(frame) {
+                        Ok(_) if !crc_valid => {
+                            self.reject_invalid_crc_frame(id, debug_console)?;
+                        }
+                        Ok(ref command) if command.protocol_version != OSCC_PROTOCOL_VERSION => {
+                            self.reject_protocol_mismatch(command.protocol_version, debug_console)?;
+                        }
+                        Ok(ref command) if is_absurd_brake_command::<V>(command.pedal_command) => {
+                            self.reject_absurd_command(command.pedal_command, debug_console)?;
+                        }
+                        Ok(ref command) => self.process_brake_command(command, rx_timestamp_ms)?,
+                        Err(e) => {
+                            log!(
+                                debug_console,
+                                Module::Brake,
+                                Level::Warn,
+                                "Dropped malformed brake command: {:?}",
+                                e
+                            );
+                            return Err(BrakeModuleError::MalformedFrame);
+                        }
+                    }
+                } else if id == OSCC_BRAKE_CLEAR_FAULTS_CAN_ID.into() {
+                    if crc_valid {
+                        self.clear_faults(debug_console)?;
+                    } else {
+                        self.reject_invalid_crc_frame(id, debug_console)?;
+                    }
+                } else if id == OSCC_BRAKE_REGEN_STATUS_CAN_ID.into() {
+                    self.process_regen_status(frame, rx_timestamp_ms);
+                } else if id == OSCC_FAULT_REPORT_CAN_ID.into() {
+                    match OsccFaultReport::from_frame(frame) {
+                        Ok(ref fault_report) => {
+                            self.process_fault_report(fault_report, debug_console)?
+                        }
+                        Err(e) => {
+                            log!(
+                                debug_console,
+                                Module::Brake,
+                                Level::Warn,
+                                "Dropped malformed fault report: {:?}",
+                                e
+                            );
+                            return Err(BrakeModuleError::MalformedFrame);
+                        }
+                    }
+                } else if id == OSCC_MODULE_INFO_REQUEST_CAN_ID.into() {
+                    self.module_info_requested = true;
+                } else if id == OSCC_PARAM_WRITE_CAN_ID.into() {
+                    match OsccParamWrite::from_frame(frame) {
+                        Ok(write) => self.process_param_request(
+                            write.param_id,
+                            Some(write.raw),
+                            debug_console,
+                        ),
+                        Err(e) => {
+                            log!(
+                                debug_console,
+                                Module::Brake,
+                                Level::Warn,
+                                "Dropped parameter write: {:?}",
+                                e
+                            );
+                        }
+                    }
+                } else if id == OSCC_PARAM_READ_CAN_ID.into() {
+                    if let Some(read) = OsccParamRead::from_frame(frame) {
+                        self.process_param_request(read.param_id, None, debug_console);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Clears all DTCs and an override or fault state, like a diagnostic tool
+    /// clearing codes. Refused while control is enabled. The system fault
+    /// latch is left latched, a hard fault anywhere keeps every module
+    /// disabled until reset.
+    fn clear_faults(&mut self, debug_console: &mut DebugConsole) -> Result<(), BrakeModuleError> {
+        if self.control_state.state == ControlState::Enabled {
+            log!(
+                debug_console,
+                Module::Brake,
+                Level::Warn,
+                "Refusing to clear brake DTCs while enabled"
+            );
+            return Ok(());
+        }
+
+        self.control_state.dtcs.clear_all();
+        self.control_state.extended_dtcs.clear_all();
+        self.brake_dac.clear_verification_fault();
+        self.transition_to(ControlState::Disabled, debug_console)?;
+        self.update_fault_report();
+
+        log!(
+            debug_console,
+            Module::Brake,
+            Level::Info,
+            "Brake DTCs cleared"
+        );
+
+        Ok(())
+    }
+
+    fn reject_invalid_crc_frame(
+        &mut self,
+        id: u32,
+        debug_console: &mut DebugConsole,
+    ) -> Result<(), BrakeModuleError> {
+        self.control_state.dtcs.set(OSCC_BRAKE_DTC_INVALID_CRC);
+        self.control_state.rejected_command_fault = true;
+
+        self.update_fault_report();
+
+        log!(
+            debug_console,
+            Module::Brake,
+            Level::Warn,
+            "Dropped brake frame 0x{:X} with invalid CRC",
+            id
+        );
+
+        Err(BrakeModuleError::MalformedFrame)
+    }
+
+    fn reject_protocol_mismatch(
+        &mut self,
+        version: u8,
+        debug_console: &mut DebugConsole,
+    ) -> Result<(), BrakeModuleError> {
+        self.control_state
+            .dtcs
+            .set(OSCC_BRAKE_DTC_PROTOCOL_MISMATCH);
+        self.control_state.rejected_command_fault = true;
+
+        self.update_fault_report();
+
+        log!(
+            debug_console,
+            Module::Brake,
+            Level::Warn,
+            "Dropped brake command with protocol version {}, expected {}",
+            version,
+            OSCC_PROTOCOL_VERSION
+        );
+
+        Err(BrakeModuleError::MalformedFrame)
+    }
+
+    fn reject_absurd_command(
+        &mut self,
+        pedal_command: f32,
+        debug_console: &mut DebugConsole,
+    ) -> Result<(), BrakeModuleError> {
+        self.fault(debug_console)?;
+
+        self.control_state
+            .extended_dtcs
+            .set(OSCC_BRAKE_EXTENDED_DTC_ABSURD_COMMAND);
+        self.control_state.rejected_command_fault = true;
+
+        self.update_fault_report();
+
+        log!(
+            debug_console,
+            Module::Brake,
+            Level::Error,
+            "Dropped brake command with absurd pedal command {}",
+            pedal_command
+        );
+
+        Err(BrakeModuleError::MalformedFrame)
+    }
+
+    /// Disables control regardless of the current state and latches the
+    /// emergency stop, blocking re-enable until the e-stop clear frame
+    fn emergency_stop(&mut self, debug_console: &mut DebugConsole) -> Result<(), BrakeModuleError> {
+        EMERGENCY_STOP_STATE.latch();
+
+        log!(
+            debug_console,
+            Module::Brake,
+            Level::Warn,
+            "Emergency stop received"
+        );
+
+        self.disable_control(debug_console)
+    }
+
+    fn process_fault_report(
+        &mut self,
+        fault_report: &OsccFaultReport,
+        debug_console: &mut DebugConsole,
+    ) -> Result<(), BrakeModuleError> {
+        log!(
+            debug_console,
+            Module::Brake,
+            Level::Warn,
+            "Fault report received from: {} DTCs: {}",
+            fault_origin_name(fault_report.fault_origin_id),
+            fault_report.dtcs
+        );
+
+        let dtcs = describe_dtc(fault_report.fault_origin_id, fault_report.dtcs);
+        let extended_dtcs =
+            describe_extended_dtc(fault_report.fault_origin_id, fault_report.extended_dtcs);
+
+        for dtc in dtcs.chain(extended_dtcs) {
+            log!(debug_console, Module::Brake, Level::Warn, "  {}", dtc);
+        }
+
+        if fault_report.is_hard_fault() {
+            SYSTEM_FAULT_STATE.latch();
+        }
+
+        self.disable_control(debug_console)
+    }
+
+    fn process_brake_command(
+        &mut self,
+        command: &OsccBrakeCommand,
+        rx_timestamp_ms: u32,
+    ) -> Result<(), BrakeModuleError> {
+        if (self.control_state.state != ControlState::Enabled)
+            && self.control_state.buffer_commands_before_enable
+        {
+            self.control_state.pending_command = Some((command.pedal_command, rx_timestamp_ms));
+            return Ok(());
+        }
+
+        self.apply_brake_command(command.pedal_command, rx_timestamp_ms)
+    }
+
+    /// Applies the command buffered while disabled, unless it is older than
+    /// the command timeout
+    ///
+    /// The output ramps from the pedal values written on enable to the
+    /// command, see `step_enable_ramp`. The command timeout runs from when
+    /// the buffered command was received, not from the enable.
+    fn apply_pending_command(&mut self) -> Result<(), BrakeModuleError> {
+        if let Some((pedal_command, rx_timestamp_ms)) = self.control_state.pending_command.take() {
+            let is_stale = system_clock::is_timeout(
+                rx_timestamp_ms,
+                system_clock::get_timestamp_ms(),
+                V::BRAKE_COMMAND_TIMEOUT_IN_MSEC,
+            );
+
+            if !is_stale {
+                self.control_state.enable_ramp_target = self.control_state.last_spoof;
+                return self.apply_brake_command(pedal_command, rx_timestamp_ms);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn apply_brake_command(
+        &mut self,
+        pedal_command: f32,
+        rx_timestamp_ms: u32,
+    ) -> Result<(), BrakeModuleError> {
+        self.control_state.last_command_ms = rx_timestamp_ms;
+
+        let limited_command = num::clamp(
+            pedal_command,
+            V::MINIMUM_BRAKE_COMMAND,
+            V::MAXIMUM_BRAKE_COMMAND,
+        );
+
+        if limited_command != pedal_command {
+            self.control_state.output_clamped = true;
+        }
+
+        let clamped_position = apply_command_deadband(
+            limited_command,
+            V::MINIMUM_BRAKE_COMMAND,
+            V::BRAKE_COMMAND_DEADBAND,
+        );
+
+        self.control_state.last_commanded_position = clamped_position;
+
+        let filtered_position = self.filter_command(clamped_position);
+
+        #[cfg(feature = "brake-regen-blending")]
+        let filtered_position = self.blend_regen(filtered_position);
+
+        self.apply_position(filtered_position)
+    }
+
+    /// Requests the share of `position` regen can meet and returns the
+    /// position left for the friction brake. Without a recent regen status
+    /// reporting regen available, the friction brake takes all of it.
+    #[cfg(feature = "brake-regen-blending")]
+    fn blend_regen(&mut self, position: f32) -> f32 {
+        self.control_state.total_command = position;
+
+        if !self.is_regen_available() {
+            self.control_state.regen_request = 0.0;
+            return position;
+        }
+
+        let (friction, regen) = blend_regen_braking(
+            position,
+            V::MINIMUM_BRAKE_COMMAND,
+            V::MAXIMUM_BRAKE_COMMAND,
+            V::BRAKE_REGEN_CROSSOVER,
+        );
+
+        self.control_state.regen_request = regen;
+        friction
+    }
+
+    #[cfg(feature = "brake-regen-blending")]
+    fn is_regen_available(&self) -> bool {
+        match self.control_state.regen_available_ms {
+            Some(rx_timestamp_ms) => !system_clock::is_timeout(
+                rx_timestamp_ms,
+                system_clock::get_timestamp_ms(),
+                V::BRAKE_REGEN_STATUS_TIMEOUT_IN_MSEC,
+            ),
+            None => false,
+        }
+    }
+
+    #[cfg(not(feature = "brake-regen-blending"))]
+    fn process_regen_status(&mut self, _frame: &DataFrame, _rx_timestamp_ms: u32) {}
+
+    #[cfg(feature = "brake-regen-blending")]
+    fn process_regen_status(&mut self, frame: &DataFrame, rx_timestamp_ms: u32) {
+        if let Ok(status) = OsccBrakeRegenStatus::from_frame(frame) {
+            self.control_state.regen_available_ms = if status.available {
+                Some(rx_timestamp_ms)
+            } else {
+                None
+            };
+        }
+    }
+
+    /// Hands the braking back to the friction brake once regen is no longer
+    /// reported available, called every FAULT_CHECK_PERIOD_MS. Commands are
+    /// blended again when it is.
+    #[cfg(feature = "brake-regen-blending")]
+    pub fn check_regen_status(
+        &mut self,
+        debug_console: &mut DebugConsole,
+    ) -> Result<(), BrakeModuleError> {
+        if (self.control_state.state != ControlState::Enabled)
+            || (self.control_state.regen_request == 0.0)
+            || self.is_regen_available()
+        {
+            return Ok(());
+        }
+
+        log!(
+            debug_console,
+            Module::Brake,
+            Level::Warn,
+            "Regen braking unavailable, friction braking"
+        );
+
+        self.control_state.regen_request = 0.0;
+
+        let position = self.control_state.total_command;
+        self.apply_position(position)
+    }
+
+    /// Drives the spoof output straight from the commanded position
+    #[cfg(not(feature = "brake-pressure-loop"))]
+    fn apply_position(&mut self, position: f32) -> Result<(), BrakeModuleError> {
+        self.output_position(position)
+    }
+
+    /// Turns the commanded position into the pressure setpoint,
+    /// `step_pressure_loop` drives the spoof output towards it
+    #[cfg(feature = "brake-pressure-loop")]
+    fn apply_position(&mut self, position: f32) -> Result<(), BrakeModuleError> {
+        self.control_state.pressure_setpoint =
+            Some(position * V::BRAKE_PRESSURE_AT_MAXIMUM_COMMAND);
+        Ok(())
+    }
+
+    #[cfg(not(feature = "brake-pressure-loop"))]
+    fn reset_pressure_loop(&mut self) {}
+
+    #[cfg(feature = "brake-pressure-loop")]
+    fn reset_pressure_loop(&mut self) {
+        self.control_state.pressure_setpoint = None;
+        self.control_state.last_pressure = None;
+        self.pressure_loop.reset();
+    }
+
+    /// Adjusts the spoof output to bring the measured brake pressure to the
+    /// commanded pressure, called every FAULT_CHECK_PERIOD_MS
+    ///
+    /// A pressure reading outside BRAKE_PRESSURE_SENSOR_MIN..MAX, or
+    /// changing by more than BRAKE_PRESSURE_SENSOR_MAX_DELTA a step, for
+    /// SENSOR_FAULT_HYSTERESIS faults the module with INVALID_SENSOR_VAL.
+    #[cfg(feature = "brake-pressure-loop")]
+    pub fn step_pressure_loop(
+        &mut self,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, BrakeModuleError> {
+        if self.control_state.state != ControlState::Enabled {
+            return Ok(None);
+        }
+
+        let pressure = self
+            .brake_pedal_position
+            .reader()
+            .read_signal(AdcSignal::BrakePressure);
+
+        let implausible = fault_condition::is_reading_implausible(
+            pressure,
+            self.control_state.last_pressure,
+            V::BRAKE_PRESSURE_SENSOR_MIN,
+            V::BRAKE_PRESSURE_SENSOR_MAX,
+            V::BRAKE_PRESSURE_SENSOR_MAX_DELTA,
+        );
+        self.control_state.last_pressure = Some(pressure);
+
+        if self
+            .pressure_fault_state
+            .condition_exceeded_duration(implausible)
+        {
+            self.hard_fault(debug_console)?;
+
+            self.control_state
+                .dtcs
+                .set(OSCC_BRAKE_DTC_INVALID_SENSOR_VAL);
+
+            self.update_fault_report();
+
+            log!(
+                debug_console,
+                Module::Brake,
+                Level::Error,
+                "Implausible brake pressure sensor reading {}",
+                pressure
+            );
+
+            return Ok(Some(&self.fault_report));
+        }
+
+        let setpoint = match self.control_state.pressure_setpoint {
+            Some(setpoint) => setpoint,
+            None => return Ok(None),
+        };
+
+        let position = self.pressure_loop.update(
+            setpoint - f32::from(pressure),
+            FAULT_CHECK_PERIOD_MS as f32 / 1000.0,
+        );
+
+        self.output_position(position)?;
+
+        Ok(None)
+    }
+
+    /// Brake pressure loop gains, default to BRAKE_PRESSURE_LOOP_KP and
+    /// BRAKE_PRESSURE_LOOP_KI
+    #[cfg(feature = "brake-pressure-loop")]
+    fn set_pressure_loop_gains(&mut self, kp: f32, ki: f32) {
+        self.pressure_loop.set_gains(kp, ki);
+        self.pressure_loop.reset();
+    }
+
+    /// Returns the (kp, ki) brake pressure loop gains
+    #[cfg(feature = "brake-pressure-loop")]
+    pub fn pressure_loop_gains(&self) -> (f32, f32) {
+        self.pressure_loop.gains()
+    }
+
+    fn output_position(&mut self, position: f32) -> Result<(), BrakeModuleError> {
+        let spoof = self.position_spoof(position);
+
+        self.update_brake(&spoof)
+    }
+
+    fn position_spoof(&self, position: f32) -> SpoofSignalPair {
+        let spoof_voltage_high = Volts(V::brake_position_to_volts_high(position));
+        let spoof_voltage_low = Volts(V::brake_position_to_volts_low(position));
+
+        SpoofSignalPair::from_volts(
+            spoof_voltage_high,
+            spoof_voltage_low,
+            &V::BRAKE_SPOOF_SIGNAL_CALIBRATION,
+            self.brake_dac.steps_per_volt(),
+        )
+    }
+}
+
+impl<V: Vehicle> ControlModule for BrakeModule<V> {
+    fn disable_control(&mut self, debug_console: &mut DebugConsole) -> Result<(), OxccError> {
+        BrakeModule::disable_control(self, debug_console).map_err(OxccError::from)
+    }
+
+    fn check_for_faults(
+        &mut self,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, OxccError> {
+        BrakeModule::check_for_faults(self, debug_console).map_err(OxccError::from)
+    }
+
+    fn check_can_health(
+        &mut self,
+        can_health: &CanHealth,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, OxccError> {
+        BrakeModule::check_can_health(self, can_health, debug_console).map_err(OxccError::from)
+    }
+
+    fn check_spoof_enable(
+        &mut self,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, OxccError> {
+        BrakeModule::check_spoof_enable(self, debug_console).map_err(OxccError::from)
+    }
+
+    fn check_dac_output(
+        &mut self,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, OxccError> {
+        BrakeModule::check_dac_output(self, debug_console).map_err(OxccError::from)
+    }
+
+    fn set_can_id_offset(&mut self, can_id_offset: CanIdOffset) {
+        BrakeModule::set_can_id_offset(self, can_id_offset)
+    }
+
+    fn process_rx_frame(
+        &mut self,
+        can_frame: &CanFrame,
+        rx_timestamp_ms: u32,
+        debug_console: &mut DebugConsole,
+    ) -> Result<(), OxccError> {
+        BrakeModule::process_rx_frame(self, can_frame, rx_timestamp_ms, debug_console)
+            .map_err(OxccError::from)
+    }
+
+    fn step_range_learning(&mut self) {
+        BrakeModule::step_range_learning(self)
+    }
+
+    fn publish_reports(&mut self, publisher: &mut ReportPublisher) -> Result<(), CanError> {
+        let report_result = publisher.publish_brake_report(self.supply_brake_report());
+        let heartbeat_result = publisher.publish_brake_heartbeat(self.supply_brake_heartbeat());
+        let result = prefer_non_timeout(report_result, heartbeat_result);
+
+        // published with the reports so regen releases once control is
+        // disabled
+        #[cfg(feature = "brake-regen-blending")]
+        let result = {
+            let regen_result =
+                publisher.publish_brake_regen_request(self.supply_brake_regen_request());
+            prefer_non_timeout(result, regen_result)
+        };
+
+        result
+    }
+
+    fn supply_control_ack(&mut self) -> Option<OsccControlAck> {
+        BrakeModule::supply_control_ack(self)
+    }
+
+    fn supply_module_info(&mut self) -> Option<&OsccModuleInfo> {
+        BrakeModule::supply_module_info(self)
+    }
+
+    fn supply_param_value(&mut self) -> Option<OsccParamValue> {
+        BrakeModule::supply_param_value(self)
+    }
+
+    fn supply_fault_report(&mut self) -> &OsccFaultReport {
+        BrakeModule::supply_fault_report(self)
+    }
+}
+
+trait HighLowReader {
+    fn read_high(&self) -> u16;
     fn read_low(&self) -> u16;
 }
+
+// the first error other than a timeout, else the first timeout
+fn prefer_non_timeout(
+    first: Result<(), CanError>,
+    second: Result<(), CanError>,
+) -> Result<(), CanError> {
+    match second {
+        Err(e) if e != CanError::Timeout => Err(e),
+        _ => first.and(second),
+    }
+}
+
+// a NaN would pass straight through the clamp
+fn is_absurd_brake_command<V: Vehicle>(pedal_command: f32) -> bool {
+    is_command_absurd(
+        pedal_command,
+        V::MINIMUM_BRAKE_COMMAND,
+        V::MAXIMUM_BRAKE_COMMAND,
+        COMMAND_REJECT_MARGIN,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::STARTUP_FAULT_GRACE_MS;
+    use oscc_crc::OSCC_CRC_BYTE_INDEX;
+    use oscc_protocol_version::OSCC_PROTOCOL_VERSION_BYTE_INDEX;
+    use override_source::OverrideSwitch;
+    use sim_board::{oscc_frame, SimBoard, SimConsole, SimInputPin};
+    use spoof_signal_pair::SpoofSignalCalibration;
+    use system_clock::{StartupGrace, TestClock};
+
+    // pedal at rest, the low channel at the high one over the ratio
+    const RELEASED: (u16, u16) = (400, 195);
+    // pressed well past the override threshold
+    const PRESSED: (u16, u16) = (1200, 585);
+
+    // the clock is held for the whole test, it also keeps the tests sharing
+    // the fault latches and the vehicle state from running at once
+    fn brake_module() -> (TestClock, BrakeModule, SimConsole) {
+        brake_module_for::<KiaSoulEvNiro>()
+    }
+
+    fn brake_module_for<V: Vehicle>() -> (TestClock, BrakeModule<V>, SimConsole) {
+        let clock = TestClock::take();
+        SYSTEM_FAULT_STATE.clear();
+        EMERGENCY_STOP_STATE.clear();
+        VEHICLE_STATE.set_brake_operator_override(false);
+        VEHICLE_STATE.set_brake_applied(false);
+        VEHICLE_STATE.set_brake_pedal_pressed(false);
+
+        let board = SimBoard::new();
+        board.brake_pedal_position.set(RELEASED.0, RELEASED.1);
+        let mut console = board.debug_console;
+
+        let brake = UnpreparedBrakeModule::new(
+            board.brake_dac,
+            board.brake_pins,
+            board.brake_pedal_position,
+        )
+        .prepare_module(&mut console);
+
+        (clock, brake, console)
+    }
+
+    fn command_frame(pedal_command: f32) -> CanFrame {
+        let mut payload = [0; 5];
+        let bits = pedal_command.to_bits();
+        for (i, byte) in payload.iter_mut().take(4).enumerate() {
+            *byte = (bits >> (8 * i)) as u8;
+        }
+        payload[OSCC_PROTOCOL_VERSION_BYTE_INDEX - 2] = OSCC_PROTOCOL_VERSION;
+        oscc_frame(OSCC_BRAKE_COMMAND_CAN_ID, &payload)
+    }
+
+    // `frame` with its CRC byte flipped
+    fn corrupted(frame: CanFrame) -> CanFrame {
+        match frame {
+            CanFrame::DataFrame(mut frame) => {
+                frame.data_as_mut()[OSCC_CRC_BYTE_INDEX] ^= 0xFF;
+                frame.into()
+            }
+            frame => frame,
+        }
+    }
+
+    fn receive<V: Vehicle>(brake: &mut BrakeModule<V>, frame: &CanFrame, console: &mut SimConsole) {
+        brake
+            .process_rx_frame(frame, system_clock::get_timestamp_ms(), console)
+            .unwrap();
+    }
+
+    fn spi_write_count(brake: &mut BrakeModule) -> usize {
+        brake.brake_dac.spi_mut().write_count()
+    }
+
+    #[test]
+    fn full_control_cycle_against_the_sim_board() {
+        let (clock, mut brake, mut console) = brake_module();
+
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+        assert_eq!(brake.control_state(), ControlState::Enabled);
+        assert!(brake.brake_pins.spoof_enable.is_high());
+
+        let writes_before_command = spi_write_count(&mut brake);
+        receive(&mut brake, &command_frame(0.5), &mut console);
+        // the pressure loop drives the output towards the command
+        #[cfg(feature = "brake-pressure-loop")]
+        {
+            brake.brake_pedal_position.reader().set_pressure(500);
+            brake.step_pressure_loop(&mut console).unwrap();
+        }
+        assert!(spi_write_count(&mut brake) > writes_before_command);
+        assert_eq!(brake.state().commanded_position, 0.5);
+        assert!(brake.brake_pins.brake_light_enable.is_high());
+        assert!(VEHICLE_STATE.is_braking());
+
+        // the override has to be held for the hysteresis
+        brake
+            .brake_pedal_position
+            .reader()
+            .set(PRESSED.0, PRESSED.1);
+        assert!(brake.check_for_faults(&mut console).unwrap().is_none());
+        clock.advance_ms(KiaSoulEvNiro::OPERATOR_OVERRIDE_HYSTERESIS);
+        let dtcs = brake
+            .check_for_faults(&mut console)
+            .unwrap()
+            .map(|r| r.dtcs);
+        assert!(dtcs.unwrap().check(OSCC_BRAKE_DTC_OPERATOR_OVERRIDE));
+        assert_eq!(brake.control_state(), ControlState::OverrideActive);
+
+        // the outputs ramp back to the pressed pedal then spoofing is released
+        while brake.brake_pins.spoof_enable.is_high() {
+            brake.step_disable_ramp(&mut console).unwrap();
+        }
+        assert!(!brake.brake_pins.brake_light_enable.is_high());
+
+        // and the override clears once the pedal stays released for the debounce
+        brake
+            .brake_pedal_position
+            .reader()
+            .set(RELEASED.0, RELEASED.1);
+        assert!(brake.check_for_faults(&mut console).unwrap().is_none());
+        assert_eq!(brake.control_state(), ControlState::OverrideActive);
+        clock.advance_ms(KiaSoulEvNiro::OPERATOR_OVERRIDE_RELEASE_DEBOUNCE);
+        assert!(brake.check_for_faults(&mut console).unwrap().is_none());
+        assert_eq!(brake.control_state(), ControlState::Disabled);
+        assert_eq!(brake.state().dtcs, 0);
+        assert!(!VEHICLE_STATE.is_braking());
+
+        // the dwell has passed with the debounce, control can be enabled again
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+        assert_eq!(brake.control_state(), ControlState::Enabled);
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_DISABLE_CAN_ID, &[]),
+            &mut console,
+        );
+        assert_eq!(brake.control_state(), ControlState::Disabled);
+        assert!(!brake.brake_pins.spoof_enable.is_high());
+        assert!(console.line_count() > 0);
+    }
+
+    #[test]
+    fn invalid_crc_frame_publishes_a_soft_fault_once() {
+        let (_clock, mut brake, mut console) = brake_module();
+
+        let enable = corrupted(oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]));
+        assert_eq!(
+            brake.process_rx_frame(&enable, 0, &mut console),
+            Err(BrakeModuleError::MalformedFrame)
+        );
+        assert_eq!(brake.control_state(), ControlState::Disabled);
+
+        let report = brake
+            .check_rejected_command()
+            .map(|r| (r.dtcs, r.is_hard_fault()));
+        assert_eq!(report, Some((1 << OSCC_BRAKE_DTC_INVALID_CRC, false)));
+        assert!(brake.check_rejected_command().is_none());
+    }
+
+    #[test]
+    fn protocol_mismatch_keeps_control_and_publishes_a_soft_fault() {
+        let (_clock, mut brake, mut console) = brake_module();
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+
+        let mut payload = [0; 5];
+        payload[OSCC_PROTOCOL_VERSION_BYTE_INDEX - 2] = OSCC_PROTOCOL_VERSION + 1;
+        let command = oscc_frame(OSCC_BRAKE_COMMAND_CAN_ID, &payload);
+        assert_eq!(
+            brake.process_rx_frame(&command, 0, &mut console),
+            Err(BrakeModuleError::MalformedFrame)
+        );
+        assert_eq!(brake.control_state(), ControlState::Enabled);
+
+        let report = brake
+            .check_rejected_command()
+            .map(|r| (r.dtcs, r.is_hard_fault()));
+        assert_eq!(report, Some((1 << OSCC_BRAKE_DTC_PROTOCOL_MISMATCH, false)));
+    }
+
+    #[test]
+    fn supply_voltage_fault_sets_its_extended_dtc_until_recovered() {
+        let (clock, mut brake, mut console) = brake_module();
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+
+        brake.brake_pedal_position.reader().set_vdda(2.9);
+        let supply_voltage = brake.measure_supply_voltage();
+        assert!(!supply_voltage.out_of_tolerance);
+        clock.advance_ms(KiaSoulEvNiro::FAULT_HYSTERESIS);
+        let supply_voltage = brake.measure_supply_voltage();
+        assert!(supply_voltage.out_of_tolerance);
+
+        let extended_dtcs = brake
+            .check_supply_voltage(&supply_voltage, &mut console)
+            .unwrap()
+            .map(|r| r.extended_dtcs);
+        assert_eq!(
+            extended_dtcs,
+            Some(1 << OSCC_BRAKE_EXTENDED_DTC_SUPPLY_VOLTAGE)
+        );
+        assert_eq!(brake.control_state(), ControlState::Faulted);
+        assert!(!brake.brake_pins.spoof_enable.is_high());
+        // published once
+        assert!(brake
+            .check_supply_voltage(&supply_voltage, &mut console)
+            .unwrap()
+            .is_none());
+
+        brake.brake_pedal_position.reader().set_vdda(VDDA_NOMINAL);
+        brake.measure_supply_voltage();
+        clock.advance_ms(KiaSoulEvNiro::FAULT_HYSTERESIS);
+        let supply_voltage = brake.measure_supply_voltage();
+        assert!(!supply_voltage.out_of_tolerance);
+        brake
+            .check_supply_voltage(&supply_voltage, &mut console)
+            .unwrap();
+        assert_eq!(brake.supply_fault_report().extended_dtcs, 0);
+    }
+
+    #[test]
+    fn clear_faults_while_disabled_clears_the_dtcs() {
+        let (clock, mut brake, mut console) = brake_module();
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+        clock.advance_ms(KiaSoulEvNiro::BRAKE_COMMAND_TIMEOUT_IN_MSEC);
+        assert!(brake.check_command_timeout(&mut console).unwrap().is_some());
+        assert_eq!(brake.control_state(), ControlState::Faulted);
+        SYSTEM_FAULT_STATE.latch();
+
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_CLEAR_FAULTS_CAN_ID, &[]),
+            &mut console,
+        );
+
+        assert_eq!(brake.control_state(), ControlState::Disabled);
+        assert_eq!(brake.state().dtcs, 0);
+        assert_eq!(brake.supply_fault_report().dtcs, 0);
+        // only a reset releases the system fault latch
+        assert!(SYSTEM_FAULT_STATE.is_latched());
+    }
+
+    #[test]
+    fn enable_frame_recovers_from_a_command_timeout() {
+        let (clock, mut brake, mut console) = brake_module();
+        let enable = oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]);
+        receive(&mut brake, &enable, &mut console);
+        clock.advance_ms(KiaSoulEvNiro::BRAKE_COMMAND_TIMEOUT_IN_MSEC);
+        assert!(brake.check_command_timeout(&mut console).unwrap().is_some());
+        assert_eq!(brake.control_state(), ControlState::Faulted);
+
+        clock.advance_ms(KiaSoulEvNiro::BRAKE_MIN_CONTROL_DWELL_IN_MSEC);
+        receive(&mut brake, &enable, &mut console);
+
+        assert_eq!(brake.control_state(), ControlState::Enabled);
+        assert!(!brake.state().dtcs.check(OSCC_BRAKE_DTC_COMMAND_TIMEOUT));
+    }
+
+    #[test]
+    fn enable_frame_keeps_a_hard_fault_alongside_a_command_timeout() {
+        let (clock, mut brake, mut console) = brake_module();
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+        clock.advance_ms(KiaSoulEvNiro::BRAKE_COMMAND_TIMEOUT_IN_MSEC);
+        brake.check_command_timeout(&mut console).unwrap();
+        brake
+            .control_state
+            .dtcs
+            .set(OSCC_BRAKE_DTC_INVALID_SENSOR_VAL);
+
+        clock.advance_ms(KiaSoulEvNiro::BRAKE_MIN_CONTROL_DWELL_IN_MSEC);
+        let enable = oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]);
+        assert_eq!(
+            brake.process_rx_frame(&enable, system_clock::get_timestamp_ms(), &mut console),
+            Err(BrakeModuleError::NotReady)
+        );
+        assert_eq!(brake.control_state(), ControlState::Faulted);
+    }
+
+    #[test]
+    fn clear_faults_is_refused_while_enabled() {
+        let (_clock, mut brake, mut console) = brake_module();
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+        let corrupted_command = corrupted(command_frame(0.5));
+        assert!(brake
+            .process_rx_frame(&corrupted_command, 0, &mut console)
+            .is_err());
+
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_CLEAR_FAULTS_CAN_ID, &[]),
+            &mut console,
+        );
+
+        assert_eq!(brake.control_state(), ControlState::Enabled);
+        assert!(brake.state().dtcs.check(OSCC_BRAKE_DTC_INVALID_CRC));
+    }
+
+    #[test]
+    fn clear_faults_needs_a_valid_crc() {
+        let (_clock, mut brake, mut console) = brake_module();
+        let supply_voltage = SupplyVoltage {
+            vdda: 0.0,
+            out_of_tolerance: true,
+        };
+        assert!(brake
+            .check_supply_voltage(&supply_voltage, &mut console)
+            .unwrap()
+            .is_some());
+
+        let clear = corrupted(oscc_frame(OSCC_BRAKE_CLEAR_FAULTS_CAN_ID, &[]));
+        assert_eq!(
+            brake.process_rx_frame(&clear, 0, &mut console),
+            Err(BrakeModuleError::MalformedFrame)
+        );
+
+        assert_eq!(brake.control_state(), ControlState::Faulted);
+        assert_ne!(brake.supply_fault_report().extended_dtcs, 0);
+    }
+
+    #[test]
+    fn emergency_stop_blocks_enable_until_cleared() {
+        let (clock, mut brake, mut console) = brake_module();
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_ALL_DISABLE_CAN_ID, &[]),
+            &mut console,
+        );
+        assert_eq!(brake.control_state(), ControlState::Disabled);
+        assert!(!brake.brake_pins.spoof_enable.is_high());
+
+        let enable = oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]);
+        assert_eq!(
+            brake.process_rx_frame(&enable, 0, &mut console),
+            Err(BrakeModuleError::NotReady)
+        );
+
+        // a corrupted clear leaves the emergency stop latched
+        let clear = corrupted(oscc_frame(OSCC_ALL_DISABLE_CLEAR_CAN_ID, &[]));
+        assert!(brake.process_rx_frame(&clear, 0, &mut console).is_err());
+        assert!(EMERGENCY_STOP_STATE.is_latched());
+
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_ALL_DISABLE_CLEAR_CAN_ID, &[]),
+            &mut console,
+        );
+        clock.advance_ms(KiaSoulEvNiro::BRAKE_MIN_CONTROL_DWELL_IN_MSEC);
+        receive(&mut brake, &enable, &mut console);
+        assert_eq!(brake.control_state(), ControlState::Enabled);
+    }
+
+    #[test]
+    fn pressed_pedal_is_braking_while_disabled() {
+        let (_clock, mut brake, mut console) = brake_module();
+
+        brake
+            .brake_pedal_position
+            .reader()
+            .set(PRESSED.0, PRESSED.1);
+        assert!(brake.check_for_faults(&mut console).unwrap().is_none());
+        assert_eq!(brake.control_state(), ControlState::Disabled);
+        assert!(VEHICLE_STATE.is_braking());
+
+        brake
+            .brake_pedal_position
+            .reader()
+            .set(RELEASED.0, RELEASED.1);
+        brake.check_for_faults(&mut console).unwrap();
+        assert!(!VEHICLE_STATE.is_braking());
+    }
+
+    #[test]
+    fn dac_write_failure_keeps_control_disabled() {
+        let (_clock, mut brake, mut console) = brake_module();
+        brake.brake_dac.spi_mut().set_failing(true);
+
+        let enable = oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]);
+        match brake.process_rx_frame(&enable, 0, &mut console) {
+            Err(BrakeModuleError::DacWrite(_)) => (),
+            result => panic!("expected a DAC write error, got {:?}", result),
+        }
+        assert_eq!(brake.control_state(), ControlState::Disabled);
+        assert!(!brake.brake_pins.spoof_enable.is_high());
+    }
+
+    // the pressure loop writes the spoof output from step_pressure_loop
+    #[cfg(not(feature = "brake-pressure-loop"))]
+    #[test]
+    fn lowered_brake_light_thresholds_turn_the_lights_on() {
+        let (_clock, mut brake, mut console) = brake_module();
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+
+        let light_command = 0.02;
+        let spoof = brake.position_spoof(light_command);
+        assert!(spoof.high() <= KiaSoulEvNiro::BRAKE_LIGHT_SPOOF_HIGH_THRESHOLD);
+        assert!(spoof.low() <= KiaSoulEvNiro::BRAKE_LIGHT_SPOOF_LOW_THRESHOLD);
+        receive(&mut brake, &command_frame(light_command), &mut console);
+        assert!(!brake.brake_pins.brake_light_enable.is_high());
+
+        brake.set_brake_light_thresholds(spoof.high() - 10, spoof.low() - 10);
+        receive(&mut brake, &command_frame(light_command), &mut console);
+
+        assert!(brake.brake_pins.brake_light_enable.is_high());
+        assert_eq!(
+            brake.brake_light_thresholds(),
+            (spoof.high() - 10, spoof.low() - 10)
+        );
+    }
+
+    #[cfg(feature = "fault-injection")]
+    #[test]
+    fn injected_fault_disables_control_and_reports_the_dtc() {
+        let (_clock, mut brake, mut console) = brake_module();
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+
+        let (origin, dtcs) = brake
+            .inject_fault(OSCC_BRAKE_DTC_SENSOR_MISMATCH, &mut console)
+            .map(|r| (r.fault_origin_id, r.dtcs))
+            .unwrap();
+
+        assert_eq!(origin, FAULT_ORIGIN_BRAKE);
+        assert!(dtcs.check(OSCC_BRAKE_DTC_SENSOR_MISMATCH));
+        assert_eq!(brake.control_state(), ControlState::Faulted);
+        assert!(!brake.brake_pins.spoof_enable.is_high());
+    }
+
+    #[test]
+    fn disable_ramp_steps_at_most_the_max_delta() {
+        let (_clock, mut brake, mut console) = brake_module();
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+        brake.write_spoof(1800, 900).unwrap();
+
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_DISABLE_CAN_ID, &[]),
+            &mut console,
+        );
+
+        let max_delta = KiaSoulEvNiro::BRAKE_DISABLE_RAMP_MAX_DELTA;
+        let mut steps = 0;
+        while brake.brake_pins.spoof_enable.is_high() {
+            let (last_high, last_low) = brake.control_state.last_spoof.unwrap();
+            brake.step_disable_ramp(&mut console).unwrap();
+            let (high, low) = brake.control_state.last_spoof.unwrap();
+
+            assert!((i32::from(high) - i32::from(last_high)).abs() <= i32::from(max_delta));
+            assert!((i32::from(low) - i32::from(last_low)).abs() <= i32::from(max_delta));
+            steps += 1;
+            assert!(steps < 100);
+        }
+
+        assert!(steps > 1);
+        assert_eq!(brake.control_state.last_spoof, Some(RELEASED));
+    }
+
+    #[test]
+    fn failed_dac_write_is_reported_as_an_unhealthy_dac() {
+        let (_clock, mut brake, mut console) = brake_module();
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+        assert!(brake.supply_brake_report().dac_healthy);
+
+        brake.brake_dac.spi_mut().set_failing(true);
+        let command = command_frame(0.5);
+        #[cfg(not(feature = "brake-pressure-loop"))]
+        let result = brake.process_rx_frame(&command, 0, &mut console);
+        #[cfg(feature = "brake-pressure-loop")]
+        let result = {
+            receive(&mut brake, &command, &mut console);
+            brake.step_pressure_loop(&mut console).map(|_| ())
+        };
+        match result {
+            Err(BrakeModuleError::DacWrite(_)) => (),
+            result => panic!("expected a DAC write error, got {:?}", result),
+        }
+
+        assert!(!brake.supply_brake_report().dac_healthy);
+        assert_eq!(brake.control_state(), ControlState::Enabled);
+    }
+
+    #[test]
+    fn lowered_override_threshold_triggers_the_override() {
+        let (clock, mut brake, mut console) = brake_module();
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+
+        // a light touch, below the default threshold
+        brake.brake_pedal_position.reader().set(600, 300);
+        assert!(brake.check_for_faults(&mut console).unwrap().is_none());
+        clock.advance_ms(KiaSoulEvNiro::OPERATOR_OVERRIDE_HYSTERESIS);
+        assert!(brake.check_for_faults(&mut console).unwrap().is_none());
+        assert_eq!(brake.control_state(), ControlState::Enabled);
+
+        brake.set_override_threshold(400);
+        assert!(brake.check_for_faults(&mut console).unwrap().is_none());
+        clock.advance_ms(KiaSoulEvNiro::OPERATOR_OVERRIDE_HYSTERESIS);
+        assert!(brake.check_for_faults(&mut console).unwrap().is_some());
+        assert_eq!(brake.control_state(), ControlState::OverrideActive);
+    }
+
+    #[test]
+    fn dwell_defers_a_re_enable_but_not_a_grounded_sensor() {
+        let (clock, mut brake, mut console) = brake_module();
+        let enable = oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]);
+        receive(&mut brake, &enable, &mut console);
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_DISABLE_CAN_ID, &[]),
+            &mut console,
+        );
+        brake.supply_control_ack();
+
+        // flapping straight back to enabled is NACKed until the dwell passes
+        assert_eq!(
+            brake.process_rx_frame(&enable, 0, &mut console),
+            Err(BrakeModuleError::NotReady)
+        );
+        assert!(!brake.supply_control_ack().unwrap().accepted());
+        assert_eq!(brake.control_state(), ControlState::Disabled);
+
+        clock.advance_ms(KiaSoulEvNiro::BRAKE_MIN_CONTROL_DWELL_IN_MSEC);
+        receive(&mut brake, &enable, &mut console);
+        assert_eq!(brake.control_state(), ControlState::Enabled);
+
+        // a hard fault inside the dwell after enabling disables at once
+        brake.brake_pedal_position.reader().set(0, 0);
+        brake.check_for_faults(&mut console).unwrap();
+        clock.advance_ms(KiaSoulEvNiro::SENSOR_FAULT_HYSTERESIS);
+        assert!(brake.is_within_control_dwell());
+        assert!(brake.check_for_faults(&mut console).unwrap().is_some());
+        assert_eq!(brake.control_state(), ControlState::Faulted);
+        assert!(!brake.brake_pins.spoof_enable.is_high());
+    }
+
+    #[test]
+    fn state_snapshot_follows_enable_and_override() {
+        let (clock, mut brake, mut console) = brake_module();
+        assert_eq!(
+            brake.state(),
+            BrakeStateSnapshot {
+                control_state: ControlState::Disabled,
+                enabled: false,
+                operator_override: false,
+                dtcs: 0,
+                dac_healthy: true,
+                commanded_position: 0.0,
+                output_clamped: false,
+            }
+        );
+
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+        let enabled = brake.state();
+        assert_eq!(enabled.control_state, ControlState::Enabled);
+        assert!(enabled.enabled && !enabled.operator_override);
+
+        brake
+            .brake_pedal_position
+            .reader()
+            .set(PRESSED.0, PRESSED.1);
+        brake.check_for_faults(&mut console).unwrap();
+        clock.advance_ms(KiaSoulEvNiro::OPERATOR_OVERRIDE_HYSTERESIS);
+        brake.check_for_faults(&mut console).unwrap();
+
+        let overridden = brake.state();
+        assert_eq!(overridden.control_state, ControlState::OverrideActive);
+        assert!(!overridden.enabled && overridden.operator_override);
+        assert_eq!(overridden.dtcs, 1 << OSCC_BRAKE_DTC_OPERATOR_OVERRIDE);
+    }
+
+    #[test]
+    fn signal_mapping_routes_each_spoof_signal_to_its_channel() {
+        let (_clock, mut brake, mut console) = brake_module();
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+
+        let expected = [
+            (SignalMapping::HighToALowToB, (1500, 700)),
+            (SignalMapping::HighToBLowToA, (700, 1500)),
+        ];
+        for &(mapping, (channel_a, channel_b)) in expected.iter() {
+            brake.set_signal_mapping(mapping);
+            brake.write_spoof(1500, 700).unwrap();
+
+            // MCP4922 words carry the channel in bit 15 and the value in the
+            // low 12 bits
+            let mut outputs = [0; 2];
+            for back in 0..2 {
+                let word = brake.brake_dac.spi_mut().word(back).unwrap();
+                outputs[usize::from(word >> 15)] = word & 0x0FFF;
+            }
+            assert_eq!(outputs, [channel_a, channel_b]);
+        }
+    }
+
+    #[test]
+    fn command_buffered_before_enable_is_applied_on_enable() {
+        let (_clock, mut brake, mut console) = brake_module();
+        brake.set_buffer_commands_before_enable(true);
+
+        receive(&mut brake, &command_frame(0.5), &mut console);
+        assert_eq!(brake.state().commanded_position, 0.0);
+
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+        assert_eq!(brake.control_state(), ControlState::Enabled);
+        assert_eq!(brake.state().commanded_position, 0.5);
+
+        // the output ramps from the pedal to the buffered command
+        #[cfg(not(feature = "brake-pressure-loop"))]
+        {
+            let spoof = brake.position_spoof(0.5);
+            for _ in 0..100 {
+                brake.step_enable_ramp(&mut console).unwrap();
+            }
+            assert_eq!(
+                brake.control_state.last_spoof,
+                Some((spoof.high(), spoof.low()))
+            );
+        }
+    }
+
+    // the Kia constants, with the given brake command range and deadband
+    macro_rules! stub_vehicle {
+        ($name:ident, $maximum_brake_command:expr, $brake_command_deadband:expr) => {
+            struct $name;
+
+            impl Vehicle for $name {
+                const FAULT_HYSTERESIS: u32 = KiaSoulEvNiro::FAULT_HYSTERESIS;
+                const SENSOR_FAULT_HYSTERESIS: u32 = KiaSoulEvNiro::SENSOR_FAULT_HYSTERESIS;
+                const OPERATOR_OVERRIDE_HYSTERESIS: u32 =
+                    KiaSoulEvNiro::OPERATOR_OVERRIDE_HYSTERESIS;
+                const OPERATOR_OVERRIDE_RELEASE_DEBOUNCE: u32 =
+                    KiaSoulEvNiro::OPERATOR_OVERRIDE_RELEASE_DEBOUNCE;
+                const MINIMUM_BRAKE_COMMAND: f32 = KiaSoulEvNiro::MINIMUM_BRAKE_COMMAND;
+                const MAXIMUM_BRAKE_COMMAND: f32 = $maximum_brake_command;
+                const BRAKE_COMMAND_DEADBAND: f32 = $brake_command_deadband;
+                const BRAKE_SPOOF_SIGNAL_CALIBRATION: SpoofSignalCalibration =
+                    KiaSoulEvNiro::BRAKE_SPOOF_SIGNAL_CALIBRATION;
+                const BRAKE_PEDAL_OVERRIDE_THRESHOLD: u16 =
+                    KiaSoulEvNiro::BRAKE_PEDAL_OVERRIDE_THRESHOLD;
+                const BRAKE_PEDAL_POSITION_SENSOR_HIGH_LOW_RATIO: f32 =
+                    KiaSoulEvNiro::BRAKE_PEDAL_POSITION_SENSOR_HIGH_LOW_RATIO;
+                const BRAKE_PEDAL_POSITION_SENSOR_MISMATCH_TOLERANCE: u16 =
+                    KiaSoulEvNiro::BRAKE_PEDAL_POSITION_SENSOR_MISMATCH_TOLERANCE;
+                const BRAKE_PEDAL_POSITION_SENSOR_SHORT_HIGH_THRESHOLD: u16 =
+                    KiaSoulEvNiro::BRAKE_PEDAL_POSITION_SENSOR_SHORT_HIGH_THRESHOLD;
+                const BRAKE_LIGHT_SPOOF_LOW_THRESHOLD: u16 =
+                    KiaSoulEvNiro::BRAKE_LIGHT_SPOOF_LOW_THRESHOLD;
+                const BRAKE_LIGHT_SPOOF_HIGH_THRESHOLD: u16 =
+                    KiaSoulEvNiro::BRAKE_LIGHT_SPOOF_HIGH_THRESHOLD;
+                const BRAKE_LIGHT_SPOOF_HYSTERESIS: u16 =
+                    KiaSoulEvNiro::BRAKE_LIGHT_SPOOF_HYSTERESIS;
+                const BRAKE_COMMAND_TIMEOUT_IN_MSEC: u32 =
+                    KiaSoulEvNiro::BRAKE_COMMAND_TIMEOUT_IN_MSEC;
+                const BRAKE_MIN_CONTROL_DWELL_IN_MSEC: u32 =
+                    KiaSoulEvNiro::BRAKE_MIN_CONTROL_DWELL_IN_MSEC;
+                const BRAKE_DISABLE_RAMP_MAX_DELTA: u16 =
+                    KiaSoulEvNiro::BRAKE_DISABLE_RAMP_MAX_DELTA;
+                const BRAKE_PRESSURE_AT_MAXIMUM_COMMAND: f32 =
+                    KiaSoulEvNiro::BRAKE_PRESSURE_AT_MAXIMUM_COMMAND;
+                const BRAKE_PRESSURE_LOOP_KP: f32 = KiaSoulEvNiro::BRAKE_PRESSURE_LOOP_KP;
+                const BRAKE_PRESSURE_LOOP_KI: f32 = KiaSoulEvNiro::BRAKE_PRESSURE_LOOP_KI;
+                const BRAKE_PRESSURE_SENSOR_MIN: u16 = KiaSoulEvNiro::BRAKE_PRESSURE_SENSOR_MIN;
+                const BRAKE_PRESSURE_SENSOR_MAX: u16 = KiaSoulEvNiro::BRAKE_PRESSURE_SENSOR_MAX;
+                const BRAKE_PRESSURE_SENSOR_MAX_DELTA: u16 =
+                    KiaSoulEvNiro::BRAKE_PRESSURE_SENSOR_MAX_DELTA;
+                const BRAKE_REGEN_CROSSOVER: f32 = KiaSoulEvNiro::BRAKE_REGEN_CROSSOVER;
+                const BRAKE_REGEN_STATUS_TIMEOUT_IN_MSEC: u32 =
+                    KiaSoulEvNiro::BRAKE_REGEN_STATUS_TIMEOUT_IN_MSEC;
+
+                fn brake_position_to_volts_low(position: f32) -> f32 {
+                    KiaSoulEvNiro::brake_position_to_volts_low(position)
+                }
+
+                fn brake_position_to_volts_high(position: f32) -> f32 {
+                    KiaSoulEvNiro::brake_position_to_volts_high(position)
+                }
+            }
+        };
+    }
+
+    stub_vehicle!(
+        HalfTravelVehicle,
+        0.5,
+        KiaSoulEvNiro::BRAKE_COMMAND_DEADBAND
+    );
+    stub_vehicle!(DeadbandVehicle, KiaSoulEvNiro::MAXIMUM_BRAKE_COMMAND, 0.1);
+
+    #[test]
+    fn vehicle_constants_set_the_clamped_output() {
+        let outputs = {
+            let (_clock, mut brake, mut console) = brake_module();
+            receive(
+                &mut brake,
+                &oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]),
+                &mut console,
+            );
+            receive(&mut brake, &command_frame(0.8), &mut console);
+            (
+                brake.state(),
+                brake.position_spoof(brake.state().commanded_position),
+            )
+        };
+        let half_travel_outputs = {
+            let (_clock, mut brake, mut console) = brake_module_for::<HalfTravelVehicle>();
+            receive(
+                &mut brake,
+                &oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]),
+                &mut console,
+            );
+            receive(&mut brake, &command_frame(0.8), &mut console);
+            (
+                brake.state(),
+                brake.position_spoof(brake.state().commanded_position),
+            )
+        };
+
+        let (state, spoof) = outputs;
+        assert_eq!(state.commanded_position, 0.8);
+        assert!(!state.output_clamped);
+
+        let (half_travel_state, half_travel_spoof) = half_travel_outputs;
+        assert_eq!(half_travel_state.commanded_position, 0.5);
+        assert!(half_travel_state.output_clamped);
+        assert!(half_travel_spoof.high() < spoof.high());
+        assert!(half_travel_spoof.low() < spoof.low());
+    }
+
+    // the pressure loop writes the spoof output from step_pressure_loop
+    #[cfg(not(feature = "brake-pressure-loop"))]
+    #[test]
+    fn brake_light_does_not_chatter_around_its_threshold() {
+        let (_clock, mut brake, mut console) = brake_module();
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+
+        // the thresholds just below one command, a slightly lower command
+        // sits below them but within the hysteresis
+        let (above, below) = (0.3, 0.29);
+        let spoof = brake.position_spoof(above);
+        brake.set_brake_light_thresholds(spoof.high() - 1, spoof.low() - 1);
+        let lower_spoof = brake.position_spoof(below);
+        assert!(lower_spoof.high() < spoof.high() - 1);
+        assert!(lower_spoof.high() > spoof.high() - 1 - brake.brake_light_hysteresis());
+
+        receive(&mut brake, &command_frame(above), &mut console);
+        for i in 0..20 {
+            let command = if i % 2 == 0 { below } else { above };
+            receive(&mut brake, &command_frame(command), &mut console);
+            assert!(brake.brake_pins.brake_light_enable.is_high());
+        }
+
+        receive(&mut brake, &command_frame(0.1), &mut console);
+        assert!(!brake.brake_pins.brake_light_enable.is_high());
+    }
+
+    #[test]
+    fn pressed_pedal_alone_triggers_the_override() {
+        let (clock, mut brake, mut console) = brake_module();
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+        assert!(!brake.brake_pins.override_switch.is_override_requested());
+
+        brake
+            .brake_pedal_position
+            .reader()
+            .set(PRESSED.0, PRESSED.1);
+        assert!(brake.check_for_faults(&mut console).unwrap().is_none());
+        clock.advance_ms(KiaSoulEvNiro::OPERATOR_OVERRIDE_HYSTERESIS);
+        assert!(brake.check_for_faults(&mut console).unwrap().is_some());
+        assert_eq!(brake.control_state(), ControlState::OverrideActive);
+    }
+
+    #[test]
+    fn pressed_override_switch_alone_triggers_the_override() {
+        let (clock, mut brake, mut console) = brake_module();
+        // pressed, the switch pulls its input low
+        brake.brake_pins.override_switch = Some(OverrideSwitch::new(SimInputPin::new(false)));
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+
+        // the pedal stays at rest
+        assert!(brake.check_for_faults(&mut console).unwrap().is_none());
+        clock.advance_ms(KiaSoulEvNiro::OPERATOR_OVERRIDE_HYSTERESIS);
+        assert!(brake.check_for_faults(&mut console).unwrap().is_some());
+        assert_eq!(brake.control_state(), ControlState::OverrideActive);
+    }
+
+    #[test]
+    fn command_within_the_deadband_is_treated_as_minimum() {
+        let (_clock, mut brake, mut console) = brake_module_for::<DeadbandVehicle>();
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+
+        receive(&mut brake, &command_frame(0.05), &mut console);
+        assert_eq!(
+            brake.state().commanded_position,
+            KiaSoulEvNiro::MINIMUM_BRAKE_COMMAND
+        );
+
+        receive(&mut brake, &command_frame(0.2), &mut console);
+        assert_eq!(brake.state().commanded_position, 0.2);
+    }
+
+    #[test]
+    fn default_deadband_passes_small_commands_through() {
+        let (_clock, mut brake, mut console) = brake_module();
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+
+        receive(&mut brake, &command_frame(0.05), &mut console);
+        assert_eq!(brake.state().commanded_position, 0.05);
+    }
+
+    #[test]
+    fn enable_blocked_by_an_override_is_nacked_with_the_override_dtc() {
+        let (clock, mut brake, mut console) = brake_module();
+        let enable = oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]);
+        receive(&mut brake, &enable, &mut console);
+        assert!(brake.supply_control_ack().unwrap().accepted());
+
+        brake
+            .brake_pedal_position
+            .reader()
+            .set(PRESSED.0, PRESSED.1);
+        assert!(brake.check_for_faults(&mut console).unwrap().is_none());
+        clock.advance_ms(KiaSoulEvNiro::OPERATOR_OVERRIDE_HYSTERESIS);
+        assert!(brake.check_for_faults(&mut console).unwrap().is_some());
+        assert_eq!(brake.control_state(), ControlState::OverrideActive);
+
+        assert_eq!(
+            brake.process_rx_frame(&enable, system_clock::get_timestamp_ms(), &mut console),
+            Err(BrakeModuleError::NotReady)
+        );
+        let ack = brake.supply_control_ack().unwrap();
+        assert!(!ack.accepted());
+        assert_eq!(ack.module_origin_id, FAULT_ORIGIN_BRAKE);
+        assert_eq!(ack.request, ControlRequest::Enable);
+        assert!(!ack.enabled);
+        assert!(ack.dtcs.check(OSCC_BRAKE_DTC_OPERATOR_OVERRIDE));
+    }
+
+    #[test]
+    fn grounded_sensor_is_latched_only_after_the_startup_grace() {
+        let (clock, mut brake, mut console) = brake_module();
+        let mut startup_grace = StartupGrace::new(STARTUP_FAULT_GRACE_MS);
+        let mut fault_latch = FaultLatch::new();
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+
+        // the module still disables, the main loop leaves the fault unlatched
+        brake.brake_pedal_position.reader().set(0, 0);
+        brake.check_for_faults(&mut console).unwrap();
+        clock.advance_ms(KiaSoulEvNiro::SENSOR_FAULT_HYSTERESIS);
+        assert!(brake.check_for_faults(&mut console).unwrap().is_some());
+        assert_eq!(brake.control_state(), ControlState::Faulted);
+        assert!(startup_grace.is_active());
+
+        // the DTC it still holds is latched once the grace is over
+        clock.set_ms(STARTUP_FAULT_GRACE_MS);
+        assert!(!startup_grace.is_active());
+        let fault_report = brake.supply_fault_report();
+        assert!(fault_report.dtcs.check(OSCC_BRAKE_DTC_INVALID_SENSOR_VAL));
+        assert!(fault_latch.latch(fault_report));
+    }
+
+    #[test]
+    fn brief_dip_below_the_threshold_keeps_the_override() {
+        let (clock, mut brake, mut console) = brake_module();
+        receive(
+            &mut brake,
+            &oscc_frame(OSCC_BRAKE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+        brake
+            .brake_pedal_position
+            .reader()
+            .set(PRESSED.0, PRESSED.1);
+        assert!(brake.check_for_faults(&mut console).unwrap().is_none());
+        clock.advance_ms(KiaSoulEvNiro::OPERATOR_OVERRIDE_HYSTERESIS);
+        assert!(brake.check_for_faults(&mut console).unwrap().is_some());
+        assert_eq!(brake.control_state(), ControlState::OverrideActive);
+
+        // released for less than the debounce, then modulated back on
+        brake
+            .brake_pedal_position
+            .reader()
+            .set(RELEASED.0, RELEASED.1);
+        assert!(brake.check_for_faults(&mut console).unwrap().is_none());
+        clock.advance_ms(KiaSoulEvNiro::OPERATOR_OVERRIDE_RELEASE_DEBOUNCE - 1);
+        assert!(brake.check_for_faults(&mut console).unwrap().is_none());
+        assert_eq!(brake.control_state(), ControlState::OverrideActive);
+
+        brake
+            .brake_pedal_position
+            .reader()
+            .set(PRESSED.0, PRESSED.1);
+        brake.check_for_faults(&mut console).unwrap();
+        clock.advance_ms(1);
+        brake.check_for_faults(&mut console).unwrap();
+        assert_eq!(brake.control_state(), ControlState::OverrideActive);
+
+        // the debounce restarts from the next release
+        brake
+            .brake_pedal_position
+            .reader()
+            .set(RELEASED.0, RELEASED.1);
+        assert!(brake.check_for_faults(&mut console).unwrap().is_none());
+        clock.advance_ms(KiaSoulEvNiro::OPERATOR_OVERRIDE_RELEASE_DEBOUNCE - 1);
+        assert!(brake.check_for_faults(&mut console).unwrap().is_none());
+        assert_eq!(brake.control_state(), ControlState::OverrideActive);
+        clock.advance_ms(1);
+        assert!(brake.check_for_faults(&mut console).unwrap().is_none());
+        assert_eq!(brake.control_state(), ControlState::Disabled);
+    }
+}
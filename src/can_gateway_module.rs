@@ -1,43 +1,188 @@
 // https://github.com/jonlamb-gh/oscc/tree/devel/firmware/can_gateway
 
 use brake_can_protocol::*;
+use can_health::{self, BusError, BusErrorMonitor, CanHealth};
+use config;
+use control_ack_can_protocol::*;
 use fault_can_protocol::*;
-use nucleo_f767zi::hal::can::{CanError, CanFrame, DataFrame, RxFifo};
+use loop_timing_can_protocol::*;
+use module_info_can_protocol::*;
+use nucleo_f767zi::hal::can::{BaseID, CanError, CanFrame, DataFrame, RxFifo, ID};
 use nucleo_f767zi::hal::prelude::*;
+use nucleo_f767zi::hal::stm32f7x7::{CAN1, CAN2};
 use oscc_magic_byte::*;
 use oxcc_error::OxccError;
+use param_can_protocol::*;
 use steering_can_protocol::*;
+use system_clock;
 use throttle_can_protocol::*;
 use types::*;
 use vehicle::*;
 
+// frames buffered while the control CAN TX mailboxes are full
+const CONTROL_CAN_TX_QUEUE_DEPTH: usize = 8;
+
+/// FIFO of frames waiting for a free TX mailbox
+struct TxQueue {
+    frames: [DataFrame; CONTROL_CAN_TX_QUEUE_DEPTH],
+    head: usize,
+    len: usize,
+}
+
+impl TxQueue {
+    fn new() -> Self {
+        TxQueue {
+            frames: [DataFrame::new(ID::BaseID(BaseID::new(0))); CONTROL_CAN_TX_QUEUE_DEPTH],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, frame: DataFrame) -> Result<(), CanError> {
+        if self.len == CONTROL_CAN_TX_QUEUE_DEPTH {
+            return Err(CanError::BufferExhausted);
+        }
+
+        self.frames[(self.head + self.len) % CONTROL_CAN_TX_QUEUE_DEPTH] = frame;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn front(&self) -> Option<DataFrame> {
+        if self.len == 0 {
+            None
+        } else {
+            Some(self.frames[self.head])
+        }
+    }
+
+    fn pop(&mut self) {
+        if self.len > 0 {
+            self.head = (self.head + 1) % CONTROL_CAN_TX_QUEUE_DEPTH;
+            self.len -= 1;
+        }
+    }
+}
+
+/// Decides which fault reports are published, per fault origin so one
+/// module's sustained fault can't mask another's
+///
+/// A report whose DTCs (or extended DTCs) differ from the last one published
+/// for its origin always goes out, repeats of the same DTCs at most once per
+/// interval.
+struct FaultReportRateLimiter {
+    min_interval_ms: u32,
+    // (time, DTCs, extended DTCs) of the last report published per origin
+    last_published: [Option<(u32, u8, u8)>; FAULT_ORIGIN_COUNT],
+}
+
+impl FaultReportRateLimiter {
+    fn new(min_interval_ms: u32) -> Self {
+        FaultReportRateLimiter {
+            min_interval_ms,
+            last_published: [None; FAULT_ORIGIN_COUNT],
+        }
+    }
+
+    /// Records the report as published if it should be
+    fn should_publish(&mut self, fault_report: &OsccFaultReport, now_ms: u32) -> bool {
+        let origin = fault_report.fault_origin_id as usize;
+        let dtcs = fault_report.dtcs;
+        let extended_dtcs = fault_report.extended_dtcs;
+
+        if origin >= FAULT_ORIGIN_COUNT {
+            return true;
+        }
+
+        if let Some((last_ms, last_dtcs, last_extended_dtcs)) = self.last_published[origin] {
+            if (last_dtcs == dtcs)
+                && (last_extended_dtcs == extended_dtcs)
+                && !system_clock::is_timeout(last_ms, now_ms, self.min_interval_ms)
+            {
+                return false;
+            }
+        }
+
+        self.last_published[origin] = Some((now_ms, dtcs, extended_dtcs));
+        true
+    }
+}
+
+// the TX mailboxes are full, the frame can be retried later
+fn is_mailbox_full(e: CanError) -> bool {
+    (e == CanError::BufferExhausted) || (e == CanError::Timeout)
+}
+
 pub struct CanGatewayModule {
-    can_publish_timer: CanPublishTimer,
     control_can: ControlCan,
     obd_can: ObdCan,
     fault_report_can_frame: DataFrame,
     throttle_report_can_frame: DataFrame,
     brake_report_can_frame: DataFrame,
     steering_report_can_frame: DataFrame,
+    brake_heartbeat_can_frame: DataFrame,
+    brake_regen_request_can_frame: DataFrame,
+    module_info_can_frame: DataFrame,
+    control_ack_can_frame: DataFrame,
+    param_value_can_frame: DataFrame,
+    loop_timing_can_frame: DataFrame,
+    control_can_tx_queue: TxQueue,
+    fault_report_rate_limiter: FaultReportRateLimiter,
+    // when the control CAN bus was first seen bus-off, None while healthy
+    control_can_bus_off_since_ms: Option<u32>,
+    control_can_bus_errors: BusErrorMonitor,
 }
 
 impl CanGatewayModule {
-    pub fn new(
-        can_publish_timer: CanPublishTimer,
-        control_can: ControlCan,
-        obd_can: ObdCan,
-    ) -> Self {
+    pub fn new(control_can: ControlCan, obd_can: ObdCan) -> Self {
         CanGatewayModule {
-            can_publish_timer,
             control_can,
             obd_can,
             fault_report_can_frame: default_fault_report_data_frame(),
             brake_report_can_frame: default_brake_report_data_frame(),
             throttle_report_can_frame: default_throttle_report_data_frame(),
             steering_report_can_frame: default_steering_report_data_frame(),
+            brake_heartbeat_can_frame: default_brake_heartbeat_data_frame(),
+            brake_regen_request_can_frame: default_brake_regen_request_data_frame(),
+            module_info_can_frame: default_module_info_data_frame(),
+            control_ack_can_frame: default_control_ack_data_frame(),
+            param_value_can_frame: default_param_value_data_frame(),
+            loop_timing_can_frame: default_loop_timing_data_frame(),
+            control_can_tx_queue: TxQueue::new(),
+            fault_report_rate_limiter: FaultReportRateLimiter::new(
+                config::FAULT_REPORT_MIN_INTERVAL_MS,
+            ),
+            control_can_bus_off_since_ms: None,
+            control_can_bus_errors: BusErrorMonitor::new(config::CAN_BUS_ERROR_SUSTAIN_MS),
         }
     }
 
+    /// Moves the published OSCC frames to IDs shifted by `can_id_offset`
+    pub fn set_can_id_offset(&mut self, can_id_offset: config::CanIdOffset) {
+        self.fault_report_can_frame = can_id_offset.data_frame(OSCC_FAULT_REPORT_CAN_ID);
+        self.brake_report_can_frame = can_id_offset.data_frame(OSCC_BRAKE_REPORT_CAN_ID);
+        self.throttle_report_can_frame = can_id_offset.data_frame(OSCC_THROTTLE_REPORT_CAN_ID);
+        self.steering_report_can_frame = can_id_offset.data_frame(OSCC_STEERING_REPORT_CAN_ID);
+        self.brake_heartbeat_can_frame = can_id_offset.data_frame(OSCC_BRAKE_HEARTBEAT_CAN_ID);
+        self.brake_regen_request_can_frame =
+            can_id_offset.data_frame(OSCC_BRAKE_REGEN_REQUEST_CAN_ID);
+        self.module_info_can_frame = can_id_offset.data_frame(OSCC_MODULE_INFO_CAN_ID);
+        self.control_ack_can_frame = can_id_offset.data_frame(OSCC_CONTROL_ACK_CAN_ID);
+        self.param_value_can_frame = can_id_offset.data_frame(OSCC_PARAM_VALUE_CAN_ID);
+        self.loop_timing_can_frame = can_id_offset.data_frame(OSCC_LOOP_TIMING_CAN_ID);
+    }
+
+    /// Fault reports repeating the DTCs last published for their origin
+    /// are published at most once per `interval_ms`, a change in the DTCs
+    /// is always published at once
+    pub fn set_fault_report_min_interval(&mut self, interval_ms: u32) {
+        self.fault_report_rate_limiter.min_interval_ms = interval_ms;
+    }
+
+    pub fn fault_report_min_interval(&self) -> u32 {
+        self.fault_report_rate_limiter.min_interval_ms
+    }
+
     pub fn republish_obd_frames_to_control_can_bus(&mut self) -> Result<(), OxccError> {
         // poll both OBD CAN FIFOs
         for fifo in &[RxFifo::Fifo0, RxFifo::Fifo1] {
@@ -73,6 +218,102 @@ impl CanGatewayModule {
         Ok(())
     }
 
+    /// Transmits the queued report frames, oldest first, until the queue is
+    /// empty or the TX mailboxes are full. Called every loop.
+    pub fn drain_control_can_tx_queue(&mut self) -> Result<(), CanError> {
+        while let Some(frame) = self.control_can_tx_queue.front() {
+            match self.control_can.transmit(&frame.into()) {
+                Ok(()) => self.control_can_tx_queue.pop(),
+                Err(e) => {
+                    return if is_mailbox_full(e) { Ok(()) } else { Err(e) };
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn control_can_tx_queue_len(&self) -> usize {
+        self.control_can_tx_queue.len
+    }
+
+    /// Transmits `frame`, queueing it if the TX mailboxes are full
+    ///
+    /// Frames already queued go first so reports stay in order. Returns
+    /// `CanError::BufferExhausted` if the queue is full and the frame is lost.
+    fn transmit_control_frame(&mut self, frame: DataFrame) -> Result<(), CanError> {
+        self.drain_control_can_tx_queue()?;
+
+        if self.control_can_tx_queue.len > 0 {
+            return self.control_can_tx_queue.push(frame);
+        }
+
+        match self.control_can.transmit(&frame.into()) {
+            Err(ref e) if is_mailbox_full(*e) => self.control_can_tx_queue.push(frame),
+            result => result,
+        }
+    }
+
+    /// Error counters, bus-off state and sustained bus errors of the control
+    /// CAN bus (CAN1)
+    pub fn control_can_health(&self) -> CanHealth {
+        // read-only access to the status register, owned by control_can
+        let mut health = CanHealth::read(unsafe { &*CAN1::ptr() });
+        health.bus_errors_sustained = self.control_can_bus_errors.bus_errors_sustained();
+        health.unacknowledged = self.control_can_bus_errors.unacknowledged();
+        health
+    }
+
+    /// Takes the control CAN bus error signalled since the previous call,
+    /// called every fault check
+    ///
+    /// The errors count as sustained, see `control_can_health`, once one has
+    /// been seen by every call for CAN_BUS_ERROR_SUSTAIN_MS. Acknowledgment
+    /// errors are tracked on their own, see `BusErrorMonitor`.
+    pub fn check_control_can_bus_errors(&mut self) -> Option<BusError> {
+        // only the last error code is written, owned by control_can
+        let bus_error = can_health::take_last_error(unsafe { &*CAN1::ptr() });
+
+        self.control_can_bus_errors.update(bus_error);
+
+        bus_error
+    }
+
+    /// Error counters and bus-off state of the OBD CAN bus (CAN2)
+    pub fn obd_can_health(&self) -> CanHealth {
+        // read-only access to the status register, owned by obd_can
+        CanHealth::read(unsafe { &*CAN2::ptr() })
+    }
+
+    /// Restarts the control CAN peripheral once it has been bus-off for
+    /// CAN_BUS_OFF_RECOVERY_BACKOFF_MS, returns true while recovering
+    ///
+    /// The modules hold their CAN bus DTC for as long as the bus is bus-off.
+    pub fn recover_control_can_bus_off(&mut self) -> bool {
+        if !self.control_can_health().bus_off {
+            self.control_can_bus_off_since_ms = None;
+            return false;
+        }
+
+        let now = system_clock::get_timestamp_ms();
+
+        match self.control_can_bus_off_since_ms {
+            None => self.control_can_bus_off_since_ms = Some(now),
+            Some(since_ms) => {
+                if system_clock::is_timeout(since_ms, now, config::CAN_BUS_OFF_RECOVERY_BACKOFF_MS)
+                {
+                    // read-modify-write of the mode bits only, owned by control_can
+                    can_health::restart_after_bus_off(unsafe { &*CAN1::ptr() });
+
+                    // back off again if the bus is still faulted
+                    self.control_can_bus_off_since_ms = Some(now);
+                }
+            }
+        }
+
+        true
+    }
+
     // TODO - hide these details, switch to a publisher approach
     pub fn control_can(&mut self) -> &mut ControlCan {
         &mut self.control_can
@@ -81,14 +322,18 @@ impl CanGatewayModule {
     pub fn obd_can(&mut self) -> &mut ObdCan {
         &mut self.obd_can
     }
-
-    pub fn wait_for_publish(&mut self) -> bool {
-        self.can_publish_timer.wait().is_ok()
-    }
 }
 
 impl FaultReportPublisher for CanGatewayModule {
     fn publish_fault_report(&mut self, fault_report: &OsccFaultReport) -> Result<(), CanError> {
+        if !self
+            .fault_report_rate_limiter
+            .should_publish(fault_report, system_clock::get_timestamp_ms())
+        {
+            // a repeat, the fault is already latched by the caller
+            return Ok(());
+        }
+
         {
             self.fault_report_can_frame
                 .set_data_length(OSCC_FAULT_REPORT_CAN_DLC as _);
@@ -102,30 +347,92 @@ impl FaultReportPublisher for CanGatewayModule {
             data[4] = ((fault_report.fault_origin_id >> 16) & 0xFF) as _;
             data[5] = ((fault_report.fault_origin_id >> 24) & 0xFF) as _;
             data[6] = fault_report.dtcs;
+            data[OSCC_FAULT_REPORT_EXTENDED_DTCS_BYTE_INDEX] = fault_report.extended_dtcs;
         }
 
-        self.control_can
-            .transmit(&self.fault_report_can_frame.into())
+        let frame = self.fault_report_can_frame;
+        self.transmit_control_frame(frame)
     }
 }
 
 impl BrakeReportPublisher for CanGatewayModule {
     fn publish_brake_report(&mut self, brake_report: &OsccBrakeReport) -> Result<(), CanError> {
+        brake_report.encode(&mut self.brake_report_can_frame);
+
+        let frame = self.brake_report_can_frame;
+        self.transmit_control_frame(frame)
+    }
+}
+
+impl BrakeHeartbeatPublisher for CanGatewayModule {
+    fn publish_brake_heartbeat(
+        &mut self,
+        brake_heartbeat: &OsccBrakeHeartbeat,
+    ) -> Result<(), CanError> {
         {
-            self.brake_report_can_frame
-                .set_data_length(OSCC_BRAKE_REPORT_CAN_DLC as _);
+            self.brake_heartbeat_can_frame
+                .set_data_length(OSCC_BRAKE_HEARTBEAT_CAN_DLC as _);
 
-            let data = self.brake_report_can_frame.data_as_mut();
+            let data = self.brake_heartbeat_can_frame.data_as_mut();
 
             data[0] = OSCC_MAGIC_BYTE_0;
             data[1] = OSCC_MAGIC_BYTE_1;
-            data[2] = brake_report.enabled as _;
-            data[3] = brake_report.operator_override as _;
-            data[4] = brake_report.dtcs;
+            data[2] = (brake_heartbeat.sequence & 0xFF) as _;
+            data[3] = ((brake_heartbeat.sequence >> 8) & 0xFF) as _;
+            data[4] = brake_heartbeat.enabled as _;
+            data[5] = brake_heartbeat.operator_override as _;
         }
 
-        self.control_can
-            .transmit(&self.brake_report_can_frame.into())
+        let frame = self.brake_heartbeat_can_frame;
+        self.transmit_control_frame(frame)
+    }
+}
+
+impl BrakeRegenRequestPublisher for CanGatewayModule {
+    fn publish_brake_regen_request(
+        &mut self,
+        regen_request: &OsccBrakeRegenRequest,
+    ) -> Result<(), CanError> {
+        regen_request.encode(&mut self.brake_regen_request_can_frame);
+
+        let frame = self.brake_regen_request_can_frame;
+        self.transmit_control_frame(frame)
+    }
+}
+
+impl ModuleInfoPublisher for CanGatewayModule {
+    fn publish_module_info(&mut self, module_info: &OsccModuleInfo) -> Result<(), CanError> {
+        module_info.encode(&mut self.module_info_can_frame);
+
+        let frame = self.module_info_can_frame;
+        self.transmit_control_frame(frame)
+    }
+}
+
+impl ControlAckPublisher for CanGatewayModule {
+    fn publish_control_ack(&mut self, control_ack: &OsccControlAck) -> Result<(), CanError> {
+        control_ack.encode(&mut self.control_ack_can_frame);
+
+        let frame = self.control_ack_can_frame;
+        self.transmit_control_frame(frame)
+    }
+}
+
+impl ParamValuePublisher for CanGatewayModule {
+    fn publish_param_value(&mut self, param_value: &OsccParamValue) -> Result<(), CanError> {
+        param_value.encode(&mut self.param_value_can_frame);
+
+        let frame = self.param_value_can_frame;
+        self.transmit_control_frame(frame)
+    }
+}
+
+impl LoopTimingPublisher for CanGatewayModule {
+    fn publish_loop_timing(&mut self, loop_timing: &OsccLoopTiming) -> Result<(), CanError> {
+        loop_timing.encode(&mut self.loop_timing_can_frame);
+
+        let frame = self.loop_timing_can_frame;
+        self.transmit_control_frame(frame)
     }
 }
 
@@ -134,21 +441,10 @@ impl ThrottleReportPublisher for CanGatewayModule {
         &mut self,
         throttle_report: &OsccThrottleReport,
     ) -> Result<(), CanError> {
-        {
-            self.throttle_report_can_frame
-                .set_data_length(OSCC_THROTTLE_REPORT_CAN_DLC as _);
+        throttle_report.encode(&mut self.throttle_report_can_frame);
 
-            let data = self.throttle_report_can_frame.data_as_mut();
-
-            data[0] = OSCC_MAGIC_BYTE_0;
-            data[1] = OSCC_MAGIC_BYTE_1;
-            data[2] = throttle_report.enabled as _;
-            data[3] = throttle_report.operator_override as _;
-            data[4] = throttle_report.dtcs;
-        }
-
-        self.control_can
-            .transmit(&self.throttle_report_can_frame.into())
+        let frame = self.throttle_report_can_frame;
+        self.transmit_control_frame(frame)
     }
 }
 
@@ -157,20 +453,53 @@ impl SteeringReportPublisher for CanGatewayModule {
         &mut self,
         steering_report: &OsccSteeringReport,
     ) -> Result<(), CanError> {
-        {
-            self.steering_report_can_frame
-                .set_data_length(OSCC_STEERING_REPORT_CAN_DLC as _);
+        steering_report.encode(&mut self.steering_report_can_frame);
+
+        let frame = self.steering_report_can_frame;
+        self.transmit_control_frame(frame)
+    }
+}
 
-            let data = self.steering_report_can_frame.data_as_mut();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            data[0] = OSCC_MAGIC_BYTE_0;
-            data[1] = OSCC_MAGIC_BYTE_1;
-            data[2] = steering_report.enabled as _;
-            data[3] = steering_report.operator_override as _;
-            data[4] = steering_report.dtcs;
+    fn report(fault_origin_id: u32, dtcs: u8, extended_dtcs: u8) -> OsccFaultReport {
+        OsccFaultReport {
+            fault_origin_id,
+            dtcs,
+            extended_dtcs,
         }
+    }
+
+    #[test]
+    fn repeats_are_rate_limited() {
+        let mut limiter = FaultReportRateLimiter::new(100);
+
+        assert!(limiter.should_publish(&report(FAULT_ORIGIN_BRAKE, 0x01, 0), 0));
+        assert!(!limiter.should_publish(&report(FAULT_ORIGIN_BRAKE, 0x01, 0), 50));
+        assert!(limiter.should_publish(&report(FAULT_ORIGIN_BRAKE, 0x01, 0), 100));
+    }
+
+    #[test]
+    fn a_new_dtc_is_published_at_once() {
+        let mut limiter = FaultReportRateLimiter::new(100);
+
+        assert!(limiter.should_publish(&report(FAULT_ORIGIN_BRAKE, 0x01, 0), 0));
+        assert!(limiter.should_publish(&report(FAULT_ORIGIN_BRAKE, 0x03, 0), 10));
+        assert!(!limiter.should_publish(&report(FAULT_ORIGIN_BRAKE, 0x03, 0), 20));
+        // a cleared DTC is a change too
+        assert!(limiter.should_publish(&report(FAULT_ORIGIN_BRAKE, 0x02, 0), 30));
+        assert!(limiter.should_publish(&report(FAULT_ORIGIN_BRAKE, 0x02, 0x01), 40));
+    }
+
+    #[test]
+    fn origins_are_limited_separately() {
+        let mut limiter = FaultReportRateLimiter::new(100);
 
-        self.control_can
-            .transmit(&self.steering_report_can_frame.into())
+        assert!(limiter.should_publish(&report(FAULT_ORIGIN_BRAKE, 0x01, 0), 0));
+        assert!(limiter.should_publish(&report(FAULT_ORIGIN_THROTTLE, 0x01, 0), 10));
+        assert!(limiter.should_publish(&report(99, 0x01, 0), 20));
+        assert!(limiter.should_publish(&report(99, 0x01, 0), 30));
     }
 }
@@ -0,0 +1,235 @@
+use fault_condition::FaultCondition;
+use nucleo_f767zi::hal::stm32f7x7::can1::RegisterBlock;
+
+// bounds the wait for the peripheral to acknowledge a mode change
+const INIT_ACK_MAX_POLLS: u32 = 10_000;
+
+// last error code value the hardware never writes, marks the code as taken
+const LEC_SET_BY_SOFTWARE: u8 = 7;
+
+/// Error the bxCAN signalled with an error frame, from the last error code
+/// (LEC)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BusError {
+    Stuff,
+    Form,
+    Acknowledgment,
+    BitRecessive,
+    BitDominant,
+    Crc,
+}
+
+impl BusError {
+    fn from_lec(lec: u8) -> Option<Self> {
+        match lec {
+            1 => Some(BusError::Stuff),
+            2 => Some(BusError::Form),
+            3 => Some(BusError::Acknowledgment),
+            4 => Some(BusError::BitRecessive),
+            5 => Some(BusError::BitDominant),
+            6 => Some(BusError::Crc),
+            _ => None,
+        }
+    }
+}
+
+/// bxCAN error state, read from the error status register (ESR)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CanHealth {
+    /// Transmit error counter (TEC)
+    pub transmit_error_count: u8,
+    /// Receive error counter (REC)
+    pub receive_error_count: u8,
+    /// Either counter reached 96
+    pub error_warning: bool,
+    /// Either counter exceeded 127
+    pub error_passive: bool,
+    /// TEC exceeded 255, the node no longer takes part in bus activity
+    pub bus_off: bool,
+    /// Bus errors other than missing acknowledgments kept being signalled
+    /// for CAN_BUS_ERROR_SUSTAIN_MS, tracked by the CAN gateway as the ESR
+    /// only holds the last one
+    pub bus_errors_sustained: bool,
+    /// Only acknowledgment errors were signalled for CAN_BUS_ERROR_SUSTAIN_MS,
+    /// typically no other node is on the bus rather than a wiring fault
+    pub unacknowledged: bool,
+}
+
+impl CanHealth {
+    pub fn read(can: &RegisterBlock) -> Self {
+        let esr = can.esr.read();
+
+        CanHealth {
+            transmit_error_count: esr.tec().bits(),
+            receive_error_count: esr.rec().bits(),
+            error_warning: esr.ewgf().bit_is_set(),
+            error_passive: esr.epvf().bit_is_set(),
+            bus_off: esr.boff().bit_is_set(),
+            bus_errors_sustained: false,
+            unacknowledged: false,
+        }
+    }
+
+    /// Neither error-passive nor bus-off, the node takes full part in bus
+    /// activity again
+    pub fn is_error_active(&self) -> bool {
+        !self.error_passive && !self.bus_off
+    }
+}
+
+/// Sorts the bus errors taken every fault check into sustained bus errors
+/// and sustained missing acknowledgments
+///
+/// A node alone on the bus only ever sees acknowledgment errors, and stays
+/// error-passive without going bus-off, so it is told apart from a wiring
+/// fault.
+pub struct BusErrorMonitor {
+    bus_error_state: FaultCondition,
+    unacknowledged_state: FaultCondition,
+    bus_errors_sustained: bool,
+    unacknowledged: bool,
+}
+
+impl BusErrorMonitor {
+    pub fn new(sustain_ms: u32) -> Self {
+        BusErrorMonitor {
+            bus_error_state: FaultCondition::with_hysteresis(sustain_ms),
+            unacknowledged_state: FaultCondition::with_hysteresis(sustain_ms),
+            bus_errors_sustained: false,
+            unacknowledged: false,
+        }
+    }
+
+    /// Takes the error signalled since the previous update, None if there
+    /// was none
+    pub fn update(&mut self, bus_error: Option<BusError>) {
+        let unacknowledged = bus_error == Some(BusError::Acknowledgment);
+        let other_error = bus_error.is_some() && !unacknowledged;
+
+        if self
+            .bus_error_state
+            .condition_exceeded_duration(other_error)
+        {
+            self.bus_errors_sustained = true;
+        } else if !other_error {
+            self.bus_errors_sustained = false;
+        }
+
+        if self
+            .unacknowledged_state
+            .condition_exceeded_duration(unacknowledged)
+        {
+            self.unacknowledged = true;
+        } else if !unacknowledged {
+            self.unacknowledged = false;
+        }
+    }
+
+    pub fn bus_errors_sustained(&self) -> bool {
+        self.bus_errors_sustained
+    }
+
+    pub fn unacknowledged(&self) -> bool {
+        self.unacknowledged
+    }
+}
+
+/// The bus error signalled since the previous call, error frames are never
+/// stored in a receive FIFO so this is the only trace of them
+///
+/// Marks the last error code as taken, so only one caller may use this per
+/// peripheral.
+pub fn take_last_error(can: &RegisterBlock) -> Option<BusError> {
+    let lec = can.esr.read().lec().bits();
+
+    if lec == LEC_SET_BY_SOFTWARE {
+        return None;
+    }
+
+    can.esr
+        .modify(|_, w| unsafe { w.lec().bits(LEC_SET_BY_SOFTWARE) });
+
+    BusError::from_lec(lec)
+}
+
+/// Restarts the peripheral after bus-off by cycling initialization mode
+///
+/// Automatic bus-off management (ABOM) normally recovers on its own, this is
+/// the fallback for a peripheral that stays bus-off. Leaving initialization
+/// mode waits for 128 occurrences of 11 recessive bits before transmitting
+/// again, the configuration and filters are kept.
+pub fn restart_after_bus_off(can: &RegisterBlock) {
+    can.mcr.modify(|_, w| w.inrq().set_bit());
+    for _ in 0..INIT_ACK_MAX_POLLS {
+        if can.msr.read().inak().bit_is_set() {
+            break;
+        }
+    }
+
+    can.mcr.modify(|_, w| w.inrq().clear_bit());
+    for _ in 0..INIT_ACK_MAX_POLLS {
+        if can.msr.read().inak().bit_is_clear() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use system_clock::TestClock;
+
+    #[test]
+    fn acknowledgment_errors_are_kept_apart() {
+        let clock = TestClock::take();
+        let mut monitor = BusErrorMonitor::new(50);
+
+        monitor.update(Some(BusError::Acknowledgment));
+        clock.advance_ms(50);
+        monitor.update(Some(BusError::Acknowledgment));
+
+        assert!(monitor.unacknowledged());
+        assert!(!monitor.bus_errors_sustained());
+
+        monitor.update(None);
+        assert!(!monitor.unacknowledged());
+    }
+
+    #[test]
+    fn other_errors_are_sustained_after_the_duration() {
+        let clock = TestClock::take();
+        let mut monitor = BusErrorMonitor::new(50);
+
+        monitor.update(Some(BusError::Stuff));
+        clock.advance_ms(49);
+        monitor.update(Some(BusError::Crc));
+        assert!(!monitor.bus_errors_sustained());
+
+        clock.advance_ms(1);
+        monitor.update(Some(BusError::Form));
+        assert!(monitor.bus_errors_sustained());
+        assert!(!monitor.unacknowledged());
+    }
+
+    #[test]
+    fn error_passive_is_not_error_active() {
+        let health = CanHealth {
+            transmit_error_count: 128,
+            receive_error_count: 0,
+            error_warning: true,
+            error_passive: true,
+            bus_off: false,
+            bus_errors_sustained: false,
+            unacknowledged: false,
+        };
+        assert!(!health.is_error_active());
+
+        let recovered = CanHealth {
+            transmit_error_count: 0,
+            error_warning: false,
+            error_passive: false,
+            ..health
+        };
+        assert!(recovered.is_error_active());
+    }
+}
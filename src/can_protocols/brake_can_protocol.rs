@@ -1,33 +1,112 @@
 use nucleo_f767zi::hal::can::{BaseID, CanError, DataFrame, ID};
+use oscc_magic_byte::*;
+use oscc_position;
+use oscc_protocol_version::{OSCC_PROTOCOL_VERSION, OSCC_PROTOCOL_VERSION_BYTE_INDEX};
 
 pub const OSCC_BRAKE_ENABLE_CAN_ID: u16 = 0x70;
 pub const OSCC_BRAKE_DISABLE_CAN_ID: u16 = 0x71;
 pub const OSCC_BRAKE_COMMAND_CAN_ID: u16 = 0x72;
 pub const OSCC_BRAKE_REPORT_CAN_ID: u16 = 0x73;
+pub const OSCC_BRAKE_HEARTBEAT_CAN_ID: u16 = 0x74;
+pub const OSCC_BRAKE_CLEAR_FAULTS_CAN_ID: u16 = 0x75;
+/// Published with the brake report while regen blending is built in
+pub const OSCC_BRAKE_REGEN_REQUEST_CAN_ID: u16 = 0x76;
+/// Sent periodically by the vehicle side while regen braking can meet the
+/// regen request. Without it brake commands go to the friction brake alone.
+pub const OSCC_BRAKE_REGEN_STATUS_CAN_ID: u16 = 0x77;
 
+/// IDs the brake module handles, registered with the control CAN filters,
+/// the priority IDs are received on FIFO_0
+pub const OSCC_BRAKE_PRIORITY_RX_CAN_IDS: [u16; 1] = [OSCC_BRAKE_DISABLE_CAN_ID];
+pub const OSCC_BRAKE_RX_CAN_IDS: [u16; 4] = [
+    OSCC_BRAKE_ENABLE_CAN_ID,
+    OSCC_BRAKE_COMMAND_CAN_ID,
+    OSCC_BRAKE_CLEAR_FAULTS_CAN_ID,
+    OSCC_BRAKE_REGEN_STATUS_CAN_ID,
+];
+
+pub const OSCC_BRAKE_COMMAND_CAN_DLC: u8 = 8;
+
+/// Report byte echoing the last commanded position, scaled 0.0..1.0 to 0..255
+pub const OSCC_BRAKE_REPORT_COMMAND_BYTE_INDEX: usize = 7;
+/// Report byte holding the DAC status bits
+pub const OSCC_BRAKE_REPORT_DAC_STATUS_BYTE_INDEX: usize = 5;
+/// Set while the brake DAC is healthy
+pub const OSCC_BRAKE_REPORT_DAC_HEALTHY_BIT: u8 = 0;
+/// Set if a command or spoof output was clamped since the previous report
+pub const OSCC_BRAKE_REPORT_OUTPUT_CLAMPED_BIT: u8 = 1;
 pub const OSCC_BRAKE_REPORT_CAN_DLC: u8 = 8;
+pub const OSCC_BRAKE_HEARTBEAT_CAN_DLC: u8 = 8;
+pub const OSCC_BRAKE_REGEN_REQUEST_CAN_DLC: u8 = 8;
+pub const OSCC_BRAKE_REGEN_STATUS_CAN_DLC: u8 = 3;
 
 // TODO - enum
 pub const OSCC_BRAKE_DTC_INVALID_SENSOR_VAL: u8 = 0;
 pub const OSCC_BRAKE_DTC_OPERATOR_OVERRIDE: u8 = 1;
+pub const OSCC_BRAKE_DTC_INVALID_CRC: u8 = 2;
+pub const OSCC_BRAKE_DTC_COMMAND_TIMEOUT: u8 = 3;
+pub const OSCC_BRAKE_DTC_SENSOR_MISMATCH: u8 = 4;
+pub const OSCC_BRAKE_DTC_PROTOCOL_MISMATCH: u8 = 5;
+pub const OSCC_BRAKE_DTC_SENSOR_SHORT_HIGH: u8 = 6;
+pub const OSCC_BRAKE_DTC_CAN_BUS_FAULT: u8 = 7;
+
+// every DTC bit is in use, further faults go in the fault report's
+// extended DTC byte
+/// Verified brake DAC writes kept reading back a different output
+pub const OSCC_BRAKE_EXTENDED_DTC_DAC_OUTPUT_MISMATCH: u8 = 0;
+/// A non-finite pedal command, or one far outside the command limits, was
+/// received
+pub const OSCC_BRAKE_EXTENDED_DTC_ABSURD_COMMAND: u8 = 1;
+/// The spoof enable relay sense input disagreed with the enable pin, the
+/// relay is stuck
+pub const OSCC_BRAKE_EXTENDED_DTC_SPOOF_ENABLE_MISMATCH: u8 = 2;
+/// VDDA was out of tolerance for longer than the fault debounce
+pub const OSCC_BRAKE_EXTENDED_DTC_SUPPLY_VOLTAGE: u8 = 3;
+
+/// Human-readable DTC names, indexed by DTC bit
+pub const OSCC_BRAKE_DTC_NAMES: [&str; 8] = [
+    "invalid sensor value",
+    "operator override",
+    "invalid CRC",
+    "command timeout",
+    "sensor mismatch",
+    "protocol mismatch",
+    "sensor shorted high",
+    "CAN bus fault",
+];
+
+/// Human-readable extended DTC names, indexed by extended DTC bit
+pub const OSCC_BRAKE_EXTENDED_DTC_NAMES: [&str; 4] = [
+    "DAC output mismatch",
+    "absurd command",
+    "spoof enable relay stuck",
+    "supply voltage",
+];
 
 pub struct OsccBrakeCommand {
     pub pedal_command: f32,
+    pub protocol_version: u8,
 }
 
-impl<'a> From<&'a DataFrame> for OsccBrakeCommand {
-    fn from(f: &DataFrame) -> Self {
-        assert_eq!(u32::from(f.id()), u32::from(OSCC_BRAKE_COMMAND_CAN_ID));
+impl OsccBrakeCommand {
+    /// The caller matches the frame ID, which may be shifted by a CAN ID
+    /// offset
+    pub fn from_frame(f: &DataFrame) -> Result<Self, MalformedFrame> {
         let data = f.data();
 
+        if data.len() < usize::from(OSCC_BRAKE_COMMAND_CAN_DLC) {
+            return Err(MalformedFrame::TooShort);
+        }
+
         let raw_brake_request: u32 = u32::from(data[2])
             | (u32::from(data[3]) << 8)
             | (u32::from(data[4]) << 16)
             | (u32::from(data[5]) << 24);
 
-        OsccBrakeCommand {
+        Ok(OsccBrakeCommand {
             pedal_command: f32::from_bits(raw_brake_request),
-        }
+            protocol_version: data[OSCC_PROTOCOL_VERSION_BYTE_INDEX],
+        })
     }
 }
 
@@ -35,6 +114,13 @@ pub struct OsccBrakeReport {
     pub enabled: bool,
     pub operator_override: bool,
     pub dtcs: u8,
+    /// False when the most recent brake DAC write failed
+    pub dac_healthy: bool,
+    /// Last commanded pedal position, after clamping to the command limits
+    pub commanded_position: f32,
+    /// A command or spoof output was clamped during the report period, the
+    /// supervisor's commands are saturating the actuator
+    pub output_clamped: bool,
 }
 
 pub trait BrakeReportSupplier {
@@ -49,12 +135,261 @@ pub fn default_brake_report_data_frame() -> DataFrame {
     DataFrame::new(ID::BaseID(BaseID::new(OSCC_BRAKE_REPORT_CAN_ID)))
 }
 
+/// Published alongside the report so a supervisor can detect a stalled
+/// module by a sequence counter that stops incrementing
+pub struct OsccBrakeHeartbeat {
+    pub sequence: u16,
+    pub enabled: bool,
+    pub operator_override: bool,
+}
+
+pub trait BrakeHeartbeatSupplier {
+    fn supply_brake_heartbeat(&mut self) -> &OsccBrakeHeartbeat;
+}
+
+pub trait BrakeHeartbeatPublisher {
+    fn publish_brake_heartbeat(
+        &mut self,
+        brake_heartbeat: &OsccBrakeHeartbeat,
+    ) -> Result<(), CanError>;
+}
+
+pub fn default_brake_heartbeat_data_frame() -> DataFrame {
+    DataFrame::new(ID::BaseID(BaseID::new(OSCC_BRAKE_HEARTBEAT_CAN_ID)))
+}
+
+/// The share of the commanded braking the vehicle should meet with
+/// regenerative braking
+pub struct OsccBrakeRegenRequest {
+    /// 0.0 (none) to 1.0 (the full regen request), 0.0 while disabled
+    pub regen_request: f32,
+}
+
+impl OsccBrakeRegenRequest {
+    pub fn new() -> Self {
+        OsccBrakeRegenRequest { regen_request: 0.0 }
+    }
+
+    /// Sets the DLC and data of `frame` from this regen request
+    pub fn encode(&self, frame: &mut DataFrame) {
+        frame.set_data_length(OSCC_BRAKE_REGEN_REQUEST_CAN_DLC as _);
+
+        let data = frame.data_as_mut();
+
+        let raw = self.regen_request.to_bits();
+
+        data[0] = OSCC_MAGIC_BYTE_0;
+        data[1] = OSCC_MAGIC_BYTE_1;
+        data[2] = (raw & 0xFF) as _;
+        data[3] = ((raw >> 8) & 0xFF) as _;
+        data[4] = ((raw >> 16) & 0xFF) as _;
+        data[5] = ((raw >> 24) & 0xFF) as _;
+        data[OSCC_PROTOCOL_VERSION_BYTE_INDEX] = OSCC_PROTOCOL_VERSION;
+        data[7] = 0;
+    }
+}
+
+pub trait BrakeRegenRequestPublisher {
+    fn publish_brake_regen_request(
+        &mut self,
+        regen_request: &OsccBrakeRegenRequest,
+    ) -> Result<(), CanError>;
+}
+
+pub fn default_brake_regen_request_data_frame() -> DataFrame {
+    DataFrame::new(ID::BaseID(BaseID::new(OSCC_BRAKE_REGEN_REQUEST_CAN_ID)))
+}
+
+/// Whether the vehicle can currently meet the regen request
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OsccBrakeRegenStatus {
+    pub available: bool,
+}
+
+impl OsccBrakeRegenStatus {
+    /// The caller matches the frame ID, which may be shifted by a CAN ID
+    /// offset
+    pub fn from_frame(f: &DataFrame) -> Result<Self, MalformedFrame> {
+        let data = f.data();
+
+        if data.len() < usize::from(OSCC_BRAKE_REGEN_STATUS_CAN_DLC) {
+            return Err(MalformedFrame::TooShort);
+        }
+
+        Ok(OsccBrakeRegenStatus {
+            available: data[2] != 0,
+        })
+    }
+}
+
+impl OsccBrakeHeartbeat {
+    pub fn new() -> Self {
+        OsccBrakeHeartbeat {
+            sequence: 0,
+            enabled: false,
+            operator_override: false,
+        }
+    }
+}
+
 impl OsccBrakeReport {
     pub fn new() -> Self {
         OsccBrakeReport {
             enabled: false,
             operator_override: false,
             dtcs: 0,
+            dac_healthy: true,
+            commanded_position: 0.0,
+            output_clamped: false,
+        }
+    }
+
+    /// Commanded position quantized to one byte
+    pub fn commanded_position_byte(&self) -> u8 {
+        oscc_position::position_to_byte(self.commanded_position)
+    }
+
+    pub fn builder() -> OsccBrakeReportBuilder {
+        OsccBrakeReportBuilder {
+            report: OsccBrakeReport::new(),
+        }
+    }
+
+    /// Writes the report into `frame`, including the magic bytes and
+    /// protocol version
+    pub fn encode(&self, frame: &mut DataFrame) {
+        frame.set_data_length(OSCC_BRAKE_REPORT_CAN_DLC as _);
+
+        let data = frame.data_as_mut();
+
+        data[0] = OSCC_MAGIC_BYTE_0;
+        data[1] = OSCC_MAGIC_BYTE_1;
+        data[2] = self.enabled as _;
+        data[3] = self.operator_override as _;
+        data[4] = self.dtcs;
+        data[OSCC_BRAKE_REPORT_DAC_STATUS_BYTE_INDEX] = ((self.dac_healthy as u8)
+            << OSCC_BRAKE_REPORT_DAC_HEALTHY_BIT)
+            | ((self.output_clamped as u8) << OSCC_BRAKE_REPORT_OUTPUT_CLAMPED_BIT);
+        data[OSCC_BRAKE_REPORT_COMMAND_BYTE_INDEX] = self.commanded_position_byte();
+        data[OSCC_PROTOCOL_VERSION_BYTE_INDEX] = OSCC_PROTOCOL_VERSION;
+    }
+}
+
+/// Builds a ready-to-transmit report frame, unset fields keep the
+/// `OsccBrakeReport::new` defaults
+pub struct OsccBrakeReportBuilder {
+    report: OsccBrakeReport,
+}
+
+impl OsccBrakeReportBuilder {
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.report.enabled = enabled;
+        self
+    }
+
+    pub fn operator_override(mut self, operator_override: bool) -> Self {
+        self.report.operator_override = operator_override;
+        self
+    }
+
+    pub fn dtcs(mut self, dtcs: u8) -> Self {
+        self.report.dtcs = dtcs;
+        self
+    }
+
+    pub fn dac_healthy(mut self, dac_healthy: bool) -> Self {
+        self.report.dac_healthy = dac_healthy;
+        self
+    }
+
+    pub fn commanded_position(mut self, commanded_position: f32) -> Self {
+        self.report.commanded_position = commanded_position;
+        self
+    }
+
+    pub fn output_clamped(mut self, output_clamped: bool) -> Self {
+        self.report.output_clamped = output_clamped;
+        self
+    }
+
+    pub fn build(self) -> DataFrame {
+        let mut frame = default_brake_report_data_frame();
+        self.report.encode(&mut frame);
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command_frame(dlc: usize, pedal_command: f32) -> DataFrame {
+        let mut frame = DataFrame::new(ID::BaseID(BaseID::new(OSCC_BRAKE_COMMAND_CAN_ID)));
+        frame.set_data_length(dlc);
+
+        let bits = pedal_command.to_bits();
+        let data = frame.data_as_mut();
+        for (i, byte) in data.iter_mut().enumerate().take(dlc) {
+            *byte = match i {
+                0 => OSCC_MAGIC_BYTE_0,
+                1 => OSCC_MAGIC_BYTE_1,
+                2...5 => (bits >> (8 * (i - 2))) as u8,
+                OSCC_PROTOCOL_VERSION_BYTE_INDEX => OSCC_PROTOCOL_VERSION,
+                _ => 0,
+            };
         }
+
+        frame
+    }
+
+    #[test]
+    fn command_parses_the_pedal_command() {
+        let command = OsccBrakeCommand::from_frame(&command_frame(8, 0.25)).unwrap();
+        assert_eq!(command.pedal_command, 0.25);
+        assert_eq!(command.protocol_version, OSCC_PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn short_command_is_malformed() {
+        assert_eq!(
+            OsccBrakeCommand::from_frame(&command_frame(5, 0.25)).err(),
+            Some(MalformedFrame::TooShort)
+        );
+    }
+
+    #[test]
+    fn regen_status_parses_availability() {
+        let mut frame = DataFrame::new(ID::BaseID(BaseID::new(OSCC_BRAKE_REGEN_STATUS_CAN_ID)));
+        frame.set_data_length(OSCC_BRAKE_REGEN_STATUS_CAN_DLC as _);
+        frame.data_as_mut()[..3].copy_from_slice(&[OSCC_MAGIC_BYTE_0, OSCC_MAGIC_BYTE_1, 1]);
+        assert_eq!(
+            OsccBrakeRegenStatus::from_frame(&frame),
+            Ok(OsccBrakeRegenStatus { available: true })
+        );
+
+        frame.data_as_mut()[2] = 0;
+        assert_eq!(
+            OsccBrakeRegenStatus::from_frame(&frame),
+            Ok(OsccBrakeRegenStatus { available: false })
+        );
+
+        frame.set_data_length(2);
+        assert_eq!(
+            OsccBrakeRegenStatus::from_frame(&frame),
+            Err(MalformedFrame::TooShort)
+        );
+    }
+
+    #[test]
+    fn report_encodes_the_header() {
+        let frame = OsccBrakeReport::builder().enabled(true).dtcs(0x81).build();
+        let data = frame.data();
+
+        assert_eq!(data.len(), usize::from(OSCC_BRAKE_REPORT_CAN_DLC));
+        assert_eq!((data[0], data[1]), (OSCC_MAGIC_BYTE_0, OSCC_MAGIC_BYTE_1));
+        assert_eq!(
+            data[OSCC_PROTOCOL_VERSION_BYTE_INDEX],
+            OSCC_PROTOCOL_VERSION
+        );
     }
 }
@@ -0,0 +1,60 @@
+use nucleo_f767zi::hal::can::{BaseID, CanError, DataFrame, ID};
+use oscc_magic_byte::*;
+use oscc_protocol_version::{OSCC_PROTOCOL_VERSION, OSCC_PROTOCOL_VERSION_BYTE_INDEX};
+
+/// Sent by a module straight after handling one of its enable or disable
+/// frames, so a controller doesn't have to wait for the next report
+pub const OSCC_CONTROL_ACK_CAN_ID: u16 = 0xB2;
+
+pub const OSCC_CONTROL_ACK_CAN_DLC: u8 = 8;
+
+/// The frame being acknowledged
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ControlRequest {
+    Disable,
+    Enable,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OsccControlAck {
+    /// One of the FAULT_ORIGIN_* IDs
+    pub module_origin_id: u32,
+    pub request: ControlRequest,
+    /// Control state after handling the request
+    pub enabled: bool,
+    /// DTCs set after handling the request, for a NACK these include the one
+    /// that blocked the transition
+    pub dtcs: u8,
+}
+
+impl OsccControlAck {
+    /// True (an ACK) if the module ended up in the requested state, false (a
+    /// NACK) otherwise
+    pub fn accepted(&self) -> bool {
+        self.enabled == (self.request == ControlRequest::Enable)
+    }
+
+    /// Sets the DLC and data of `frame` from this acknowledgement
+    pub fn encode(&self, frame: &mut DataFrame) {
+        frame.set_data_length(OSCC_CONTROL_ACK_CAN_DLC as _);
+
+        let data = frame.data_as_mut();
+
+        data[0] = OSCC_MAGIC_BYTE_0;
+        data[1] = OSCC_MAGIC_BYTE_1;
+        data[2] = self.module_origin_id as _;
+        data[3] = (self.request == ControlRequest::Enable) as _;
+        data[4] = self.accepted() as _;
+        data[5] = self.enabled as _;
+        data[OSCC_PROTOCOL_VERSION_BYTE_INDEX] = OSCC_PROTOCOL_VERSION;
+        data[7] = self.dtcs;
+    }
+}
+
+pub trait ControlAckPublisher {
+    fn publish_control_ack(&mut self, control_ack: &OsccControlAck) -> Result<(), CanError>;
+}
+
+pub fn default_control_ack_data_frame() -> DataFrame {
+    DataFrame::new(ID::BaseID(BaseID::new(OSCC_CONTROL_ACK_CAN_ID)))
+}
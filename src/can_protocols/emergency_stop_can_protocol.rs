@@ -0,0 +1,9 @@
+/// Force-disables every module and latches the emergency stop
+///
+/// Lower than every module ID so it wins arbitration on the bus
+pub const OSCC_ALL_DISABLE_CAN_ID: u16 = 0x60;
+
+/// Clears the emergency stop latch, control must then be re-enabled per module
+///
+/// Only honored with a valid CRC, see `oscc_crc`
+pub const OSCC_ALL_DISABLE_CLEAR_CAN_ID: u16 = 0x61;
@@ -1,33 +1,159 @@
+use brake_can_protocol::{
+    OSCC_BRAKE_DTC_COMMAND_TIMEOUT, OSCC_BRAKE_DTC_INVALID_CRC, OSCC_BRAKE_DTC_NAMES,
+    OSCC_BRAKE_DTC_OPERATOR_OVERRIDE, OSCC_BRAKE_DTC_PROTOCOL_MISMATCH,
+    OSCC_BRAKE_EXTENDED_DTC_NAMES,
+};
 use nucleo_f767zi::hal::can::{BaseID, CanError, DataFrame, ID};
+use oscc_magic_byte::MalformedFrame;
+use steering_can_protocol::{OSCC_STEERING_DTC_NAMES, OSCC_STEERING_DTC_OPERATOR_OVERRIDE};
+use throttle_can_protocol::{OSCC_THROTTLE_DTC_NAMES, OSCC_THROTTLE_DTC_OPERATOR_OVERRIDE};
 
 pub const OSCC_FAULT_REPORT_CAN_ID: u16 = 0xAF;
 
 pub const OSCC_FAULT_REPORT_CAN_DLC: u8 = 8;
 
+/// Report byte holding the DTCs that don't fit the origin's DTC byte
+pub const OSCC_FAULT_REPORT_EXTENDED_DTCS_BYTE_INDEX: usize = 7;
+
 // TODO - enum
 pub const FAULT_ORIGIN_BRAKE: u32 = 0;
 pub const FAULT_ORIGIN_STEERING: u32 = 1;
 pub const FAULT_ORIGIN_THROTTLE: u32 = 2;
+pub const FAULT_ORIGIN_COUNT: usize = 3;
+
+pub fn fault_origin_name(origin: u32) -> &'static str {
+    match origin {
+        FAULT_ORIGIN_BRAKE => "brake",
+        FAULT_ORIGIN_STEERING => "steering",
+        FAULT_ORIGIN_THROTTLE => "throttle",
+        _ => "unknown",
+    }
+}
+
+/// Names of the DTC bits set in `dtcs` for the given fault origin, bits
+/// without a name (or from an unknown origin) are reported as "unknown DTC"
+pub fn describe_dtc(origin: u32, dtcs: u8) -> impl Iterator<Item = &'static str> {
+    let names: &'static [&'static str] = match origin {
+        FAULT_ORIGIN_BRAKE => &OSCC_BRAKE_DTC_NAMES,
+        FAULT_ORIGIN_STEERING => &OSCC_STEERING_DTC_NAMES,
+        FAULT_ORIGIN_THROTTLE => &OSCC_THROTTLE_DTC_NAMES,
+        _ => &[],
+    };
+
+    dtc_names(names, dtcs)
+}
+
+/// Names of the extended DTC bits set in `extended_dtcs`, see
+/// `describe_dtc`
+pub fn describe_extended_dtc(origin: u32, extended_dtcs: u8) -> impl Iterator<Item = &'static str> {
+    let names: &'static [&'static str] = match origin {
+        FAULT_ORIGIN_BRAKE => &OSCC_BRAKE_EXTENDED_DTC_NAMES,
+        _ => &[],
+    };
+
+    dtc_names(names, extended_dtcs)
+}
+
+fn dtc_names(names: &'static [&'static str], dtcs: u8) -> impl Iterator<Item = &'static str> {
+    (0..8usize)
+        .filter(move |bit| (dtcs >> bit) & 1 == 1)
+        .map(move |bit| names.get(bit).cloned().unwrap_or("unknown DTC"))
+}
+
+/// DTC bits of the given fault origin that record the operator or the
+/// supervisor taking over, or a dropped frame, rather than a fault, the
+/// module recovers from them on its own
+pub fn soft_dtc_mask(origin: u32) -> u8 {
+    match origin {
+        FAULT_ORIGIN_BRAKE => {
+            (1 << OSCC_BRAKE_DTC_OPERATOR_OVERRIDE)
+                | (1 << OSCC_BRAKE_DTC_COMMAND_TIMEOUT)
+                | (1 << OSCC_BRAKE_DTC_INVALID_CRC)
+                | (1 << OSCC_BRAKE_DTC_PROTOCOL_MISMATCH)
+        }
+        FAULT_ORIGIN_STEERING => 1 << OSCC_STEERING_DTC_OPERATOR_OVERRIDE,
+        FAULT_ORIGIN_THROTTLE => 1 << OSCC_THROTTLE_DTC_OPERATOR_OVERRIDE,
+        _ => 0,
+    }
+}
 
 // TODO - fix this organization
 pub struct OsccFaultReport {
     pub fault_origin_id: u32,
     pub dtcs: u8,
+    /// Origin specific DTCs beyond the 8 bits of `dtcs`, none are soft
+    pub extended_dtcs: u8,
 }
 
-impl<'a> From<&'a DataFrame> for OsccFaultReport {
-    fn from(f: &DataFrame) -> Self {
-        assert_eq!(u32::from(f.id()), u32::from(OSCC_FAULT_REPORT_CAN_ID));
+impl OsccFaultReport {
+    /// Parses a received fault report, the caller matches the frame ID
+    pub fn from_frame(f: &DataFrame) -> Result<Self, MalformedFrame> {
         let data = f.data();
 
+        if data.len() < usize::from(OSCC_FAULT_REPORT_CAN_DLC) {
+            return Err(MalformedFrame::TooShort);
+        }
+
         let fault_origin_id: u32 = u32::from(data[2])
             | (u32::from(data[3]) << 8)
             | (u32::from(data[4]) << 16)
             | (u32::from(data[5]) << 24);
 
-        OsccFaultReport {
+        Ok(OsccFaultReport {
             fault_origin_id,
             dtcs: data[6],
+            extended_dtcs: data[OSCC_FAULT_REPORT_EXTENDED_DTCS_BYTE_INDEX],
+        })
+    }
+
+    /// A DTC other than the origin's soft DTCs is set, only these latch the
+    /// system fault state
+    pub fn is_hard_fault(&self) -> bool {
+        ((self.dtcs & !soft_dtc_mask(self.fault_origin_id)) != 0) || (self.extended_dtcs != 0)
+    }
+}
+
+/// The DTCs latched so far per fault origin, so a fault that stays detected
+/// is latched, stored and published once, on the rising edge of its DTC
+pub struct FaultLatch {
+    // (DTCs, extended DTCs) per origin
+    latched: [(u8, u8); FAULT_ORIGIN_COUNT],
+}
+
+impl FaultLatch {
+    pub fn new() -> Self {
+        FaultLatch {
+            latched: [(0, 0); FAULT_ORIGIN_COUNT],
+        }
+    }
+
+    /// True if the report sets a DTC that isn't latched yet, the report's
+    /// DTCs are the latched set on return. Reports from unknown origins are
+    /// always new.
+    pub fn latch(&mut self, fault_report: &OsccFaultReport) -> bool {
+        let origin = fault_report.fault_origin_id as usize;
+
+        if origin >= FAULT_ORIGIN_COUNT {
+            return true;
+        }
+
+        let (latched_dtcs, latched_extended_dtcs) = self.latched[origin];
+        let rising = ((fault_report.dtcs & !latched_dtcs) != 0)
+            || ((fault_report.extended_dtcs & !latched_extended_dtcs) != 0);
+
+        self.latched[origin] = (fault_report.dtcs, fault_report.extended_dtcs);
+
+        rising
+    }
+
+    /// Forgets the latched DTCs the report no longer sets, so a fault that
+    /// clears and comes back latches again
+    pub fn release_cleared(&mut self, fault_report: &OsccFaultReport) {
+        let origin = fault_report.fault_origin_id as usize;
+
+        if let Some(latched) = self.latched.get_mut(origin) {
+            latched.0 &= fault_report.dtcs;
+            latched.1 &= fault_report.extended_dtcs;
         }
     }
 }
@@ -43,3 +169,181 @@ pub trait FaultReportPublisher {
 pub fn default_fault_report_data_frame() -> DataFrame {
     DataFrame::new(ID::BaseID(BaseID::new(OSCC_FAULT_REPORT_CAN_ID)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use brake_can_protocol::{
+        OSCC_BRAKE_DTC_INVALID_SENSOR_VAL, OSCC_BRAKE_EXTENDED_DTC_ABSURD_COMMAND,
+        OSCC_BRAKE_EXTENDED_DTC_DAC_OUTPUT_MISMATCH, OSCC_BRAKE_EXTENDED_DTC_SPOOF_ENABLE_MISMATCH,
+    };
+    use dtc::DtcBitfield;
+    use steering_can_protocol::OSCC_STEERING_DTC_SUPPLY_VOLTAGE;
+    use throttle_can_protocol::{
+        OSCC_THROTTLE_DTC_INVALID_SENSOR_VAL, OSCC_THROTTLE_DTC_SUPPLY_VOLTAGE,
+    };
+
+    fn report(fault_origin_id: u32, dtc: u8) -> OsccFaultReport {
+        let mut dtcs: u8 = 0;
+        dtcs.set(dtc);
+        OsccFaultReport {
+            fault_origin_id,
+            dtcs,
+            extended_dtcs: 0,
+        }
+    }
+
+    #[test]
+    fn overrides_and_timeouts_are_soft() {
+        assert!(!report(FAULT_ORIGIN_BRAKE, OSCC_BRAKE_DTC_OPERATOR_OVERRIDE).is_hard_fault());
+        assert!(!report(FAULT_ORIGIN_BRAKE, OSCC_BRAKE_DTC_COMMAND_TIMEOUT).is_hard_fault());
+        assert!(
+            !report(FAULT_ORIGIN_THROTTLE, OSCC_THROTTLE_DTC_OPERATOR_OVERRIDE).is_hard_fault()
+        );
+        assert!(
+            !report(FAULT_ORIGIN_STEERING, OSCC_STEERING_DTC_OPERATOR_OVERRIDE).is_hard_fault()
+        );
+    }
+
+    #[test]
+    fn dropped_brake_frames_are_soft() {
+        assert!(!report(FAULT_ORIGIN_BRAKE, OSCC_BRAKE_DTC_INVALID_CRC).is_hard_fault());
+        assert!(!report(FAULT_ORIGIN_BRAKE, OSCC_BRAKE_DTC_PROTOCOL_MISMATCH).is_hard_fault());
+    }
+
+    #[test]
+    fn sensor_faults_are_hard() {
+        assert!(report(FAULT_ORIGIN_BRAKE, 0).is_hard_fault());
+        assert!(report(FAULT_ORIGIN_THROTTLE, 0).is_hard_fault());
+        assert!(report(FAULT_ORIGIN_STEERING, 0).is_hard_fault());
+    }
+
+    #[test]
+    fn fault_report_round_trip() {
+        let mut frame = default_fault_report_data_frame();
+        frame.set_data_length(usize::from(OSCC_FAULT_REPORT_CAN_DLC));
+        {
+            let data = frame.data_as_mut();
+            data[2] = FAULT_ORIGIN_STEERING as u8;
+            data[6] = 0x24;
+            data[OSCC_FAULT_REPORT_EXTENDED_DTCS_BYTE_INDEX] = 0x01;
+        }
+
+        let report = OsccFaultReport::from_frame(&frame).unwrap();
+        assert_eq!(report.fault_origin_id, FAULT_ORIGIN_STEERING);
+        assert_eq!(report.dtcs, 0x24);
+        assert_eq!(report.extended_dtcs, 0x01);
+    }
+
+    #[test]
+    fn extended_dtcs_are_hard() {
+        let report = OsccFaultReport {
+            fault_origin_id: FAULT_ORIGIN_BRAKE,
+            dtcs: 0,
+            extended_dtcs: 1 << OSCC_BRAKE_EXTENDED_DTC_DAC_OUTPUT_MISMATCH,
+        };
+        assert!(report.is_hard_fault());
+
+        let mut names = describe_extended_dtc(FAULT_ORIGIN_BRAKE, report.extended_dtcs);
+        assert_eq!(names.next(), Some(OSCC_BRAKE_EXTENDED_DTC_NAMES[0]));
+        assert_eq!(names.next(), None);
+    }
+
+    #[test]
+    fn absurd_command_has_its_own_extended_dtc() {
+        let extended_dtcs = 1 << OSCC_BRAKE_EXTENDED_DTC_ABSURD_COMMAND;
+
+        let mut names = describe_extended_dtc(FAULT_ORIGIN_BRAKE, extended_dtcs);
+        assert_eq!(names.next(), Some("absurd command"));
+        assert_eq!(names.next(), None);
+    }
+
+    #[test]
+    fn stuck_brake_relay_is_not_a_sensor_fault() {
+        let report = OsccFaultReport {
+            fault_origin_id: FAULT_ORIGIN_BRAKE,
+            dtcs: 0,
+            extended_dtcs: 1 << OSCC_BRAKE_EXTENDED_DTC_SPOOF_ENABLE_MISMATCH,
+        };
+        assert!(report.is_hard_fault());
+        assert_eq!(describe_dtc(FAULT_ORIGIN_BRAKE, report.dtcs).next(), None);
+
+        let mut names = describe_extended_dtc(FAULT_ORIGIN_BRAKE, report.extended_dtcs);
+        assert_eq!(names.next(), Some("spoof enable relay stuck"));
+        assert_eq!(names.next(), None);
+    }
+
+    #[test]
+    fn short_fault_report_is_malformed() {
+        let mut frame = default_fault_report_data_frame();
+        frame.set_data_length(4);
+
+        assert_eq!(
+            OsccFaultReport::from_frame(&frame).err(),
+            Some(MalformedFrame::TooShort)
+        );
+    }
+
+    #[test]
+    fn dtc_names_follow_the_origin() {
+        let mut names = describe_dtc(FAULT_ORIGIN_THROTTLE, 0b11);
+        assert_eq!(names.next(), Some(OSCC_THROTTLE_DTC_NAMES[0]));
+        assert_eq!(names.next(), Some(OSCC_THROTTLE_DTC_NAMES[1]));
+        assert_eq!(names.next(), None);
+
+        assert_eq!(describe_dtc(99, 1).next(), Some("unknown DTC"));
+    }
+
+    #[test]
+    fn latches_on_the_rising_edge_only() {
+        let mut latch = FaultLatch::new();
+        let fault = report(FAULT_ORIGIN_THROTTLE, OSCC_THROTTLE_DTC_INVALID_SENSOR_VAL);
+
+        assert!(latch.latch(&fault));
+        assert!(!latch.latch(&fault));
+        assert!(!latch.latch(&fault));
+    }
+
+    #[test]
+    fn latches_a_new_dtc_next_to_a_latched_one() {
+        let mut latch = FaultLatch::new();
+        let mut fault = report(FAULT_ORIGIN_THROTTLE, OSCC_THROTTLE_DTC_INVALID_SENSOR_VAL);
+
+        assert!(latch.latch(&fault));
+        fault.dtcs.set(OSCC_THROTTLE_DTC_SUPPLY_VOLTAGE);
+        assert!(latch.latch(&fault));
+        assert!(latch.latch(&report(
+            FAULT_ORIGIN_BRAKE,
+            OSCC_BRAKE_DTC_INVALID_SENSOR_VAL
+        )));
+    }
+
+    #[test]
+    fn latches_again_once_the_dtc_cleared() {
+        let mut latch = FaultLatch::new();
+        let fault = report(FAULT_ORIGIN_STEERING, OSCC_STEERING_DTC_SUPPLY_VOLTAGE);
+        let cleared = OsccFaultReport {
+            fault_origin_id: FAULT_ORIGIN_STEERING,
+            dtcs: 0,
+            extended_dtcs: 0,
+        };
+
+        assert!(latch.latch(&fault));
+        latch.release_cleared(&fault);
+        assert!(!latch.latch(&fault));
+        latch.release_cleared(&cleared);
+        assert!(latch.latch(&fault));
+    }
+
+    #[test]
+    fn latches_a_new_extended_dtc() {
+        let mut latch = FaultLatch::new();
+        let mut fault = report(FAULT_ORIGIN_BRAKE, OSCC_BRAKE_DTC_INVALID_SENSOR_VAL);
+
+        assert!(latch.latch(&fault));
+        fault
+            .extended_dtcs
+            .set(OSCC_BRAKE_EXTENDED_DTC_DAC_OUTPUT_MISMATCH);
+        assert!(latch.latch(&fault));
+    }
+}
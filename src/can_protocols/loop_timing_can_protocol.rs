@@ -0,0 +1,94 @@
+use nucleo_f767zi::hal::can::{BaseID, CanError, DataFrame, ID};
+use oscc_magic_byte::*;
+use oscc_protocol_version::{OSCC_PROTOCOL_VERSION, OSCC_PROTOCOL_VERSION_BYTE_INDEX};
+
+/// Asks for the control loop timing, the longest time is reset once sent
+pub const OSCC_LOOP_TIMING_REQUEST_CAN_ID: u16 = 0xB7;
+
+/// Sent in answer to a loop timing request
+pub const OSCC_LOOP_TIMING_CAN_ID: u16 = 0xB8;
+
+pub const OSCC_LOOP_TIMING_CAN_DLC: u8 = 8;
+
+/// Execution time of the periodic control loop work, see `LoopTiming`
+pub struct OsccLoopTiming {
+    /// Last measured time [us]
+    pub last_us: u32,
+    /// Longest time since the previous request [us]
+    pub max_us: u32,
+}
+
+// times beyond the 16 bits of the frame saturate
+fn saturate_us(us: u32) -> u16 {
+    if us > u32::from(u16::max_value()) {
+        u16::max_value()
+    } else {
+        us as u16
+    }
+}
+
+impl OsccLoopTiming {
+    /// Sets the DLC and data of `frame` from this loop timing
+    pub fn encode(&self, frame: &mut DataFrame) {
+        frame.set_data_length(OSCC_LOOP_TIMING_CAN_DLC as _);
+
+        let data = frame.data_as_mut();
+
+        let last_us = saturate_us(self.last_us);
+        let max_us = saturate_us(self.max_us);
+
+        data[0] = OSCC_MAGIC_BYTE_0;
+        data[1] = OSCC_MAGIC_BYTE_1;
+        data[2] = (last_us & 0xFF) as _;
+        data[3] = ((last_us >> 8) & 0xFF) as _;
+        data[4] = (max_us & 0xFF) as _;
+        data[5] = ((max_us >> 8) & 0xFF) as _;
+        data[OSCC_PROTOCOL_VERSION_BYTE_INDEX] = OSCC_PROTOCOL_VERSION;
+        data[7] = 0;
+    }
+}
+
+pub trait LoopTimingPublisher {
+    fn publish_loop_timing(&mut self, loop_timing: &OsccLoopTiming) -> Result<(), CanError>;
+}
+
+pub fn default_loop_timing_data_frame() -> DataFrame {
+    DataFrame::new(ID::BaseID(BaseID::new(OSCC_LOOP_TIMING_CAN_ID)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timing_frame_carries_last_and_max() {
+        let timing = OsccLoopTiming {
+            last_us: 0x0123,
+            max_us: 0x0456,
+        };
+        let mut frame = default_loop_timing_data_frame();
+        timing.encode(&mut frame);
+
+        let data = frame.data();
+        assert_eq!(data.len(), usize::from(OSCC_LOOP_TIMING_CAN_DLC));
+        assert_eq!(data[0], OSCC_MAGIC_BYTE_0);
+        assert_eq!(data[1], OSCC_MAGIC_BYTE_1);
+        assert_eq!(&data[2..6], &[0x23, 0x01, 0x56, 0x04]);
+        assert_eq!(
+            data[OSCC_PROTOCOL_VERSION_BYTE_INDEX],
+            OSCC_PROTOCOL_VERSION
+        );
+    }
+
+    #[test]
+    fn long_times_saturate() {
+        let timing = OsccLoopTiming {
+            last_us: 70_000,
+            max_us: 1_000_000,
+        };
+        let mut frame = default_loop_timing_data_frame();
+        timing.encode(&mut frame);
+
+        assert_eq!(&frame.data()[2..6], &[0xFF, 0xFF, 0xFF, 0xFF]);
+    }
+}
@@ -0,0 +1,113 @@
+use nucleo_f767zi::hal::can::{BaseID, CanError, DataFrame, ID};
+use oscc_magic_byte::*;
+use oscc_protocol_version::{OSCC_PROTOCOL_VERSION, OSCC_PROTOCOL_VERSION_BYTE_INDEX};
+
+/// Asks every module to answer with a module info frame, for auto-discovery
+/// by diagnostic tools
+pub const OSCC_MODULE_INFO_REQUEST_CAN_ID: u16 = 0xB0;
+
+/// Sent by each module in answer to a module info request
+pub const OSCC_MODULE_INFO_CAN_ID: u16 = 0xB1;
+
+pub const OSCC_MODULE_INFO_CAN_DLC: u8 = 8;
+
+/// Firmware (major, minor, patch) version, from the crate version
+pub fn firmware_version() -> (u8, u8, u8) {
+    (
+        env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap_or(0),
+        env!("CARGO_PKG_VERSION_MINOR").parse().unwrap_or(0),
+        env!("CARGO_PKG_VERSION_PATCH").parse().unwrap_or(0),
+    )
+}
+
+/// Short git hash of the firmware build, "unknown" if it was built outside a
+/// git checkout, set by build.rs
+pub const GIT_HASH: &str = env!("OXCC_GIT_HASH");
+
+/// First byte of a git hash, 0 if it doesn't start with two hex digits
+pub fn build_id(git_hash: &str) -> u8 {
+    git_hash
+        .get(..2)
+        .and_then(|prefix| u8::from_str_radix(prefix, 16).ok())
+        .unwrap_or(0)
+}
+
+pub struct OsccModuleInfo {
+    /// One of the FAULT_ORIGIN_* IDs
+    pub module_origin_id: u32,
+    pub firmware_version: (u8, u8, u8),
+    /// Tells apart builds of the same firmware version, see `build_id`
+    pub build_id: u8,
+}
+
+impl OsccModuleInfo {
+    pub fn new(module_origin_id: u32) -> Self {
+        OsccModuleInfo {
+            module_origin_id,
+            firmware_version: firmware_version(),
+            build_id: build_id(GIT_HASH),
+        }
+    }
+
+    /// Sets the DLC and data of `frame` from this module info
+    pub fn encode(&self, frame: &mut DataFrame) {
+        frame.set_data_length(OSCC_MODULE_INFO_CAN_DLC as _);
+
+        let data = frame.data_as_mut();
+
+        let (major, minor, patch) = self.firmware_version;
+
+        data[0] = OSCC_MAGIC_BYTE_0;
+        data[1] = OSCC_MAGIC_BYTE_1;
+        data[2] = self.module_origin_id as _;
+        data[3] = major;
+        data[4] = minor;
+        data[5] = patch;
+        data[OSCC_PROTOCOL_VERSION_BYTE_INDEX] = OSCC_PROTOCOL_VERSION;
+        data[7] = self.build_id;
+    }
+}
+
+pub trait ModuleInfoPublisher {
+    fn publish_module_info(&mut self, module_info: &OsccModuleInfo) -> Result<(), CanError>;
+}
+
+pub fn default_module_info_data_frame() -> DataFrame {
+    DataFrame::new(ID::BaseID(BaseID::new(OSCC_MODULE_INFO_CAN_ID)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_id_is_the_first_hash_byte() {
+        assert_eq!(build_id("4d59d46a"), 0x4D);
+        assert_eq!(build_id("4d59d46a-dirty"), 0x4D);
+        assert_eq!(build_id("unknown"), 0);
+        assert_eq!(build_id(""), 0);
+    }
+
+    #[test]
+    fn info_frame_carries_the_origin_and_build_id() {
+        let info = OsccModuleInfo {
+            module_origin_id: 2,
+            firmware_version: (1, 2, 3),
+            build_id: 0x4D,
+        };
+        let mut frame = default_module_info_data_frame();
+        info.encode(&mut frame);
+
+        let data = frame.data();
+        assert_eq!(data.len(), usize::from(OSCC_MODULE_INFO_CAN_DLC));
+        assert_eq!(data[0], OSCC_MAGIC_BYTE_0);
+        assert_eq!(data[1], OSCC_MAGIC_BYTE_1);
+        assert_eq!(data[2], 2);
+        assert_eq!(&data[3..6], &[1, 2, 3]);
+        assert_eq!(
+            data[OSCC_PROTOCOL_VERSION_BYTE_INDEX],
+            OSCC_PROTOCOL_VERSION
+        );
+        assert_eq!(data[7], 0x4D);
+    }
+}
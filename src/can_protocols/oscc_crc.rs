@@ -0,0 +1,58 @@
+/// Command frames carry a CRC-8 of the preceding bytes in the last data byte
+pub const OSCC_CRC_BYTE_INDEX: usize = 7;
+
+const OSCC_CRC_POLYNOMIAL: u8 = 0x07;
+
+/// CRC-8 (polynomial 0x07, initial value 0x00)
+pub fn oscc_crc8(data: &[u8]) -> u8 {
+    let mut crc: u8 = 0;
+
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            if (crc & 0x80) != 0 {
+                crc = (crc << 1) ^ OSCC_CRC_POLYNOMIAL;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+
+    crc
+}
+
+/// Checks the CRC byte of a frame's data against the bytes preceding it
+pub fn is_crc_valid(data: &[u8]) -> bool {
+    if data.len() <= OSCC_CRC_BYTE_INDEX {
+        return false;
+    }
+
+    oscc_crc8(&data[..OSCC_CRC_BYTE_INDEX]) == data[OSCC_CRC_BYTE_INDEX]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc8_check_value() {
+        // CRC-8 (SMBus) check value for "123456789"
+        assert_eq!(oscc_crc8(b"123456789"), 0xF4);
+        assert_eq!(oscc_crc8(&[]), 0);
+    }
+
+    #[test]
+    fn crc_byte_is_checked() {
+        let mut data = [0x05, 0xCC, 1, 2, 3, 4, 5, 0];
+        data[OSCC_CRC_BYTE_INDEX] = oscc_crc8(&data[..OSCC_CRC_BYTE_INDEX]);
+        assert!(is_crc_valid(&data));
+
+        data[3] ^= 0x01;
+        assert!(!is_crc_valid(&data));
+    }
+
+    #[test]
+    fn short_data_has_no_crc() {
+        assert!(!is_crc_valid(&[0x05, 0xCC, 0]));
+    }
+}
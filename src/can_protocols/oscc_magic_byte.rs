@@ -1,2 +1,28 @@
 pub const OSCC_MAGIC_BYTE_0: u8 = 0x05;
 pub const OSCC_MAGIC_BYTE_1: u8 = 0xCC;
+
+/// Why a received frame could not be parsed
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MalformedFrame {
+    /// Fewer data bytes than the frame's DLC
+    TooShort,
+}
+
+/// True for frame data long enough to hold the magic bytes and starting
+/// with them
+pub fn has_oscc_magic(data: &[u8]) -> bool {
+    (data.len() >= 2) && (data[0] == OSCC_MAGIC_BYTE_0) && (data[1] == OSCC_MAGIC_BYTE_1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn magic_needs_both_bytes() {
+        assert!(has_oscc_magic(&[OSCC_MAGIC_BYTE_0, OSCC_MAGIC_BYTE_1]));
+        assert!(!has_oscc_magic(&[OSCC_MAGIC_BYTE_0]));
+        assert!(!has_oscc_magic(&[]));
+        assert!(!has_oscc_magic(&[OSCC_MAGIC_BYTE_1, OSCC_MAGIC_BYTE_0, 0]));
+    }
+}
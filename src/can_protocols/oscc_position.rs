@@ -0,0 +1,15 @@
+/// Quantizes a 0.0..1.0 position to one report byte (0..255), NaN and
+/// negative positions become 0
+pub fn position_to_byte(position: f32) -> u8 {
+    let scaled = position * 255.0;
+
+    if scaled > 0.0 {
+        if scaled < 255.0 {
+            scaled as u8
+        } else {
+            255
+        }
+    } else {
+        0
+    }
+}
@@ -0,0 +1,5 @@
+/// Revision of the OSCC frame layouts this firmware speaks
+pub const OSCC_PROTOCOL_VERSION: u8 = 1;
+
+/// Report frames and brake command frames carry the protocol version in this data byte
+pub const OSCC_PROTOCOL_VERSION_BYTE_INDEX: usize = 6;
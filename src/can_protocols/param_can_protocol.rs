@@ -0,0 +1,355 @@
+use nucleo_f767zi::hal::can::{BaseID, CanError, DataFrame, ID};
+use oscc_crc::is_crc_valid;
+use oscc_magic_byte::*;
+use oscc_protocol_version::{OSCC_PROTOCOL_VERSION, OSCC_PROTOCOL_VERSION_BYTE_INDEX};
+
+/// Sets a runtime parameter, applied only while the owning module is
+/// disabled and the value is within the parameter's range. The value is
+/// little-endian in bytes 4 and 5, the frame carries the protocol version
+/// and a CRC like a command.
+pub const OSCC_PARAM_WRITE_CAN_ID: u16 = 0xB3;
+
+/// Asks for the current value of a runtime parameter
+pub const OSCC_PARAM_READ_CAN_ID: u16 = 0xB4;
+
+/// Sent by the module owning the parameter in answer to a write or a read
+pub const OSCC_PARAM_VALUE_CAN_ID: u16 = 0xB5;
+
+pub const OSCC_PARAM_RX_CAN_IDS: [u16; 2] = [OSCC_PARAM_WRITE_CAN_ID, OSCC_PARAM_READ_CAN_ID];
+
+pub const OSCC_PARAM_WRITE_CAN_DLC: u8 = 8;
+
+pub const OSCC_PARAM_READ_CAN_DLC: u8 = 3;
+
+pub const OSCC_PARAM_VALUE_CAN_DLC: u8 = 8;
+
+// Parameter IDs are unique across the modules, only the owner answers.
+// Values are carried as 16-bit steps of a per-parameter size, see
+// `param_spec`.
+
+/// Brake pedal position average at or above which the operator is
+/// overriding [ADC steps]
+pub const OSCC_PARAM_BRAKE_OVERRIDE_THRESHOLD: u8 = 0x00;
+/// High spoof value above which the brake lights turn on [DAC steps]
+pub const OSCC_PARAM_BRAKE_LIGHT_HIGH_THRESHOLD: u8 = 0x01;
+/// Low spoof value above which the brake lights turn on [DAC steps]
+pub const OSCC_PARAM_BRAKE_LIGHT_LOW_THRESHOLD: u8 = 0x02;
+/// Drop below the brake light thresholds before the lights turn off [DAC steps]
+pub const OSCC_PARAM_BRAKE_LIGHT_HYSTERESIS: u8 = 0x03;
+/// Brake command low-pass filter alpha, 1.0 disables the filter
+pub const OSCC_PARAM_BRAKE_COMMAND_FILTER_ALPHA: u8 = 0x04;
+/// Brake pressure loop proportional gain
+pub const OSCC_PARAM_BRAKE_PRESSURE_LOOP_KP: u8 = 0x05;
+/// Brake pressure loop integral gain
+pub const OSCC_PARAM_BRAKE_PRESSURE_LOOP_KI: u8 = 0x06;
+
+/// Maximum change in each steering spoof value per update [DAC steps]
+pub const OSCC_PARAM_STEERING_MAX_SPOOF_DELTA: u8 = 0x40;
+
+/// Accelerator position average at or above which the operator is
+/// overriding, the idle calibration can lower it [ADC steps]
+pub const OSCC_PARAM_THROTTLE_OVERRIDE_THRESHOLD: u8 = 0x80;
+
+/// Outcome of a parameter request, the answering frame carries the value in
+/// effect afterwards
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ParamStatus {
+    /// Read, or written and applied
+    Ok,
+    /// The owning module is enabled, the value is unchanged
+    RefusedWhileEnabled,
+    /// Out of range for the parameter, the value is unchanged
+    InvalidValue,
+    ReadOnly,
+}
+
+impl ParamStatus {
+    fn as_u8(&self) -> u8 {
+        match *self {
+            ParamStatus::Ok => 0,
+            ParamStatus::RefusedWhileEnabled => 1,
+            ParamStatus::InvalidValue => 2,
+            ParamStatus::ReadOnly => 3,
+        }
+    }
+}
+
+/// Wire encoding and accepted range of a parameter
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParamSpec {
+    /// Raw steps per unit of the parameter, 1.0 for integer parameters
+    pub steps_per_unit: f32,
+    /// Smallest raw value a write may set
+    pub min: u16,
+    /// Largest raw value a write may set
+    pub max: u16,
+}
+
+impl ParamSpec {
+    /// The value of `raw`, None if outside the accepted range
+    pub fn value(&self, raw: u16) -> Option<f32> {
+        if (raw >= self.min) && (raw <= self.max) {
+            Some(f32::from(raw) / self.steps_per_unit)
+        } else {
+            None
+        }
+    }
+
+    /// The raw value nearest to `value`, saturating at the 16 bits of the
+    /// frame
+    pub fn raw(&self, value: f32) -> u16 {
+        let steps = (value * self.steps_per_unit) + 0.5;
+
+        if steps.is_nan() || (steps <= 0.0) {
+            0
+        } else if steps >= f32::from(u16::max_value()) {
+            u16::max_value()
+        } else {
+            steps as u16
+        }
+    }
+}
+
+/// The encoding and range of a parameter, None for an unknown ID
+///
+/// The ranges are wide enough for any supported vehicle. They keep a write
+/// from turning a safety check off, for example an override threshold the
+/// pedal can never reach or a steering slew limit of zero.
+pub fn param_spec(param_id: u8) -> Option<ParamSpec> {
+    let (steps_per_unit, min, max) = match param_id {
+        OSCC_PARAM_BRAKE_OVERRIDE_THRESHOLD => (1.0, 100, 2000),
+        OSCC_PARAM_BRAKE_LIGHT_HIGH_THRESHOLD => (1.0, 100, 2000),
+        OSCC_PARAM_BRAKE_LIGHT_LOW_THRESHOLD => (1.0, 100, 2000),
+        OSCC_PARAM_BRAKE_LIGHT_HYSTERESIS => (1.0, 0, 200),
+        // an alpha of zero would hold the filtered command forever
+        OSCC_PARAM_BRAKE_COMMAND_FILTER_ALPHA => (1000.0, 1, 1000),
+        OSCC_PARAM_BRAKE_PRESSURE_LOOP_KP => (100_000.0, 0, 10_000),
+        OSCC_PARAM_BRAKE_PRESSURE_LOOP_KI => (100_000.0, 0, 10_000),
+        OSCC_PARAM_STEERING_MAX_SPOOF_DELTA => (1.0, 1, 200),
+        OSCC_PARAM_THROTTLE_OVERRIDE_THRESHOLD => (1.0, 100, 2000),
+        _ => return None,
+    };
+
+    Some(ParamSpec {
+        steps_per_unit,
+        min,
+        max,
+    })
+}
+
+/// Why a parameter write frame was dropped
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ParamWriteError {
+    /// Fewer data bytes than the frame's DLC
+    TooShort,
+    InvalidCrc,
+    /// The frame was built for another protocol version
+    ProtocolMismatch(u8),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OsccParamWrite {
+    pub param_id: u8,
+    /// Steps of the parameter's `ParamSpec`, not yet range checked
+    pub raw: u16,
+}
+
+impl OsccParamWrite {
+    /// Checks the length, CRC and protocol version, the caller matches the
+    /// frame ID
+    pub fn from_frame(f: &DataFrame) -> Result<Self, ParamWriteError> {
+        let data = f.data();
+
+        if data.len() < usize::from(OSCC_PARAM_WRITE_CAN_DLC) {
+            return Err(ParamWriteError::TooShort);
+        }
+
+        if !is_crc_valid(data) {
+            return Err(ParamWriteError::InvalidCrc);
+        }
+
+        if data[OSCC_PROTOCOL_VERSION_BYTE_INDEX] != OSCC_PROTOCOL_VERSION {
+            return Err(ParamWriteError::ProtocolMismatch(
+                data[OSCC_PROTOCOL_VERSION_BYTE_INDEX],
+            ));
+        }
+
+        Ok(OsccParamWrite {
+            param_id: data[2],
+            raw: u16::from(data[4]) | (u16::from(data[5]) << 8),
+        })
+    }
+
+    /// The written value, None for a value outside the parameter's range or
+    /// an unknown parameter
+    pub fn value(&self) -> Option<f32> {
+        param_spec(self.param_id).and_then(|spec| spec.value(self.raw))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OsccParamRead {
+    pub param_id: u8,
+}
+
+impl OsccParamRead {
+    /// None if the frame is too short, the caller matches the frame ID
+    pub fn from_frame(f: &DataFrame) -> Option<Self> {
+        let data = f.data();
+
+        if data.len() < usize::from(OSCC_PARAM_READ_CAN_DLC) {
+            return None;
+        }
+
+        Some(OsccParamRead { param_id: data[2] })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OsccParamValue {
+    pub param_id: u8,
+    pub status: ParamStatus,
+    pub value: f32,
+}
+
+impl OsccParamValue {
+    /// Sets the DLC and data of `frame` from this parameter value
+    pub fn encode(&self, frame: &mut DataFrame) {
+        frame.set_data_length(OSCC_PARAM_VALUE_CAN_DLC as _);
+
+        let data = frame.data_as_mut();
+
+        let raw = param_spec(self.param_id).map_or(0, |spec| spec.raw(self.value));
+
+        data[0] = OSCC_MAGIC_BYTE_0;
+        data[1] = OSCC_MAGIC_BYTE_1;
+        data[2] = self.param_id;
+        data[3] = self.status.as_u8();
+        data[4] = (raw & 0xFF) as _;
+        data[5] = ((raw >> 8) & 0xFF) as _;
+        data[OSCC_PROTOCOL_VERSION_BYTE_INDEX] = OSCC_PROTOCOL_VERSION;
+        data[7] = 0;
+    }
+}
+
+pub trait ParamValuePublisher {
+    fn publish_param_value(&mut self, param_value: &OsccParamValue) -> Result<(), CanError>;
+}
+
+pub fn default_param_value_data_frame() -> DataFrame {
+    DataFrame::new(ID::BaseID(BaseID::new(OSCC_PARAM_VALUE_CAN_ID)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oscc_crc::{oscc_crc8, OSCC_CRC_BYTE_INDEX};
+
+    fn write_frame(param_id: u8, raw: u16) -> DataFrame {
+        let mut frame = DataFrame::new(ID::BaseID(BaseID::new(OSCC_PARAM_WRITE_CAN_ID)));
+        frame.set_data_length(OSCC_PARAM_WRITE_CAN_DLC as _);
+
+        {
+            let data = frame.data_as_mut();
+            data[0] = OSCC_MAGIC_BYTE_0;
+            data[1] = OSCC_MAGIC_BYTE_1;
+            data[2] = param_id;
+            data[3] = 0;
+            data[4] = (raw & 0xFF) as _;
+            data[5] = (raw >> 8) as _;
+            data[OSCC_PROTOCOL_VERSION_BYTE_INDEX] = OSCC_PROTOCOL_VERSION;
+            data[OSCC_CRC_BYTE_INDEX] = oscc_crc8(&data[..OSCC_CRC_BYTE_INDEX]);
+        }
+
+        frame
+    }
+
+    #[test]
+    fn override_threshold_write_reads_back() {
+        let write =
+            OsccParamWrite::from_frame(&write_frame(OSCC_PARAM_BRAKE_OVERRIDE_THRESHOLD, 600))
+                .unwrap();
+        assert_eq!(write.value(), Some(600.0));
+
+        let answer = OsccParamValue {
+            param_id: write.param_id,
+            status: ParamStatus::Ok,
+            value: 600.0,
+        };
+        let mut frame = default_param_value_data_frame();
+        answer.encode(&mut frame);
+
+        let data = frame.data();
+        assert_eq!(data.len(), usize::from(OSCC_PARAM_VALUE_CAN_DLC));
+        assert_eq!(data[2], OSCC_PARAM_BRAKE_OVERRIDE_THRESHOLD);
+        assert_eq!(data[3], ParamStatus::Ok.as_u8());
+        assert_eq!(&data[4..6], &[0x58, 0x02]);
+        assert_eq!(
+            data[OSCC_PROTOCOL_VERSION_BYTE_INDEX],
+            OSCC_PROTOCOL_VERSION
+        );
+    }
+
+    #[test]
+    fn write_with_invalid_crc_is_dropped() {
+        let mut frame = write_frame(OSCC_PARAM_BRAKE_OVERRIDE_THRESHOLD, 600);
+        frame.data_as_mut()[4] ^= 0x01;
+
+        assert_eq!(
+            OsccParamWrite::from_frame(&frame),
+            Err(ParamWriteError::InvalidCrc)
+        );
+    }
+
+    #[test]
+    fn write_for_another_protocol_version_is_dropped() {
+        let mut frame = write_frame(OSCC_PARAM_BRAKE_OVERRIDE_THRESHOLD, 600);
+        {
+            let data = frame.data_as_mut();
+            data[OSCC_PROTOCOL_VERSION_BYTE_INDEX] = OSCC_PROTOCOL_VERSION + 1;
+            data[OSCC_CRC_BYTE_INDEX] = oscc_crc8(&data[..OSCC_CRC_BYTE_INDEX]);
+        }
+
+        assert_eq!(
+            OsccParamWrite::from_frame(&frame),
+            Err(ParamWriteError::ProtocolMismatch(OSCC_PROTOCOL_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn short_write_is_dropped() {
+        let mut frame = write_frame(OSCC_PARAM_BRAKE_OVERRIDE_THRESHOLD, 600);
+        frame.set_data_length(3);
+
+        assert_eq!(
+            OsccParamWrite::from_frame(&frame),
+            Err(ParamWriteError::TooShort)
+        );
+    }
+
+    #[test]
+    fn values_outside_the_range_are_rejected() {
+        let write = |param_id, raw| OsccParamWrite { param_id, raw }.value();
+
+        assert_eq!(write(OSCC_PARAM_BRAKE_OVERRIDE_THRESHOLD, 0xFFFF), None);
+        assert_eq!(write(OSCC_PARAM_BRAKE_OVERRIDE_THRESHOLD, 0), None);
+        assert_eq!(write(OSCC_PARAM_STEERING_MAX_SPOOF_DELTA, 0), None);
+        assert_eq!(write(OSCC_PARAM_STEERING_MAX_SPOOF_DELTA, 0xFFFF), None);
+        assert_eq!(write(OSCC_PARAM_BRAKE_COMMAND_FILTER_ALPHA, 0), None);
+        assert_eq!(write(OSCC_PARAM_THROTTLE_OVERRIDE_THRESHOLD, 0), None);
+        assert_eq!(write(OSCC_PARAM_THROTTLE_OVERRIDE_THRESHOLD, 0xFFFF), None);
+        assert_eq!(write(0x20, 1), None);
+    }
+
+    #[test]
+    fn scaled_values_round_trip() {
+        let alpha = param_spec(OSCC_PARAM_BRAKE_COMMAND_FILTER_ALPHA).unwrap();
+        assert_eq!(alpha.value(1000), Some(1.0));
+        assert_eq!(alpha.raw(0.25), 250);
+
+        let gain = param_spec(OSCC_PARAM_BRAKE_PRESSURE_LOOP_KP).unwrap();
+        assert_eq!(gain.raw(0.0002), 20);
+        assert_eq!(gain.raw(-1.0), 0);
+        assert_eq!(gain.raw(10.0), u16::max_value());
+    }
+}
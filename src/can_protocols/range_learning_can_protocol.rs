@@ -0,0 +1,103 @@
+use nucleo_f767zi::hal::can::DataFrame;
+
+/// Drives the guided sensor range calibration, see `signal_range`. Starting
+/// is refused by an enabled module.
+pub const OSCC_RANGE_LEARNING_CAN_ID: u16 = 0xB6;
+
+pub const OSCC_RANGE_LEARNING_CAN_DLC: u8 = 3;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RangeLearningAction {
+    /// Starts recording the sensor extremes, the pedals and the steering
+    /// wheel are then swept end to end
+    Start,
+    /// The recorded ranges become the operating ranges and are kept across
+    /// resets
+    Finish,
+    /// Forgets the stored ranges and disables the range checks
+    Clear,
+}
+
+impl RangeLearningAction {
+    fn from_u8(action: u8) -> Option<Self> {
+        match action {
+            0 => Some(RangeLearningAction::Start),
+            1 => Some(RangeLearningAction::Finish),
+            2 => Some(RangeLearningAction::Clear),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OsccRangeLearningCommand {
+    pub action: RangeLearningAction,
+}
+
+impl OsccRangeLearningCommand {
+    /// None if the frame is too short or the action unknown, the caller
+    /// matches the frame ID
+    pub fn from_frame(f: &DataFrame) -> Option<Self> {
+        let data = f.data();
+
+        if data.len() < usize::from(OSCC_RANGE_LEARNING_CAN_DLC) {
+            return None;
+        }
+
+        RangeLearningAction::from_u8(data[2]).map(|action| OsccRangeLearningCommand { action })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nucleo_f767zi::hal::can::{BaseID, ID};
+    use oscc_magic_byte::*;
+
+    fn command_frame(dlc: usize, action: u8) -> DataFrame {
+        let mut frame = DataFrame::new(ID::BaseID(BaseID::new(OSCC_RANGE_LEARNING_CAN_ID)));
+        frame.set_data_length(dlc);
+
+        for (i, byte) in frame.data_as_mut().iter_mut().enumerate().take(dlc) {
+            *byte = match i {
+                0 => OSCC_MAGIC_BYTE_0,
+                1 => OSCC_MAGIC_BYTE_1,
+                _ => action,
+            };
+        }
+
+        frame
+    }
+
+    #[test]
+    fn command_parses_the_action() {
+        let actions = [
+            RangeLearningAction::Start,
+            RangeLearningAction::Finish,
+            RangeLearningAction::Clear,
+        ];
+
+        for (raw, action) in actions.iter().enumerate() {
+            let command = OsccRangeLearningCommand::from_frame(&command_frame(3, raw as u8));
+            assert_eq!(command.map(|c| c.action), Some(*action));
+        }
+    }
+
+    #[test]
+    fn unknown_action_is_rejected() {
+        assert_eq!(
+            OsccRangeLearningCommand::from_frame(&command_frame(3, 3)),
+            None
+        );
+    }
+
+    #[test]
+    fn short_command_is_rejected() {
+        for dlc in 0..usize::from(OSCC_RANGE_LEARNING_CAN_DLC) {
+            assert_eq!(
+                OsccRangeLearningCommand::from_frame(&command_frame(dlc, 0)),
+                None
+            );
+        }
+    }
+}
@@ -1,33 +1,67 @@
 use nucleo_f767zi::hal::can::{BaseID, CanError, DataFrame, ID};
+use oscc_magic_byte::*;
+use oscc_protocol_version::{OSCC_PROTOCOL_VERSION, OSCC_PROTOCOL_VERSION_BYTE_INDEX};
 
 pub const OSCC_STEERING_ENABLE_CAN_ID: u16 = 0x80;
 pub const OSCC_STEERING_DISABLE_CAN_ID: u16 = 0x81;
 pub const OSCC_STEERING_COMMAND_CAN_ID: u16 = 0x82;
 pub const OSCC_STEERING_REPORT_CAN_ID: u16 = 0x83;
 
+/// IDs the steering module handles, registered with the control CAN filters,
+/// the priority IDs are received on FIFO_0
+pub const OSCC_STEERING_PRIORITY_RX_CAN_IDS: [u16; 1] = [OSCC_STEERING_DISABLE_CAN_ID];
+pub const OSCC_STEERING_RX_CAN_IDS: [u16; 2] =
+    [OSCC_STEERING_ENABLE_CAN_ID, OSCC_STEERING_COMMAND_CAN_ID];
+
 pub const OSCC_STEERING_REPORT_CAN_DLC: u8 = 8;
 
+/// Command frames carry the request in data bytes 2 through 5
+pub const OSCC_STEERING_COMMAND_CAN_DLC: u8 = 8;
+
 // TODO - enum
 pub const OSCC_STEERING_DTC_INVALID_SENSOR_VAL: u8 = 0;
 pub const OSCC_STEERING_DTC_OPERATOR_OVERRIDE: u8 = 1;
+pub const OSCC_STEERING_DTC_SENSOR_MISMATCH: u8 = 2;
+pub const OSCC_STEERING_DTC_CAN_BUS_FAULT: u8 = 3;
+pub const OSCC_STEERING_DTC_SUPPLY_VOLTAGE: u8 = 4;
+pub const OSCC_STEERING_DTC_DIRECTION_REVERSAL: u8 = 5;
+pub const OSCC_STEERING_DTC_SPOOF_ENABLE_MISMATCH: u8 = 6;
+pub const OSCC_STEERING_DTC_DAC_OUTPUT_MISMATCH: u8 = 7;
+
+/// Human-readable DTC names, indexed by DTC bit
+pub const OSCC_STEERING_DTC_NAMES: [&str; 8] = [
+    "invalid sensor value",
+    "operator override",
+    "sensor mismatch",
+    "CAN bus fault",
+    "supply voltage out of tolerance",
+    "command direction reversing too fast",
+    "spoof enable relay stuck",
+    "DAC output mismatch",
+];
 
 pub struct OsccSteeringCommand {
     pub torque_request: f32,
 }
 
-impl<'a> From<&'a DataFrame> for OsccSteeringCommand {
-    fn from(f: &DataFrame) -> Self {
-        assert_eq!(u32::from(f.id()), u32::from(OSCC_STEERING_COMMAND_CAN_ID));
+impl OsccSteeringCommand {
+    /// The caller matches the frame ID, which may be shifted by a CAN ID
+    /// offset
+    pub fn from_frame(f: &DataFrame) -> Result<Self, MalformedFrame> {
         let data = f.data();
 
+        if data.len() < usize::from(OSCC_STEERING_COMMAND_CAN_DLC) {
+            return Err(MalformedFrame::TooShort);
+        }
+
         let raw_torque_request: u32 = u32::from(data[2])
             | (u32::from(data[3]) << 8)
             | (u32::from(data[4]) << 16)
             | (u32::from(data[5]) << 24);
 
-        OsccSteeringCommand {
+        Ok(OsccSteeringCommand {
             torque_request: f32::from_bits(raw_torque_request),
-        }
+        })
     }
 }
 
@@ -60,4 +94,90 @@ impl OsccSteeringReport {
             dtcs: 0,
         }
     }
+
+    pub fn builder() -> OsccSteeringReportBuilder {
+        OsccSteeringReportBuilder {
+            report: OsccSteeringReport::new(),
+        }
+    }
+
+    /// Writes the report into `frame`, including the magic bytes and
+    /// protocol version
+    pub fn encode(&self, frame: &mut DataFrame) {
+        frame.set_data_length(OSCC_STEERING_REPORT_CAN_DLC as _);
+
+        let data = frame.data_as_mut();
+
+        data[0] = OSCC_MAGIC_BYTE_0;
+        data[1] = OSCC_MAGIC_BYTE_1;
+        data[2] = self.enabled as _;
+        data[3] = self.operator_override as _;
+        data[4] = self.dtcs;
+        data[OSCC_PROTOCOL_VERSION_BYTE_INDEX] = OSCC_PROTOCOL_VERSION;
+    }
+}
+
+/// Builds a ready-to-transmit report frame, unset fields keep the
+/// `OsccSteeringReport::new` defaults
+pub struct OsccSteeringReportBuilder {
+    report: OsccSteeringReport,
+}
+
+impl OsccSteeringReportBuilder {
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.report.enabled = enabled;
+        self
+    }
+
+    pub fn operator_override(mut self, operator_override: bool) -> Self {
+        self.report.operator_override = operator_override;
+        self
+    }
+
+    pub fn dtcs(mut self, dtcs: u8) -> Self {
+        self.report.dtcs = dtcs;
+        self
+    }
+
+    pub fn build(self) -> DataFrame {
+        let mut frame = default_steering_report_data_frame();
+        self.report.encode(&mut frame);
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command_frame(dlc: usize, torque_request: f32) -> DataFrame {
+        let mut frame = DataFrame::new(ID::BaseID(BaseID::new(OSCC_STEERING_COMMAND_CAN_ID)));
+        frame.set_data_length(dlc);
+
+        let bits = torque_request.to_bits();
+        for (i, byte) in frame.data_as_mut().iter_mut().enumerate().take(dlc) {
+            *byte = match i {
+                0 => OSCC_MAGIC_BYTE_0,
+                1 => OSCC_MAGIC_BYTE_1,
+                2...5 => (bits >> (8 * (i - 2))) as u8,
+                _ => 0,
+            };
+        }
+
+        frame
+    }
+
+    #[test]
+    fn command_parses_the_torque_request() {
+        let command = OsccSteeringCommand::from_frame(&command_frame(8, -0.25)).unwrap();
+        assert_eq!(command.torque_request, -0.25);
+    }
+
+    #[test]
+    fn short_command_is_malformed() {
+        assert_eq!(
+            OsccSteeringCommand::from_frame(&command_frame(2, 0.0)).err(),
+            Some(MalformedFrame::TooShort)
+        );
+    }
 }
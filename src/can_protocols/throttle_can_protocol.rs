@@ -1,33 +1,69 @@
 use nucleo_f767zi::hal::can::{BaseID, CanError, DataFrame, ID};
+use oscc_magic_byte::*;
+use oscc_position;
+use oscc_protocol_version::{OSCC_PROTOCOL_VERSION, OSCC_PROTOCOL_VERSION_BYTE_INDEX};
 
 pub const OSCC_THROTTLE_ENABLE_CAN_ID: u16 = 0x90;
 pub const OSCC_THROTTLE_DISABLE_CAN_ID: u16 = 0x91;
 pub const OSCC_THROTTLE_COMMAND_CAN_ID: u16 = 0x92;
 pub const OSCC_THROTTLE_REPORT_CAN_ID: u16 = 0x93;
 
+/// IDs the throttle module handles, registered with the control CAN filters,
+/// the priority IDs are received on FIFO_0
+pub const OSCC_THROTTLE_PRIORITY_RX_CAN_IDS: [u16; 1] = [OSCC_THROTTLE_DISABLE_CAN_ID];
+pub const OSCC_THROTTLE_RX_CAN_IDS: [u16; 2] =
+    [OSCC_THROTTLE_ENABLE_CAN_ID, OSCC_THROTTLE_COMMAND_CAN_ID];
+
 pub const OSCC_THROTTLE_REPORT_CAN_DLC: u8 = 8;
 
+/// Command frames carry the request in data bytes 2 through 5
+pub const OSCC_THROTTLE_COMMAND_CAN_DLC: u8 = 8;
+
+/// Report byte echoing the last commanded position, scaled 0.0..1.0 to 0..255
+pub const OSCC_THROTTLE_REPORT_COMMAND_BYTE_INDEX: usize = 5;
+/// Report byte holding the high spoof DAC output, upper 8 of the 12 bits
+pub const OSCC_THROTTLE_REPORT_SPOOF_BYTE_INDEX: usize = 7;
+
 // TODO - enum
 pub const OSCC_THROTTLE_DTC_INVALID_SENSOR_VAL: u8 = 0;
 pub const OSCC_THROTTLE_DTC_OPERATOR_OVERRIDE: u8 = 1;
+pub const OSCC_THROTTLE_DTC_CAN_BUS_FAULT: u8 = 2;
+pub const OSCC_THROTTLE_DTC_SUPPLY_VOLTAGE: u8 = 3;
+pub const OSCC_THROTTLE_DTC_SPOOF_ENABLE_MISMATCH: u8 = 4;
+pub const OSCC_THROTTLE_DTC_DAC_OUTPUT_MISMATCH: u8 = 5;
+
+/// Human-readable DTC names, indexed by DTC bit
+pub const OSCC_THROTTLE_DTC_NAMES: [&str; 6] = [
+    "invalid sensor value",
+    "operator override",
+    "CAN bus fault",
+    "supply voltage out of tolerance",
+    "spoof enable relay stuck",
+    "DAC output mismatch",
+];
 
 pub struct OsccThrottleCommand {
     pub torque_request: f32,
 }
 
-impl<'a> From<&'a DataFrame> for OsccThrottleCommand {
-    fn from(f: &DataFrame) -> Self {
-        assert_eq!(u32::from(f.id()), u32::from(OSCC_THROTTLE_COMMAND_CAN_ID));
+impl OsccThrottleCommand {
+    /// The caller matches the frame ID, which may be shifted by a CAN ID
+    /// offset
+    pub fn from_frame(f: &DataFrame) -> Result<Self, MalformedFrame> {
         let data = f.data();
 
+        if data.len() < usize::from(OSCC_THROTTLE_COMMAND_CAN_DLC) {
+            return Err(MalformedFrame::TooShort);
+        }
+
         let raw_torque_request: u32 = u32::from(data[2])
             | (u32::from(data[3]) << 8)
             | (u32::from(data[4]) << 16)
             | (u32::from(data[5]) << 24);
 
-        OsccThrottleCommand {
+        Ok(OsccThrottleCommand {
             torque_request: f32::from_bits(raw_torque_request),
-        }
+        })
     }
 }
 
@@ -35,6 +71,10 @@ pub struct OsccThrottleReport {
     pub enabled: bool,
     pub operator_override: bool,
     pub dtcs: u8,
+    /// Last commanded position, after clamping to the command limits
+    pub commanded_position: f32,
+    /// High spoof signal DAC output [steps]
+    pub spoof_output_high: u16,
 }
 
 pub trait ThrottleReportSupplier {
@@ -58,6 +98,130 @@ impl OsccThrottleReport {
             enabled: false,
             operator_override: false,
             dtcs: 0,
+            commanded_position: 0.0,
+            spoof_output_high: 0,
+        }
+    }
+
+    /// Commanded position quantized to one byte
+    pub fn commanded_position_byte(&self) -> u8 {
+        oscc_position::position_to_byte(self.commanded_position)
+    }
+
+    /// Spoof output quantized to one byte, the DAC is 12 bit
+    pub fn spoof_output_byte(&self) -> u8 {
+        (self.spoof_output_high.min(0x0FFF) >> 4) as u8
+    }
+
+    pub fn builder() -> OsccThrottleReportBuilder {
+        OsccThrottleReportBuilder {
+            report: OsccThrottleReport::new(),
+        }
+    }
+
+    /// Writes the report into `frame`, including the magic bytes and
+    /// protocol version
+    pub fn encode(&self, frame: &mut DataFrame) {
+        frame.set_data_length(OSCC_THROTTLE_REPORT_CAN_DLC as _);
+
+        let data = frame.data_as_mut();
+
+        data[0] = OSCC_MAGIC_BYTE_0;
+        data[1] = OSCC_MAGIC_BYTE_1;
+        data[2] = self.enabled as _;
+        data[3] = self.operator_override as _;
+        data[4] = self.dtcs;
+        data[OSCC_THROTTLE_REPORT_COMMAND_BYTE_INDEX] = self.commanded_position_byte();
+        data[OSCC_THROTTLE_REPORT_SPOOF_BYTE_INDEX] = self.spoof_output_byte();
+        data[OSCC_PROTOCOL_VERSION_BYTE_INDEX] = OSCC_PROTOCOL_VERSION;
+    }
+}
+
+/// Builds a ready-to-transmit report frame, unset fields keep the
+/// `OsccThrottleReport::new` defaults
+pub struct OsccThrottleReportBuilder {
+    report: OsccThrottleReport,
+}
+
+impl OsccThrottleReportBuilder {
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.report.enabled = enabled;
+        self
+    }
+
+    pub fn operator_override(mut self, operator_override: bool) -> Self {
+        self.report.operator_override = operator_override;
+        self
+    }
+
+    pub fn dtcs(mut self, dtcs: u8) -> Self {
+        self.report.dtcs = dtcs;
+        self
+    }
+
+    pub fn commanded_position(mut self, commanded_position: f32) -> Self {
+        self.report.commanded_position = commanded_position;
+        self
+    }
+
+    pub fn spoof_output_high(mut self, spoof_output_high: u16) -> Self {
+        self.report.spoof_output_high = spoof_output_high;
+        self
+    }
+
+    pub fn build(self) -> DataFrame {
+        let mut frame = default_throttle_report_data_frame();
+        self.report.encode(&mut frame);
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command_frame(dlc: usize, torque_request: f32) -> DataFrame {
+        let mut frame = DataFrame::new(ID::BaseID(BaseID::new(OSCC_THROTTLE_COMMAND_CAN_ID)));
+        frame.set_data_length(dlc);
+
+        let bits = torque_request.to_bits();
+        for (i, byte) in frame.data_as_mut().iter_mut().enumerate().take(dlc) {
+            *byte = match i {
+                0 => OSCC_MAGIC_BYTE_0,
+                1 => OSCC_MAGIC_BYTE_1,
+                2...5 => (bits >> (8 * (i - 2))) as u8,
+                _ => 0,
+            };
+        }
+
+        frame
+    }
+
+    #[test]
+    fn command_parses_the_torque_request() {
+        let command = OsccThrottleCommand::from_frame(&command_frame(8, 0.5)).unwrap();
+        assert_eq!(command.torque_request, 0.5);
+    }
+
+    #[test]
+    fn short_command_is_malformed() {
+        for dlc in 0..usize::from(OSCC_THROTTLE_COMMAND_CAN_DLC) {
+            assert_eq!(
+                OsccThrottleCommand::from_frame(&command_frame(dlc, 0.5)).err(),
+                Some(MalformedFrame::TooShort)
+            );
         }
     }
+
+    #[test]
+    fn report_echoes_the_command_and_spoof_output() {
+        let frame = OsccThrottleReport::builder()
+            .commanded_position(1.0)
+            .spoof_output_high(0x0FFF)
+            .build();
+        let data = frame.data();
+
+        assert_eq!(data[OSCC_THROTTLE_REPORT_COMMAND_BYTE_INDEX], 0xFF);
+        assert_eq!(data[OSCC_THROTTLE_REPORT_SPOOF_BYTE_INDEX], 0xFF);
+    }
 }
@@ -1,95 +1,400 @@
 use brake_can_protocol::*;
+use emergency_stop_can_protocol::*;
 use fault_can_protocol::*;
+use loop_timing_can_protocol::{OSCC_LOOP_TIMING_CAN_ID, OSCC_LOOP_TIMING_REQUEST_CAN_ID};
+use module_info_can_protocol::OSCC_MODULE_INFO_REQUEST_CAN_ID;
 use nucleo_f767zi::hal::can::{
-    CanBitTiming, CanConfig, CanFilterConfig, FilterMode, FilterScale, RxFifo,
+    BaseID, CanBitTiming, CanConfig, CanFilterConfig, DataFrame, FilterMode, FilterScale, RxFifo,
+    ID,
 };
+use param_can_protocol::OSCC_PARAM_RX_CAN_IDS;
+use range_learning_can_protocol::OSCC_RANGE_LEARNING_CAN_ID;
 use steering_can_protocol::*;
 use throttle_can_protocol::*;
 use vehicle::*;
 
-pub const CONTROL_CAN_CONFIG: CanConfig = CanConfig {
-    loopback_mode: false,
-    silent_mode: false,
-    ttcm: false,
-    abom: true,
-    awum: false,
-    nart: false,
-    rflm: false,
-    txfp: false,
-    // TODO - update CAN impl to calculate these
-    // 500K with 216 MHz system clock /= 4 = 54 MHz pclk1
-    bit_timing: CanBitTiming {
-        prescaler: 5, // 6
-        sjw: 0,       // CAN_SJW_1TQ
-        bs1: 14,      // CAN_BS1_15TQ
-        bs2: 1,       // CAN_BS2_2TQ
-    },
-};
+/// Common OSCC CAN bus speeds
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CanBitrate {
+    Kbps125,
+    Kbps250,
+    Kbps500,
+    Mbps1,
+}
 
-pub const OBD_CAN_CONFIG: CanConfig = CanConfig {
-    loopback_mode: false,
-    silent_mode: false,
-    ttcm: false,
-    abom: true,
-    awum: false,
-    nart: false,
-    rflm: false,
-    txfp: false,
-    // 500K with 216 MHz system clock /= 4 = 54 MHz pclk1
-    bit_timing: CanBitTiming {
-        prescaler: 5, // 6
-        sjw: 0,       // CAN_SJW_1TQ
-        bs1: 14,      // CAN_BS1_15TQ
-        bs2: 1,       // CAN_BS2_2TQ
-    },
-};
+impl CanBitrate {
+    pub fn bits_per_second(&self) -> u32 {
+        match *self {
+            CanBitrate::Kbps125 => 125_000,
+            CanBitrate::Kbps250 => 250_000,
+            CanBitrate::Kbps500 => 500_000,
+            CanBitrate::Mbps1 => 1_000_000,
+        }
+    }
+}
+
+pub const CONTROL_CAN_BITRATE: CanBitrate = CanBitrate::Kbps500;
+pub const OBD_CAN_BITRATE: CanBitrate = CanBitrate::Kbps500;
+
+/// Minimum interval between fault report transmits [ms]
+///
+/// A persisting fault condition is reported every loop, this keeps it from
+/// flooding the control CAN bus.
+pub const FAULT_REPORT_MIN_INTERVAL_MS: u32 = 100;
+
+/// Time the control CAN bus is left in bus-off before the peripheral is
+/// restarted [ms]
+pub const CAN_BUS_OFF_RECOVERY_BACKOFF_MS: u32 = 100;
+
+/// Control CAN bus errors seen at every fault check for this long set the
+/// modules' CAN bus DTC [ms]
+pub const CAN_BUS_ERROR_SUSTAIN_MS: u32 = 500;
+
+/// Set when a brake override switch is wired to PD14, see
+/// `override_source::OverrideSwitch`
+pub const BRAKE_OVERRIDE_SWITCH_INSTALLED: bool = false;
+
+/// Set when the switched side of each spoof enable relay is wired back to a
+/// sense input (brake PE2, throttle PE4, steering PE5), see
+/// `spoof_enable_sense::SpoofEnableSense`
+pub const SPOOF_ENABLE_SENSE_INSTALLED: bool = false;
+
+/// Time a relay is given to follow its enable pin before a disagreeing
+/// sense input sets the module's DTC [ms]
+pub const SPOOF_ENABLE_SENSE_SETTLE_MS: u32 = 50;
+
+/// Number of accelerator position readings averaged by the startup idle
+/// calibration
+pub const THROTTLE_IDLE_CALIBRATION_SAMPLES: u8 = 16;
+
+/// Distance a sensor channel may read outside its learned operating range
+/// before it is treated as invalid [ADC steps]
+pub const SIGNAL_RANGE_MARGIN: u16 = 100;
+
+/// Periodic loop work taking longer than this is logged as a warning [us]
+pub const LOOP_TIME_BUDGET_US: u32 = 1_000;
+
+/// Time after power-up during which detected faults are not latched, the
+/// sensor readings settle while the rails come up [ms]
+pub const STARTUP_FAULT_GRACE_MS: u32 = 500;
+
+/// Distance a command may fall outside its limits and still be clamped,
+/// further out it is rejected as absurd
+pub const COMMAND_REJECT_MARGIN: f32 = 1.0;
+
+/// Expected analog supply (VDDA) [volts]
+pub const VDDA_NOMINAL: f32 = 3.3;
+
+/// Allowed VDDA deviation from VDDA_NOMINAL before the ADC readings are no
+/// longer trusted [volts]
+pub const VDDA_TOLERANCE: f32 = 0.15;
+
+/// Added to every OSCC control CAN ID, so several OXCC nodes can share one
+/// control CAN bus. Zero keeps the standard OSCC IDs.
+pub const CAN_ID_OFFSET: u16 = 0;
+
+// lowest and highest of the OSCC control CAN IDs
+const OSCC_CAN_ID_MIN: u16 = OSCC_ALL_DISABLE_CAN_ID;
+const OSCC_CAN_ID_MAX: u16 = OSCC_LOOP_TIMING_CAN_ID;
+
+const MAX_STANDARD_CAN_ID: u16 = 0x7FF;
+
+/// Why a CAN ID offset can't be used
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CanIdOffsetError {
+    /// The shifted IDs overlap the standard OSCC IDs or the OBD IDs
+    /// republished on the control CAN bus
+    Overlap,
+    /// The shifted IDs don't fit in a standard 11 bit ID
+    OutOfRange,
+}
+
+/// Shift applied to the whole set of OSCC control CAN IDs
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CanIdOffset(u16);
+
+impl CanIdOffset {
+    /// The standard OSCC IDs
+    pub const fn none() -> Self {
+        CanIdOffset(0)
+    }
+
+    /// A nonzero offset must move the whole set past the standard IDs, so a
+    /// node left at the standard IDs can share the bus
+    pub fn new(offset: u16) -> Result<Self, CanIdOffsetError> {
+        if offset == 0 {
+            return Ok(CanIdOffset::none());
+        }
+
+        if offset <= (OSCC_CAN_ID_MAX - OSCC_CAN_ID_MIN) {
+            return Err(CanIdOffsetError::Overlap);
+        }
+
+        if u32::from(OSCC_CAN_ID_MAX) + u32::from(offset) > u32::from(MAX_STANDARD_CAN_ID) {
+            return Err(CanIdOffsetError::OutOfRange);
+        }
+
+        let min = OSCC_CAN_ID_MIN + offset;
+        let max = OSCC_CAN_ID_MAX + offset;
+        let overlaps = |obd_id: u16| (obd_id >= min) && (obd_id <= max);
+
+        let mut overlaps_obd = overlaps(KIA_SOUL_OBD_STEERING_WHEEL_ANGLE_CAN_ID)
+            || overlaps(KIA_SOUL_OBD_WHEEL_SPEED_CAN_ID)
+            || overlaps(KIA_SOUL_OBD_BRAKE_PRESSURE_CAN_ID);
+
+        #[cfg(feature = "kia-soul-ev")]
+        {
+            if overlaps(KIA_SOUL_OBD_THROTTLE_PRESSURE_CAN_ID) {
+                overlaps_obd = true;
+            }
+        }
+
+        if overlaps_obd {
+            return Err(CanIdOffsetError::Overlap);
+        }
+
+        Ok(CanIdOffset(offset))
+    }
+
+    pub fn offset(&self) -> u16 {
+        self.0
+    }
+
+    /// The shifted form of a standard OSCC ID
+    pub fn apply(&self, id: u16) -> u16 {
+        id + self.0
+    }
+
+    /// The standard OSCC ID a received ID was shifted from, None for IDs
+    /// below the offset
+    pub fn base_id(&self, id: u32) -> Option<u32> {
+        id.checked_sub(u32::from(self.0))
+    }
+
+    /// An empty frame with the shifted form of a standard OSCC ID
+    pub fn data_frame(&self, id: u16) -> DataFrame {
+        DataFrame::new(ID::BaseID(BaseID::new(self.apply(id))))
+    }
+}
+
+/// CAN_ID_OFFSET, panics if it overlaps other control CAN IDs
+pub fn can_id_offset() -> CanIdOffset {
+    CanIdOffset::new(CAN_ID_OFFSET).expect("Invalid CAN_ID_OFFSET")
+}
+
+// bxCAN limits on the time segments [time quanta]
+const CAN_MAX_BS1_TQ: u32 = 16;
+const CAN_MAX_BS2_TQ: u32 = 8;
+const CAN_MAX_PRESCALER: u32 = 1024;
+
+/// Sample point of both CAN buses, as a fraction of the bit time: 7/8 is
+/// 87.5%, the CANopen recommendation
+pub const CAN_SAMPLE_POINT_EIGHTHS: u32 = 7;
+
+/// Resynchronization jump width of both CAN buses [time quanta]
+pub const CAN_SJW_TQ: u32 = 1;
+
+/// Computes the bit timing register values for `bitrate` from the APB1
+/// peripheral clock, targeting the CAN_SAMPLE_POINT_EIGHTHS sample point
+///
+/// Prefers the most time quanta per bit that divide the clock exactly,
+/// returns None if no exact timing exists.
+pub fn can_bit_timing(bitrate: CanBitrate, pclk1_hz: u32) -> Option<CanBitTiming> {
+    let bps = bitrate.bits_per_second();
+
+    for tq in (8..=(1 + CAN_MAX_BS1_TQ + CAN_MAX_BS2_TQ)).rev() {
+        if pclk1_hz % (bps * tq) != 0 {
+            continue;
+        }
+
+        let prescaler = pclk1_hz / (bps * tq);
+        // sync segment is always 1 tq, sample point at the end of BS1
+        let bs1 = ((tq * CAN_SAMPLE_POINT_EIGHTHS) / 8) - 1;
+        let bs2 = tq - 1 - bs1;
+
+        if (prescaler <= CAN_MAX_PRESCALER) && (bs1 <= CAN_MAX_BS1_TQ) && (bs2 <= CAN_MAX_BS2_TQ) {
+            // registers hold each value minus one
+            return Some(CanBitTiming {
+                prescaler: (prescaler - 1) as _,
+                sjw: (CAN_SJW_TQ - 1) as _,
+                bs1: (bs1 - 1) as _,
+                bs2: (bs2 - 1) as _,
+            });
+        }
+    }
+
+    None
+}
+
+/// Peripheral configuration shared by the control and OBD CAN buses, only
+/// the bit timing differs between them
+pub fn can_config(bit_timing: CanBitTiming) -> CanConfig {
+    CanConfig {
+        loopback_mode: false,
+        silent_mode: false,
+        ttcm: false,
+        abom: true,
+        awum: false,
+        nart: false,
+        rflm: false,
+        txfp: false,
+        bit_timing,
+    }
+}
+
+// CAN1 owns filter banks 0 through 13, CAN2 starts at bank 14
+const MAX_CONTROL_CAN_FILTERS: usize = 14;
+
+// 16 bit ID list mode holds four IDs per filter
+const IDS_PER_FILTER: usize = 4;
+
+/// Packs the IDs registered by each module into 16 bit ID list filters
+///
+/// Filters are numbered in registration order. In ID list mode the lowest
+/// numbered matching filter has the highest priority, so register the most
+/// important IDs first.
+pub struct ControlCanFilters {
+    ids: [[u16; IDS_PER_FILTER]; MAX_CONTROL_CAN_FILTERS],
+    id_counts: [usize; MAX_CONTROL_CAN_FILTERS],
+    uses_fifo1: [bool; MAX_CONTROL_CAN_FILTERS],
+    accepts_all: [bool; MAX_CONTROL_CAN_FILTERS],
+    count: usize,
+    can_id_offset: CanIdOffset,
+}
+
+impl ControlCanFilters {
+    pub fn new() -> Self {
+        ControlCanFilters {
+            ids: [[0; IDS_PER_FILTER]; MAX_CONTROL_CAN_FILTERS],
+            id_counts: [0; MAX_CONTROL_CAN_FILTERS],
+            uses_fifo1: [false; MAX_CONTROL_CAN_FILTERS],
+            accepts_all: [false; MAX_CONTROL_CAN_FILTERS],
+            count: 0,
+            can_id_offset: CanIdOffset::none(),
+        }
+    }
+
+    /// Shifts the IDs registered from now on
+    pub fn set_can_id_offset(&mut self, can_id_offset: CanIdOffset) {
+        self.can_id_offset = can_id_offset;
+    }
+
+    /// Accepts `ids` into `fifo`, sharing the last filter if it targets the
+    /// same FIFO and has room
+    pub fn register(&mut self, fifo: RxFifo, ids: &[u16]) {
+        let uses_fifo1 = match fifo {
+            RxFifo::Fifo0 => false,
+            RxFifo::Fifo1 => true,
+        };
+
+        for id in ids {
+            let shares_last = (self.count > 0)
+                && !self.accepts_all[self.count - 1]
+                && (self.uses_fifo1[self.count - 1] == uses_fifo1)
+                && (self.id_counts[self.count - 1] < IDS_PER_FILTER);
+
+            if !shares_last {
+                assert!(
+                    self.count < MAX_CONTROL_CAN_FILTERS,
+                    "Too many control CAN filters"
+                );
+                self.uses_fifo1[self.count] = uses_fifo1;
+                self.count += 1;
+            }
+
+            let index = self.count - 1;
+            self.ids[index][self.id_counts[index]] = self.can_id_offset.apply(*id);
+            self.id_counts[index] += 1;
+        }
+    }
+
+    /// Accepts every standard ID into `fifo` with a 16 bit mask filter
+    ///
+    /// ID list filters of the same scale have priority over mask filters, so
+    /// registered IDs still land in their own FIFO.
+    pub fn register_accept_all(&mut self, fifo: RxFifo) {
+        assert!(
+            self.count < MAX_CONTROL_CAN_FILTERS,
+            "Too many control CAN filters"
+        );
+        self.uses_fifo1[self.count] = match fifo {
+            RxFifo::Fifo0 => false,
+            RxFifo::Fifo1 => true,
+        };
+        self.accepts_all[self.count] = true;
+        self.count += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn filter(&self, index: usize) -> CanFilterConfig {
+        let ids = &self.ids[index];
+        let id_count = self.id_counts[index];
+        // unused slots repeat the first ID rather than accepting ID 0
+        let slot = |n: usize| u32::from(ids[if n < id_count { n } else { 0 }] << 5);
+
+        let mut f = CanFilterConfig::default();
+        f.filter_number = index as _;
+        f.enabled = true;
+        f.mode = FilterMode::IdList;
+        f.fifo_assignment = if self.uses_fifo1[index] {
+            RxFifo::Fifo1
+        } else {
+            RxFifo::Fifo0
+        };
+        f.scale = FilterScale::Fs16Bit;
+
+        if self.accepts_all[index] {
+            // zero masks, every ID matches
+            f.mode = FilterMode::IdMask;
+            return f;
+        }
+
+        f.filter_mask_id_low = slot(0);
+        f.filter_id_low = slot(1);
+        f.filter_mask_id_high = slot(2);
+        f.filter_id_high = slot(3);
+        f
+    }
+}
+
+/// The OSCC IDs are registered shifted by `can_id_offset`
+pub fn gather_control_can_filters(can_id_offset: CanIdOffset) -> ControlCanFilters {
+    let mut filters = ControlCanFilters::new();
+    filters.set_can_id_offset(can_id_offset);
 
-// TODO - docs on priority ordering in ID list mode
-// can we make a pub type instead?
-// CanFilterConfig { enabled: true, ..Default::default() }
-pub fn gather_control_can_filters() -> [CanFilterConfig; 3] {
     // filter 0 is the highest priority filter in ID list mode
-    // it stores the disable control IDs for throttle, brake, steering
-    // and the fault report ID
+    // it stores the e-stop IDs, then the disable control IDs for
+    // throttle, brake, steering and the fault report ID
     // FIFO_0
-    let mut f0 = CanFilterConfig::default();
-    f0.filter_number = 0;
-    f0.enabled = true;
-    f0.mode = FilterMode::IdList;
-    f0.fifo_assignment = RxFifo::Fifo0;
-    f0.scale = FilterScale::Fs16Bit;
-    f0.filter_mask_id_low = u32::from(OSCC_THROTTLE_DISABLE_CAN_ID << 5);
-    f0.filter_id_low = u32::from(OSCC_BRAKE_DISABLE_CAN_ID << 5);
-    f0.filter_mask_id_high = u32::from(OSCC_STEERING_DISABLE_CAN_ID << 5);
-    f0.filter_id_high = u32::from(OSCC_FAULT_REPORT_CAN_ID << 5);
-
-    // filter 1 stores the control command IDs for brake, throttle, and steering
+    filters.register(
+        RxFifo::Fifo0,
+        &[OSCC_ALL_DISABLE_CAN_ID, OSCC_ALL_DISABLE_CLEAR_CAN_ID],
+    );
+    filters.register(RxFifo::Fifo0, &OSCC_THROTTLE_PRIORITY_RX_CAN_IDS);
+    filters.register(RxFifo::Fifo0, &OSCC_BRAKE_PRIORITY_RX_CAN_IDS);
+    filters.register(RxFifo::Fifo0, &OSCC_STEERING_PRIORITY_RX_CAN_IDS);
+    filters.register(RxFifo::Fifo0, &[OSCC_FAULT_REPORT_CAN_ID]);
+
+    // the remaining module IDs (enable, command, clear faults, regen status)
+    // and the requests every module answers
     // FIFO_1
-    let mut f1 = CanFilterConfig::default();
-    f1.filter_number = 1;
-    f1.enabled = true;
-    f1.mode = FilterMode::IdList;
-    f1.fifo_assignment = RxFifo::Fifo1;
-    f1.scale = FilterScale::Fs16Bit;
-    f1.filter_mask_id_low = u32::from(OSCC_BRAKE_COMMAND_CAN_ID << 5);
-    f1.filter_id_low = u32::from(OSCC_THROTTLE_COMMAND_CAN_ID << 5);
-    f1.filter_mask_id_high = u32::from(OSCC_STEERING_COMMAND_CAN_ID << 5);
-    f1.filter_id_high = 0;
-
-    // filter 2 stores the enable control IDs for brake, throttle, and steering
+    filters.register(RxFifo::Fifo1, &OSCC_BRAKE_RX_CAN_IDS);
+    filters.register(RxFifo::Fifo1, &OSCC_THROTTLE_RX_CAN_IDS);
+    filters.register(RxFifo::Fifo1, &OSCC_STEERING_RX_CAN_IDS);
+    filters.register(RxFifo::Fifo1, &[OSCC_MODULE_INFO_REQUEST_CAN_ID]);
+    filters.register(RxFifo::Fifo1, &OSCC_PARAM_RX_CAN_IDS);
+    filters.register(RxFifo::Fifo1, &[OSCC_RANGE_LEARNING_CAN_ID]);
+    filters.register(RxFifo::Fifo1, &[OSCC_LOOP_TIMING_REQUEST_CAN_ID]);
+
+    // everything else, so unhandled IDs can be logged
     // FIFO_1
-    let mut f2 = CanFilterConfig::default();
-    f2.filter_number = 2;
-    f2.enabled = true;
-    f2.mode = FilterMode::IdList;
-    f2.fifo_assignment = RxFifo::Fifo1;
-    f2.scale = FilterScale::Fs16Bit;
-    f2.filter_mask_id_low = u32::from(OSCC_BRAKE_ENABLE_CAN_ID << 5);
-    f2.filter_id_low = u32::from(OSCC_THROTTLE_ENABLE_CAN_ID << 5);
-    f2.filter_mask_id_high = u32::from(OSCC_STEERING_ENABLE_CAN_ID << 5);
-    f2.filter_id_high = 0;
-
-    [f0, f1, f2]
+    #[cfg(feature = "can-sniffer")]
+    filters.register_accept_all(RxFifo::Fifo1);
+
+    filters
 }
 
 pub fn gather_obd_can_filters() -> [CanFilterConfig; 1] {
@@ -113,3 +418,40 @@ pub fn gather_obd_can_filters() -> [CanFilterConfig; 1] {
 
     [f3]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bit_timing_for_a_50_mhz_apb1_clock() {
+        let timing = can_bit_timing(CanBitrate::Kbps500, 50_000_000).unwrap();
+
+        // 20 tq per bit: 1 sync + 16 BS1 + 3 BS2, sampled at 85%
+        assert!(timing.prescaler == 4);
+        assert!(timing.sjw == 0);
+        assert!(timing.bs1 == 15);
+        assert!(timing.bs2 == 2);
+    }
+
+    #[test]
+    fn no_bit_timing_without_an_exact_divisor() {
+        assert!(can_bit_timing(CanBitrate::Mbps1, 1_234_567).is_none());
+    }
+
+    #[test]
+    fn both_buses_share_the_config() {
+        let timing = can_bit_timing(CanBitrate::Kbps250, 50_000_000).unwrap();
+        let config = can_config(timing);
+
+        assert!(config.abom);
+        assert!(!config.loopback_mode && !config.silent_mode);
+    }
+
+    #[test]
+    fn can_id_offset_must_clear_the_standard_ids() {
+        assert_eq!(CanIdOffset::new(0), Ok(CanIdOffset::none()));
+        assert_eq!(CanIdOffset::new(1), Err(CanIdOffsetError::Overlap));
+        assert_eq!(CanIdOffset::new(0x780), Err(CanIdOffsetError::OutOfRange));
+    }
+}
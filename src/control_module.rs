@@ -0,0 +1,209 @@
+// The surface the brake, throttle and steering modules share, so the main
+// loop can run the same steps over all of them. Each module keeps its
+// concrete type, module specific steps (the brake disable ramp, supply
+// voltage checks, tuning) are still called directly.
+//
+// Control is enabled through `process_rx_frame`, an enable frame is the
+// only way in.
+
+use brake_can_protocol::{
+    BrakeHeartbeatPublisher, BrakeRegenRequestPublisher, BrakeReportPublisher,
+};
+use can_health::CanHealth;
+use config::CanIdOffset;
+use control_ack_can_protocol::OsccControlAck;
+use fault_can_protocol::OsccFaultReport;
+use module_info_can_protocol::OsccModuleInfo;
+use nucleo_f767zi::hal::can::{CanError, CanFrame};
+use oxcc_error::OxccError;
+use param_can_protocol::OsccParamValue;
+use steering_can_protocol::SteeringReportPublisher;
+use throttle_can_protocol::ThrottleReportPublisher;
+use types::DebugConsole;
+
+/// Publishes the periodic reports of every module, the CAN gateway on the
+/// vehicle
+pub trait ReportPublisher:
+    BrakeReportPublisher
+    + BrakeHeartbeatPublisher
+    + BrakeRegenRequestPublisher
+    + ThrottleReportPublisher
+    + SteeringReportPublisher
+{
+}
+
+impl<T> ReportPublisher for T where
+    T: BrakeReportPublisher
+        + BrakeHeartbeatPublisher
+        + BrakeRegenRequestPublisher
+        + ThrottleReportPublisher
+        + SteeringReportPublisher
+{
+}
+
+/// Control state machine of the brake, throttle and steering modules, moved
+/// through each module's `transition_to`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ControlState {
+    /// Not spoofing, an enable frame enables control
+    Disabled,
+    /// Spoofing the sensor
+    Enabled,
+    /// The operator took over, left once the input is released
+    OverrideActive,
+    /// A fault detected by the module disabled control, how it is left is up
+    /// to the module
+    Faulted,
+}
+
+impl ControlState {
+    /// Staying in the same state is always allowed
+    pub fn can_transition_to(self, next: ControlState) -> bool {
+        match (self, next) {
+            (ControlState::Disabled, _) | (ControlState::Enabled, _) => true,
+            (ControlState::OverrideActive, ControlState::Disabled)
+            | (ControlState::OverrideActive, ControlState::Faulted) => true,
+            (ControlState::Faulted, ControlState::Disabled) => true,
+            (current, next) => current == next,
+        }
+    }
+}
+
+pub trait ControlModule {
+    fn disable_control(&mut self, debug_console: &mut DebugConsole) -> Result<(), OxccError>;
+
+    /// Some fault report if a fault was detected by this check
+    fn check_for_faults(
+        &mut self,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, OxccError>;
+
+    fn check_can_health(
+        &mut self,
+        can_health: &CanHealth,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, OxccError>;
+
+    fn check_spoof_enable(
+        &mut self,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, OxccError>;
+
+    fn check_dac_output(
+        &mut self,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, OxccError>;
+
+    fn set_can_id_offset(&mut self, can_id_offset: CanIdOffset);
+
+    /// Frames for other modules are ignored
+    fn process_rx_frame(
+        &mut self,
+        can_frame: &CanFrame,
+        rx_timestamp_ms: u32,
+        debug_console: &mut DebugConsole,
+    ) -> Result<(), OxccError>;
+
+    fn step_range_learning(&mut self);
+
+    /// Publishes this module's periodic reports, all are attempted and a
+    /// failure other than a timeout is returned over a timeout
+    fn publish_reports(&mut self, publisher: &mut ReportPublisher) -> Result<(), CanError>;
+
+    fn supply_control_ack(&mut self) -> Option<OsccControlAck>;
+
+    fn supply_module_info(&mut self) -> Option<&OsccModuleInfo>;
+
+    fn supply_param_value(&mut self) -> Option<OsccParamValue>;
+
+    fn supply_fault_report(&mut self) -> &OsccFaultReport;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use brake_can_protocol::{OSCC_BRAKE_DISABLE_CAN_ID, OSCC_BRAKE_ENABLE_CAN_ID};
+    use brake_module::{BrakeModule, UnpreparedBrakeModule};
+    use control_ack_can_protocol::ControlRequest;
+    use fault_can_protocol::{FAULT_ORIGIN_BRAKE, FAULT_ORIGIN_STEERING, FAULT_ORIGIN_THROTTLE};
+    use sim_board::{oscc_frame, SimBoard};
+    use steering_can_protocol::{OSCC_STEERING_DISABLE_CAN_ID, OSCC_STEERING_ENABLE_CAN_ID};
+    use steering_module::UnpreparedSteeringModule;
+    use system_clock::{self, TestClock};
+    use throttle_can_protocol::{OSCC_THROTTLE_DISABLE_CAN_ID, OSCC_THROTTLE_ENABLE_CAN_ID};
+    use throttle_module::UnpreparedThrottleModule;
+    use vehicle::{EMERGENCY_STOP_STATE, SYSTEM_FAULT_STATE, VEHICLE_STATE};
+
+    #[test]
+    fn each_module_handles_only_its_own_control_frames() {
+        let _clock = TestClock::take();
+        SYSTEM_FAULT_STATE.clear();
+        EMERGENCY_STOP_STATE.clear();
+        VEHICLE_STATE.set_brake_operator_override(false);
+        VEHICLE_STATE.set_brake_applied(false);
+        VEHICLE_STATE.set_brake_pedal_pressed(false);
+
+        let board = SimBoard::new();
+        board.brake_pedal_position.set(400, 195);
+        board.accelerator_position.set(400, 200);
+        board.torque_sensor.set(2000, 1940);
+        let mut console = board.debug_console;
+
+        let mut brake: BrakeModule = UnpreparedBrakeModule::new(
+            board.brake_dac,
+            board.brake_pins,
+            board.brake_pedal_position,
+        )
+        .prepare_module(&mut console);
+        let mut throttle = UnpreparedThrottleModule::new(
+            board.accelerator_position,
+            board.throttle_dac,
+            board.throttle_pins,
+        )
+        .prepare_module(&mut console);
+        let mut steering = UnpreparedSteeringModule::new(
+            board.torque_sensor,
+            board.steering_dac,
+            board.steering_pins,
+        )
+        .prepare_module(&mut console);
+
+        let mut modules: [&mut ControlModule; 3] = [&mut brake, &mut throttle, &mut steering];
+        let origins = [
+            FAULT_ORIGIN_BRAKE,
+            FAULT_ORIGIN_THROTTLE,
+            FAULT_ORIGIN_STEERING,
+        ];
+        let control_ids = [
+            (OSCC_BRAKE_ENABLE_CAN_ID, OSCC_BRAKE_DISABLE_CAN_ID),
+            (OSCC_THROTTLE_ENABLE_CAN_ID, OSCC_THROTTLE_DISABLE_CAN_ID),
+            (OSCC_STEERING_ENABLE_CAN_ID, OSCC_STEERING_DISABLE_CAN_ID),
+        ];
+
+        for (target, &(enable_id, disable_id)) in control_ids.iter().enumerate() {
+            for &(request, id) in &[
+                (ControlRequest::Enable, enable_id),
+                (ControlRequest::Disable, disable_id),
+            ] {
+                let frame = oscc_frame(id, &[]);
+                for module in modules.iter_mut() {
+                    module
+                        .process_rx_frame(&frame, system_clock::get_timestamp_ms(), &mut console)
+                        .unwrap();
+                }
+
+                for (index, module) in modules.iter_mut().enumerate() {
+                    let control_ack = module.supply_control_ack();
+                    if index == target {
+                        let control_ack = control_ack.unwrap();
+                        assert_eq!(control_ack.module_origin_id, origins[index]);
+                        assert_eq!(control_ack.request, request);
+                        assert!(control_ack.accepted());
+                    } else {
+                        assert!(control_ack.is_none());
+                    }
+                }
+            }
+        }
+    }
+}
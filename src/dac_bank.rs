@@ -0,0 +1,223 @@
+// Several MCP4922s sharing one SPI bus, each on its own chip select,
+// addressed as a single bank of channels. Device 0 has bank channels 0 (A)
+// and 1 (B), device 1 has 2 and 3, and so on.
+
+use core::marker::PhantomData;
+use dac_mcp4922::{Channel, DacOutput, Error, Mcp4922, NoLdac};
+use embedded_hal::blocking::spi::Write;
+use embedded_hal::digital::OutputPin;
+
+/// A channel across all the devices of a bank
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BankChannel(pub u8);
+
+impl BankChannel {
+    pub fn new(device: u8, channel: Channel) -> Self {
+        let offset = match channel {
+            Channel::ChannelA => 0,
+            Channel::ChannelB => 1,
+        };
+
+        BankChannel((device * 2) + offset)
+    }
+
+    /// Index of the device holding this channel
+    pub fn device(&self) -> usize {
+        usize::from(self.0 / 2)
+    }
+
+    /// The channel on that device
+    pub fn channel(&self) -> Channel {
+        if self.0 % 2 == 0 {
+            Channel::ChannelA
+        } else {
+            Channel::ChannelB
+        }
+    }
+}
+
+/// Owns the devices, `D` is an array (or slice) of them
+///
+/// Each device holds its own handle to the shared bus, its writes only
+/// assert its own chip select.
+pub struct DacBank<D, SPI, CS, LDAC = NoLdac> {
+    devices: D,
+    device: PhantomData<Mcp4922<SPI, CS, LDAC>>,
+}
+
+impl<D, SPI, CS, LDAC, E> DacBank<D, SPI, CS, LDAC>
+where
+    D: AsMut<[Mcp4922<SPI, CS, LDAC>]> + AsRef<[Mcp4922<SPI, CS, LDAC>]>,
+    SPI: Write<u8, Error = E>,
+    CS: OutputPin,
+    LDAC: OutputPin,
+{
+    pub fn new(devices: D) -> Self {
+        DacBank {
+            devices,
+            device: PhantomData,
+        }
+    }
+
+    pub fn into_devices(self) -> D {
+        self.devices
+    }
+
+    /// Two per device
+    pub fn channel_count(&self) -> usize {
+        self.devices.as_ref().len() * 2
+    }
+
+    pub fn device(&self, index: usize) -> Option<&Mcp4922<SPI, CS, LDAC>> {
+        self.devices.as_ref().get(index)
+    }
+
+    pub fn device_mut(&mut self, index: usize) -> Option<&mut Mcp4922<SPI, CS, LDAC>> {
+        self.devices.as_mut().get_mut(index)
+    }
+
+    /// Writes `data` to the device and channel behind `bank_channel`
+    pub fn output(&mut self, bank_channel: BankChannel, data: DacOutput) -> Result<(), Error<E>> {
+        match self.device_mut(bank_channel.device()) {
+            Some(device) => device.output(data, bank_channel.channel()),
+            None => Err(Error::NoSuchChannel),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Default)]
+    struct Bus {
+        // index of the device whose chip select is low
+        selected: Option<usize>,
+        // (selected device, command word) of every write
+        writes: Vec<(Option<usize>, [u8; 2])>,
+    }
+
+    struct MockSpi(Rc<RefCell<Bus>>);
+
+    impl Write<u8> for MockSpi {
+        type Error = ();
+
+        fn write(&mut self, words: &[u8]) -> Result<(), ()> {
+            let mut bus = self.0.borrow_mut();
+            let selected = bus.selected;
+            bus.writes.push((selected, [words[0], words[1]]));
+            Ok(())
+        }
+    }
+
+    struct MockCs {
+        bus: Rc<RefCell<Bus>>,
+        device: usize,
+    }
+
+    impl OutputPin for MockCs {
+        fn set_low(&mut self) {
+            let mut bus = self.bus.borrow_mut();
+            assert_eq!(bus.selected, None, "two chip selects low");
+            bus.selected = Some(self.device);
+        }
+        fn set_high(&mut self) {
+            let mut bus = self.bus.borrow_mut();
+            if bus.selected == Some(self.device) {
+                bus.selected = None;
+            }
+        }
+    }
+
+    fn bank() -> (
+        DacBank<[Mcp4922<MockSpi, MockCs>; 2], MockSpi, MockCs>,
+        Rc<RefCell<Bus>>,
+    ) {
+        let bus = Rc::new(RefCell::new(Bus::default()));
+        let device = |index| {
+            Mcp4922::new(
+                MockSpi(bus.clone()),
+                MockCs {
+                    bus: bus.clone(),
+                    device: index,
+                },
+            )
+        };
+        let bank = DacBank::new([device(0), device(1)]);
+        bus.borrow_mut().writes.clear();
+        (bank, bus)
+    }
+
+    fn is_channel_b(word: [u8; 2]) -> bool {
+        (word[0] & (1 << 7)) != 0
+    }
+
+    fn steps(word: [u8; 2]) -> u16 {
+        (u16::from(word[0] & 0x0F) << 8) | u16::from(word[1])
+    }
+
+    #[test]
+    fn bank_channels_number_the_device_channels_in_order() {
+        assert_eq!(BankChannel::new(0, Channel::ChannelA), BankChannel(0));
+        assert_eq!(BankChannel::new(0, Channel::ChannelB), BankChannel(1));
+        assert_eq!(BankChannel::new(1, Channel::ChannelA), BankChannel(2));
+        assert_eq!(BankChannel::new(1, Channel::ChannelB), BankChannel(3));
+
+        assert_eq!(BankChannel(3).device(), 1);
+        assert_eq!(BankChannel(3).channel(), Channel::ChannelB);
+        assert_eq!(BankChannel(2).channel(), Channel::ChannelA);
+    }
+
+    #[test]
+    fn writes_go_to_the_device_and_channel_of_the_bank_channel() {
+        let (mut bank, bus) = bank();
+        assert_eq!(bank.channel_count(), 4);
+
+        for (index, value) in [0x100, 0x200, 0x300, 0x400].iter().enumerate() {
+            let bank_channel = BankChannel(index as u8);
+            assert!(bank.output(bank_channel, DacOutput::clamp(*value)).is_ok());
+        }
+
+        let writes = &bus.borrow().writes;
+        assert_eq!(writes.len(), 4);
+
+        let expected = [
+            (0, false, 0x100),
+            (0, true, 0x200),
+            (1, false, 0x300),
+            (1, true, 0x400),
+        ];
+        for (&(selected, word), &(device, channel_b, value)) in writes.iter().zip(expected.iter()) {
+            assert_eq!(selected, Some(device));
+            assert_eq!(is_channel_b(word), channel_b);
+            assert_eq!(steps(word), value);
+        }
+    }
+
+    #[test]
+    fn chip_select_is_released_after_each_write() {
+        let (mut bank, bus) = bank();
+
+        assert!(bank.output(BankChannel(2), DacOutput::clamp(0x123)).is_ok());
+        assert_eq!(bus.borrow().selected, None);
+
+        assert!(bank.output(BankChannel(1), DacOutput::clamp(0x321)).is_ok());
+        assert_eq!(bus.borrow().selected, None);
+
+        let selected: Vec<Option<usize>> = bus.borrow().writes.iter().map(|w| w.0).collect();
+        assert_eq!(selected, vec![Some(1), Some(0)]);
+    }
+
+    #[test]
+    fn a_channel_beyond_the_devices_is_refused_without_a_write() {
+        let (mut bank, bus) = bank();
+
+        assert_eq!(
+            bank.output(BankChannel(4), DacOutput::clamp(0x100)),
+            Err(Error::NoSuchChannel)
+        );
+        assert!(bus.borrow().writes.is_empty());
+    }
+}
@@ -0,0 +1,181 @@
+// MCP4822 support, built on the MCP4922 driver
+//
+// The MCP4822 uses the same 16 bit command word as the MCP4922, except that
+// bit 14 (VREF input buffer) is "don't care" since the reference is internal.
+// The wrapped driver is always left unbuffered, so that bit is written as 0.
+
+use embedded_hal::blocking::spi::Write;
+use embedded_hal::digital::OutputPin;
+
+use dac_mcp4922::{Channel, DacMode, DacOutput, Error, Gain, Mcp4922, NoLdac, OutputVerifier};
+
+/// Internal reference voltage [volts]
+///
+/// Full scale output is 2.048 V with 1x gain and 4.096 V with 2x gain.
+pub const INTERNAL_VREF: f32 = 2.048;
+
+pub struct Mcp4822<SPI, CS, LDAC = NoLdac> {
+    dac: Mcp4922<SPI, CS, LDAC>,
+}
+
+impl<SPI, CS, E> Mcp4822<SPI, CS, NoLdac>
+where
+    SPI: Write<u8, Error = E>,
+    CS: OutputPin,
+{
+    pub fn new(spi: SPI, cs: CS) -> Self {
+        Mcp4822::new_with_ldac(spi, cs, NoLdac)
+    }
+}
+
+impl<SPI, CS, LDAC, E> Mcp4822<SPI, CS, LDAC>
+where
+    SPI: Write<u8, Error = E>,
+    CS: OutputPin,
+    LDAC: OutputPin,
+{
+    pub fn new_with_ldac(spi: SPI, cs: CS, ldac: LDAC) -> Self {
+        let mut dac = Mcp4922::new_with_ldac(spi, cs, ldac);
+        dac.set_reference_voltage(INTERNAL_VREF);
+
+        Mcp4822 { dac }
+    }
+
+    /// Selects the output gain used by subsequent writes, defaults to 1x
+    pub fn set_gain(&mut self, gain: Gain) {
+        self.dac.set_gain(gain);
+    }
+
+    pub fn gain(&self) -> Gain {
+        self.dac.gain()
+    }
+
+    pub fn set_mode(&mut self, mode: DacMode) {
+        self.dac.set_mode(mode);
+    }
+
+    pub fn mode(&self) -> DacMode {
+        self.dac.mode()
+    }
+
+    /// Output steps per volt for the internal reference and gain
+    pub fn steps_per_volt(&self) -> f32 {
+        self.dac.steps_per_volt()
+    }
+
+    pub fn set_channel_trim(&mut self, channel: Channel, numerator: u16, denominator: u16) {
+        self.dac.set_channel_trim(channel, numerator, denominator);
+    }
+
+    pub fn channel_trim(&self, channel: Channel) -> (u16, u16) {
+        self.dac.channel_trim(channel)
+    }
+
+    pub fn set_output_verifier(&mut self, verifier: Option<OutputVerifier>) {
+        self.dac.set_output_verifier(verifier);
+    }
+
+    pub fn has_verification_fault(&self) -> bool {
+        self.dac.has_verification_fault()
+    }
+
+    pub fn verification_failures(&self) -> u8 {
+        self.dac.verification_failures()
+    }
+
+    pub fn clear_verification_fault(&mut self) {
+        self.dac.clear_verification_fault();
+    }
+
+    pub fn output_ab(&mut self, output_a: DacOutput, output_b: DacOutput) -> Result<(), Error<E>> {
+        self.dac.output_ab(output_a, output_b)
+    }
+
+    pub fn init(&mut self, output_a: DacOutput, output_b: DacOutput) -> Result<(), Error<E>> {
+        self.dac.init(output_a, output_b)
+    }
+
+    pub fn write_all(&mut self, values: &[(Channel, DacOutput)]) -> Result<(), Error<E>> {
+        self.dac.write_all(values)
+    }
+
+    pub fn output_ab_latched(
+        &mut self,
+        output_a: DacOutput,
+        output_b: DacOutput,
+    ) -> Result<(), Error<E>> {
+        self.dac.output_ab_latched(output_a, output_b)
+    }
+
+    pub fn output(&mut self, data: DacOutput, channel: Channel) -> Result<(), Error<E>> {
+        self.dac.output(data, channel)
+    }
+
+    pub fn output_with_retries(
+        &mut self,
+        data: DacOutput,
+        channel: Channel,
+        retries: u8,
+    ) -> Result<(), Error<E>> {
+        self.dac.output_with_retries(data, channel, retries)
+    }
+
+    pub fn last_output(&self, channel: Channel) -> DacOutput {
+        self.dac.last_output(channel)
+    }
+
+    pub fn shutdown(&mut self, channel: Channel) -> Result<(), Error<E>> {
+        self.dac.shutdown(channel)
+    }
+
+    pub fn wake(&mut self, channel: Channel) {
+        self.dac.wake(channel);
+    }
+
+    pub fn is_shutdown(&self, channel: Channel) -> bool {
+        self.dac.is_shutdown(channel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sim_board::{SimPin, SimSpi};
+
+    // bit 14 of the command word, the VREF input buffer on the MCP4922
+    const BUF_BIT: u16 = 1 << 14;
+
+    fn dac() -> Mcp4822<SimSpi, SimPin> {
+        Mcp4822::new(SimSpi::new(), SimPin::new())
+    }
+
+    #[test]
+    fn steps_per_volt_follow_the_internal_reference() {
+        let mut dac = dac();
+        assert!((dac.steps_per_volt() - 2000.0).abs() < 0.01);
+
+        dac.set_gain(Gain::Gain2x);
+        assert!((dac.steps_per_volt() - 1000.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn buffer_bit_is_written_as_zero() {
+        let mut dac = dac();
+
+        assert!(dac
+            .output(DacOutput::clamp(0xABC), Channel::ChannelB)
+            .is_ok());
+        // channel B, 1x gain, active
+        let word = dac.dac.spi_mut().last_word().unwrap();
+        assert_eq!(word, 0b1011_0000_0000_0000 | 0xABC);
+        assert_eq!(word & BUF_BIT, 0);
+
+        dac.set_gain(Gain::Gain2x);
+        assert!(dac
+            .output(DacOutput::clamp(0x123), Channel::ChannelA)
+            .is_ok());
+        let word = dac.dac.spi_mut().last_word().unwrap();
+        assert_eq!(word, 0b0001_0000_0000_0000 | 0x123);
+        assert_eq!(word & BUF_BIT, 0);
+    }
+}
@@ -1,13 +1,12 @@
 // TODO
-// - latching
-// - gain
-// - buffer vref
 // - other errors?
 
+use core::sync::atomic;
 use embedded_hal::blocking::spi::Write;
 use embedded_hal::digital::OutputPin;
 use embedded_hal::spi::{Mode, Phase, Polarity};
 
+use num;
 use ranges::Bounded;
 use typenum::{U0, U1, U4096};
 
@@ -22,16 +21,144 @@ pub const MODE: Mode = Mode {
     polarity: Polarity::IdleLow,
 };
 
+/// Fastest SPI clock the device supports [Hz]
+pub const MAX_SPI_CLOCK_HZ: u32 = 20_000_000;
+
+/// Most retries `output_with_retries` makes, whatever is asked for
+pub const MAX_SPI_RETRIES: u8 = 4;
+
+/// Consecutive verified writes that must read back wrong before the
+/// verification fault is set, a single bad read is taken as noise
+pub const MAX_VERIFICATION_FAILURES: u8 = 3;
+
+// busy-wait before the first retry, doubled for each retry after it, so a
+// transient disturbance has time to pass [spin loop iterations]
+const SPI_RETRY_BACKOFF_SPINS: u32 = 64;
+
+/// Why an SPI configuration can't drive the device
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SpiConfigError {
+    /// Clock polarity or phase other than `expected_mode`
+    WrongMode,
+    /// Clock faster than MAX_SPI_CLOCK_HZ
+    ClockTooFast,
+    /// Frames other than 8 bits, MSB first
+    WrongFrameFormat,
+}
+
+/// SPI bus configuration as read back from the peripheral
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SpiConfig {
+    pub mode: Mode,
+    /// SCK frequency [Hz]
+    pub clock_hz: u32,
+    /// Data frame size [bits]
+    pub frame_bits: u8,
+    pub lsb_first: bool,
+}
+
+/// SDI is sampled on the rising edge of SCK, which idles low: mode 0,
+/// CPOL = 0 and CPHA = 0
+pub fn expected_mode() -> Mode {
+    MODE
+}
+
+/// Checks an SPI bus configuration before the first write, a wrong mode,
+/// clock or frame format silently corrupts every command
+///
+/// Each 16 bit command is sent as two bytes, MSB first.
+pub fn check_spi_config(config: &SpiConfig) -> Result<(), SpiConfigError> {
+    let expected = expected_mode();
+
+    if (config.mode.polarity != expected.polarity) || (config.mode.phase != expected.phase) {
+        Err(SpiConfigError::WrongMode)
+    } else if config.clock_hz > MAX_SPI_CLOCK_HZ {
+        Err(SpiConfigError::ClockTooFast)
+    } else if (config.frame_bits != 8) || config.lsb_first {
+        Err(SpiConfigError::WrongFrameFormat)
+    } else {
+        Ok(())
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Channel {
     ChannelA,
     ChannelB,
 }
 
-#[derive(Debug)]
+impl Channel {
+    /// Both channels, A first
+    pub fn both() -> [Channel; 2] {
+        [Channel::ChannelA, Channel::ChannelB]
+    }
+}
+
+/// Number of output steps across the reference voltage at 1x gain
+pub const FULL_SCALE_STEPS: f32 = 4096.0;
+
+/// Reference voltage assumed until `set_reference_voltage` is called [volts]
+pub const DEFAULT_VREF: f32 = 5.0;
+
+/// Output gain selection
+///
+/// Vout = gain * Vref * D / 4096
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Gain {
+    Gain1x,
+    Gain2x,
+}
+
+impl Default for Gain {
+    fn default() -> Self {
+        Gain::Gain1x
+    }
+}
+
+/// VREF input buffer selection
+///
+/// The buffered input presents a high impedance to the reference source but
+/// limits the usable VREF range to roughly VSS + 40 mV through VDD - 40 mV.
+/// This applies before the gain stage, and the output is always bounded by
+/// VDD, so a buffered reference with 2x gain still saturates at the rail.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum VrefBuffer {
+    Unbuffered,
+    Buffered,
+}
+
+impl Default for VrefBuffer {
+    fn default() -> Self {
+        VrefBuffer::Unbuffered
+    }
+}
+
+/// How `output_ab` derives the channel B value
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DacMode {
+    /// Channels A and B are written with independent values
+    Independent,
+    /// Channel B is the complement of channel A around `midpoint`, for
+    /// push-pull front-ends: B = 2 * midpoint - A, saturating at 0 and 4095
+    Differential { midpoint: u16 },
+}
+
+impl Default for DacMode {
+    fn default() -> Self {
+        DacMode::Independent
+    }
+}
+
+/// Reads a channel's analog output back, through an external ADC or a
+/// loopback, and returns true if it matches the expected value
+pub type OutputVerifier = fn(Channel, DacOutput) -> bool;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Error<E> {
     /// SPI error
     Spi(E),
+    /// A `DacBank` channel beyond the bank's devices
+    NoSuchChannel,
 }
 
 impl<E> From<E> for Error<E> {
@@ -40,47 +167,432 @@ impl<E> From<E> for Error<E> {
     }
 }
 
-pub struct Mcp4922<SPI, CS> {
+/// Placeholder LDAC pin for devices that have LDAC tied low in hardware
+pub struct NoLdac;
+
+impl OutputPin for NoLdac {
+    fn set_low(&mut self) {}
+    fn set_high(&mut self) {}
+}
+
+pub struct Mcp4922<SPI, CS, LDAC = NoLdac> {
     spi: SPI,
     cs: CS,
+    ldac: LDAC,
+    gain: Gain,
+    vref_buffer: VrefBuffer,
+    mode: DacMode,
+    vref: f32,
+    channel_a_shutdown: bool,
+    channel_b_shutdown: bool,
+    last_a: u16,
+    last_b: u16,
+    trim_a: (u16, u16),
+    trim_b: (u16, u16),
+    verifier: Option<OutputVerifier>,
+    // verified writes in a row that read back wrong
+    verification_failures: u8,
+    verification_fault: bool,
+    dithering: bool,
+    // fraction of a step carried over to the next `output_fractional`
+    dither_residual_a: f32,
+    dither_residual_b: f32,
 }
 
-impl<SPI, CS, E> Mcp4922<SPI, CS>
+impl<SPI, CS, E> Mcp4922<SPI, CS, NoLdac>
 where
     SPI: Write<u8, Error = E>,
     CS: OutputPin,
 {
-    pub fn new(spi: SPI, mut cs: CS) -> Self {
+    pub fn new(spi: SPI, cs: CS) -> Self {
+        Mcp4922::new_with_ldac(spi, cs, NoLdac)
+    }
+}
+
+impl<SPI, CS, LDAC, E> Mcp4922<SPI, CS, LDAC>
+where
+    SPI: Write<u8, Error = E>,
+    CS: OutputPin,
+    LDAC: OutputPin,
+{
+    /// LDAC is left low, so each write transfers to the output as soon as
+    /// CS is raised. Use `output_ab_latched` to update both channels together.
+    pub fn new_with_ldac(spi: SPI, mut cs: CS, mut ldac: LDAC) -> Self {
         // unselect the device
         cs.set_high();
 
-        Mcp4922 { spi, cs }
+        ldac.set_low();
+
+        Mcp4922 {
+            spi,
+            cs,
+            ldac,
+            gain: Gain::default(),
+            vref_buffer: VrefBuffer::default(),
+            mode: DacMode::default(),
+            vref: DEFAULT_VREF,
+            channel_a_shutdown: false,
+            channel_b_shutdown: false,
+            last_a: 0,
+            last_b: 0,
+            trim_a: (1, 1),
+            trim_b: (1, 1),
+            verifier: None,
+            verification_failures: 0,
+            verification_fault: false,
+            dithering: false,
+            dither_residual_a: 0.0,
+            dither_residual_b: 0.0,
+        }
+    }
+
+    /// The bus the device is written through, for inspecting and scripting
+    /// a simulated one
+    #[cfg(any(test, feature = "sim"))]
+    pub fn spi_mut(&mut self) -> &mut SPI {
+        &mut self.spi
+    }
+
+    /// Selects the output gain used by subsequent writes, defaults to 1x
+    pub fn set_gain(&mut self, gain: Gain) {
+        self.gain = gain;
     }
 
-    pub fn output_ab(&mut self, output_a: DacOutput, output_b: DacOutput) -> Result<(), E> {
-        // TODO latching?
+    pub fn gain(&self) -> Gain {
+        self.gain
+    }
+
+    /// Selects the VREF input buffer used by subsequent writes, defaults to
+    /// unbuffered
+    pub fn set_vref_buffer(&mut self, vref_buffer: VrefBuffer) {
+        self.vref_buffer = vref_buffer;
+    }
+
+    pub fn vref_buffer(&self) -> VrefBuffer {
+        self.vref_buffer
+    }
+
+    /// Selects how `output_ab` and `output_ab_latched` derive channel B,
+    /// defaults to `DacMode::Independent`
+    pub fn set_mode(&mut self, mode: DacMode) {
+        self.mode = mode;
+    }
+
+    pub fn mode(&self) -> DacMode {
+        self.mode
+    }
+
+    /// Sets the voltage on the VREF pin, used to convert voltages to steps.
+    /// Defaults to DEFAULT_VREF, a non-positive or NaN `vref` is ignored.
+    pub fn set_reference_voltage(&mut self, vref: f32) {
+        if vref > 0.0 {
+            self.vref = vref;
+        }
+    }
+
+    pub fn reference_voltage(&self) -> f32 {
+        self.vref
+    }
+
+    /// Output steps per volt for the reference voltage and gain
+    pub fn steps_per_volt(&self) -> f32 {
+        let gain = match self.gain {
+            Gain::Gain1x => 1.0,
+            Gain::Gain2x => 2.0,
+        };
+
+        FULL_SCALE_STEPS / (self.vref * gain)
+    }
+
+    /// Scales every value written to the channel by `numerator / denominator`,
+    /// saturating at the 12 bit maximum.
+    ///
+    /// A trim of 1/1 is the identity and is the default. A zero denominator
+    /// is treated as 1/1.
+    pub fn set_channel_trim(&mut self, channel: Channel, numerator: u16, denominator: u16) {
+        let trim = if denominator == 0 {
+            (1, 1)
+        } else {
+            (numerator, denominator)
+        };
+
+        match channel {
+            Channel::ChannelA => self.trim_a = trim,
+            Channel::ChannelB => self.trim_b = trim,
+        }
+    }
+
+    /// Returns the (numerator, denominator) trim of the channel
+    pub fn channel_trim(&self, channel: Channel) -> (u16, u16) {
+        match channel {
+            Channel::ChannelA => self.trim_a,
+            Channel::ChannelB => self.trim_b,
+        }
+    }
+
+    /// Checks every successful `output` write with `verifier`,
+    /// MAX_VERIFICATION_FAILURES mismatches in a row latch the verification
+    /// fault. None (the default) disables it.
+    pub fn set_output_verifier(&mut self, verifier: Option<OutputVerifier>) {
+        self.verifier = verifier;
+    }
+
+    /// True once MAX_VERIFICATION_FAILURES verified writes in a row read
+    /// back a different value, until `clear_verification_fault` is called
+    pub fn has_verification_fault(&self) -> bool {
+        self.verification_fault
+    }
+
+    /// Verified writes in a row that read back a different value
+    pub fn verification_failures(&self) -> u8 {
+        self.verification_failures
+    }
+
+    pub fn clear_verification_fault(&mut self) {
+        self.verification_failures = 0;
+        self.verification_fault = false;
+    }
+
+    /// Lets `output_fractional` alternate between the adjacent steps around a
+    /// fractional value, defaults to off. Only worth it on actuators slow
+    /// enough to average the steps.
+    pub fn set_dithering(&mut self, dithering: bool) {
+        self.dithering = dithering;
+        self.dither_residual_a = 0.0;
+        self.dither_residual_b = 0.0;
+    }
+
+    pub fn dithering(&self) -> bool {
+        self.dithering
+    }
+
+    /// Writes a fractional step `value`, clamped to 0..4095, a NaN writes 0
+    ///
+    /// With dithering on, the fraction left over by each write is carried to
+    /// the next, so the outputs of repeated calls (one per loop) average to
+    /// `value`. Otherwise `value` is rounded to the nearest step.
+    pub fn output_fractional(&mut self, channel: Channel, value: f32) -> Result<(), Error<E>> {
+        let value = if value.is_nan() {
+            0.0
+        } else {
+            num::clamp(value, 0.0, 4095.0)
+        };
+
+        if !self.dithering {
+            return self.output(DacOutput::clamp((value + 0.5) as u16), channel);
+        }
+
+        let residual = match channel {
+            Channel::ChannelA => self.dither_residual_a,
+            Channel::ChannelB => self.dither_residual_b,
+        };
+
+        // truncating picks the lower step until the carried fraction tops
+        // up to a whole one
+        let target = value + residual;
+        let step = num::clamp(target as u16, 0, 4095);
+        self.output(DacOutput::clamp(step), channel)?;
+
+        let residual = target - f32::from(step);
+        match channel {
+            Channel::ChannelA => self.dither_residual_a = residual,
+            Channel::ChannelB => self.dither_residual_b = residual,
+        }
+
+        Ok(())
+    }
+
+    /// In `DacMode::Differential`, `output_b` is ignored and channel B is
+    /// written with the complement of `output_a`
+    pub fn output_ab(&mut self, output_a: DacOutput, output_b: DacOutput) -> Result<(), Error<E>> {
+        let output_b = self.channel_b_value(&output_a, output_b);
         self.output(output_a, Channel::ChannelA)?;
         self.output(output_b, Channel::ChannelB)
     }
 
-    pub fn output(&mut self, data: DacOutput, channel: Channel) -> Result<(), E> {
+    /// Writes both channels with LDAC held high, then pulses LDAC low so
+    /// both outputs update simultaneously.
+    ///
+    /// LDAC is always returned low, even if a write fails, so subsequent
+    /// unlatched writes still take effect.
+    pub fn output_ab_latched(
+        &mut self,
+        output_a: DacOutput,
+        output_b: DacOutput,
+    ) -> Result<(), Error<E>> {
+        let output_b = self.channel_b_value(&output_a, output_b);
+
+        self.ldac.set_high();
+
+        let mut result = self.output(output_a, Channel::ChannelA);
+        if result.is_ok() {
+            result = self.output(output_b, Channel::ChannelB);
+        }
+
+        // the falling edge transfers both input registers to the outputs
+        self.ldac.set_low();
+
+        result
+    }
+
+    /// Puts both channels into active operation and writes `output_a` and
+    /// `output_b`, so the outputs are defined before the first spoof instead
+    /// of whatever the device powered up with
+    pub fn init(&mut self, output_a: DacOutput, output_b: DacOutput) -> Result<(), Error<E>> {
+        self.wake(Channel::ChannelA);
+        self.wake(Channel::ChannelB);
+        self.output_ab(output_a, output_b)
+    }
+
+    /// Writes each (channel, value) in slice order, stopping at the first
+    /// failed write
+    ///
+    /// This is not a single transaction: the device latches a command on the
+    /// rising edge of CS and ignores clocks past the 16th, so a second
+    /// command in the same CS-low window would be dropped. Every update gets
+    /// its own CS window and CS is high again on return, even on error.
+    pub fn write_all(&mut self, values: &[(Channel, DacOutput)]) -> Result<(), Error<E>> {
+        for &(channel, ref data) in values {
+            self.output(DacOutput::clamp(*data.val()), channel)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn output(&mut self, data: DacOutput, channel: Channel) -> Result<(), Error<E>> {
+        self.output_with_retries(data, channel, 0)
+    }
+
+    /// Like `output`, but re-attempts a failed SPI write up to `retries`
+    /// more times, at most MAX_SPI_RETRIES, before returning the last error.
+    ///
+    /// Each retry waits twice as long as the one before it. CS is raised
+    /// after every attempt, so each retry starts a fresh transaction, and CS
+    /// is always left high on return.
+    pub fn output_with_retries(
+        &mut self,
+        data: DacOutput,
+        channel: Channel,
+        retries: u8,
+    ) -> Result<(), Error<E>> {
+        let trimmed = self.trimmed(data, channel);
+        let active = !self.is_shutdown(channel);
+
+        let mut result = self.write_command(*trimmed.val(), channel, active);
+        for retry in 0..retries.min(MAX_SPI_RETRIES) {
+            if result.is_ok() {
+                break;
+            }
+
+            retry_backoff(retry);
+            result = self.write_command(*trimmed.val(), channel, active);
+        }
+        result?;
+
+        // only cache values that made it to the device
+        match channel {
+            Channel::ChannelA => self.last_a = *trimmed.val(),
+            Channel::ChannelB => self.last_b = *trimmed.val(),
+        }
+
+        // a shut down channel's output is released, nothing to read back
+        if let Some(verify) = self.verifier {
+            if active {
+                if verify(channel, trimmed) {
+                    self.verification_failures = 0;
+                } else {
+                    self.verification_failures = self.verification_failures.saturating_add(1);
+                    if self.verification_failures >= MAX_VERIFICATION_FAILURES {
+                        self.verification_fault = true;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The last value successfully written to the channel with `output`,
+    /// after trim, zero if nothing has been written yet
+    pub fn last_output(&self, channel: Channel) -> DacOutput {
+        match channel {
+            Channel::ChannelA => DacOutput::clamp(self.last_a),
+            Channel::ChannelB => DacOutput::clamp(self.last_b),
+        }
+    }
+
+    /// Puts the channel into the power-down state, the output is tri-stated
+    /// (pulled down through 500 kOhm) until `wake` is called.
+    ///
+    /// Writes made with `output` while shut down update the DAC register but
+    /// leave the output released.
+    pub fn shutdown(&mut self, channel: Channel) -> Result<(), Error<E>> {
+        self.write_command(0, channel, false)?;
+        self.set_shutdown(channel, true);
+        Ok(())
+    }
+
+    /// Returns the channel to active operation, the output is driven again
+    /// starting with the next `output` write.
+    pub fn wake(&mut self, channel: Channel) {
+        self.set_shutdown(channel, false);
+    }
+
+    pub fn is_shutdown(&self, channel: Channel) -> bool {
+        match channel {
+            Channel::ChannelA => self.channel_a_shutdown,
+            Channel::ChannelB => self.channel_b_shutdown,
+        }
+    }
+
+    fn channel_b_value(&self, output_a: &DacOutput, output_b: DacOutput) -> DacOutput {
+        match self.mode {
+            DacMode::Independent => output_b,
+            DacMode::Differential { midpoint } => {
+                let complement = (2 * i32::from(midpoint)) - i32::from(*output_a.val());
+                DacOutput::clamp(num::clamp(complement, 0, 4095) as u16)
+            }
+        }
+    }
+
+    fn trimmed(&self, data: DacOutput, channel: Channel) -> DacOutput {
+        let (numerator, denominator) = self.channel_trim(channel);
+        let scaled = u32::from(*data.val()) * u32::from(numerator) / u32::from(denominator);
+
+        if scaled > u32::from(u16::max_value()) {
+            DacOutput::clamp(u16::max_value())
+        } else {
+            DacOutput::clamp(scaled as u16)
+        }
+    }
+
+    fn set_shutdown(&mut self, channel: Channel, shutdown: bool) {
+        match channel {
+            Channel::ChannelA => self.channel_a_shutdown = shutdown,
+            Channel::ChannelB => self.channel_b_shutdown = shutdown,
+        }
+    }
+
+    fn write_command(&mut self, data: u16, channel: Channel, active: bool) -> Result<(), Error<E>> {
         self.cs.set_low();
 
         // NOTE: swapping the bytes here, the HAL should be able to handle such a thing
         let mut buffer = [0u8; 2];
         // bits 11 through 0: data
-        buffer[1] = (data.val() & 0x00FF) as _;
-        buffer[0] = ((data.val() >> 8) & (0x000F as u16)) as u8
-            // bit 12: shutdown bit. 1 for active operation
-            | (1 << 4)
-            // bit 13: gain bit; 0 for 1x gain, 1 for 2x
-            // bit 14: buffer VREF?
+        buffer[1] = (data & 0x00FF) as _;
+        buffer[0] = ((data >> 8) & (0x000F as u16)) as u8
+            // bit 12: shutdown bit. 1 for active operation, 0 for power-down
+            | (active as u8) << 4
+            // bit 13: gain bit; 1 for 1x gain, 0 for 2x
+            | u8::from(self.gain) << 5
+            // bit 14: VREF input buffer; 1 for buffered, 0 for unbuffered
+            | u8::from(self.vref_buffer) << 6
             // bit 15: 0 for DAC A, 1 for DAC B
             | u8::from(channel) << 7;
 
         if let Err(e) = self.spi.write(&buffer) {
             self.cs.set_high();
-            return Err(e);
+            return Err(Error::Spi(e));
         }
 
         self.cs.set_high();
@@ -89,6 +601,14 @@ where
     }
 }
 
+// the backoff is short enough to busy-wait in the control loop, a few
+// microseconds before the last retry
+fn retry_backoff(retry: u8) {
+    for _ in 0..(SPI_RETRY_BACKOFF_SPINS << retry) {
+        atomic::spin_loop_hint();
+    }
+}
+
 impl From<Channel> for u8 {
     fn from(c: Channel) -> u8 {
         match c {
@@ -97,3 +617,567 @@ impl From<Channel> for u8 {
         }
     }
 }
+
+impl From<Gain> for u8 {
+    fn from(g: Gain) -> u8 {
+        match g {
+            Gain::Gain1x => 0b1,
+            Gain::Gain2x => 0b0,
+        }
+    }
+}
+
+impl From<VrefBuffer> for u8 {
+    fn from(b: VrefBuffer) -> u8 {
+        match b {
+            VrefBuffer::Unbuffered => 0b0,
+            VrefBuffer::Buffered => 0b1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    enum Event {
+        CsLow,
+        CsHigh,
+        Write([u8; 2]),
+        LdacLow,
+        LdacHigh,
+    }
+
+    #[derive(Default)]
+    struct Bus {
+        failures_left: u8,
+        attempts: u32,
+        writes: Vec<[u8; 2]>,
+        cs_high: bool,
+        // every pin change and successful write, in order
+        events: Vec<Event>,
+    }
+
+    struct MockSpi(Rc<RefCell<Bus>>);
+
+    impl Write<u8> for MockSpi {
+        type Error = ();
+
+        fn write(&mut self, words: &[u8]) -> Result<(), ()> {
+            let mut bus = self.0.borrow_mut();
+            assert!(!bus.cs_high, "SPI write with CS high");
+            bus.attempts += 1;
+
+            if bus.failures_left > 0 {
+                bus.failures_left -= 1;
+                return Err(());
+            }
+
+            bus.writes.push([words[0], words[1]]);
+            bus.events.push(Event::Write([words[0], words[1]]));
+            Ok(())
+        }
+    }
+
+    struct MockCs(Rc<RefCell<Bus>>);
+
+    impl OutputPin for MockCs {
+        fn set_low(&mut self) {
+            let mut bus = self.0.borrow_mut();
+            bus.cs_high = false;
+            bus.events.push(Event::CsLow);
+        }
+        fn set_high(&mut self) {
+            let mut bus = self.0.borrow_mut();
+            bus.cs_high = true;
+            bus.events.push(Event::CsHigh);
+        }
+    }
+
+    struct MockLdac(Rc<RefCell<Bus>>);
+
+    impl OutputPin for MockLdac {
+        fn set_low(&mut self) {
+            self.0.borrow_mut().events.push(Event::LdacLow);
+        }
+        fn set_high(&mut self) {
+            self.0.borrow_mut().events.push(Event::LdacHigh);
+        }
+    }
+
+    fn dac(failures: u8) -> (Mcp4922<MockSpi, MockCs>, Rc<RefCell<Bus>>) {
+        let bus = Rc::new(RefCell::new(Bus {
+            failures_left: failures,
+            ..Bus::default()
+        }));
+        let dac = Mcp4922::new(MockSpi(bus.clone()), MockCs(bus.clone()));
+        (dac, bus)
+    }
+
+    // the events of construction are dropped
+    fn latched_dac() -> (Mcp4922<MockSpi, MockCs, MockLdac>, Rc<RefCell<Bus>>) {
+        let bus = Rc::new(RefCell::new(Bus::default()));
+        let dac = Mcp4922::new_with_ldac(
+            MockSpi(bus.clone()),
+            MockCs(bus.clone()),
+            MockLdac(bus.clone()),
+        );
+        bus.borrow_mut().events.clear();
+        (dac, bus)
+    }
+
+    fn ldac_and_writes(bus: &Rc<RefCell<Bus>>) -> Vec<Event> {
+        bus.borrow()
+            .events
+            .iter()
+            .cloned()
+            .filter(|e| (*e != Event::CsLow) && (*e != Event::CsHigh))
+            .collect()
+    }
+
+    fn spi_config() -> SpiConfig {
+        SpiConfig {
+            mode: MODE,
+            clock_hz: 13_500_000,
+            frame_bits: 8,
+            lsb_first: false,
+        }
+    }
+
+    #[test]
+    fn accepts_the_expected_spi_config() {
+        assert_eq!(check_spi_config(&spi_config()), Ok(()));
+    }
+
+    #[test]
+    fn rejects_a_wrong_spi_mode() {
+        let config = SpiConfig {
+            mode: Mode {
+                phase: Phase::CaptureOnSecondTransition,
+                polarity: Polarity::IdleLow,
+            },
+            ..spi_config()
+        };
+
+        assert_eq!(check_spi_config(&config), Err(SpiConfigError::WrongMode));
+    }
+
+    #[test]
+    fn rejects_a_fast_spi_clock() {
+        let config = SpiConfig {
+            clock_hz: MAX_SPI_CLOCK_HZ + 1,
+            ..spi_config()
+        };
+
+        assert_eq!(check_spi_config(&config), Err(SpiConfigError::ClockTooFast));
+    }
+
+    #[test]
+    fn rejects_a_wrong_spi_frame_format() {
+        let wide = SpiConfig {
+            frame_bits: 16,
+            ..spi_config()
+        };
+        let lsb_first = SpiConfig {
+            lsb_first: true,
+            ..spi_config()
+        };
+
+        assert_eq!(
+            check_spi_config(&wide),
+            Err(SpiConfigError::WrongFrameFormat)
+        );
+        assert_eq!(
+            check_spi_config(&lsb_first),
+            Err(SpiConfigError::WrongFrameFormat)
+        );
+    }
+
+    #[test]
+    fn retries_until_the_write_succeeds() {
+        let (mut dac, bus) = dac(2);
+
+        assert!(dac
+            .output_with_retries(DacOutput::clamp(0x123), Channel::ChannelB, 3)
+            .is_ok());
+        assert_eq!(bus.borrow().attempts, 3);
+        assert!(bus.borrow().cs_high);
+        assert_eq!(*dac.last_output(Channel::ChannelB).val(), 0x123);
+    }
+
+    #[test]
+    fn gives_up_after_the_retries() {
+        let (mut dac, bus) = dac(5);
+
+        assert!(dac
+            .output_with_retries(DacOutput::clamp(0x123), Channel::ChannelA, 2)
+            .is_err());
+        assert_eq!(bus.borrow().attempts, 3);
+        assert!(bus.borrow().cs_high);
+        assert_eq!(*dac.last_output(Channel::ChannelA).val(), 0);
+    }
+
+    #[test]
+    fn retries_are_limited() {
+        let (mut dac, bus) = dac(u8::max_value());
+
+        assert!(dac
+            .output_with_retries(DacOutput::clamp(1), Channel::ChannelA, u8::max_value())
+            .is_err());
+        assert_eq!(bus.borrow().attempts, 1 + u32::from(MAX_SPI_RETRIES));
+    }
+
+    #[test]
+    fn command_word_layout() {
+        let (mut dac, bus) = dac(0);
+
+        assert!(dac
+            .output(DacOutput::clamp(0xABC), Channel::ChannelB)
+            .is_ok());
+        // channel B, unbuffered, 1x gain, active
+        assert_eq!(bus.borrow().writes[0], [0b1011_0000 | 0x0A, 0xBC]);
+    }
+
+    // bit 13 of the command word, the high bit 5 of the first byte
+    fn gain_bit(word: [u8; 2]) -> bool {
+        (word[0] & (1 << 5)) != 0
+    }
+
+    #[test]
+    fn gain_bit_follows_the_selected_gain_on_both_channels() {
+        let (mut dac, bus) = dac(0);
+
+        for channel in Channel::both().iter() {
+            assert!(dac.output(DacOutput::clamp(0x800), *channel).is_ok());
+        }
+        dac.set_gain(Gain::Gain2x);
+        for channel in Channel::both().iter() {
+            assert!(dac.output(DacOutput::clamp(0x800), *channel).is_ok());
+        }
+
+        let writes = bus.borrow().writes.clone();
+        // 1 selects 1x gain, 0 selects 2x
+        assert!(gain_bit(writes[0]) && gain_bit(writes[1]));
+        assert!(!gain_bit(writes[2]) && !gain_bit(writes[3]));
+        // the channel select still differs
+        assert_eq!(writes[2][0] >> 7, 0);
+        assert_eq!(writes[3][0] >> 7, 1);
+    }
+
+    #[test]
+    fn buffer_and_gain_bits_for_every_combination() {
+        // channel A, active, data 0x5A5: only bits 14 and 13 change
+        let combinations = [
+            (VrefBuffer::Unbuffered, Gain::Gain1x, 0b0011_0000),
+            (VrefBuffer::Unbuffered, Gain::Gain2x, 0b0001_0000),
+            (VrefBuffer::Buffered, Gain::Gain1x, 0b0111_0000),
+            (VrefBuffer::Buffered, Gain::Gain2x, 0b0101_0000),
+        ];
+
+        for &(vref_buffer, gain, config_bits) in combinations.iter() {
+            let (mut dac, bus) = dac(0);
+            dac.set_vref_buffer(vref_buffer);
+            dac.set_gain(gain);
+
+            assert!(dac
+                .output(DacOutput::clamp(0x5A5), Channel::ChannelA)
+                .is_ok());
+            assert_eq!(bus.borrow().writes[0], [config_bits | 0x05, 0xA5]);
+        }
+    }
+
+    // bit 12 of the command word, 1 for active operation
+    fn active_bit(word: [u8; 2]) -> bool {
+        (word[0] & (1 << 4)) != 0
+    }
+
+    #[test]
+    fn shutdown_clears_the_active_bit() {
+        let (mut dac, bus) = dac(0);
+
+        assert!(dac
+            .output(DacOutput::clamp(0x100), Channel::ChannelA)
+            .is_ok());
+        assert!(dac.shutdown(Channel::ChannelA).is_ok());
+        assert!(dac.is_shutdown(Channel::ChannelA));
+        assert!(!dac.is_shutdown(Channel::ChannelB));
+        // still released, writes while shut down don't drive the output
+        assert!(dac
+            .output(DacOutput::clamp(0x100), Channel::ChannelA)
+            .is_ok());
+
+        let writes = bus.borrow().writes.clone();
+        assert!(active_bit(writes[0]));
+        assert_eq!(writes[1], [0b0010_0000, 0x00]);
+        assert!(!active_bit(writes[2]));
+    }
+
+    #[test]
+    fn output_ab_drives_both_channels_after_a_shutdown_and_wake() {
+        let (mut dac, bus) = dac(0);
+
+        for channel in Channel::both().iter() {
+            assert!(dac.shutdown(*channel).is_ok());
+            dac.wake(*channel);
+        }
+        bus.borrow_mut().writes.clear();
+
+        assert!(dac
+            .output_ab(DacOutput::clamp(0x321), DacOutput::clamp(0x654))
+            .is_ok());
+        assert_eq!(
+            bus.borrow().writes,
+            vec![[0b0011_0000 | 0x03, 0x21], [0b1011_0000 | 0x06, 0x54]]
+        );
+        assert_eq!(*dac.last_output(Channel::ChannelA).val(), 0x321);
+        assert_eq!(*dac.last_output(Channel::ChannelB).val(), 0x654);
+    }
+
+    #[test]
+    fn write_all_frames_each_update_in_its_own_cs_window() {
+        let (mut dac, bus) = dac(0);
+        bus.borrow_mut().events.clear();
+
+        assert!(dac
+            .write_all(&[
+                (Channel::ChannelB, DacOutput::clamp(0x222)),
+                (Channel::ChannelA, DacOutput::clamp(0x111)),
+            ])
+            .is_ok());
+        assert_eq!(
+            bus.borrow().events,
+            vec![
+                Event::CsLow,
+                Event::Write([0b1011_0000 | 0x02, 0x22]),
+                Event::CsHigh,
+                Event::CsLow,
+                Event::Write([0b0011_0000 | 0x01, 0x11]),
+                Event::CsHigh,
+            ]
+        );
+    }
+
+    #[test]
+    fn write_all_stops_at_the_first_failure_with_cs_high() {
+        let (mut dac, bus) = dac(1);
+        bus.borrow_mut().events.clear();
+
+        assert!(dac
+            .write_all(&[
+                (Channel::ChannelA, DacOutput::clamp(0x111)),
+                (Channel::ChannelB, DacOutput::clamp(0x222)),
+            ])
+            .is_err());
+        assert_eq!(bus.borrow().events, vec![Event::CsLow, Event::CsHigh]);
+        assert_eq!(bus.borrow().attempts, 1);
+    }
+
+    #[test]
+    fn three_quarter_trim_scales_a_mid_value() {
+        let (mut dac, bus) = dac(0);
+        dac.set_channel_trim(Channel::ChannelA, 3, 4);
+
+        assert!(dac
+            .output(DacOutput::clamp(0x800), Channel::ChannelA)
+            .is_ok());
+        assert!(dac
+            .output(DacOutput::clamp(0x800), Channel::ChannelB)
+            .is_ok());
+        assert_eq!(*dac.last_output(Channel::ChannelA).val(), 0x600);
+        // the other channel keeps the identity trim
+        assert_eq!(*dac.last_output(Channel::ChannelB).val(), 0x800);
+        assert_eq!(bus.borrow().writes[0], [0b0011_0000 | 0x06, 0x00]);
+    }
+
+    #[test]
+    fn trimmed_output_stays_within_twelve_bits() {
+        let (mut dac, _bus) = dac(0);
+
+        dac.set_channel_trim(Channel::ChannelA, 3, 4);
+        assert!(dac
+            .output(DacOutput::clamp(4095), Channel::ChannelA)
+            .is_ok());
+        assert_eq!(*dac.last_output(Channel::ChannelA).val(), 3071);
+
+        dac.set_channel_trim(Channel::ChannelA, u16::max_value(), 1);
+        assert!(dac
+            .output(DacOutput::clamp(4095), Channel::ChannelA)
+            .is_ok());
+        assert_eq!(*dac.last_output(Channel::ChannelA).val(), 4095);
+    }
+
+    #[test]
+    fn zero_denominator_trim_is_the_identity() {
+        let (mut dac, _bus) = dac(0);
+        dac.set_channel_trim(Channel::ChannelB, 3, 0);
+
+        assert_eq!(dac.channel_trim(Channel::ChannelB), (1, 1));
+    }
+
+    #[test]
+    fn differential_mode_writes_the_complement_on_channel_b() {
+        let (mut dac, _bus) = dac(0);
+        dac.set_mode(DacMode::Differential { midpoint: 2048 });
+
+        // channel B is computed, the given value is ignored
+        assert!(dac
+            .output_ab(DacOutput::clamp(2548), DacOutput::clamp(7))
+            .is_ok());
+        assert_eq!(*dac.last_output(Channel::ChannelA).val(), 2548);
+        assert_eq!(*dac.last_output(Channel::ChannelB).val(), 1548);
+    }
+
+    #[test]
+    fn differential_complement_saturates_at_the_rails() {
+        let (mut dac, _bus) = dac(0);
+
+        dac.set_mode(DacMode::Differential { midpoint: 3000 });
+        assert!(dac
+            .output_ab(DacOutput::clamp(1000), DacOutput::clamp(0))
+            .is_ok());
+        assert_eq!(*dac.last_output(Channel::ChannelB).val(), 4095);
+
+        dac.set_mode(DacMode::Differential { midpoint: 1000 });
+        assert!(dac
+            .output_ab(DacOutput::clamp(3000), DacOutput::clamp(0))
+            .is_ok());
+        assert_eq!(*dac.last_output(Channel::ChannelB).val(), 0);
+    }
+
+    // the 12 bit data of each write, in order
+    fn written_steps(bus: &Rc<RefCell<Bus>>) -> Vec<u16> {
+        bus.borrow()
+            .writes
+            .iter()
+            .map(|w| (u16::from(w[0] & 0x0F) << 8) | u16::from(w[1]))
+            .collect()
+    }
+
+    #[test]
+    fn dithered_outputs_average_to_the_fractional_value() {
+        let (mut dac, bus) = dac(0);
+        dac.set_dithering(true);
+
+        let loops = 100;
+        for _ in 0..loops {
+            assert!(dac.output_fractional(Channel::ChannelA, 1000.3).is_ok());
+        }
+
+        let steps = written_steps(&bus);
+        // only the two adjacent codes are used
+        assert!(steps.iter().all(|s| (*s == 1000) || (*s == 1001)));
+        let average = steps.iter().map(|s| f32::from(*s)).sum::<f32>() / loops as f32;
+        // within one LSB
+        assert!((average - 1000.3).abs() < 1.0);
+    }
+
+    #[test]
+    fn fractional_output_rounds_without_dithering() {
+        let (mut dac, bus) = dac(0);
+        assert!(!dac.dithering());
+
+        for value in [1000.3, 1000.5, 5000.0, -1.0].iter() {
+            assert!(dac.output_fractional(Channel::ChannelB, *value).is_ok());
+        }
+        assert!(dac
+            .output_fractional(Channel::ChannelB, ::std::f32::NAN)
+            .is_ok());
+
+        assert_eq!(written_steps(&bus), vec![1000, 1001, 4095, 0, 0]);
+    }
+
+    #[test]
+    fn channels_carry_their_own_dither_residual() {
+        let (mut dac, bus) = dac(0);
+        dac.set_dithering(true);
+
+        for _ in 0..4 {
+            assert!(dac.output_fractional(Channel::ChannelA, 10.5).is_ok());
+            assert!(dac.output_fractional(Channel::ChannelB, 20.0).is_ok());
+        }
+
+        assert_eq!(written_steps(&bus), vec![10, 20, 11, 20, 10, 20, 11, 20]);
+    }
+
+    #[test]
+    fn latched_write_pulses_ldac_once_after_both_channels() {
+        let (mut dac, bus) = latched_dac();
+
+        assert!(dac
+            .output_ab_latched(DacOutput::clamp(0x123), DacOutput::clamp(0x456))
+            .is_ok());
+        assert_eq!(
+            ldac_and_writes(&bus),
+            vec![
+                Event::LdacHigh,
+                Event::Write([0b0011_0000 | 0x01, 0x23]),
+                Event::Write([0b1011_0000 | 0x04, 0x56]),
+                Event::LdacLow,
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_latched_write_still_returns_ldac_low() {
+        let (mut dac, bus) = latched_dac();
+        bus.borrow_mut().failures_left = 1;
+
+        assert!(dac
+            .output_ab_latched(DacOutput::clamp(0x123), DacOutput::clamp(0x456))
+            .is_err());
+        assert_eq!(ldac_and_writes(&bus), vec![Event::LdacHigh, Event::LdacLow]);
+    }
+
+    fn reads_back_wrong(_channel: Channel, _data: DacOutput) -> bool {
+        false
+    }
+
+    fn reads_back_right(_channel: Channel, _data: DacOutput) -> bool {
+        true
+    }
+
+    #[test]
+    fn consecutive_mismatches_set_the_verification_fault() {
+        let (mut dac, _bus) = dac(0);
+        dac.set_output_verifier(Some(reads_back_wrong));
+
+        for _ in 1..MAX_VERIFICATION_FAILURES {
+            assert!(dac.output(DacOutput::clamp(1), Channel::ChannelA).is_ok());
+            assert!(!dac.has_verification_fault());
+        }
+
+        assert!(dac.output(DacOutput::clamp(1), Channel::ChannelA).is_ok());
+        assert!(dac.has_verification_fault());
+        assert_eq!(dac.verification_failures(), MAX_VERIFICATION_FAILURES);
+
+        // latched until cleared, a good read doesn't clear it
+        dac.set_output_verifier(Some(reads_back_right));
+        assert!(dac.output(DacOutput::clamp(1), Channel::ChannelA).is_ok());
+        assert!(dac.has_verification_fault());
+
+        dac.clear_verification_fault();
+        assert!(!dac.has_verification_fault());
+        assert_eq!(dac.verification_failures(), 0);
+    }
+
+    #[test]
+    fn a_good_read_resets_the_failure_count() {
+        let (mut dac, _bus) = dac(0);
+
+        dac.set_output_verifier(Some(reads_back_wrong));
+        for _ in 1..MAX_VERIFICATION_FAILURES {
+            assert!(dac.output(DacOutput::clamp(1), Channel::ChannelA).is_ok());
+        }
+
+        dac.set_output_verifier(Some(reads_back_right));
+        assert!(dac.output(DacOutput::clamp(1), Channel::ChannelA).is_ok());
+        assert_eq!(dac.verification_failures(), 0);
+
+        dac.set_output_verifier(Some(reads_back_wrong));
+        assert!(dac.output(DacOutput::clamp(1), Channel::ChannelA).is_ok());
+        assert!(!dac.has_verification_fault());
+    }
+}
@@ -4,15 +4,25 @@ pub trait DtcBitfield {
     fn clear_all(&mut self);
     fn check(&self, dtc: u8) -> bool;
     fn are_any_set(&self) -> bool;
+    fn count(&self) -> u32;
+
+    fn is_set(&self, dtc: u8) -> bool {
+        self.check(dtc)
+    }
+}
+
+// a code past the width of the bitfield has no bit, it is never set
+fn dtc_bit(dtc: u8) -> u8 {
+    1u8.checked_shl(u32::from(dtc)).unwrap_or(0)
 }
 
 impl DtcBitfield for u8 {
     fn set(&mut self, dtc: u8) {
-        *self |= 1 << dtc;
+        *self |= dtc_bit(dtc);
     }
 
     fn clear(&mut self, dtc: u8) {
-        *self &= !(1 << dtc);
+        *self &= !dtc_bit(dtc);
     }
 
     fn clear_all(&mut self) {
@@ -20,10 +30,64 @@ impl DtcBitfield for u8 {
     }
 
     fn check(&self, dtc: u8) -> bool {
-        *self & (1 << dtc) != 0
+        *self & dtc_bit(dtc) != 0
     }
 
     fn are_any_set(&self) -> bool {
         *self > 0
     }
+
+    fn count(&self) -> u32 {
+        self.count_ones()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_and_clear_single_bits() {
+        let mut dtcs: u8 = 0;
+        dtcs.set(1);
+        dtcs.set(7);
+        assert!(dtcs.check(1) && dtcs.is_set(7));
+        assert!(!dtcs.check(0));
+        assert_eq!(dtcs.count(), 2);
+
+        dtcs.clear(1);
+        assert!(!dtcs.check(1));
+        assert!(dtcs.are_any_set());
+
+        dtcs.clear_all();
+        assert!(!dtcs.are_any_set());
+    }
+
+    #[test]
+    fn clear_one_of_several_codes() {
+        let mut dtcs: u8 = 0;
+        for dtc in [0, 2, 3, 6].iter() {
+            dtcs.set(*dtc);
+        }
+        assert_eq!(dtcs.count(), 4);
+
+        dtcs.clear(3);
+        dtcs.clear(5);
+        assert_eq!(dtcs.count(), 3);
+        assert!(dtcs.is_set(0) && dtcs.is_set(2) && dtcs.is_set(6));
+        assert!(!dtcs.is_set(3) && !dtcs.is_set(5));
+    }
+
+    #[test]
+    fn codes_past_the_bitfield_are_ignored() {
+        let mut dtcs: u8 = 0;
+        dtcs.set(8);
+        dtcs.set(200);
+        assert!(!dtcs.are_any_set());
+        assert!(!dtcs.check(8));
+
+        dtcs.set(7);
+        dtcs.clear(8);
+        assert!(dtcs.check(7));
+    }
 }
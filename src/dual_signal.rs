@@ -1,9 +1,36 @@
 use board::DAC_SAMPLE_AVERAGE_COUNT;
 use num;
+use signal_range::SignalRange;
+
+/// How `update` derives each channel's value from its samples
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FilterMode {
+    /// The latest (possibly oversampled) reading
+    Mean,
+    /// Median of the last three readings, rejects single sample spikes
+    Median3,
+}
+
+impl Default for FilterMode {
+    fn default() -> Self {
+        FilterMode::Mean
+    }
+}
 
 pub struct DualSignal<T: HighLowReader> {
     high: u16,
     low: u16,
+    high_offset: i16,
+    low_offset: i16,
+    high_low_ratio: f32,
+    oversample_count: u8,
+    filter_mode: FilterMode,
+    high_history: [u16; 3],
+    low_history: [u16; 3],
+    history_index: usize,
+    history_primed: bool,
+    operating_range: Option<SignalRange>,
+    learning_range: Option<SignalRange>,
     reader: T,
 }
 
@@ -15,15 +42,167 @@ where
         DualSignal {
             high,
             low,
+            high_offset: 0,
+            low_offset: 0,
+            high_low_ratio: 1.0,
+            oversample_count: 1,
+            filter_mode: FilterMode::default(),
+            high_history: [0; 3],
+            low_history: [0; 3],
+            history_index: 0,
+            history_primed: false,
+            operating_range: None,
+            learning_range: None,
             reader: high_low_reader,
         }
     }
 
+    /// Sets the offsets applied to the raw ADC readings to zero out sensor
+    /// bias, the calibrated values saturate to the u16 range
+    pub fn set_calibration(&mut self, high_offset: i16, low_offset: i16) {
+        self.high_offset = high_offset;
+        self.low_offset = low_offset;
+    }
+
+    pub fn calibration(&self) -> (i16, i16) {
+        (self.high_offset, self.low_offset)
+    }
+
+    /// Sets the expected ratio of the high channel to the low channel used by
+    /// the plausibility check, defaults to 1.0
+    pub fn set_high_low_ratio(&mut self, ratio: f32) {
+        self.high_low_ratio = ratio;
+    }
+
+    /// Sets the number of samples averaged per channel by `update`,
+    /// defaults to 1 (a single read per channel)
+    pub fn set_oversampling(&mut self, samples: u8) {
+        self.oversample_count = samples;
+    }
+
+    /// Selects how `update` filters the readings, defaults to `FilterMode::Mean`
+    pub fn set_filter_mode(&mut self, filter_mode: FilterMode) {
+        self.filter_mode = filter_mode;
+    }
+
+    pub fn filter_mode(&self) -> FilterMode {
+        self.filter_mode
+    }
+
+    /// Returns true when the high reading diverges from the value expected
+    /// from the low reading by more than `tolerance` steps
+    pub fn plausibility_fault(&self, tolerance: u16) -> bool {
+        let expected_high = f32::from(self.low) * self.high_low_ratio;
+        let error = f32::from(self.high) - expected_high;
+        let error = if error < 0.0 { -error } else { error };
+
+        error > f32::from(tolerance)
+    }
+
+    /// Returns true when the average of the readings is more than `tolerance`
+    /// steps from `expected_average`, for complementary sensors whose
+    /// channels move in opposite directions
+    pub fn average_plausibility_fault(&self, expected_average: u32, tolerance: u32) -> bool {
+        let average = self.average();
+        let error = if average > expected_average {
+            average - expected_average
+        } else {
+            expected_average - average
+        };
+
+        error > tolerance
+    }
+
+    /// Sets the range the channels are expected to stay in, checked by
+    /// `is_outside_operating_range`. None, the default, disables the check.
+    pub fn set_operating_range(&mut self, range: Option<SignalRange>) {
+        self.operating_range = range;
+    }
+
+    pub fn operating_range(&self) -> Option<SignalRange> {
+        self.operating_range
+    }
+
+    /// Returns true when a channel is more than `margin` steps outside the
+    /// operating range, always false without one
+    pub fn is_outside_operating_range(&self, margin: u16) -> bool {
+        match self.operating_range {
+            Some(ref range) => range.excludes(self.high, self.low, margin),
+            None => false,
+        }
+    }
+
+    /// Starts recording the extremes seen by `update`, restarting any
+    /// learning already in progress
+    pub fn start_range_learning(&mut self) {
+        self.learning_range = Some(SignalRange::new());
+    }
+
+    pub fn is_learning_range(&self) -> bool {
+        self.learning_range.is_some()
+    }
+
+    /// Stops learning and makes the recorded extremes the operating range.
+    /// Returns None, keeping the previous operating range, if learning
+    /// wasn't started or no sample was recorded.
+    pub fn finish_range_learning(&mut self) -> Option<SignalRange> {
+        match self.learning_range.take() {
+            Some(range) if range.is_valid() => {
+                self.operating_range = Some(range);
+                Some(range)
+            }
+            _ => None,
+        }
+    }
+
     pub fn update(&mut self) {
-        self.high = self.reader.read_high();
-        self.low = self.reader.read_low();
+        let high = apply_offset(
+            self.reader.read_high_averaged(self.oversample_count),
+            self.high_offset,
+        );
+        let low = apply_offset(
+            self.reader.read_low_averaged(self.oversample_count),
+            self.low_offset,
+        );
+
+        self.push_history(high, low);
+
+        match self.filter_mode {
+            FilterMode::Mean => {
+                self.high = high;
+                self.low = low;
+            }
+            FilterMode::Median3 => {
+                self.high = median3(&self.high_history);
+                self.low = median3(&self.low_history);
+            }
+        }
+
+        if let Some(ref mut range) = self.learning_range {
+            range.record(self.high, self.low);
+        }
     }
 
+    fn push_history(&mut self, high: u16, low: u16) {
+        // fill the history on the first sample so the median doesn't start at zero
+        if !self.history_primed {
+            self.high_history = [high; 3];
+            self.low_history = [low; 3];
+            self.history_primed = true;
+        } else {
+            self.high_history[self.history_index] = high;
+            self.low_history[self.history_index] = low;
+        }
+
+        self.history_index = (self.history_index + 1) % 3;
+    }
+
+    /// Takes a fresh reading of both channels, bypassing the filter
+    ///
+    /// Must be called immediately before the DAC is set to `high()`/`low()`
+    /// on an enable or disable transition. The values stored by the last
+    /// `update` may be stale by then and would step the signal seen by the
+    /// vehicle.
     // not sure if the averaging is needed, we might be able to just use a
     // single read with large Cycles480 sample time?
     // https://github.com/jonlamb-gh/oscc/blob/devel/firmware/common/libs/dac/oscc_dac.cpp#L17
@@ -31,16 +210,19 @@ where
         let mut low: u32 = 0;
         let mut high: u32 = 0;
 
+        // interleaved so both channels are sampled over the same window
         for _ in 0..DAC_SAMPLE_AVERAGE_COUNT {
             low += u32::from(self.reader.read_low());
-        }
-
-        for _ in 0..DAC_SAMPLE_AVERAGE_COUNT {
             high += u32::from(self.reader.read_high());
         }
 
-        self.low = (low / DAC_SAMPLE_AVERAGE_COUNT) as _;
-        self.high = (high / DAC_SAMPLE_AVERAGE_COUNT) as _;
+        self.low = apply_offset((low / DAC_SAMPLE_AVERAGE_COUNT) as _, self.low_offset);
+        self.high = apply_offset((high / DAC_SAMPLE_AVERAGE_COUNT) as _, self.high_offset);
+
+        // restart the median filter from this known good reading
+        self.history_primed = false;
+        let (high, low) = (self.high, self.low);
+        self.push_history(high, low);
     }
 
     pub fn average(&self) -> u32 {
@@ -58,9 +240,158 @@ where
     pub fn low(&self) -> u16 {
         self.low
     }
+
+    /// The sensor the signals are read from
+    pub fn reader(&self) -> &T {
+        &self.reader
+    }
+}
+
+fn median3(samples: &[u16; 3]) -> u16 {
+    let (a, b, c) = (samples[0], samples[1], samples[2]);
+    a.min(b).max(a.max(b).min(c))
+}
+
+fn apply_offset(raw: u16, offset: i16) -> u16 {
+    num::clamp(
+        i32::from(raw) + i32::from(offset),
+        0,
+        i32::from(u16::max_value()),
+    ) as u16
 }
 
 pub trait HighLowReader {
     fn read_high(&self) -> u16;
     fn read_low(&self) -> u16;
+
+    /// Mean of `samples` high readings, zero samples is treated as one
+    fn read_high_averaged(&self, samples: u8) -> u16 {
+        let samples = u32::from(samples.max(1));
+        let mut sum: u32 = 0;
+        for _ in 0..samples {
+            sum += u32::from(self.read_high());
+        }
+        (sum / samples) as u16
+    }
+
+    /// Mean of `samples` low readings, zero samples is treated as one
+    fn read_low_averaged(&self, samples: u8) -> u16 {
+        let samples = u32::from(samples.max(1));
+        let mut sum: u32 = 0;
+        for _ in 0..samples {
+            sum += u32::from(self.read_low());
+        }
+        (sum / samples) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use sim_board::SimSensor;
+
+    // alternates between two readings on every sample of either channel
+    struct AlternatingReader {
+        samples: [u16; 2],
+        next: Cell<usize>,
+    }
+
+    impl AlternatingReader {
+        fn read(&self) -> u16 {
+            let index = self.next.get();
+            self.next.set((index + 1) % 2);
+            self.samples[index]
+        }
+    }
+
+    impl HighLowReader for AlternatingReader {
+        fn read_high(&self) -> u16 {
+            self.read()
+        }
+        fn read_low(&self) -> u16 {
+            self.read()
+        }
+    }
+
+    fn signal(high: u16, low: u16) -> DualSignal<SimSensor> {
+        DualSignal::new(0, 0, SimSensor::new(high, low))
+    }
+
+    #[test]
+    fn calibration_offsets_shift_the_average() {
+        let mut signal = signal(1000, 500);
+        signal.update();
+        assert_eq!(signal.average(), 750);
+
+        signal.set_calibration(20, -10);
+        signal.update();
+        assert_eq!((signal.high(), signal.low()), (1020, 490));
+        assert_eq!(signal.average(), 755);
+
+        signal.prevent_signal_discontinuity();
+        assert_eq!(signal.average(), 755);
+    }
+
+    #[test]
+    fn calibrated_values_saturate() {
+        let mut signal = signal(10, u16::max_value() - 10);
+        signal.set_calibration(-20, 20);
+        signal.update();
+        assert_eq!((signal.high(), signal.low()), (0, u16::max_value()));
+    }
+
+    #[test]
+    fn oversampling_averages_alternating_samples_to_the_midpoint() {
+        let reader = AlternatingReader {
+            samples: [1000, 2000],
+            next: Cell::new(0),
+        };
+        let mut signal = DualSignal::new(0, 0, reader);
+
+        // a single sample passes the noise straight through
+        signal.update();
+        assert_eq!((signal.high(), signal.low()), (1000, 2000));
+
+        signal.set_oversampling(4);
+        signal.update();
+        assert_eq!((signal.high(), signal.low()), (1500, 1500));
+        assert_eq!(signal.average(), 1500);
+    }
+
+    // (mean, median) filtered (high, low) after each of `readings`
+    fn filtered(readings: &[(u16, u16)]) -> Vec<((u16, u16), (u16, u16))> {
+        let mut mean = signal(0, 0);
+        let mut median = signal(0, 0);
+        median.set_filter_mode(FilterMode::Median3);
+
+        readings
+            .iter()
+            .map(|&(high, low)| {
+                mean.reader().set(high, low);
+                median.reader().set(high, low);
+                mean.update();
+                median.update();
+                ((mean.high(), mean.low()), (median.high(), median.low()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn median_filter_rejects_a_single_sample_spike() {
+        let outputs = filtered(&[(1000, 500), (1000, 500), (4000, 3500), (1000, 500)]);
+
+        // the mean passes the spike straight through
+        assert_eq!(outputs[2].0, (4000, 3500));
+        assert_eq!(outputs[2].1, (1000, 500));
+        assert_eq!(outputs[3].1, (1000, 500));
+    }
+
+    #[test]
+    fn median_filter_follows_a_sustained_step() {
+        let outputs = filtered(&[(1000, 500), (2000, 1000), (2000, 1000)]);
+
+        assert_eq!(outputs[1].1, (1000, 500));
+        assert_eq!(outputs[2].1, (2000, 1000));
+    }
 }
@@ -1,23 +1,136 @@
 // https://github.com/jonlamb-gh/oscc/blob/master/firmware/common/libs/fault_check/oscc_check.cpp
 // https://github.com/jonlamb-gh/oscc/blob/master/firmware/common/libs/fault_check/oscc_check.h#L19
 
+use config::SIGNAL_RANGE_MARGIN;
 use dual_signal::{DualSignal, HighLowReader};
 use embedded_hal::timer::CountDown;
 use nucleo_f767zi::hal::timer::OnePulse;
+use system_clock;
+use vehicle::FAULT_HYSTERESIS;
 
-pub struct FaultCondition<TIMER> {
+pub struct FaultCondition {
+    monitoring_active: bool,
+    condition_start_time: u32,
+    hysteresis_ms: u32,
+}
+
+impl FaultCondition {
+    /// Uses the default FAULT_HYSTERESIS duration
+    pub const fn new() -> Self {
+        FaultCondition::with_hysteresis(FAULT_HYSTERESIS)
+    }
+
+    /// `hysteresis_ms` is the duration used by `condition_exceeded_duration`
+    /// and the voltage checks
+    pub const fn with_hysteresis(hysteresis_ms: u32) -> Self {
+        FaultCondition {
+            monitoring_active: false,
+            condition_start_time: 0,
+            hysteresis_ms,
+        }
+    }
+
+    pub fn hysteresis(&self) -> u32 {
+        self.hysteresis_ms
+    }
+
+    /// Uses the hysteresis this condition was created with
+    pub fn condition_exceeded_duration(&mut self, condition_active: bool) -> bool {
+        let hysteresis_ms = self.hysteresis_ms;
+        self.condition_exceeded_ms(condition_active, hysteresis_ms)
+    }
+
+    /// Returns true once the condition has been continuously active for
+    /// `duration_ms`, measured with the system clock so the result does not
+    /// depend on the control loop rate.
+    ///
+    /// While the condition remains active, this returns true again every
+    /// `duration_ms`.
+    pub fn condition_exceeded_ms(&mut self, condition_active: bool, duration_ms: u32) -> bool {
+        let mut faulted = false;
+
+        if !condition_active {
+            /*
+             * If a fault condition is not active, update the state to clear
+             * the condition active flag and reset the last detection time.
+             */
+            self.monitoring_active = false;
+        } else {
+            let now = system_clock::get_timestamp_ms();
+
+            if !self.monitoring_active {
+                /* We just detected a condition that may lead to a fault. Update
+                 * the state to track that the condition is active and store the
+                 * first time of detection.
+                 */
+                self.monitoring_active = true;
+                self.condition_start_time = now;
+            }
+
+            if system_clock::is_timeout(self.condition_start_time, now, duration_ms) {
+                /* The fault condition has been active for longer than the maximum
+                 * acceptable duration.
+                 */
+                faulted = true;
+                self.condition_start_time = now;
+            }
+        }
+
+        faulted
+    }
+
+    /// Debounces the signal reading grounded or outside its learned
+    /// operating range
+    pub fn check_voltage_grounded<T: HighLowReader>(&mut self, signal: &DualSignal<T>) -> bool {
+        let condition_active = is_signal_invalid(signal);
+
+        self.condition_exceeded_duration(condition_active)
+    }
+
+    /// Debounces both channels reading above `threshold`, which indicates
+    /// the sensor is shorted to the supply rail
+    pub fn check_voltage_high<T: HighLowReader>(
+        &mut self,
+        signal: &DualSignal<T>,
+        threshold: u16,
+    ) -> bool {
+        let condition_active = is_voltage_high(signal, threshold);
+
+        self.condition_exceeded_duration(condition_active)
+    }
+
+    /// Debounces the invalid signal and plausibility conditions as a single
+    /// sensor fault, use `is_signal_invalid` to tell them apart once latched
+    pub fn check_voltage_grounded_or_mismatched<T: HighLowReader>(
+        &mut self,
+        signal: &DualSignal<T>,
+        tolerance: u16,
+    ) -> bool {
+        let condition_active = is_signal_invalid(signal) || signal.plausibility_fault(tolerance);
+
+        self.condition_exceeded_duration(condition_active)
+    }
+}
+
+/// The original one-pulse timer based debounce, kept for callers that
+/// still own a spare hardware timer.
+///
+/// New code should use `FaultCondition`, which measures the duration with
+/// the system clock and doesn't tie up a timer per condition.
+pub struct TimerFaultCondition<TIMER> {
     monitoring_active: bool,
     timer: TIMER,
 }
 
-impl<TIMER> FaultCondition<TIMER>
+impl<TIMER> TimerFaultCondition<TIMER>
 where
     TIMER: CountDown + OnePulse,
 {
+    /// The duration is the period `timer` was started with
     pub fn new(mut timer: TIMER) -> Self {
         timer.reconfigure_one_pulse_mode();
 
-        FaultCondition {
+        TimerFaultCondition {
             monitoring_active: false,
             timer,
         }
@@ -53,10 +166,231 @@ where
 
         faulted
     }
+}
 
-    pub fn check_voltage_grounded<T: HighLowReader>(&mut self, signal: &DualSignal<T>) -> bool {
-        let condition_active = (signal.high() == 0) || (signal.low() == 0);
+pub fn is_voltage_grounded<T: HighLowReader>(signal: &DualSignal<T>) -> bool {
+    (signal.high() == 0) || (signal.low() == 0)
+}
 
-        self.condition_exceeded_duration(condition_active)
+/// Grounded, or more than SIGNAL_RANGE_MARGIN outside the learned
+/// operating range
+pub fn is_signal_invalid<T: HighLowReader>(signal: &DualSignal<T>) -> bool {
+    is_voltage_grounded(signal) || signal.is_outside_operating_range(SIGNAL_RANGE_MARGIN)
+}
+
+pub fn is_voltage_high<T: HighLowReader>(signal: &DualSignal<T>, threshold: u16) -> bool {
+    (signal.high() > threshold) && (signal.low() > threshold)
+}
+
+/// Outside `min..=max`, or moved more than `max_delta` since the previous
+/// reading, for single-ended sensors with no redundant channel to compare
+pub fn is_reading_implausible(
+    reading: u16,
+    previous: Option<u16>,
+    min: u16,
+    max: u16,
+    max_delta: u16,
+) -> bool {
+    let out_of_range = (reading < min) || (reading > max);
+    let too_fast = match previous {
+        Some(previous) => {
+            let delta = if reading > previous {
+                reading - previous
+            } else {
+                previous - reading
+            };
+            delta > max_delta
+        }
+        None => false,
+    };
+
+    out_of_range || too_fast
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dual_signal::DualSignal;
+    use nb;
+    use system_clock::TestClock;
+    use void::Void;
+
+    /// Expires `period_ms` after the last reset, as read from the test clock
+    struct MockTimer {
+        period_ms: u32,
+        reset_at: u32,
+        one_pulse: bool,
+    }
+
+    impl MockTimer {
+        fn new(period_ms: u32) -> Self {
+            MockTimer {
+                period_ms,
+                reset_at: 0,
+                one_pulse: false,
+            }
+        }
+    }
+
+    impl CountDown for MockTimer {
+        type Time = u32;
+
+        fn start<T>(&mut self, count: T)
+        where
+            T: Into<u32>,
+        {
+            self.period_ms = count.into();
+            self.reset_at = system_clock::get_timestamp_ms();
+        }
+
+        fn wait(&mut self) -> nb::Result<(), Void> {
+            let now = system_clock::get_timestamp_ms();
+            if system_clock::is_timeout(self.reset_at, now, self.period_ms) {
+                Ok(())
+            } else {
+                Err(nb::Error::WouldBlock)
+            }
+        }
+    }
+
+    impl OnePulse for MockTimer {
+        fn reconfigure_one_pulse_mode(&mut self) {
+            self.one_pulse = true;
+        }
+
+        fn reset(&mut self) {
+            self.reset_at = system_clock::get_timestamp_ms();
+        }
+    }
+
+    struct FixedReader(u16, u16);
+
+    impl HighLowReader for FixedReader {
+        fn read_high(&self) -> u16 {
+            self.0
+        }
+        fn read_low(&self) -> u16 {
+            self.1
+        }
+    }
+
+    fn signal(high: u16, low: u16) -> DualSignal<FixedReader> {
+        let mut signal = DualSignal::new(0, 0, FixedReader(high, low));
+        signal.update();
+        signal
+    }
+
+    #[test]
+    fn fault_needs_the_full_hysteresis() {
+        let clock = TestClock::take();
+        let mut condition = FaultCondition::with_hysteresis(50);
+
+        assert!(!condition.condition_exceeded_duration(true));
+        clock.advance_ms(49);
+        assert!(!condition.condition_exceeded_duration(true));
+        clock.advance_ms(1);
+        assert!(condition.condition_exceeded_duration(true));
+    }
+
+    #[test]
+    fn inactive_condition_restarts_the_debounce() {
+        let clock = TestClock::take();
+        let mut condition = FaultCondition::with_hysteresis(50);
+
+        assert!(!condition.condition_exceeded_duration(true));
+        clock.advance_ms(40);
+        assert!(!condition.condition_exceeded_duration(false));
+        assert!(!condition.condition_exceeded_duration(true));
+        clock.advance_ms(40);
+        assert!(!condition.condition_exceeded_duration(true));
+    }
+
+    #[test]
+    fn sustained_condition_fires_every_duration() {
+        let clock = TestClock::take();
+        let mut condition = FaultCondition::with_hysteresis(10);
+
+        assert!(!condition.condition_exceeded_duration(true));
+        clock.advance_ms(10);
+        assert!(condition.condition_exceeded_duration(true));
+        assert!(!condition.condition_exceeded_duration(true));
+        clock.advance_ms(10);
+        assert!(condition.condition_exceeded_duration(true));
+    }
+
+    #[test]
+    fn grounded_channel_is_invalid() {
+        assert!(is_voltage_grounded(&signal(0, 400)));
+        assert!(is_voltage_grounded(&signal(400, 0)));
+        assert!(!is_signal_invalid(&signal(800, 400)));
+    }
+
+    #[test]
+    fn both_channels_high_is_a_short() {
+        assert!(is_voltage_high(&signal(4000, 3900), 3800));
+        assert!(!is_voltage_high(&signal(4000, 400), 3800));
+    }
+
+    #[test]
+    fn reading_outside_the_range_is_implausible() {
+        assert!(is_reading_implausible(99, None, 100, 3995, 400));
+        assert!(is_reading_implausible(3996, None, 100, 3995, 400));
+        assert!(!is_reading_implausible(100, None, 100, 3995, 400));
+        assert!(!is_reading_implausible(3995, None, 100, 3995, 400));
+    }
+
+    #[test]
+    fn reading_changing_too_fast_is_implausible() {
+        assert!(!is_reading_implausible(1400, Some(1000), 100, 3995, 400));
+        assert!(!is_reading_implausible(600, Some(1000), 100, 3995, 400));
+        assert!(is_reading_implausible(1401, Some(1000), 100, 3995, 400));
+        assert!(is_reading_implausible(599, Some(1000), 100, 3995, 400));
+    }
+
+    #[test]
+    fn explicit_duration_overrides_the_hysteresis() {
+        let clock = TestClock::take();
+        let mut condition = FaultCondition::with_hysteresis(50);
+
+        assert!(!condition.condition_exceeded_ms(true, 20));
+        clock.advance_ms(19);
+        assert!(!condition.condition_exceeded_ms(true, 20));
+        clock.advance_ms(1);
+        assert!(condition.condition_exceeded_ms(true, 20));
+    }
+
+    #[test]
+    fn timer_condition_puts_the_timer_in_one_pulse_mode() {
+        let condition = TimerFaultCondition::new(MockTimer::new(50));
+
+        assert!(condition.timer.one_pulse);
+    }
+
+    #[test]
+    fn timer_condition_needs_the_full_period() {
+        let clock = TestClock::take();
+        let mut condition = TimerFaultCondition::new(MockTimer::new(50));
+
+        assert!(!condition.condition_exceeded_duration(true));
+        clock.advance_ms(49);
+        assert!(!condition.condition_exceeded_duration(true));
+        clock.advance_ms(1);
+        assert!(condition.condition_exceeded_duration(true));
+        assert!(!condition.condition_exceeded_duration(true));
+    }
+
+    #[test]
+    fn inactive_timer_condition_restarts_the_debounce() {
+        let clock = TestClock::take();
+        let mut condition = TimerFaultCondition::new(MockTimer::new(50));
+
+        assert!(!condition.condition_exceeded_duration(true));
+        clock.advance_ms(40);
+        assert!(!condition.condition_exceeded_duration(false));
+        assert!(!condition.condition_exceeded_duration(true));
+        clock.advance_ms(40);
+        assert!(!condition.condition_exceeded_duration(true));
+        clock.advance_ms(10);
+        assert!(condition.condition_exceeded_duration(true));
     }
 }
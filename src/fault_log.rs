@@ -0,0 +1,191 @@
+// Fault events kept in the backup SRAM, which survives resets (and power
+// loss while VBAT is supplied)
+
+/// Number of fault events kept, the oldest is overwritten when full
+pub const FAULT_LOG_CAPACITY: usize = 16;
+
+// marks the backup SRAM as holding a log, anything else is a cold start.
+// Changes with the record layout, so an older log is dropped, not misread.
+const FAULT_LOG_MAGIC: u32 = 0x0CCF_A018;
+
+/// One stored fault event
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FaultRecord {
+    /// Increments with every stored event, across resets
+    pub sequence: u32,
+    /// System clock time since the reset the event happened in [ms]
+    pub timestamp_ms: u32,
+    pub fault_origin_id: u32,
+    pub dtcs: u8,
+    pub extended_dtcs: u8,
+}
+
+const EMPTY_RECORD: FaultRecord = FaultRecord {
+    sequence: 0,
+    timestamp_ms: 0,
+    fault_origin_id: 0,
+    dtcs: 0,
+    extended_dtcs: 0,
+};
+
+/// Layout of the log in the backup SRAM
+#[repr(C)]
+pub struct FaultLogStorage {
+    magic: u32,
+    next_sequence: u32,
+    // index of the next record written
+    head: u32,
+    len: u32,
+    records: [FaultRecord; FAULT_LOG_CAPACITY],
+}
+
+impl FaultLogStorage {
+    pub const fn new() -> Self {
+        FaultLogStorage {
+            magic: FAULT_LOG_MAGIC,
+            next_sequence: 0,
+            head: 0,
+            len: 0,
+            records: [EMPTY_RECORD; FAULT_LOG_CAPACITY],
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        (self.magic == FAULT_LOG_MAGIC)
+            && ((self.head as usize) < FAULT_LOG_CAPACITY)
+            && ((self.len as usize) <= FAULT_LOG_CAPACITY)
+    }
+}
+
+/// Ring buffer of the last FAULT_LOG_CAPACITY fault events
+pub struct FaultLog {
+    storage: &'static mut FaultLogStorage,
+}
+
+impl FaultLog {
+    /// Keeps the events already in `storage`, or starts an empty log if it
+    /// does not hold a valid log
+    pub fn new(storage: &'static mut FaultLogStorage) -> Self {
+        if !storage.is_valid() {
+            *storage = FaultLogStorage::new();
+        }
+
+        FaultLog { storage }
+    }
+
+    /// Stores a fault event, a repeat of the most recent event is not stored
+    /// again so a persisting fault doesn't flush the log
+    pub fn store(&mut self, fault_origin_id: u32, dtcs: u8, extended_dtcs: u8, timestamp_ms: u32) {
+        if let Some(last) = self.last() {
+            if (last.fault_origin_id == fault_origin_id)
+                && (last.dtcs == dtcs)
+                && (last.extended_dtcs == extended_dtcs)
+            {
+                return;
+            }
+        }
+
+        let head = self.storage.head as usize;
+        self.storage.records[head] = FaultRecord {
+            sequence: self.storage.next_sequence,
+            timestamp_ms,
+            fault_origin_id,
+            dtcs,
+            extended_dtcs,
+        };
+
+        self.storage.next_sequence = self.storage.next_sequence.wrapping_add(1);
+        self.storage.head = ((head + 1) % FAULT_LOG_CAPACITY) as u32;
+        if (self.storage.len as usize) < FAULT_LOG_CAPACITY {
+            self.storage.len += 1;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.storage.len as usize
+    }
+
+    /// Most recently stored event
+    pub fn last(&self) -> Option<FaultRecord> {
+        if self.storage.len == 0 {
+            None
+        } else {
+            let index = (self.storage.head as usize + FAULT_LOG_CAPACITY - 1) % FAULT_LOG_CAPACITY;
+            Some(self.storage.records[index])
+        }
+    }
+
+    /// Stored events, oldest first
+    pub fn records(&self) -> FaultLogRecords {
+        let len = self.storage.len as usize;
+        FaultLogRecords {
+            records: &self.storage.records,
+            next: (self.storage.head as usize + FAULT_LOG_CAPACITY - len) % FAULT_LOG_CAPACITY,
+            remaining: len,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.storage.head = 0;
+        self.storage.len = 0;
+    }
+}
+
+pub struct FaultLogRecords<'a> {
+    records: &'a [FaultRecord; FAULT_LOG_CAPACITY],
+    next: usize,
+    remaining: usize,
+}
+
+impl<'a> Iterator for FaultLogRecords<'a> {
+    type Item = FaultRecord;
+
+    fn next(&mut self) -> Option<FaultRecord> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let record = self.records[self.next];
+        self.next = (self.next + 1) % FAULT_LOG_CAPACITY;
+        self.remaining -= 1;
+        Some(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log() -> FaultLog {
+        FaultLog::new(Box::leak(Box::new(FaultLogStorage::new())))
+    }
+
+    #[test]
+    fn repeats_of_the_last_event_are_not_stored() {
+        let mut log = log();
+
+        log.store(0, 0x01, 0, 10);
+        log.store(0, 0x01, 0, 20);
+        assert_eq!(log.len(), 1);
+
+        log.store(0, 0x01, 0x01, 30);
+        assert_eq!(log.len(), 2);
+        assert_eq!(
+            log.last().map(|r| (r.sequence, r.extended_dtcs)),
+            Some((1, 0x01))
+        );
+    }
+
+    #[test]
+    fn the_oldest_event_is_overwritten_when_full() {
+        let mut log = log();
+
+        for i in 0..(FAULT_LOG_CAPACITY as u32 + 1) {
+            log.store(i, 0x01, 0, i);
+        }
+
+        assert_eq!(log.len(), FAULT_LOG_CAPACITY);
+        assert_eq!(log.records().next().map(|r| r.fault_origin_id), Some(1));
+    }
+}
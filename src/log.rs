@@ -0,0 +1,111 @@
+/// Severity of a log message, most severe first
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+        }
+    }
+}
+
+/// Source of a log message, printed as the message prefix
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Module {
+    Brake,
+    Throttle,
+    Steering,
+    CanGateway,
+    System,
+}
+
+impl Module {
+    pub fn prefix(&self) -> &'static str {
+        match *self {
+            Module::Brake => "BRAKE",
+            Module::Throttle => "THROTTLE",
+            Module::Steering => "STEERING",
+            Module::CanGateway => "CAN_GATEWAY",
+            Module::System => "SYSTEM",
+        }
+    }
+}
+
+/// Messages less severe than this are dropped, release builds drop info
+#[cfg(debug_assertions)]
+pub const MAX_LOG_LEVEL: Level = Level::Info;
+#[cfg(not(debug_assertions))]
+pub const MAX_LOG_LEVEL: Level = Level::Warn;
+
+/// Writes a line like `[1234] [BRAKE] WARN: message` to the debug console
+///
+/// `log!(debug_console, Module::Brake, Level::Warn, "message {}", x)`
+///
+/// The timestamp is the system clock in milliseconds. Write failures are
+/// ignored, logging must never affect control.
+macro_rules! log {
+    ($console:expr, $module:expr, $level:expr, $($arg:tt)+) => {{
+        let level: $crate::log::Level = $level;
+        if level <= $crate::log::MAX_LOG_LEVEL {
+            let module: $crate::log::Module = $module;
+            let _ = ::core::fmt::Write::write_fmt(
+                &mut *$console,
+                format_args!(
+                    "[{}] [{}] {}: {}\n",
+                    $crate::system_clock::get_timestamp_ms(),
+                    module.prefix(),
+                    level.as_str(),
+                    format_args!($($arg)+)
+                ),
+            );
+        }
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::String;
+    use system_clock::TestClock;
+
+    #[test]
+    fn levels_order_most_severe_first() {
+        assert!(Level::Error < Level::Warn);
+        assert!(Level::Warn < Level::Info);
+        assert!(Level::Error <= MAX_LOG_LEVEL);
+    }
+
+    #[test]
+    fn console_line_carries_the_timestamp_module_and_level() {
+        let clock = TestClock::take();
+        clock.set_ms(1234);
+        let mut console = String::new();
+
+        {
+            let console = &mut console;
+            log!(console, Module::Brake, Level::Warn, "pedal {}", 42);
+        }
+
+        assert_eq!(console, "[1234] [BRAKE] WARN: pedal 42\n");
+    }
+
+    #[cfg(not(debug_assertions))]
+    #[test]
+    fn release_builds_drop_info_lines() {
+        let mut console = String::new();
+
+        {
+            let console = &mut console;
+            log!(console, Module::System, Level::Info, "dropped");
+        }
+
+        assert!(console.is_empty());
+    }
+}
@@ -0,0 +1,73 @@
+// Execution time of the periodic control loop work (fault checks and report
+// publishing), for catching timing regressions on target
+
+use loop_timing_can_protocol::OsccLoopTiming;
+
+/// Last and longest measured durations [us]
+pub struct LoopTiming {
+    last_us: u32,
+    max_us: u32,
+}
+
+impl LoopTiming {
+    pub const fn new() -> Self {
+        LoopTiming {
+            last_us: 0,
+            max_us: 0,
+        }
+    }
+
+    /// Records the work between two `system_clock::get_timestamp_us` reads,
+    /// returns true if it is the longest so far
+    pub fn record(&mut self, start_us: u32, end_us: u32) -> bool {
+        self.last_us = end_us.wrapping_sub(start_us);
+
+        if self.last_us > self.max_us {
+            self.max_us = self.last_us;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn last_us(&self) -> u32 {
+        self.last_us
+    }
+
+    pub fn max_us(&self) -> u32 {
+        self.max_us
+    }
+
+    pub fn reset_max(&mut self) {
+        self.max_us = 0;
+    }
+
+    /// The timing for a loop timing request, the longest time restarts from
+    /// the next recording
+    pub fn read(&mut self) -> OsccLoopTiming {
+        let timing = OsccLoopTiming {
+            last_us: self.last_us(),
+            max_us: self.max_us(),
+        };
+        self.reset_max();
+        timing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_resets_the_longest_time() {
+        let mut timing = LoopTiming::new();
+        assert!(timing.record(0, 300));
+        assert!(!timing.record(1_000, 1_100));
+
+        let read = timing.read();
+        assert_eq!((read.last_us, read.max_us), (100, 300));
+        assert_eq!(timing.max_us(), 0);
+
+        assert!(timing.record(2_000, 2_050));
+    }
+}
@@ -0,0 +1,59 @@
+// Bench loopback, each command frame is answered straight away with the
+// matching report carrying the commanded value. Nothing reaches the control
+// modules, so the DACs and spoof enables are never touched.
+
+use brake_can_protocol::*;
+use config::CanIdOffset;
+use nucleo_f767zi::hal::can::{CanError, CanFrame};
+use oscc_magic_byte::*;
+use steering_can_protocol::*;
+use throttle_can_protocol::*;
+
+/// Publishes the echo report for a brake, throttle or steering command frame
+/// at IDs shifted by `can_id_offset`, any other frame is ignored
+pub fn echo_command_frame<P>(
+    can_frame: &CanFrame,
+    can_id_offset: CanIdOffset,
+    publisher: &mut P,
+) -> Result<(), CanError>
+where
+    P: BrakeReportPublisher + ThrottleReportPublisher + SteeringReportPublisher,
+{
+    if let CanFrame::DataFrame(ref frame) = can_frame {
+        let id = match can_id_offset.base_id(frame.id().into()) {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        let data = frame.data();
+
+        if !has_oscc_magic(data) {
+            return Ok(());
+        }
+
+        if id == OSCC_BRAKE_COMMAND_CAN_ID.into() {
+            if let Ok(command) = OsccBrakeCommand::from_frame(frame) {
+                let report = OsccBrakeReport {
+                    commanded_position: command.pedal_command,
+                    ..OsccBrakeReport::new()
+                };
+                publisher.publish_brake_report(&report)?;
+            }
+        } else if id == OSCC_THROTTLE_COMMAND_CAN_ID.into() {
+            if let Ok(command) = OsccThrottleCommand::from_frame(frame) {
+                let report = OsccThrottleReport {
+                    commanded_position: command.torque_request,
+                    ..OsccThrottleReport::new()
+                };
+                publisher.publish_throttle_report(&report)?;
+            }
+        } else if id == OSCC_STEERING_COMMAND_CAN_ID.into() {
+            // the steering report has no commanded value, the echo only
+            // confirms the command made it through
+            if OsccSteeringCommand::from_frame(frame).is_ok() {
+                publisher.publish_steering_report(&OsccSteeringReport::new())?;
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -1,40 +1,100 @@
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 #![feature(const_fn)]
+// the host test and sim builds leave out the firmware entry and the board
+// bring-up, see scripts/test-host
+#![cfg_attr(any(test, feature = "sim"), allow(dead_code, unused_imports))]
 
+// no_std brings core in, the host test build links std instead
+#[cfg(test)]
+extern crate core;
 extern crate cortex_m;
 extern crate cortex_m_rt as rt;
 #[cfg(feature = "panic-over-semihosting")]
 extern crate cortex_m_semihosting;
 extern crate embedded_hal;
+#[cfg(test)]
+extern crate nb;
 extern crate nucleo_f767zi;
 extern crate num;
-#[cfg(feature = "panic-over-abort")]
+#[cfg(all(feature = "panic-over-abort", not(test)))]
 extern crate panic_abort;
-#[cfg(feature = "panic-over-semihosting")]
+#[cfg(all(feature = "panic-over-semihosting", not(test)))]
 extern crate panic_semihosting;
 #[macro_use]
 extern crate typenum;
+#[cfg(test)]
+extern crate void;
+
+// only one panic handler can be linked
+#[cfg(all(
+    feature = "panic-safe-outputs",
+    any(feature = "panic-over-semihosting", feature = "panic-over-abort")
+))]
+compile_error!(
+    "panic-safe-outputs replaces the other panic handlers, build it with --no-default-features"
+);
 
+#[macro_use]
+mod log;
+mod bitbang_spi;
 mod board;
 mod can_gateway_module;
+mod can_health;
 mod config;
+mod control_module;
+mod dac_bank;
+mod dac_mcp4822;
 mod dac_mcp4922;
 mod dtc;
 mod dual_signal;
 mod fault_condition;
+mod fault_log;
+mod loop_timing;
+#[cfg(feature = "loopback")]
+mod loopback;
+mod override_source;
 mod oxcc_error;
+#[cfg(all(feature = "panic-safe-outputs", not(any(test, feature = "sim"))))]
+mod panic_handler;
+#[cfg(feature = "brake-pressure-loop")]
+mod pi_controller;
 mod ranges;
+mod signal_range;
+#[cfg(any(test, feature = "sim"))]
+mod sim_board;
+mod spoof_enable_sense;
+mod spoof_signal_pair;
 mod steering_module;
+mod system_clock;
 mod throttle_module;
 mod types;
+mod units;
 
 #[path = "can_protocols/brake_can_protocol.rs"]
 mod brake_can_protocol;
+#[path = "can_protocols/control_ack_can_protocol.rs"]
+mod control_ack_can_protocol;
+#[path = "can_protocols/emergency_stop_can_protocol.rs"]
+mod emergency_stop_can_protocol;
 #[path = "can_protocols/fault_can_protocol.rs"]
 mod fault_can_protocol;
+#[path = "can_protocols/loop_timing_can_protocol.rs"]
+mod loop_timing_can_protocol;
+#[path = "can_protocols/module_info_can_protocol.rs"]
+mod module_info_can_protocol;
+#[path = "can_protocols/oscc_crc.rs"]
+mod oscc_crc;
 #[path = "can_protocols/oscc_magic_byte.rs"]
 mod oscc_magic_byte;
+#[path = "can_protocols/oscc_position.rs"]
+mod oscc_position;
+#[path = "can_protocols/oscc_protocol_version.rs"]
+mod oscc_protocol_version;
+#[path = "can_protocols/param_can_protocol.rs"]
+mod param_can_protocol;
+#[path = "can_protocols/range_learning_can_protocol.rs"]
+mod range_learning_can_protocol;
 #[path = "can_protocols/steering_can_protocol.rs"]
 mod steering_can_protocol;
 #[path = "can_protocols/throttle_can_protocol.rs"]
@@ -58,24 +118,43 @@ mod brake_module;
 #[path = "brake/kia_soul_petrol/brake_module.rs"]
 mod brake_module;
 
-use board::{hard_fault_indicator, FullBoard};
-use brake_can_protocol::BrakeReportPublisher;
+use board::{hard_fault_indicator, Board, FullBoard};
+#[cfg(feature = "fault-injection")]
+use brake_can_protocol::OSCC_BRAKE_DTC_INVALID_SENSOR_VAL;
 use brake_module::{BrakeModule, UnpreparedBrakeModule};
 use can_gateway_module::CanGatewayModule;
-use core::fmt::Write;
-use fault_can_protocol::FaultReportPublisher;
-use nucleo_f767zi::debug_console::DebugConsole;
+use can_health::BusError;
+use config::THROTTLE_IDLE_CALIBRATION_SAMPLES;
+#[cfg(not(feature = "loopback"))]
+use control_ack_can_protocol::ControlAckPublisher;
+use control_module::ControlModule;
+use fault_can_protocol::{FaultLatch, FaultReportPublisher, OsccFaultReport};
+use log::{Level, Module};
+use loop_timing::LoopTiming;
+#[cfg(not(feature = "loopback"))]
+use loop_timing_can_protocol::{LoopTimingPublisher, OSCC_LOOP_TIMING_REQUEST_CAN_ID};
+use module_info_can_protocol::ModuleInfoPublisher;
 use nucleo_f767zi::hal::can::CanError;
+use nucleo_f767zi::hal::can::CanFrame;
 use nucleo_f767zi::hal::can::RxFifo;
 use nucleo_f767zi::led::{Color, Leds};
+#[cfg(any(feature = "can-sniffer", not(feature = "loopback")))]
+use oscc_magic_byte::*;
+#[cfg(not(feature = "loopback"))]
+use oxcc_error::BrakeModuleError;
 use oxcc_error::OxccError;
+#[cfg(not(feature = "loopback"))]
+use param_can_protocol::ParamValuePublisher;
+#[cfg(not(feature = "loopback"))]
+use range_learning_can_protocol::{
+    OsccRangeLearningCommand, RangeLearningAction, OSCC_RANGE_LEARNING_CAN_ID,
+};
 use rt::{entry, exception, ExceptionFrame};
-use steering_can_protocol::SteeringReportPublisher;
+use signal_range::RangeSignal;
 use steering_module::{SteeringModule, UnpreparedSteeringModule};
-use throttle_can_protocol::ThrottleReportPublisher;
 use throttle_module::{ThrottleModule, UnpreparedThrottleModule};
-
-const DEBUG_WRITE_FAILURE: &str = "Failed to write to debug_console";
+use types::DebugConsole;
+use vehicle::{ControlLoopScheduler, SYSTEM_FAULT_STATE};
 
 struct ControlModules {
     pub brake: BrakeModule,
@@ -83,6 +162,14 @@ struct ControlModules {
     pub steering: SteeringModule,
 }
 
+impl ControlModules {
+    /// Brake, throttle then steering, for the steps they all share
+    fn as_array(&mut self) -> [&mut ControlModule; 3] {
+        [&mut self.brake, &mut self.throttle, &mut self.steering]
+    }
+}
+
+#[cfg(not(any(test, feature = "sim")))]
 #[entry]
 fn main() -> ! {
     // once the organization is cleaned up, the entire board doesn't need to be
@@ -92,19 +179,13 @@ fn main() -> ! {
         brake_dac,
         brake_pins,
         brake_pedal_position_sensor,
-        brake_grounded_fault_timer,
-        brake_override_fault_timer,
         accelerator_position_sensor,
         throttle_dac,
         throttle_pins,
-        throttle_grounded_fault_timer,
-        throttle_override_fault_timer,
         torque_sensor,
         steering_dac,
         steering_pins,
-        steering_grounded_fault_timer,
         mut debug_console,
-        can_publish_timer,
         control_can,
         obd_can,
     ) = FullBoard::new().split_components();
@@ -115,58 +196,137 @@ fn main() -> ! {
     // show startup message and reset warnings if debugging
     #[cfg(debug_assertions)]
     {
-        writeln!(debug_console, "OxCC is running").unwrap();
+        log!(
+            &mut debug_console,
+            Module::System,
+            Level::Info,
+            "OxCC {} ({}) is running",
+            env!("CARGO_PKG_VERSION"),
+            module_info_can_protocol::GIT_HASH
+        );
 
         // TODO - some of these are worthy of disabling controls?
         if board.reset_conditions.low_power {
-            writeln!(debug_console, "WARNING: low-power reset detected")
-                .expect(DEBUG_WRITE_FAILURE);
+            log!(
+                &mut debug_console,
+                Module::System,
+                Level::Warn,
+                "low-power reset detected"
+            );
         }
         if board.reset_conditions.window_watchdog || board.reset_conditions.independent_watchdog {
-            writeln!(debug_console, "WARNING: watchdog reset detected").expect(DEBUG_WRITE_FAILURE);
+            log!(
+                &mut debug_console,
+                Module::System,
+                Level::Warn,
+                "watchdog reset detected"
+            );
         }
         if board.reset_conditions.software {
-            writeln!(debug_console, "WARNING: software reset detected").expect(DEBUG_WRITE_FAILURE);
+            log!(
+                &mut debug_console,
+                Module::System,
+                Level::Warn,
+                "software reset detected"
+            );
         }
         if board.reset_conditions.por_pdr {
-            writeln!(debug_console, "WARNING: POR/PDR reset detected").expect(DEBUG_WRITE_FAILURE);
+            log!(
+                &mut debug_console,
+                Module::System,
+                Level::Warn,
+                "POR/PDR reset detected"
+            );
         }
         if board.reset_conditions.pin {
-            writeln!(debug_console, "WARNING: PIN reset detected").expect(DEBUG_WRITE_FAILURE);
+            log!(
+                &mut debug_console,
+                Module::System,
+                Level::Warn,
+                "PIN reset detected"
+            );
         }
         if board.reset_conditions.bor {
-            writeln!(debug_console, "WARNING: BOR reset detected").expect(DEBUG_WRITE_FAILURE);
+            log!(
+                &mut debug_console,
+                Module::System,
+                Level::Warn,
+                "BOR reset detected"
+            );
+        }
+
+        for fault in board.read_fault_log() {
+            log!(
+                &mut debug_console,
+                Module::System,
+                Level::Warn,
+                "Stored fault {}: {} DTCs 0x{:X} extended DTCs 0x{:X} at {} ms",
+                fault.sequence,
+                fault_can_protocol::fault_origin_name(fault.fault_origin_id),
+                fault.dtcs,
+                fault.extended_dtcs,
+                fault.timestamp_ms
+            );
         }
     }
 
-    let unprepared_brake_module = UnpreparedBrakeModule::new(
-        brake_dac,
-        brake_pins,
-        brake_pedal_position_sensor,
-        brake_grounded_fault_timer,
-        brake_override_fault_timer,
-    );
-    let unprepared_throttle_module = UnpreparedThrottleModule::new(
-        accelerator_position_sensor,
-        throttle_dac,
-        throttle_pins,
-        throttle_grounded_fault_timer,
-        throttle_override_fault_timer,
-    );
-    let unprepared_steering_module = UnpreparedSteeringModule::new(
-        torque_sensor,
-        steering_dac,
-        steering_pins,
-        steering_grounded_fault_timer,
-    );
-    let mut can_gateway = CanGatewayModule::new(can_publish_timer, control_can, obd_can);
+    // a wrong SPI mode or clock silently corrupts every DAC write
+    if let Err(e) = board.dac_spi_config {
+        log!(
+            &mut debug_console,
+            Module::System,
+            Level::Warn,
+            "DAC SPI misconfigured: {:?}",
+            e
+        );
+    }
+
+    let unprepared_brake_module =
+        UnpreparedBrakeModule::new(brake_dac, brake_pins, brake_pedal_position_sensor);
+    let unprepared_throttle_module =
+        UnpreparedThrottleModule::new(accelerator_position_sensor, throttle_dac, throttle_pins);
+    let unprepared_steering_module =
+        UnpreparedSteeringModule::new(torque_sensor, steering_dac, steering_pins);
+    let mut can_gateway = CanGatewayModule::new(control_can, obd_can);
 
     let mut modules = ControlModules {
-        brake: unprepared_brake_module.prepare_module(),
-        throttle: unprepared_throttle_module.prepare_module(),
-        steering: unprepared_steering_module.prepare_module(),
+        brake: unprepared_brake_module.prepare_module(&mut debug_console),
+        throttle: unprepared_throttle_module.prepare_module(&mut debug_console),
+        steering: unprepared_steering_module.prepare_module(&mut debug_console),
     };
 
+    // the filters were shifted by the same offset when the board came up
+    let can_id_offset = config::can_id_offset();
+    can_gateway.set_can_id_offset(can_id_offset);
+    for module in modules.as_array().iter_mut() {
+        module.set_can_id_offset(can_id_offset);
+    }
+
+    // learn the resting accelerator position before accepting commands
+    if let Err(dtc) = modules
+        .throttle
+        .calibrate_idle(THROTTLE_IDLE_CALIBRATION_SAMPLES)
+    {
+        log!(
+            &mut debug_console,
+            Module::System,
+            Level::Warn,
+            "throttle idle calibration rejected, DTC 0x{:X}",
+            dtc
+        );
+    }
+
+    // sensor ranges learned by an earlier guided calibration
+    modules
+        .brake
+        .set_operating_range(board.learned_range(RangeSignal::BrakePedalPosition));
+    modules
+        .throttle
+        .set_operating_range(board.learned_range(RangeSignal::AcceleratorPosition));
+    modules
+        .steering
+        .set_operating_range(board.learned_range(RangeSignal::SteeringTorque));
+
     // send reports immediately
     if let Err(e) = publish_reports(&mut modules, &mut can_gateway) {
         handle_error(
@@ -178,14 +338,24 @@ fn main() -> ! {
         );
     }
 
+    let mut scheduler = ControlLoopScheduler::new(system_clock::get_timestamp_ms());
+    let mut loop_timing = LoopTiming::new();
+    let mut fault_latch = FaultLatch::new();
+
     loop {
         // refresh the independent watchdog
-        board.wdg.refresh();
+        board.pet_watchdog();
+
+        let due_tasks = scheduler.poll(system_clock::get_timestamp_ms());
 
         // check the control CAN FIFOs for any frames to be processed
-        if let Err(e) =
-            process_control_can_frames(&mut modules, &mut can_gateway, &mut debug_console)
-        {
+        if let Err(e) = process_control_can_frames(
+            &mut modules,
+            &mut board,
+            &mut loop_timing,
+            &mut can_gateway,
+            &mut debug_console,
+        ) {
             handle_error(
                 e,
                 &mut modules,
@@ -195,22 +365,47 @@ fn main() -> ! {
             );
         }
 
+        // times the fault checks through the report publishing
+        let periodic_start_us = system_clock::get_timestamp_us();
+
         // check modules for fault conditions, sending reports as needed
         // NOTE
         // ignoring transmit timeouts until a proper error handling strategy is
         // implemented
-        if let Err(e) = check_for_faults(&mut modules, &mut can_gateway, &mut debug_console) {
-            if e != OxccError::Can(CanError::Timeout) {
-                handle_error(
-                    e,
-                    &mut modules,
-                    &mut can_gateway,
-                    &mut debug_console,
-                    &mut board.leds,
-                );
+        if due_tasks.check_faults {
+            if let Err(e) = check_for_faults(
+                &mut modules,
+                &mut fault_latch,
+                &mut can_gateway,
+                &mut board,
+                &mut debug_console,
+            ) {
+                if e != OxccError::Can(CanError::Timeout) {
+                    handle_error(
+                        e,
+                        &mut modules,
+                        &mut can_gateway,
+                        &mut debug_console,
+                        &mut board.leds,
+                    );
+                }
             }
         }
 
+        // restart the control CAN peripheral if it went bus-off
+        can_gateway.recover_control_can_bus_off();
+
+        // send any report frames that were waiting for a free TX mailbox
+        if let Err(e) = can_gateway.drain_control_can_tx_queue() {
+            handle_error(
+                OxccError::from(e),
+                &mut modules,
+                &mut can_gateway,
+                &mut debug_console,
+                &mut board.leds,
+            );
+        }
+
         // republish OBD frames to control CAN bus
         if let Err(e) = can_gateway.republish_obd_frames_to_control_can_bus() {
             handle_error(
@@ -223,7 +418,7 @@ fn main() -> ! {
         }
 
         // periodically publish all report frames
-        if can_gateway.wait_for_publish() {
+        if due_tasks.publish_reports {
             board.leds[Color::Green].toggle();
 
             if let Err(e) = publish_reports(&mut modules, &mut can_gateway) {
@@ -237,8 +432,45 @@ fn main() -> ! {
             }
         }
 
+        if due_tasks.check_faults || due_tasks.publish_reports {
+            let periodic_end_us = system_clock::get_timestamp_us();
+
+            if loop_timing.record(periodic_start_us, periodic_end_us) {
+                let level = if loop_timing.max_us() > config::LOOP_TIME_BUDGET_US {
+                    Level::Warn
+                } else {
+                    Level::Info
+                };
+                log!(
+                    &mut debug_console,
+                    Module::System,
+                    level,
+                    "Longest loop since the last timing request {} us",
+                    loop_timing.max_us()
+                );
+            }
+        }
+
         // TODO - do anything with the user button?
         if board.user_button() {
+            #[cfg(feature = "fault-injection")]
+            {
+                if let Err(e) = inject_brake_fault(
+                    &mut modules,
+                    &mut can_gateway,
+                    &mut board,
+                    &mut debug_console,
+                ) {
+                    handle_error(
+                        e,
+                        &mut modules,
+                        &mut can_gateway,
+                        &mut debug_console,
+                        &mut board.leds,
+                    );
+                }
+            }
+
             // can only do this when we're debugging/semihosting
             #[cfg(feature = "panic-over-semihosting")]
             cortex_m::asm::bkpt();
@@ -248,20 +480,44 @@ fn main() -> ! {
 
 fn process_control_can_frames(
     modules: &mut ControlModules,
+    board: &mut Board,
+    loop_timing: &mut LoopTiming,
     can_gateway: &mut CanGatewayModule,
     debug_console: &mut DebugConsole,
 ) -> Result<(), OxccError> {
     // poll both control CAN FIFOs
     for fifo in &[RxFifo::Fifo0, RxFifo::Fifo1] {
-        match can_gateway.control_can().receive(fifo) {
+        // bound first so the gateway is free again for the loopback echo
+        let rx_result = can_gateway.control_can().receive(fifo);
+
+        match rx_result {
+            // nothing on the control bus is requested with a remote frame
+            Ok(CanFrame::RemoteFrame(ref remote_frame)) => {
+                let id: u32 = remote_frame.id().into();
+
+                log!(
+                    debug_console,
+                    Module::CanGateway,
+                    Level::Info,
+                    "Ignoring remote frame 0x{:03X}",
+                    id
+                );
+            }
             Ok(rx_frame) => {
-                modules.brake.process_rx_frame(&rx_frame, debug_console)?;
-                modules
-                    .throttle
-                    .process_rx_frame(&rx_frame, debug_console)?;
-                modules
-                    .steering
-                    .process_rx_frame(&rx_frame, debug_console)?;
+                let rx_timestamp_ms = system_clock::get_timestamp_ms();
+
+                #[cfg(feature = "can-sniffer")]
+                sniff_control_can_frame(&rx_frame, debug_console);
+
+                dispatch_control_can_frame(
+                    &rx_frame,
+                    rx_timestamp_ms,
+                    modules,
+                    board,
+                    loop_timing,
+                    can_gateway,
+                    debug_console,
+                )?;
             }
             Err(e) => {
                 // report all but BufferExhausted (no data)
@@ -275,29 +531,426 @@ fn process_control_can_frames(
     Ok(())
 }
 
+#[cfg(not(feature = "loopback"))]
+fn dispatch_control_can_frame(
+    rx_frame: &CanFrame,
+    rx_timestamp_ms: u32,
+    modules: &mut ControlModules,
+    board: &mut Board,
+    loop_timing: &mut LoopTiming,
+    can_gateway: &mut CanGatewayModule,
+    debug_console: &mut DebugConsole,
+) -> Result<(), OxccError> {
+    process_range_learning_frame(rx_frame, modules, board, debug_console);
+    answer_loop_timing_request(rx_frame, loop_timing, can_gateway)?;
+
+    for module in modules.as_array().iter_mut() {
+        match module.process_rx_frame(rx_frame, rx_timestamp_ms, debug_console) {
+            // already logged, the frame is dropped without disabling
+            // the other modules
+            Err(OxccError::Brake(BrakeModuleError::MalformedFrame))
+            | Err(OxccError::Brake(BrakeModuleError::NotReady))
+            | Err(OxccError::NotReady) => (),
+            result => result?,
+        }
+    }
+
+    // acknowledge enable and disable frames, and answer parameter requests,
+    // straight away rather than with the next report
+    // attempt to publish them all, only report the last to fail
+    let mut result = Ok(());
+
+    // a full transmit mailbox or an idle bus drops the answer, the frame
+    // that asked for it has already been handled
+    for module in modules.as_array().iter_mut() {
+        if let Some(control_ack) = module.supply_control_ack() {
+            if let Err(e) = can_gateway.publish_control_ack(&control_ack) {
+                if (e != CanError::Timeout) && (e != CanError::BufferExhausted) {
+                    result = Err(OxccError::from(e));
+                }
+            }
+        }
+
+        if let Some(param_value) = module.supply_param_value() {
+            if let Err(e) = can_gateway.publish_param_value(&param_value) {
+                if (e != CanError::Timeout) && (e != CanError::BufferExhausted) {
+                    result = Err(OxccError::from(e));
+                }
+            }
+        }
+    }
+
+    result
+}
+
+// the longest loop time restarts once it has been sent
+#[cfg(not(feature = "loopback"))]
+fn answer_loop_timing_request(
+    rx_frame: &CanFrame,
+    loop_timing: &mut LoopTiming,
+    can_gateway: &mut CanGatewayModule,
+) -> Result<(), OxccError> {
+    if let CanFrame::DataFrame(ref frame) = rx_frame {
+        let id = config::can_id_offset().base_id(frame.id().into());
+
+        if (id == Some(OSCC_LOOP_TIMING_REQUEST_CAN_ID.into())) && has_oscc_magic(frame.data()) {
+            match can_gateway.publish_loop_timing(&loop_timing.read()) {
+                Err(e) if (e != CanError::Timeout) && (e != CanError::BufferExhausted) => {
+                    return Err(OxccError::from(e));
+                }
+                _ => (),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// guided calibration of the sensor operating ranges, the learned ranges are
+// kept in the backup SRAM and applied again at boot
+#[cfg(not(feature = "loopback"))]
+fn process_range_learning_frame(
+    rx_frame: &CanFrame,
+    modules: &mut ControlModules,
+    board: &mut Board,
+    debug_console: &mut DebugConsole,
+) {
+    let frame = match rx_frame {
+        CanFrame::DataFrame(ref frame) => frame,
+        _ => return,
+    };
+
+    let id = config::can_id_offset().base_id(frame.id().into());
+
+    if (id != Some(OSCC_RANGE_LEARNING_CAN_ID.into())) || !has_oscc_magic(frame.data()) {
+        return;
+    }
+
+    let command = match OsccRangeLearningCommand::from_frame(frame) {
+        Some(command) => command,
+        None => {
+            log!(
+                debug_console,
+                Module::System,
+                Level::Warn,
+                "Dropped malformed range learning command"
+            );
+            return;
+        }
+    };
+
+    match command.action {
+        RangeLearningAction::Start => {
+            let started = [
+                modules.brake.start_range_learning(),
+                modules.throttle.start_range_learning(),
+                modules.steering.start_range_learning(),
+            ];
+
+            if started.iter().all(|started| *started) {
+                log!(
+                    debug_console,
+                    Module::System,
+                    Level::Info,
+                    "Range learning started"
+                );
+            } else {
+                log!(
+                    debug_console,
+                    Module::System,
+                    Level::Warn,
+                    "Range learning refused by an enabled module (brake, throttle, steering): {:?}",
+                    started
+                );
+            }
+        }
+        RangeLearningAction::Finish => {
+            let learned = [
+                (
+                    RangeSignal::BrakePedalPosition,
+                    modules.brake.finish_range_learning(),
+                ),
+                (
+                    RangeSignal::AcceleratorPosition,
+                    modules.throttle.finish_range_learning(),
+                ),
+                (
+                    RangeSignal::SteeringTorque,
+                    modules.steering.finish_range_learning(),
+                ),
+            ];
+
+            for &(signal, range) in learned.iter() {
+                match range {
+                    Some(range) => {
+                        board.store_learned_range(signal, range);
+                        log!(
+                            debug_console,
+                            Module::System,
+                            Level::Info,
+                            "Learned {:?} range {:?}",
+                            signal,
+                            range
+                        );
+                    }
+                    None => log!(
+                        debug_console,
+                        Module::System,
+                        Level::Warn,
+                        "No {:?} range learned, keeping the previous range",
+                        signal
+                    ),
+                }
+            }
+        }
+        RangeLearningAction::Clear => {
+            board.clear_learned_ranges();
+            modules.brake.set_operating_range(None);
+            modules.throttle.set_operating_range(None);
+            modules.steering.set_operating_range(None);
+
+            log!(
+                debug_console,
+                Module::System,
+                Level::Info,
+                "Learned ranges cleared"
+            );
+        }
+    }
+}
+
+// bench loopback, commands are echoed back as reports and never reach the
+// modules
+#[cfg(feature = "loopback")]
+fn dispatch_control_can_frame(
+    rx_frame: &CanFrame,
+    _rx_timestamp_ms: u32,
+    _modules: &mut ControlModules,
+    _board: &mut Board,
+    _loop_timing: &mut LoopTiming,
+    can_gateway: &mut CanGatewayModule,
+    _debug_console: &mut DebugConsole,
+) -> Result<(), OxccError> {
+    loopback::echo_command_frame(rx_frame, config::can_id_offset(), can_gateway)?;
+    Ok(())
+}
+
+// logs every frame carrying the OSCC magic bytes, handled or not
+#[cfg(feature = "can-sniffer")]
+fn sniff_control_can_frame(frame: &CanFrame, debug_console: &mut DebugConsole) {
+    if let CanFrame::DataFrame(ref frame) = frame {
+        let id: u32 = frame.id().into();
+        let data = frame.data();
+
+        if has_oscc_magic(data) {
+            log!(
+                debug_console,
+                Module::CanGateway,
+                Level::Info,
+                "RX 0x{:03X} {:02X?}",
+                id,
+                data
+            );
+        }
+    }
+}
+
 fn check_for_faults(
     modules: &mut ControlModules,
+    fault_latch: &mut FaultLatch,
     can_gateway: &mut CanGatewayModule,
+    board: &mut Board,
     debug_console: &mut DebugConsole,
 ) -> Result<(), OxccError> {
-    let maybe_fault = modules.brake.check_for_faults(debug_console)?;
-    if let Some(brake_fault) = maybe_fault {
-        can_gateway.publish_fault_report(brake_fault)?;
+    modules.brake.step_disable_ramp(debug_console)?;
+    modules.brake.step_enable_ramp(debug_console)?;
+
+    for module in modules.as_array().iter_mut() {
+        module.step_range_learning();
+    }
+
+    #[cfg(feature = "brake-pressure-loop")]
+    {
+        if let Some(brake_fault) = modules.brake.step_pressure_loop(debug_console)? {
+            latch_detected_fault(brake_fault, fault_latch, can_gateway, board)?;
+        }
+    }
+
+    #[cfg(feature = "brake-regen-blending")]
+    modules.brake.check_regen_status(debug_console)?;
+
+    if let Some(brake_fault) = modules.brake.check_command_timeout(debug_console)? {
+        latch_detected_fault(brake_fault, fault_latch, can_gateway, board)?;
+    }
+
+    if let Some(brake_fault) = modules.brake.check_rejected_command() {
+        latch_detected_fault(brake_fault, fault_latch, can_gateway, board)?;
+    }
+
+    for module in modules.as_array().iter_mut() {
+        if let Some(fault) = module.check_for_faults(debug_console)? {
+            latch_detected_fault(fault, fault_latch, can_gateway, board)?;
+        }
+    }
+
+    let previous_can_health = can_gateway.control_can_health();
+
+    match can_gateway.check_control_can_bus_errors() {
+        // expected while alone on the bus, reported once it is sustained
+        Some(BusError::Acknowledgment) | None => (),
+        Some(bus_error) => log!(
+            debug_console,
+            Module::CanGateway,
+            Level::Warn,
+            "Control CAN bus error: {:?}",
+            bus_error
+        ),
+    }
+
+    let control_can_health = can_gateway.control_can_health();
+
+    if control_can_health.unacknowledged && !previous_can_health.unacknowledged {
+        log!(
+            debug_console,
+            Module::CanGateway,
+            Level::Warn,
+            "Control CAN frames are not acknowledged, no other node on the bus?"
+        );
+    }
+
+    if control_can_health.bus_errors_sustained && !previous_can_health.bus_errors_sustained {
+        log!(
+            debug_console,
+            Module::CanGateway,
+            Level::Warn,
+            "Control CAN bus errors sustained, TEC {} REC {}",
+            control_can_health.transmit_error_count,
+            control_can_health.receive_error_count
+        );
+    }
+
+    for module in modules.as_array().iter_mut() {
+        if let Some(fault) = module.check_can_health(&control_can_health, debug_console)? {
+            latch_detected_fault(fault, fault_latch, can_gateway, board)?;
+        }
+
+        if let Some(fault) = module.check_spoof_enable(debug_console)? {
+            latch_detected_fault(fault, fault_latch, can_gateway, board)?;
+        }
+
+        if let Some(fault) = module.check_dac_output(debug_console)? {
+            latch_detected_fault(fault, fault_latch, can_gateway, board)?;
+        }
     }
 
-    let maybe_fault = modules.throttle.check_for_faults(debug_console)?;
-    if let Some(throttle_fault) = maybe_fault {
-        can_gateway.publish_fault_report(throttle_fault)?;
+    // ADC1 is on the brake module, it measures VDDA for all of them
+    let supply_voltage = modules.brake.measure_supply_voltage();
+
+    if let Some(brake_fault) = modules
+        .brake
+        .check_supply_voltage(&supply_voltage, debug_console)?
+    {
+        latch_detected_fault(brake_fault, fault_latch, can_gateway, board)?;
     }
 
-    let maybe_fault = modules.steering.check_for_faults(debug_console)?;
-    if let Some(steering_fault) = maybe_fault {
-        can_gateway.publish_fault_report(steering_fault)?;
+    if let Some(throttle_fault) = modules
+        .throttle
+        .check_supply_voltage(&supply_voltage, debug_console)?
+    {
+        latch_detected_fault(throttle_fault, fault_latch, can_gateway, board)?;
+    }
+
+    if let Some(steering_fault) = modules
+        .steering
+        .check_supply_voltage(&supply_voltage, debug_console)?
+    {
+        latch_detected_fault(steering_fault, fault_latch, can_gateway, board)?;
+    }
+
+    // a fault detected during the startup grace period was left to the
+    // module, the DTCs it still holds are latched once the period is over
+    if !board.in_startup_grace_period() {
+        for module in modules.as_array().iter_mut() {
+            let fault_report = module.supply_fault_report();
+
+            if (fault_report.dtcs != 0) || (fault_report.extended_dtcs != 0) {
+                latch_detected_fault(fault_report, fault_latch, can_gateway, board)?;
+            }
+        }
+    }
+
+    // a DTC a module has cleared since latches again when it comes back
+    for module in modules.as_array().iter_mut() {
+        fault_latch.release_cleared(module.supply_fault_report());
+    }
+
+    // our own fault reports are not received back, so bring the other
+    // modules into the safe state here
+    if SYSTEM_FAULT_STATE.is_latched() {
+        for module in modules.as_array().iter_mut() {
+            module.disable_control(debug_console)?;
+        }
     }
 
     Ok(())
 }
 
+// latch before publishing so a failed transmit can't skip it, the event is
+// also kept in the backup SRAM fault log. Overrides and command timeouts
+// only disable their own module, which recovers from them.
+fn latch_fault(
+    fault_report: &OsccFaultReport,
+    can_gateway: &mut CanGatewayModule,
+    board: &mut Board,
+) -> Result<(), OxccError> {
+    if fault_report.is_hard_fault() {
+        SYSTEM_FAULT_STATE.latch();
+    }
+    board.store_fault(
+        fault_report.fault_origin_id,
+        fault_report.dtcs,
+        fault_report.extended_dtcs,
+    );
+    can_gateway.publish_fault_report(fault_report)?;
+    Ok(())
+}
+
+// faults found during the startup grace period are left to the modules,
+// which have disabled and set their DTCs, the DTCs still set are latched
+// once the period is over. Only DTCs on their rising edge are latched, a
+// fault detected again every check is stored and published once.
+fn latch_detected_fault(
+    fault_report: &OsccFaultReport,
+    fault_latch: &mut FaultLatch,
+    can_gateway: &mut CanGatewayModule,
+    board: &mut Board,
+) -> Result<(), OxccError> {
+    if board.in_startup_grace_period() || !fault_latch.latch(fault_report) {
+        return Ok(());
+    }
+
+    latch_fault(fault_report, can_gateway, board)
+}
+
+// reports the injected fault the same way check_for_faults reports a real one
+#[cfg(feature = "fault-injection")]
+fn inject_brake_fault(
+    modules: &mut ControlModules,
+    can_gateway: &mut CanGatewayModule,
+    board: &mut Board,
+    debug_console: &mut DebugConsole,
+) -> Result<(), OxccError> {
+    {
+        let brake_fault = modules
+            .brake
+            .inject_fault(OSCC_BRAKE_DTC_INVALID_SENSOR_VAL, debug_console)?;
+        latch_fault(brake_fault, can_gateway, board)?;
+    }
+
+    modules.throttle.disable_control(debug_console)?;
+    modules.steering.disable_control(debug_console)
+}
+
 // NOTE
 // ignoring transmit timeouts until a proper error handling strategy is
 // implemented
@@ -310,21 +963,20 @@ fn publish_reports(
 
     // it is typically to get timeout errors if the CAN bus is not active or
     // there are no other nodes connected to it
-    if let Err(e) = can_gateway.publish_brake_report(modules.brake.supply_brake_report()) {
-        if e != CanError::Timeout {
-            result = Err(OxccError::from(e));
-        }
-    }
-
-    if let Err(e) = can_gateway.publish_throttle_report(modules.throttle.supply_throttle_report()) {
-        if e != CanError::Timeout {
-            result = Err(OxccError::from(e));
+    for module in modules.as_array().iter_mut() {
+        if let Err(e) = module.publish_reports(can_gateway) {
+            if e != CanError::Timeout {
+                result = Err(OxccError::from(e));
+            }
         }
-    }
 
-    if let Err(e) = can_gateway.publish_steering_report(modules.steering.supply_steering_report()) {
-        if e != CanError::Timeout {
-            result = Err(OxccError::from(e));
+        // answer a module info request, once per module
+        if let Some(module_info) = module.supply_module_info() {
+            if let Err(e) = can_gateway.publish_module_info(module_info) {
+                if e != CanError::Timeout {
+                    result = Err(OxccError::from(e));
+                }
+            }
         }
     }
 
@@ -341,7 +993,7 @@ fn handle_error(
 ) {
     leds[Color::Red].on();
 
-    writeln!(debug_console, "ERROR: {:#?}", error).expect(DEBUG_WRITE_FAILURE);
+    log!(debug_console, Module::System, Level::Error, "{:#?}", error);
 
     // disable all controls
     let _ = modules.throttle.disable_control(debug_console);
@@ -353,16 +1005,22 @@ fn handle_error(
 }
 
 // TODO - any safety related things we can do in these contexts?
-// Might be worth implementing a panic handler here as well
-// For example:
-// - disable controls
-// - indication LED
+// The panic-safe-outputs panic handler drives the outputs safe once these
+// panic
+#[cfg(not(any(test, feature = "sim")))]
 #[exception]
 fn HardFault(ef: &ExceptionFrame) -> ! {
     hard_fault_indicator();
     panic!("HardFault at {:#?}", ef);
 }
 
+#[cfg(not(any(test, feature = "sim")))]
+#[exception]
+fn SysTick() {
+    system_clock::tick();
+}
+
+#[cfg(not(any(test, feature = "sim")))]
 #[exception]
 fn DefaultHandler(irqn: i16) {
     hard_fault_indicator();
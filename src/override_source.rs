@@ -0,0 +1,49 @@
+// Inputs that signal the operator taking back control, a module combines
+// its sources and treats any one of them as an override
+
+use embedded_hal::digital::InputPin;
+
+pub trait OverrideSource {
+    /// True while this source requests an operator override
+    fn is_override_requested(&self) -> bool;
+}
+
+/// An uninstalled source never requests an override
+impl<S: OverrideSource> OverrideSource for Option<S> {
+    fn is_override_requested(&self) -> bool {
+        match *self {
+            Some(ref source) => source.is_override_requested(),
+            None => false,
+        }
+    }
+}
+
+/// A normally-open switch to ground on a pulled-up input, a pressed switch
+/// reads low
+pub struct OverrideSwitch<P: InputPin> {
+    pin: P,
+}
+
+impl<P: InputPin> OverrideSwitch<P> {
+    pub fn new(pin: P) -> Self {
+        OverrideSwitch { pin }
+    }
+}
+
+impl<P: InputPin> OverrideSource for OverrideSwitch<P> {
+    fn is_override_requested(&self) -> bool {
+        self.pin.is_low()
+    }
+}
+
+/// A signal average at or above a threshold, like a pressed pedal
+pub struct ThresholdOverride {
+    pub average: u32,
+    pub threshold: u32,
+}
+
+impl OverrideSource for ThresholdOverride {
+    fn is_override_requested(&self) -> bool {
+        self.average >= self.threshold
+    }
+}
@@ -1,5 +1,6 @@
 // might make more sense to just use the existing HAL errors?
 
+use dac_mcp4922;
 use nucleo_f767zi::hal::can::CanError;
 use nucleo_f767zi::hal::spi;
 
@@ -7,8 +8,28 @@ use nucleo_f767zi::hal::spi;
 pub enum OxccError {
     Spi(spi::Error),
     Can(CanError),
+    Dac(DacError),
+    Brake(BrakeModuleError),
+    /// A throttle or steering enable was refused by a latched fault or
+    /// e-stop, the control state or a failed self test
+    NotReady,
 }
 
+/// Errors returned by the brake module
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BrakeModuleError {
+    /// Writing the spoof outputs failed
+    DacWrite(DacError),
+    /// A brake frame failed the length, CRC or protocol version check and
+    /// was dropped
+    MalformedFrame,
+    /// An enable was refused by a latched fault or e-stop, the control state
+    /// or the control dwell
+    NotReady,
+}
+
+pub type DacError = dac_mcp4922::Error<spi::Error>;
+
 impl From<spi::Error> for OxccError {
     fn from(e: spi::Error) -> Self {
         OxccError::Spi(e)
@@ -20,3 +41,21 @@ impl From<CanError> for OxccError {
         OxccError::Can(e)
     }
 }
+
+impl From<DacError> for OxccError {
+    fn from(e: DacError) -> Self {
+        OxccError::Dac(e)
+    }
+}
+
+impl From<DacError> for BrakeModuleError {
+    fn from(e: DacError) -> Self {
+        BrakeModuleError::DacWrite(e)
+    }
+}
+
+impl From<BrakeModuleError> for OxccError {
+    fn from(e: BrakeModuleError) -> Self {
+        OxccError::Brake(e)
+    }
+}
@@ -0,0 +1,33 @@
+// Panic handler that hands control back to the vehicle before halting, the
+// spoof enables are driven low and the DACs shut down.
+//
+// Safety assumptions:
+// - nothing runs after the handler, it halts with interrupts disabled, so
+//   stealing the peripherals from the abandoned control loop can't race it
+// - the pins and SPIs are rebuilt from their reset state, so a panic in
+//   the middle of a DAC write or GPIO update doesn't matter
+// - a panic while driving the outputs (or any later one) skips straight to
+//   the halt rather than re-entering `drive_outputs_safe`
+// - the halt stops petting the watchdog, it resets the MCU within
+//   `board::WATCHDOG_TIMEOUT` and every module comes back up disabled
+
+use board;
+use core::panic::PanicInfo;
+use core::sync::atomic::{self, AtomicBool, Ordering};
+use cortex_m;
+
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+
+    if !PANICKING.swap(true, Ordering::SeqCst) {
+        board::drive_outputs_safe();
+        board::hard_fault_indicator();
+    }
+
+    loop {
+        atomic::compiler_fence(Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,140 @@
+use num;
+
+/// Proportional-integral controller with a clamped output
+pub struct PiController {
+    kp: f32,
+    ki: f32,
+    output_min: f32,
+    output_max: f32,
+    integral: f32,
+}
+
+impl PiController {
+    pub fn new(kp: f32, ki: f32, output_min: f32, output_max: f32) -> Self {
+        PiController {
+            kp,
+            ki,
+            output_min,
+            output_max,
+            integral: 0.0,
+        }
+    }
+
+    /// Returns the output for `error` (setpoint - measurement) after
+    /// `dt_s` seconds since the last update.
+    ///
+    /// The integral is not accumulated while the output is saturated and the
+    /// error would drive it further out, so it doesn't wind up while the
+    /// spoof output is pinned at a limit.
+    pub fn update(&mut self, error: f32, dt_s: f32) -> f32 {
+        let integral = self.integral + (error * dt_s);
+        let unclamped = (self.kp * error) + (self.ki * integral);
+        let output = num::clamp(unclamped, self.output_min, self.output_max);
+
+        let winding_up = ((unclamped > self.output_max) && (error > 0.0))
+            || ((unclamped < self.output_min) && (error < 0.0));
+
+        if !winding_up {
+            self.integral = integral;
+        }
+
+        output
+    }
+
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+    }
+
+    pub fn set_gains(&mut self, kp: f32, ki: f32) {
+        self.kp = kp;
+        self.ki = ki;
+    }
+
+    /// Returns the (kp, ki) gains
+    pub fn gains(&self) -> (f32, f32) {
+        (self.kp, self.ki)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vehicle::{
+        BRAKE_PRESSURE_AT_MAXIMUM_COMMAND, BRAKE_PRESSURE_LOOP_KI, BRAKE_PRESSURE_LOOP_KP,
+        FAULT_CHECK_PERIOD_MS,
+    };
+
+    const DT_S: f32 = FAULT_CHECK_PERIOD_MS as f32 / 1000.0;
+
+    // brake pressure following the commanded position through a first
+    // order lag
+    struct BrakePlant {
+        pressure: f32,
+        time_constant_s: f32,
+    }
+
+    impl BrakePlant {
+        fn step(&mut self, position: f32) -> f32 {
+            let target = position * BRAKE_PRESSURE_AT_MAXIMUM_COMMAND;
+            self.pressure += (target - self.pressure) * (DT_S / self.time_constant_s);
+            self.pressure
+        }
+    }
+
+    // (settled pressure, peak pressure) after `steps` updates toward
+    // `setpoint`
+    fn run(controller: &mut PiController, setpoint: f32, steps: usize) -> (f32, f32) {
+        let mut plant = BrakePlant {
+            pressure: 0.0,
+            time_constant_s: 0.05,
+        };
+        let mut peak: f32 = 0.0;
+
+        for _ in 0..steps {
+            let position = controller.update(setpoint - plant.pressure, DT_S);
+            peak = peak.max(plant.step(position));
+        }
+
+        (plant.pressure, peak)
+    }
+
+    #[test]
+    fn converges_to_the_setpoint_with_bounded_overshoot() {
+        let mut controller =
+            PiController::new(BRAKE_PRESSURE_LOOP_KP, BRAKE_PRESSURE_LOOP_KI, 0.0, 1.0);
+        let setpoint = 0.5 * BRAKE_PRESSURE_AT_MAXIMUM_COMMAND;
+
+        // 5 seconds of updates
+        let (pressure, peak) = run(&mut controller, setpoint, 1000);
+
+        assert!((pressure - setpoint).abs() < (0.01 * setpoint));
+        assert!(peak < (1.05 * setpoint));
+    }
+
+    #[test]
+    fn saturated_output_does_not_wind_up() {
+        let mut controller =
+            PiController::new(BRAKE_PRESSURE_LOOP_KP, BRAKE_PRESSURE_LOOP_KI, 0.0, 1.0);
+
+        // an unreachable setpoint pins the output at its maximum
+        for _ in 0..1000 {
+            assert_eq!(
+                controller.update(10.0 * BRAKE_PRESSURE_AT_MAXIMUM_COMMAND, DT_S),
+                1.0
+            );
+        }
+
+        // leaves the limit as soon as the error reverses
+        assert!(controller.update(-0.1 * BRAKE_PRESSURE_AT_MAXIMUM_COMMAND, DT_S) < 1.0);
+    }
+
+    #[test]
+    fn reset_clears_the_integral() {
+        let mut controller = PiController::new(0.0, 1.0, -10.0, 10.0);
+        assert_eq!(controller.update(1.0, 1.0), 1.0);
+        assert_eq!(controller.update(1.0, 1.0), 2.0);
+
+        controller.reset();
+        assert_eq!(controller.update(1.0, 1.0), 1.0);
+    }
+}
@@ -1,5 +1,6 @@
 use core::marker::PhantomData;
 use num;
+use num::traits::Saturating;
 
 use typenum::{Unsigned, Cmp, B1, Same};
 // Although this says private, it's needed to write generic inequality
@@ -24,6 +25,15 @@ impl<T: Unsigned> ReifyTo<u16> for T {
     }
 }
 
+/// Why a value was rejected by `Bounded::try_new`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RangeError {
+    /// The value is less than the lower bound
+    BelowLowerBound,
+    /// The value is greater than the upper bound
+    AboveUpperBound,
+}
+
 #[derive(Debug)]
 pub struct Bounded<T,L,U> {
     val: T,
@@ -40,11 +50,38 @@ impl<T: PartialOrd, L: ReifyTo<T>, U: ReifyTo<T>> Bounded<T,L,U> {
         }
     }
 
+    /// Like `clamp`, but rejects out of range values instead of clamping them
+    pub fn try_new(val: T) -> Result<Bounded<T,L,U>, RangeError> {
+        if val < L::reify() {
+            Err(RangeError::BelowLowerBound)
+        } else if val > U::reify() {
+            Err(RangeError::AboveUpperBound)
+        } else {
+            Ok(Bounded {
+                val,
+                _lower_inclusive: PhantomData,
+                _upper_inclusive: PhantomData
+            })
+        }
+    }
+
     pub fn val(&self) -> &T {
         &self.val
     }
 }
 
+impl<T: PartialOrd + Saturating, L: ReifyTo<T>, U: ReifyTo<T>> Bounded<T,L,U> {
+    /// Adds `rhs`, clamping to the bounds rather than wrapping
+    pub fn saturating_add(self, rhs: T) -> Bounded<T,L,U> {
+        Bounded::clamp(self.val.saturating_add(rhs))
+    }
+
+    /// Subtracts `rhs`, clamping to the bounds rather than wrapping
+    pub fn saturating_sub(self, rhs: T) -> Bounded<T,L,U> {
+        Bounded::clamp(self.val.saturating_sub(rhs))
+    }
+}
+
 pub fn coerce<T, Lower1, Upper1, Lower2, Upper2>(b: Bounded<T, Lower1, Upper1>) -> Bounded<T, Lower2, Upper2>
 where
     T: PartialOrd,
@@ -0,0 +1,124 @@
+// Sensor signal ranges learned during a guided calibration, while learning
+// the operator sweeps the pedal (or the wheel) end to end. Harnesses differ
+// slightly in the voltages they present, the learned range replaces the
+// fixed checks' assumptions about them.
+//
+// The learned ranges are kept in the backup SRAM after the fault log.
+
+/// Observed extremes of both channels of a `DualSignal` [ADC steps]
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SignalRange {
+    pub high_min: u16,
+    pub high_max: u16,
+    pub low_min: u16,
+    pub low_max: u16,
+}
+
+impl SignalRange {
+    /// An empty range, the first recorded sample sets it
+    pub const fn new() -> Self {
+        SignalRange {
+            high_min: 0xFFFF,
+            high_max: 0,
+            low_min: 0xFFFF,
+            low_max: 0,
+        }
+    }
+
+    /// Widens the range to include the sample
+    pub fn record(&mut self, high: u16, low: u16) {
+        self.high_min = self.high_min.min(high);
+        self.high_max = self.high_max.max(high);
+        self.low_min = self.low_min.min(low);
+        self.low_max = self.low_max.max(low);
+    }
+
+    /// False until a sample has been recorded
+    pub fn is_valid(&self) -> bool {
+        (self.high_min <= self.high_max) && (self.low_min <= self.low_max)
+    }
+
+    /// True when either channel is more than `margin` outside the range
+    pub fn excludes(&self, high: u16, low: u16, margin: u16) -> bool {
+        (high < self.high_min.saturating_sub(margin))
+            || (high > self.high_max.saturating_add(margin))
+            || (low < self.low_min.saturating_sub(margin))
+            || (low > self.low_max.saturating_add(margin))
+    }
+}
+
+/// The signals a range is learned for
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RangeSignal {
+    BrakePedalPosition,
+    AcceleratorPosition,
+    SteeringTorque,
+}
+
+const RANGE_SIGNAL_COUNT: usize = 3;
+
+impl RangeSignal {
+    fn index(&self) -> usize {
+        match *self {
+            RangeSignal::BrakePedalPosition => 0,
+            RangeSignal::AcceleratorPosition => 1,
+            RangeSignal::SteeringTorque => 2,
+        }
+    }
+}
+
+// marks the backup SRAM as holding learned ranges
+const LEARNED_RANGES_MAGIC: u32 = 0x0CCF_5167;
+
+/// Layout of the learned ranges in the backup SRAM, a signal without a
+/// learned range holds an empty range
+#[repr(C)]
+pub struct LearnedRangeStorage {
+    magic: u32,
+    ranges: [SignalRange; RANGE_SIGNAL_COUNT],
+}
+
+impl LearnedRangeStorage {
+    pub const fn new() -> Self {
+        LearnedRangeStorage {
+            magic: LEARNED_RANGES_MAGIC,
+            ranges: [SignalRange::new(); RANGE_SIGNAL_COUNT],
+        }
+    }
+}
+
+pub struct LearnedRanges {
+    storage: &'static mut LearnedRangeStorage,
+}
+
+impl LearnedRanges {
+    /// Keeps the ranges already in `storage`, or starts with none learned
+    /// if it does not hold valid ranges
+    pub fn new(storage: &'static mut LearnedRangeStorage) -> Self {
+        if storage.magic != LEARNED_RANGES_MAGIC {
+            *storage = LearnedRangeStorage::new();
+        }
+
+        LearnedRanges { storage }
+    }
+
+    pub fn store(&mut self, signal: RangeSignal, range: SignalRange) {
+        self.storage.ranges[signal.index()] = range;
+    }
+
+    /// The stored range, None if no range was learned
+    pub fn get(&self, signal: RangeSignal) -> Option<SignalRange> {
+        let range = self.storage.ranges[signal.index()];
+
+        if range.is_valid() {
+            Some(range)
+        } else {
+            None
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self.storage = LearnedRangeStorage::new();
+    }
+}
@@ -0,0 +1,536 @@
+//! In-memory board backend for running the control logic without hardware
+//!
+//! The drivers already consume their peripherals through traits: the DACs
+//! through the embedded-hal SPI and `OutputPin` traits, the relay sense and
+//! override inputs through `InputPin` and the sensors through
+//! `HighLowReader`. The mocks here implement those traits, record what was
+//! written and serve scripted values.
+//!
+//! The host tests and the `sim` feature build the control modules on these,
+//! the module peripheral types in `types` and the sensor types in `board`
+//! name them instead of the board peripherals.
+
+use board::AdcSignal;
+use config::VDDA_NOMINAL;
+use core::cell::Cell;
+use core::fmt;
+use dac_mcp4922::Mcp4922;
+use dual_signal::HighLowReader;
+use embedded_hal::blocking::spi::Write;
+use embedded_hal::digital::{InputPin, OutputPin};
+use nucleo_f767zi::hal::can::{BaseID, CanError, CanFrame, DataFrame, ID};
+use nucleo_f767zi::hal::spi;
+use oscc_crc::{self, OSCC_CRC_BYTE_INDEX};
+use oscc_magic_byte::{OSCC_MAGIC_BYTE_0, OSCC_MAGIC_BYTE_1};
+use types::{BrakePins, SteeringPins, ThrottlePins};
+
+// MCP4922 commands are one 16 bit word
+const SIM_SPI_HISTORY: usize = 16;
+
+const SIM_CAN_QUEUE_DEPTH: usize = 8;
+
+/// SPI bus that records every 16 bit word written to it
+pub struct SimSpi {
+    words: [u16; SIM_SPI_HISTORY],
+    count: usize,
+    failing: bool,
+}
+
+impl SimSpi {
+    pub fn new() -> Self {
+        SimSpi {
+            words: [0; SIM_SPI_HISTORY],
+            count: 0,
+            failing: false,
+        }
+    }
+
+    /// While set every write fails with a mode fault and nothing is recorded
+    pub fn set_failing(&mut self, failing: bool) {
+        self.failing = failing;
+    }
+
+    /// Number of words written, including those no longer in the history
+    pub fn write_count(&self) -> usize {
+        self.count
+    }
+
+    /// Most recently written word
+    pub fn last_word(&self) -> Option<u16> {
+        self.word(0)
+    }
+
+    /// Word written `back` words before the most recent one, None if it is
+    /// no longer in the history
+    pub fn word(&self, back: usize) -> Option<u16> {
+        if (back >= self.count) || (back >= SIM_SPI_HISTORY) {
+            None
+        } else {
+            Some(self.words[(self.count - 1 - back) % SIM_SPI_HISTORY])
+        }
+    }
+}
+
+impl Write<u8> for SimSpi {
+    type Error = spi::Error;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), spi::Error> {
+        if self.failing {
+            return Err(spi::Error::ModeFault);
+        }
+
+        for pair in words.chunks(2) {
+            let word = if pair.len() == 2 {
+                (u16::from(pair[0]) << 8) | u16::from(pair[1])
+            } else {
+                u16::from(pair[0]) << 8
+            };
+
+            self.words[self.count % SIM_SPI_HISTORY] = word;
+            self.count += 1;
+        }
+
+        Ok(())
+    }
+}
+
+/// Output pin that remembers its last state
+pub struct SimPin {
+    high: bool,
+}
+
+impl SimPin {
+    pub fn new() -> Self {
+        SimPin { high: false }
+    }
+
+    pub fn is_high(&self) -> bool {
+        self.high
+    }
+}
+
+impl OutputPin for SimPin {
+    fn set_low(&mut self) {
+        self.high = false;
+    }
+    fn set_high(&mut self) {
+        self.high = true;
+    }
+}
+
+/// Input pin reading a fixed level, replaced to change it
+pub struct SimInputPin {
+    high: bool,
+}
+
+impl SimInputPin {
+    pub fn new(high: bool) -> Self {
+        SimInputPin { high }
+    }
+}
+
+impl InputPin for SimInputPin {
+    fn is_high(&self) -> bool {
+        self.high
+    }
+    fn is_low(&self) -> bool {
+        !self.high
+    }
+}
+
+/// Stands in for an analog input pin, the sensor serves the readings
+pub struct SimAnalogPin;
+
+/// Console that counts the lines logged to it
+pub struct SimConsole {
+    lines: usize,
+}
+
+impl SimConsole {
+    pub fn new() -> Self {
+        SimConsole { lines: 0 }
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.lines
+    }
+}
+
+impl fmt::Write for SimConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.lines += s.matches('\n').count();
+        Ok(())
+    }
+}
+
+/// Dual signal sensor serving scripted ADC values
+pub struct SimSensor {
+    high: Cell<u16>,
+    low: Cell<u16>,
+}
+
+impl SimSensor {
+    pub fn new(high: u16, low: u16) -> Self {
+        SimSensor {
+            high: Cell::new(high),
+            low: Cell::new(low),
+        }
+    }
+
+    pub fn set(&self, high: u16, low: u16) {
+        self.high.set(high);
+        self.low.set(low);
+    }
+}
+
+impl HighLowReader for SimSensor {
+    fn read_high(&self) -> u16 {
+        self.high.get()
+    }
+    fn read_low(&self) -> u16 {
+        self.low.get()
+    }
+}
+
+/// Brake pedal position sensor on ADC1, which also measures VDDA and, with
+/// the pressure loop, the brake pressure
+pub struct SimBrakePedalPositionSensor {
+    position: SimSensor,
+    pressure: Cell<u16>,
+    vdda: Cell<f32>,
+}
+
+impl SimBrakePedalPositionSensor {
+    /// VDDA starts out at VDDA_NOMINAL and the pressure at zero
+    pub fn new(high: u16, low: u16) -> Self {
+        SimBrakePedalPositionSensor {
+            position: SimSensor::new(high, low),
+            pressure: Cell::new(0),
+            vdda: Cell::new(VDDA_NOMINAL),
+        }
+    }
+
+    pub fn set(&self, high: u16, low: u16) {
+        self.position.set(high, low);
+    }
+
+    pub fn set_pressure(&self, pressure: u16) {
+        self.pressure.set(pressure);
+    }
+
+    pub fn set_vdda(&self, vdda: f32) {
+        self.vdda.set(vdda);
+    }
+
+    /// The internal reference reads zero, `read_vdda` serves VDDA directly
+    pub fn read_signal(&self, signal: AdcSignal) -> u16 {
+        match signal {
+            AdcSignal::BrakePedalPositionHigh => self.position.read_high(),
+            AdcSignal::BrakePedalPositionLow => self.position.read_low(),
+            #[cfg(feature = "brake-pressure-loop")]
+            AdcSignal::BrakePressure => self.pressure.get(),
+            AdcSignal::InternalReference => 0,
+        }
+    }
+
+    /// [volts]
+    pub fn read_vdda(&self) -> f32 {
+        self.vdda.get()
+    }
+}
+
+impl HighLowReader for SimBrakePedalPositionSensor {
+    fn read_high(&self) -> u16 {
+        self.position.read_high()
+    }
+    fn read_low(&self) -> u16 {
+        self.position.read_low()
+    }
+}
+
+#[derive(Copy, Clone)]
+struct SimCanFrame {
+    id: u16,
+    dlc: u8,
+    data: [u8; 8],
+}
+
+/// Fixed depth FIFO of base ID data frames
+struct SimCanQueue {
+    frames: [SimCanFrame; SIM_CAN_QUEUE_DEPTH],
+    head: usize,
+    len: usize,
+}
+
+impl SimCanQueue {
+    fn new() -> Self {
+        SimCanQueue {
+            frames: [SimCanFrame {
+                id: 0,
+                dlc: 0,
+                data: [0; 8],
+            }; SIM_CAN_QUEUE_DEPTH],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, frame: SimCanFrame) -> Result<(), CanError> {
+        if self.len == SIM_CAN_QUEUE_DEPTH {
+            return Err(CanError::BufferExhausted);
+        }
+
+        self.frames[(self.head + self.len) % SIM_CAN_QUEUE_DEPTH] = frame;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<SimCanFrame> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let frame = self.frames[self.head];
+        self.head = (self.head + 1) % SIM_CAN_QUEUE_DEPTH;
+        self.len -= 1;
+        Some(frame)
+    }
+}
+
+/// CAN bus with separate RX (injected) and TX (recorded) queues
+///
+/// `receive` and `transmit` mirror the HAL `Can` signatures, frames that
+/// are not base ID data frames are dropped on transmit.
+pub struct SimCan {
+    rx: SimCanQueue,
+    tx: SimCanQueue,
+}
+
+impl SimCan {
+    pub fn new() -> Self {
+        SimCan {
+            rx: SimCanQueue::new(),
+            tx: SimCanQueue::new(),
+        }
+    }
+
+    /// Queues a frame to be returned by `receive`
+    pub fn inject_rx(&mut self, id: u16, data: &[u8]) -> Result<(), CanError> {
+        let mut frame = SimCanFrame {
+            id,
+            dlc: data.len().min(8) as u8,
+            data: [0; 8],
+        };
+        frame.data[..frame.dlc as usize].copy_from_slice(&data[..frame.dlc as usize]);
+        self.rx.push(frame)
+    }
+
+    /// Oldest transmitted frame as (id, dlc, data)
+    pub fn pop_tx(&mut self) -> Option<(u16, u8, [u8; 8])> {
+        self.tx.pop().map(|f| (f.id, f.dlc, f.data))
+    }
+
+    pub fn tx_pending(&self) -> usize {
+        self.tx.len
+    }
+
+    pub fn receive(&mut self) -> Result<CanFrame, CanError> {
+        if let Some(f) = self.rx.pop() {
+            let mut frame = DataFrame::new(ID::BaseID(BaseID::new(f.id)));
+            frame.set_data_length(f.dlc as _);
+            frame.data_as_mut()[..f.dlc as usize].copy_from_slice(&f.data[..f.dlc as usize]);
+            Ok(frame.into())
+        } else {
+            Err(CanError::BufferExhausted)
+        }
+    }
+
+    pub fn transmit(&mut self, frame: &CanFrame) -> Result<(), CanError> {
+        if let CanFrame::DataFrame(ref frame) = frame {
+            if let ID::BaseID(_) = frame.id() {
+                let id: u32 = frame.id().into();
+                let data = frame.data();
+                let mut sim_frame = SimCanFrame {
+                    id: id as u16,
+                    dlc: data.len() as u8,
+                    data: [0; 8],
+                };
+                sim_frame.data[..data.len()].copy_from_slice(data);
+                return self.tx.push(sim_frame);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// An eight byte OSCC frame with a valid CRC, `payload` follows the magic
+/// bytes and the rest is zero
+pub fn oscc_frame(id: u16, payload: &[u8]) -> CanFrame {
+    let mut frame = DataFrame::new(ID::BaseID(BaseID::new(id)));
+    frame.set_data_length(8);
+    {
+        let data = frame.data_as_mut();
+        data[0] = OSCC_MAGIC_BYTE_0;
+        data[1] = OSCC_MAGIC_BYTE_1;
+        data[2..(2 + payload.len())].copy_from_slice(payload);
+        data[OSCC_CRC_BYTE_INDEX] = oscc_crc::oscc_crc8(&data[..OSCC_CRC_BYTE_INDEX]);
+    }
+    frame.into()
+}
+
+pub type SimDac = Mcp4922<SimSpi, SimPin>;
+
+/// Simulated counterpart of the board components the control modules use,
+/// split up to build the modules like `FullBoard::split_components`
+///
+/// No relay sense inputs or override switch are installed, the sensors read
+/// zero and VDDA is nominal.
+pub struct SimBoard {
+    pub debug_console: SimConsole,
+    pub brake_dac: SimDac,
+    pub brake_pins: BrakePins,
+    pub brake_pedal_position: SimBrakePedalPositionSensor,
+    pub throttle_dac: SimDac,
+    pub throttle_pins: ThrottlePins,
+    pub accelerator_position: SimSensor,
+    pub steering_dac: SimDac,
+    pub steering_pins: SteeringPins,
+    pub torque_sensor: SimSensor,
+    pub control_can: SimCan,
+}
+
+impl SimBoard {
+    pub fn new() -> Self {
+        SimBoard {
+            debug_console: SimConsole::new(),
+            brake_dac: Mcp4922::new(SimSpi::new(), SimPin::new()),
+            brake_pins: BrakePins {
+                spoof_enable: SimPin::new(),
+                spoof_enable_sense: None,
+                brake_light_enable: SimPin::new(),
+                pedal_pos_sensor_high: SimAnalogPin,
+                pedal_pos_sensor_low: SimAnalogPin,
+                override_switch: None,
+                #[cfg(feature = "brake-pressure-loop")]
+                pressure_sensor: SimAnalogPin,
+            },
+            brake_pedal_position: SimBrakePedalPositionSensor::new(0, 0),
+            throttle_dac: Mcp4922::new(SimSpi::new(), SimPin::new()),
+            throttle_pins: ThrottlePins {
+                spoof_enable: SimPin::new(),
+                spoof_enable_sense: None,
+                accel_pos_sensor_high: SimAnalogPin,
+                accel_pos_sensor_low: SimAnalogPin,
+            },
+            accelerator_position: SimSensor::new(0, 0),
+            steering_dac: Mcp4922::new(SimSpi::new(), SimPin::new()),
+            steering_pins: SteeringPins {
+                spoof_enable: SimPin::new(),
+                spoof_enable_sense: None,
+                torque_sensor_high: SimAnalogPin,
+                torque_sensor_low: SimAnalogPin,
+            },
+            torque_sensor: SimSensor::new(0, 0),
+            control_can: SimCan::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write as FmtWrite;
+
+    #[test]
+    fn spi_records_the_words_written() {
+        let mut spi = SimSpi::new();
+        assert_eq!(spi.last_word(), None);
+
+        spi.write(&[0x30, 0x0F, 0xB1, 0x23]).unwrap();
+
+        assert_eq!(spi.write_count(), 2);
+        assert_eq!(spi.last_word(), Some(0xB123));
+    }
+
+    #[test]
+    fn failing_spi_records_nothing() {
+        let mut spi = SimSpi::new();
+        spi.set_failing(true);
+
+        assert_eq!(spi.write(&[0x30, 0x0F]), Err(spi::Error::ModeFault));
+        assert_eq!(spi.write_count(), 0);
+
+        spi.set_failing(false);
+        spi.write(&[0x30, 0x0F]).unwrap();
+        assert_eq!(spi.last_word(), Some(0x300F));
+    }
+
+    #[test]
+    fn can_returns_injected_frames_in_order() {
+        let mut can = SimCan::new();
+        can.inject_rx(0x70, &[0x05, 0xCC]).unwrap();
+        can.inject_rx(0x71, &[0x05, 0xCC, 0x01]).unwrap();
+
+        for &(id, dlc) in &[(0x70u32, 2), (0x71, 3)] {
+            match can.receive() {
+                Ok(CanFrame::DataFrame(ref frame)) => {
+                    let frame_id: u32 = frame.id().into();
+                    assert_eq!(frame_id, id);
+                    assert_eq!(frame.data().len(), dlc);
+                }
+                _ => panic!("expected a data frame"),
+            }
+        }
+
+        assert!(can.receive().is_err());
+    }
+
+    #[test]
+    fn can_records_transmitted_frames() {
+        let mut can = SimCan::new();
+        let mut frame = DataFrame::new(ID::BaseID(BaseID::new(0x73)));
+        frame.set_data_length(2);
+        frame.data_as_mut().copy_from_slice(&[0x05, 0xCC]);
+
+        can.transmit(&frame.into()).unwrap();
+
+        assert_eq!(can.tx_pending(), 1);
+        let (id, dlc, data) = can.pop_tx().unwrap();
+        assert_eq!((id, dlc), (0x73, 2));
+        assert_eq!(&data[..2], &[0x05, 0xCC]);
+        assert_eq!(can.pop_tx(), None);
+    }
+
+    #[test]
+    fn full_rx_queue_refuses_frames() {
+        let mut can = SimCan::new();
+        for _ in 0..SIM_CAN_QUEUE_DEPTH {
+            can.inject_rx(0x70, &[]).unwrap();
+        }
+
+        assert_eq!(can.inject_rx(0x70, &[]), Err(CanError::BufferExhausted));
+    }
+
+    #[test]
+    fn console_counts_lines() {
+        let mut console = SimConsole::new();
+        write!(console, "first\nsecond").unwrap();
+        writeln!(console, " line").unwrap();
+
+        assert_eq!(console.line_count(), 2);
+    }
+
+    #[test]
+    fn brake_sensor_serves_the_scripted_readings() {
+        let sensor = SimBrakePedalPositionSensor::new(400, 195);
+        assert_eq!((sensor.read_high(), sensor.read_low()), (400, 195));
+        assert_eq!(sensor.read_vdda(), VDDA_NOMINAL);
+
+        sensor.set(1200, 585);
+        sensor.set_vdda(2.9);
+        assert_eq!(sensor.read_signal(AdcSignal::BrakePedalPositionHigh), 1200);
+        assert_eq!(sensor.read_signal(AdcSignal::BrakePedalPositionLow), 585);
+        assert_eq!(sensor.read_vdda(), 2.9);
+    }
+}
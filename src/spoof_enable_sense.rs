@@ -0,0 +1,47 @@
+// Read-back of the spoof enable relays. A sense input wired to the switched
+// side of a relay shows whether it followed its enable pin, a disagreement
+// outlasting the relay's switching time means the relay is stuck.
+
+use config::SPOOF_ENABLE_SENSE_SETTLE_MS;
+use embedded_hal::digital::InputPin;
+use fault_condition::FaultCondition;
+
+pub trait EnableReadback {
+    /// True once the sensed relay state has disagreed with `commanded` for
+    /// SPOOF_ENABLE_SENSE_SETTLE_MS
+    fn is_stuck(&mut self, commanded: bool) -> bool;
+}
+
+/// An uninstalled sense input never reports a stuck relay
+impl<S: EnableReadback> EnableReadback for Option<S> {
+    fn is_stuck(&mut self, commanded: bool) -> bool {
+        match *self {
+            Some(ref mut sense) => sense.is_stuck(commanded),
+            None => false,
+        }
+    }
+}
+
+/// A pulled-down input reading high while the relay is closed
+pub struct SpoofEnableSense<P: InputPin> {
+    pin: P,
+    mismatch_state: FaultCondition,
+}
+
+impl<P: InputPin> SpoofEnableSense<P> {
+    pub fn new(pin: P) -> Self {
+        SpoofEnableSense {
+            pin,
+            mismatch_state: FaultCondition::with_hysteresis(SPOOF_ENABLE_SENSE_SETTLE_MS),
+        }
+    }
+}
+
+impl<P: InputPin> EnableReadback for SpoofEnableSense<P> {
+    fn is_stuck(&mut self, commanded: bool) -> bool {
+        let sensed = self.pin.is_high();
+
+        self.mismatch_state
+            .condition_exceeded_duration(sensed != commanded)
+    }
+}
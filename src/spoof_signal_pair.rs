@@ -0,0 +1,199 @@
+use dac_mcp4922::DacOutput;
+use num;
+use units::{DacSteps, Volts};
+use vehicle::STEPS_PER_VOLT;
+
+/// Converts a voltage to DAC steps, saturating at the DAC range limits
+pub fn volts_to_dac_steps(volts: f32, steps_per_volt: f32) -> DacOutput {
+    DacOutput::clamp(DacSteps::from_volts(Volts(volts), steps_per_volt).0)
+}
+
+// rescales a step count for the vehicle's STEPS_PER_VOLT to `steps_per_volt`,
+// rounding to the nearest step
+fn rescale_steps(steps: u16, steps_per_volt: f32) -> u16 {
+    let scaled = (f32::from(steps) * steps_per_volt / STEPS_PER_VOLT) + 0.5;
+    num::clamp(scaled, 0.0, f32::from(u16::max_value())) as u16
+}
+
+/// Moves `current` towards `target` by at most `max_delta`
+pub fn ramp_toward(current: u16, target: u16, max_delta: u16) -> u16 {
+    if target > current {
+        current.saturating_add(max_delta).min(target)
+    } else {
+        current.saturating_sub(max_delta).max(target)
+    }
+}
+
+/// Which DAC channel each spoof signal is wired to
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SignalMapping {
+    /// High spoof signal on DAC channel A, low on channel B
+    HighToALowToB,
+    /// High spoof signal on DAC channel B, low on channel A
+    HighToBLowToA,
+}
+
+impl Default for SignalMapping {
+    fn default() -> Self {
+        SignalMapping::HighToALowToB
+    }
+}
+
+impl SignalMapping {
+    /// Orders (high, low) as (channel A, channel B)
+    pub fn route<T>(&self, high: T, low: T) -> (T, T) {
+        match *self {
+            SignalMapping::HighToALowToB => (high, low),
+            SignalMapping::HighToBLowToA => (low, high),
+        }
+    }
+}
+
+/// Per-module voltage and step limits of the spoof signals, the step limits
+/// are for the vehicle's STEPS_PER_VOLT
+pub struct SpoofSignalCalibration {
+    pub high_voltage_min: f32,
+    pub high_voltage_max: f32,
+    pub low_voltage_min: f32,
+    pub low_voltage_max: f32,
+    pub high_range_min: u16,
+    pub high_range_max: u16,
+    pub low_range_min: u16,
+    pub low_range_max: u16,
+}
+
+/// High and low spoof signal values [steps], clamped to their signal ranges
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SpoofSignalPair {
+    high: u16,
+    low: u16,
+    clamped: bool,
+}
+
+impl SpoofSignalPair {
+    /// Clamps the voltages to the calibrated limits before converting them
+    /// to steps with `steps_per_volt`, the step limits are rescaled to match
+    pub fn from_volts(
+        high: Volts,
+        low: Volts,
+        calibration: &SpoofSignalCalibration,
+        steps_per_volt: f32,
+    ) -> Self {
+        let spoof_voltage_high: f32 = num::clamp(
+            high.0,
+            calibration.high_voltage_min,
+            calibration.high_voltage_max,
+        );
+
+        let spoof_voltage_low: f32 = num::clamp(
+            low.0,
+            calibration.low_voltage_min,
+            calibration.low_voltage_max,
+        );
+
+        let steps_high = DacSteps::from_volts(Volts(spoof_voltage_high), steps_per_volt).0;
+        let steps_low = DacSteps::from_volts(Volts(spoof_voltage_low), steps_per_volt).0;
+
+        let spoof_high = num::clamp(
+            steps_high,
+            rescale_steps(calibration.high_range_min, steps_per_volt),
+            rescale_steps(calibration.high_range_max, steps_per_volt),
+        );
+        let spoof_low = num::clamp(
+            steps_low,
+            rescale_steps(calibration.low_range_min, steps_per_volt),
+            rescale_steps(calibration.low_range_max, steps_per_volt),
+        );
+
+        SpoofSignalPair {
+            high: spoof_high,
+            low: spoof_low,
+            clamped: (spoof_voltage_high != high.0)
+                || (spoof_voltage_low != low.0)
+                || (spoof_high != steps_high)
+                || (spoof_low != steps_low),
+        }
+    }
+
+    pub fn high(&self) -> u16 {
+        self.high
+    }
+
+    /// True if either signal was limited to its voltage or step range
+    pub fn was_clamped(&self) -> bool {
+        self.clamped
+    }
+
+    pub fn low(&self) -> u16 {
+        self.low
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CALIBRATION: SpoofSignalCalibration = SpoofSignalCalibration {
+        high_voltage_min: 0.5,
+        high_voltage_max: 2.0,
+        low_voltage_min: 0.25,
+        low_voltage_max: 1.0,
+        high_range_min: 100,
+        high_range_max: 4000,
+        low_range_min: 50,
+        low_range_max: 4000,
+    };
+
+    #[test]
+    fn ramp_is_limited_and_stops_at_the_target() {
+        assert_eq!(ramp_toward(100, 200, 30), 130);
+        assert_eq!(ramp_toward(190, 200, 30), 200);
+        assert_eq!(ramp_toward(200, 100, 30), 170);
+        assert_eq!(ramp_toward(110, 100, 30), 100);
+        assert_eq!(ramp_toward(10, 0, 30), 0);
+        assert_eq!(
+            ramp_toward(u16::max_value() - 5, u16::max_value(), 30),
+            u16::max_value()
+        );
+    }
+
+    #[test]
+    fn large_step_is_reached_over_several_loops() {
+        let (mut high, mut low) = (600, 300);
+        let mut loops = 0;
+
+        while (high, low) != (3000, 1500) {
+            high = ramp_toward(high, 3000, 100);
+            low = ramp_toward(low, 1500, 100);
+            loops += 1;
+        }
+
+        assert_eq!(loops, 24);
+    }
+
+    #[test]
+    fn mapping_routes_high_and_low() {
+        assert_eq!(SignalMapping::HighToALowToB.route(1, 2), (1, 2));
+        assert_eq!(SignalMapping::HighToBLowToA.route(1, 2), (2, 1));
+    }
+
+    #[test]
+    fn in_range_voltages_are_not_clamped() {
+        let pair =
+            SpoofSignalPair::from_volts(Volts(1.0), Volts(0.5), &CALIBRATION, STEPS_PER_VOLT);
+        assert!(!pair.was_clamped());
+        assert!(pair.high() > pair.low());
+    }
+
+    #[test]
+    fn out_of_range_voltages_are_clamped() {
+        let pair =
+            SpoofSignalPair::from_volts(Volts(5.0), Volts(0.0), &CALIBRATION, STEPS_PER_VOLT);
+        assert!(pair.was_clamped());
+
+        let limit =
+            SpoofSignalPair::from_volts(Volts(2.0), Volts(0.25), &CALIBRATION, STEPS_PER_VOLT);
+        assert_eq!(pair.high(), limit.high());
+        assert_eq!(pair.low(), limit.low());
+    }
+}
@@ -1,29 +1,56 @@
 // https://github.com/jonlamb-gh/oscc/tree/devel/firmware/steering
 
-use board::TorqueSensor;
-use core::fmt::Write;
-use dac_mcp4922::DacOutput;
+use board::{SupplyVoltage, TorqueSensor};
+use can_health::CanHealth;
+use config::CanIdOffset;
+use control_ack_can_protocol::*;
+use control_module::{ControlModule, ControlState, ReportPublisher};
+use dac_mcp4922::{Channel, DacOutput};
 use dtc::DtcBitfield;
 use dual_signal::DualSignal;
+use emergency_stop_can_protocol::*;
 use fault_can_protocol::*;
 use fault_condition::FaultCondition;
-use nucleo_f767zi::debug_console::DebugConsole;
-use nucleo_f767zi::hal::can::CanFrame;
+use log::{Level, Module};
+use module_info_can_protocol::*;
+use nucleo_f767zi::hal::can::{CanError, CanFrame};
 use nucleo_f767zi::hal::prelude::*;
 use num;
+use oscc_crc;
 use oscc_magic_byte::*;
 use oxcc_error::OxccError;
-use ranges;
+use param_can_protocol::*;
+use signal_range::SignalRange;
+use spoof_enable_sense::EnableReadback;
+use spoof_signal_pair::{ramp_toward, volts_to_dac_steps};
 use steering_can_protocol::*;
+use system_clock;
 use types::*;
 use vehicle::*;
 
 const FILTER_ALPHA: f32 = 0.01_f32;
 
 struct SteeringControlState<DTCS: DtcBitfield> {
-    enabled: bool,
-    operator_override: bool,
+    /// `Faulted` is left once the torque sensor reads plausibly again
+    state: ControlState,
     dtcs: DTCS,
+    last_command_ms: u32,
+    /// Last (high, low) spoof values written, used to ramp limit commands
+    last_spoof: Option<(u16, u16)>,
+    max_spoof_delta: u16,
+    /// Direction of the last nonzero torque request, true if positive
+    last_direction: Option<bool>,
+    reversal_window_start_ms: u32,
+    reversals: u8,
+    /// Set when too many reversals faulted and disabled control, keeps the
+    /// direction reversal DTC until control is enabled again
+    direction_interlock: bool,
+    /// The interlock disabled control, the fault report is yet to be
+    /// returned by `check_for_faults`
+    direction_reversal_fault: bool,
+    /// The spoof enable relay sense input disagrees with the enable pin,
+    /// control is not enabled while it does
+    spoof_enable_mismatch: bool,
 }
 
 impl<DTCS> SteeringControlState<DTCS>
@@ -32,20 +59,44 @@ where
 {
     pub const fn new(dtcs: DTCS) -> Self {
         SteeringControlState {
-            enabled: false,
-            operator_override: false,
+            state: ControlState::Disabled,
             dtcs,
+            last_command_ms: 0,
+            last_spoof: None,
+            max_spoof_delta: STEERING_MAX_SPOOF_DELTA,
+            last_direction: None,
+            reversal_window_start_ms: 0,
+            reversals: 0,
+            direction_interlock: false,
+            direction_reversal_fault: false,
+            spoof_enable_mismatch: false,
         }
     }
 }
 
+/// Read-only copy of the steering control state
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SteeringStateSnapshot {
+    pub control_state: ControlState,
+    pub enabled: bool,
+    pub operator_override: bool,
+    pub dtcs: u8,
+}
+
 pub struct SteeringModule {
     steering_torque: DualSignal<TorqueSensor>,
     control_state: SteeringControlState<u8>,
-    grounded_fault_state: FaultCondition<SteeringGroundedFaultTimer>,
+    grounded_fault_state: FaultCondition,
+    mismatch_fault_state: FaultCondition,
+    override_release_state: FaultCondition,
     filtered_diff: u16,
     steering_report: OsccSteeringReport,
     fault_report: OsccFaultReport,
+    module_info: OsccModuleInfo,
+    module_info_requested: bool,
+    control_ack: Option<OsccControlAck>,
+    param_value: Option<OsccParamValue>,
+    can_id_offset: CanIdOffset,
     steering_dac: SteeringDac,
     steering_pins: SteeringPins,
 }
@@ -59,76 +110,197 @@ impl UnpreparedSteeringModule {
         torque_sensor: TorqueSensor,
         steering_dac: SteeringDac,
         steering_pins: SteeringPins,
-        grounded_fault_timer: SteeringGroundedFaultTimer,
     ) -> Self {
         UnpreparedSteeringModule {
             steering_module: SteeringModule {
                 steering_torque: DualSignal::new(0, 0, torque_sensor),
                 control_state: SteeringControlState::new(u8::default()),
-                grounded_fault_state: FaultCondition::new(grounded_fault_timer),
+                grounded_fault_state: FaultCondition::with_hysteresis(SENSOR_FAULT_HYSTERESIS),
+                mismatch_fault_state: FaultCondition::new(),
+                override_release_state: FaultCondition::with_hysteresis(
+                    OPERATOR_OVERRIDE_RELEASE_DEBOUNCE,
+                ),
                 filtered_diff: 0,
                 steering_report: OsccSteeringReport::new(),
                 fault_report: OsccFaultReport {
                     fault_origin_id: FAULT_ORIGIN_STEERING,
                     dtcs: 0,
+                    extended_dtcs: 0,
                 },
+                module_info: OsccModuleInfo::new(FAULT_ORIGIN_STEERING),
+                module_info_requested: false,
+                control_ack: None,
+                param_value: None,
+                can_id_offset: CanIdOffset::none(),
                 steering_dac,
                 steering_pins,
             },
         }
     }
 
-    pub fn prepare_module(self) -> SteeringModule {
+    /// Releases the spoof output and sets the DAC to the zero torque spoof
+    /// values
+    pub fn prepare_module(self, debug_console: &mut DebugConsole) -> SteeringModule {
         let mut steering_module = self.steering_module;
         steering_module.steering_pins.spoof_enable.set_low();
+
+        let (centered_high, centered_low) =
+            centered_spoof_values(steering_module.steering_dac.steps_per_volt());
+        steering_module.steering_dac.wake(Channel::ChannelA);
+        steering_module.steering_dac.wake(Channel::ChannelB);
+        if let Err(e) = steering_module.write_spoof(centered_high, centered_low) {
+            log!(
+                debug_console,
+                Module::Steering,
+                Level::Error,
+                "Steering DAC initialization failed: {:?}",
+                e
+            );
+        }
+
         steering_module
     }
 }
 
 impl SteeringModule {
+    /// An active override or fault is kept
     pub fn disable_control(&mut self, debug_console: &mut DebugConsole) -> Result<(), OxccError> {
-        if self.control_state.enabled {
-            self.steering_torque.prevent_signal_discontinuity();
+        if self.control_state.state == ControlState::Enabled {
+            self.transition_to(ControlState::Disabled, debug_console)?;
+        }
+
+        Ok(())
+    }
 
-            let result = self.steering_dac.output_ab(
-                DacOutput::clamp(self.steering_torque.low()),
-                DacOutput::clamp(self.steering_torque.high()),
+    pub fn control_state(&self) -> ControlState {
+        self.control_state.state
+    }
+
+    /// Moves the control state machine to `next`, all enable and disable
+    /// side effects happen here. Returns false, leaving the state unchanged,
+    /// if `next` can't be reached from the current state.
+    ///
+    /// Leaving `Enabled` hands the output back to the torque sensor,
+    /// entering it expects the DAC to already match the sensor.
+    fn transition_to(
+        &mut self,
+        next: ControlState,
+        debug_console: &mut DebugConsole,
+    ) -> Result<bool, OxccError> {
+        let previous = self.control_state.state;
+
+        if previous == next {
+            return Ok(true);
+        }
+
+        if !previous.can_transition_to(next) {
+            log!(
+                debug_console,
+                Module::Steering,
+                Level::Warn,
+                "Rejected steering control transition {:?} -> {:?}",
+                previous,
+                next
             );
+            return Ok(false);
+        }
+
+        self.control_state.state = next;
+
+        log!(
+            debug_console,
+            Module::Steering,
+            Level::Info,
+            "Steering control {:?} -> {:?}",
+            previous,
+            next
+        );
+
+        if next == ControlState::Enabled {
+            self.steering_pins.spoof_enable.set_high();
+            self.control_state.last_direction = None;
+            self.control_state.reversals = 0;
+            self.control_state.direction_interlock = false;
+        } else if previous == ControlState::Enabled {
+            self.steering_torque.prevent_signal_discontinuity();
+
+            let torque_high = self.steering_torque.high();
+            let torque_low = self.steering_torque.low();
+            let result = self.write_spoof(torque_high, torque_low);
 
             // even if we've encountered an error, we can still disable
             self.steering_pins.spoof_enable.set_low();
-            self.control_state.enabled = false;
-            writeln!(debug_console, "Steering control disabled");
 
-            return if let Err(e) = result {
-                Err(OxccError::from(e))
-            } else {
-                Ok(())
-            };
+            result?;
         }
 
-        Ok(())
+        Ok(true)
+    }
+
+    /// Time since the last steering command frame was received [ms]
+    pub fn time_since_last_command_ms(&self) -> u32 {
+        system_clock::get_timestamp_ms().wrapping_sub(self.control_state.last_command_ms)
     }
 
     pub fn enable_control(&mut self, debug_console: &mut DebugConsole) -> Result<(), OxccError> {
-        if !self.control_state.enabled && !self.control_state.operator_override {
-            self.steering_torque.prevent_signal_discontinuity();
+        if SYSTEM_FAULT_STATE.is_latched() {
+            log!(
+                debug_console,
+                Module::Steering,
+                Level::Warn,
+                "Steering control not enabled, system fault latched"
+            );
+            return Err(OxccError::NotReady);
+        }
 
-            let result = self.steering_dac.output_ab(
-                DacOutput::clamp(self.steering_torque.low()),
-                DacOutput::clamp(self.steering_torque.high()),
+        if EMERGENCY_STOP_STATE.is_latched() {
+            log!(
+                debug_console,
+                Module::Steering,
+                Level::Warn,
+                "Steering control not enabled, emergency stop latched"
             );
+            return Err(OxccError::NotReady);
+        }
 
-            return if let Err(e) = result {
-                Err(OxccError::from(e))
-            } else {
-                self.steering_pins.spoof_enable.set_high();
-                self.control_state.enabled = true;
-                writeln!(debug_console, "Steering control enabled");
-                Ok(())
-            };
+        if self.control_state.spoof_enable_mismatch {
+            log!(
+                debug_console,
+                Module::Steering,
+                Level::Warn,
+                "Steering control not enabled, spoof enable relay stuck"
+            );
+            return Err(OxccError::NotReady);
         }
 
+        if self.control_state.state == ControlState::Enabled {
+            return Ok(());
+        }
+
+        if !self
+            .control_state
+            .state
+            .can_transition_to(ControlState::Enabled)
+        {
+            log!(
+                debug_console,
+                Module::Steering,
+                Level::Warn,
+                "Steering control not enabled, {:?}",
+                self.control_state.state
+            );
+            return Err(OxccError::NotReady);
+        }
+
+        self.steering_torque.prevent_signal_discontinuity();
+
+        // ramp from where the torque sensor currently is
+        let torque_high = self.steering_torque.high();
+        let torque_low = self.steering_torque.low();
+        self.write_spoof(torque_high, torque_low)?;
+
+        self.transition_to(ControlState::Enabled, debug_console)?;
+
         Ok(())
     }
 
@@ -137,22 +309,61 @@ impl SteeringModule {
         spoof_command_high: u16,
         spoof_command_low: u16,
     ) -> Result<(), OxccError> {
-        if self.control_state.enabled {
-            // TODO - revisit this, enforce high->A, low->B
-            self.steering_dac.output_ab(
-                ranges::coerce(SteeringSpoofHighSignal::clamp(spoof_command_high)),
-                ranges::coerce(SteeringSpoofLowSignal::clamp(spoof_command_low)),
-            )?;
+        if self.control_state.state == ControlState::Enabled {
+            let target_high = *SteeringSpoofHighSignal::clamp(spoof_command_high).val();
+            let target_low = *SteeringSpoofLowSignal::clamp(spoof_command_low).val();
+            let max_delta = self.control_state.max_spoof_delta;
+
+            let (spoof_high, spoof_low) = match self.control_state.last_spoof {
+                Some((last_high, last_low)) => (
+                    ramp_toward(last_high, target_high, max_delta),
+                    ramp_toward(last_low, target_low, max_delta),
+                ),
+                None => (target_high, target_low),
+            };
+
+            let spoof_high = *SteeringSpoofHighSignal::clamp(spoof_high).val();
+            let spoof_low = *SteeringSpoofLowSignal::clamp(spoof_low).val();
+            self.write_spoof(spoof_high, spoof_low)?;
         }
 
         Ok(())
     }
 
+    /// Writes the high spoof to DAC channel A and the low spoof to channel B,
+    /// and records them as the values to ramp from
+    fn write_spoof(&mut self, spoof_high: u16, spoof_low: u16) -> Result<(), OxccError> {
+        self.steering_dac
+            .output_ab(DacOutput::clamp(spoof_high), DacOutput::clamp(spoof_low))?;
+
+        self.control_state.last_spoof = Some((spoof_high, spoof_low));
+
+        Ok(())
+    }
+
+    /// Maximum change in each spoof value per steering update [steps],
+    /// defaults to STEERING_MAX_SPOOF_DELTA
+    fn set_max_spoof_delta(&mut self, max_delta: u16) {
+        self.control_state.max_spoof_delta = max_delta;
+    }
+
+    pub fn max_spoof_delta(&self) -> u16 {
+        self.control_state.max_spoof_delta
+    }
+
     pub fn check_for_faults(
         &mut self,
         debug_console: &mut DebugConsole,
     ) -> Result<Option<&OsccFaultReport>, OxccError> {
-        if !self.control_state.enabled && !self.control_state.dtcs.are_any_set() {
+        // found while processing a command, see `process_steering_command`
+        if self.control_state.direction_reversal_fault {
+            self.control_state.direction_reversal_fault = false;
+            return Ok(Some(&self.fault_report));
+        }
+
+        if (self.control_state.state != ControlState::Enabled)
+            && !self.control_state.dtcs.are_any_set()
+        {
             // Assumes this module already went through the proper transition into a faulted
             // and disabled state, and we do not want to double-report a possible duplicate
             // fault.
@@ -179,9 +390,22 @@ impl SteeringModule {
             .grounded_fault_state
             .check_voltage_grounded(&self.steering_torque);
 
+        let inputs_mismatched: bool = self.mismatch_fault_state.condition_exceeded_duration(
+            self.steering_torque.average_plausibility_fault(
+                TORQUE_SENSOR_EXPECTED_AVERAGE,
+                TORQUE_SENSOR_AVERAGE_TOLERANCE,
+            ),
+        );
+
+        let operator_overridden: bool = self.filtered_diff > TORQUE_DIFFERENCE_OVERRIDE_THRESHOLD;
+
+        let override_released: bool = self
+            .override_release_state
+            .condition_exceeded_duration(!operator_overridden);
+
         // sensor pins tied to ground - a value of zero indicates disconnection
         if inputs_grounded {
-            self.disable_control(debug_console)?;
+            self.transition_to(ControlState::Faulted, debug_console)?;
 
             self.control_state
                 .dtcs
@@ -189,13 +413,39 @@ impl SteeringModule {
 
             self.update_fault_report();
 
-            writeln!(debug_console, "Bad value read from torque sensor");
+            log!(
+                debug_console,
+                Module::Steering,
+                Level::Error,
+                "Bad value read from torque sensor"
+            );
+
+            Ok(Some(&self.fault_report))
+        } else if inputs_mismatched {
+            self.transition_to(ControlState::Faulted, debug_console)?;
+
+            self.control_state
+                .dtcs
+                .set(OSCC_STEERING_DTC_SENSOR_MISMATCH);
+
+            self.update_fault_report();
+
+            log!(
+                debug_console,
+                Module::Steering,
+                Level::Error,
+                "Torque sensor high/low mismatch"
+            );
 
             Ok(Some(&self.fault_report))
-        } else if (self.filtered_diff > TORQUE_DIFFERENCE_OVERRIDE_THRESHOLD)
-            && !self.control_state.operator_override
+        } else if operator_overridden
+            && self
+                .control_state
+                .state
+                .can_transition_to(ControlState::OverrideActive)
+            && (self.control_state.state != ControlState::OverrideActive)
         {
-            self.disable_control(debug_console)?;
+            self.transition_to(ControlState::OverrideActive, debug_console)?;
 
             self.control_state
                 .dtcs
@@ -203,18 +453,267 @@ impl SteeringModule {
 
             self.update_fault_report();
 
-            self.control_state.operator_override = true;
-
-            writeln!(debug_console, "Steering operator override");
+            log!(
+                debug_console,
+                Module::Steering,
+                Level::Warn,
+                "Steering operator override"
+            );
 
             Ok(Some(&self.fault_report))
+        } else if operator_overridden
+            || ((self.control_state.state == ControlState::OverrideActive) && !override_released)
+        {
+            // override was already handled on the rising edge, hold the state
+            // until it has stayed released for the release debounce rather
+            // than re-reporting every loop
+            Ok(None)
         } else {
-            self.control_state.dtcs.clear_all();
-            self.control_state.operator_override = false;
+            // the CAN bus, supply, relay and DAC DTCs are cleared by their
+            // own checks
+            self.control_state
+                .dtcs
+                .clear(OSCC_STEERING_DTC_INVALID_SENSOR_VAL);
+            self.control_state
+                .dtcs
+                .clear(OSCC_STEERING_DTC_SENSOR_MISMATCH);
+            self.control_state
+                .dtcs
+                .clear(OSCC_STEERING_DTC_OPERATOR_OVERRIDE);
+            if !self.control_state.direction_interlock {
+                self.control_state
+                    .dtcs
+                    .clear(OSCC_STEERING_DTC_DIRECTION_REVERSAL);
+            }
+            if self.control_state.state != ControlState::Enabled {
+                self.transition_to(ControlState::Disabled, debug_console)?;
+            }
+            self.update_fault_report();
             Ok(None)
         }
     }
 
+    /// Disables control and sets the spoof enable DTC when the spoof enable relay sense
+    /// input disagrees with the enable pin, see
+    /// `config::SPOOF_ENABLE_SENSE_INSTALLED`. The DTC clears once they agree
+    /// again.
+    pub fn check_spoof_enable(
+        &mut self,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, OxccError> {
+        let commanded = self.control_state.state == ControlState::Enabled;
+        let mismatch = self.steering_pins.spoof_enable_sense.is_stuck(commanded);
+        self.control_state.spoof_enable_mismatch = mismatch;
+
+        let dtc_set = self
+            .control_state
+            .dtcs
+            .check(OSCC_STEERING_DTC_SPOOF_ENABLE_MISMATCH);
+
+        if !mismatch {
+            if dtc_set {
+                self.control_state
+                    .dtcs
+                    .clear(OSCC_STEERING_DTC_SPOOF_ENABLE_MISMATCH);
+                self.update_fault_report();
+            }
+
+            return Ok(None);
+        }
+
+        if dtc_set {
+            return Ok(None);
+        }
+
+        self.disable_control(debug_console)?;
+
+        self.control_state
+            .dtcs
+            .set(OSCC_STEERING_DTC_SPOOF_ENABLE_MISMATCH);
+
+        self.update_fault_report();
+
+        log!(
+            debug_console,
+            Module::Steering,
+            Level::Error,
+            "Steering spoof enable relay stuck {}",
+            if commanded { "open" } else { "closed" }
+        );
+
+        Ok(Some(&self.fault_report))
+    }
+
+    /// Disables control and sets the DAC output mismatch DTC once the
+    /// verified steering DAC writes keep reading back wrong, see
+    /// `Mcp4922::set_output_verifier`
+    pub fn check_dac_output(
+        &mut self,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, OxccError> {
+        if !self.steering_dac.has_verification_fault()
+            || self
+                .control_state
+                .dtcs
+                .check(OSCC_STEERING_DTC_DAC_OUTPUT_MISMATCH)
+        {
+            return Ok(None);
+        }
+
+        self.disable_control(debug_console)?;
+
+        self.control_state
+            .dtcs
+            .set(OSCC_STEERING_DTC_DAC_OUTPUT_MISMATCH);
+
+        self.update_fault_report();
+
+        log!(
+            debug_console,
+            Module::Steering,
+            Level::Error,
+            "Steering DAC output mismatch, {} verified writes read back wrong",
+            self.steering_dac.verification_failures()
+        );
+
+        Ok(Some(&self.fault_report))
+    }
+
+    /// Disables control and sets the CAN bus DTC when the control CAN bus is
+    /// bus-off, the DTC clears once the bus is error-active again
+    ///
+    /// Error-passive alone is not a fault, a node with no other node on the
+    /// bus goes error-passive from missing acknowledgments.
+    pub fn check_can_health(
+        &mut self,
+        can_health: &CanHealth,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, OxccError> {
+        if can_health.is_error_active() {
+            if self
+                .control_state
+                .dtcs
+                .check(OSCC_STEERING_DTC_CAN_BUS_FAULT)
+            {
+                self.control_state
+                    .dtcs
+                    .clear(OSCC_STEERING_DTC_CAN_BUS_FAULT);
+                self.update_fault_report();
+            }
+
+            return Ok(None);
+        }
+
+        if !can_health.bus_off
+            || self
+                .control_state
+                .dtcs
+                .check(OSCC_STEERING_DTC_CAN_BUS_FAULT)
+        {
+            return Ok(None);
+        }
+
+        self.disable_control(debug_console)?;
+
+        self.control_state.dtcs.set(OSCC_STEERING_DTC_CAN_BUS_FAULT);
+
+        self.update_fault_report();
+
+        log!(
+            debug_console,
+            Module::Steering,
+            Level::Error,
+            "Steering control CAN bus-off, TEC {} REC {}",
+            can_health.transmit_error_count,
+            can_health.receive_error_count
+        );
+
+        Ok(Some(&self.fault_report))
+    }
+
+    /// Disables control and sets the supply voltage DTC when VDDA, measured
+    /// by the brake module, is out of tolerance, the DTC clears once VDDA is
+    /// back in tolerance
+    pub fn check_supply_voltage(
+        &mut self,
+        supply_voltage: &SupplyVoltage,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, OxccError> {
+        if !supply_voltage.out_of_tolerance {
+            if self
+                .control_state
+                .dtcs
+                .check(OSCC_STEERING_DTC_SUPPLY_VOLTAGE)
+            {
+                self.control_state
+                    .dtcs
+                    .clear(OSCC_STEERING_DTC_SUPPLY_VOLTAGE);
+                self.update_fault_report();
+            }
+
+            return Ok(None);
+        }
+
+        if self
+            .control_state
+            .dtcs
+            .check(OSCC_STEERING_DTC_SUPPLY_VOLTAGE)
+        {
+            return Ok(None);
+        }
+
+        self.disable_control(debug_console)?;
+
+        self.control_state
+            .dtcs
+            .set(OSCC_STEERING_DTC_SUPPLY_VOLTAGE);
+
+        self.update_fault_report();
+
+        log!(
+            debug_console,
+            Module::Steering,
+            Level::Error,
+            "Steering supply voltage fault, VDDA {} V",
+            supply_voltage.vdda
+        );
+
+        Ok(Some(&self.fault_report))
+    }
+
+    /// Starts learning the steering torque sensor operating range for a
+    /// guided calibration, the wheel is swept end to end until
+    /// `finish_range_learning`. Returns false while control is enabled.
+    pub fn start_range_learning(&mut self) -> bool {
+        if self.control_state.state == ControlState::Enabled {
+            return false;
+        }
+
+        self.steering_torque.start_range_learning();
+        true
+    }
+
+    /// Samples the sensor while learning, called every FAULT_CHECK_PERIOD_MS
+    pub fn step_range_learning(&mut self) {
+        if self.steering_torque.is_learning_range()
+            && (self.control_state.state != ControlState::Enabled)
+        {
+            self.steering_torque.update();
+        }
+    }
+
+    /// Makes the learned range the operating range used by the sensor
+    /// fault checks, see `DualSignal::finish_range_learning`
+    pub fn finish_range_learning(&mut self) -> Option<SignalRange> {
+        self.steering_torque.finish_range_learning()
+    }
+
+    /// Sets a previously learned operating range, None disables the range
+    /// check
+    pub fn set_operating_range(&mut self, range: Option<SignalRange>) {
+        self.steering_torque.set_operating_range(range);
+    }
+
     fn update_fault_report(&mut self) {
         self.fault_report.dtcs = self.control_state.dtcs;
     }
@@ -223,31 +722,234 @@ impl SteeringModule {
         (alpha * input) + ((1.0 - alpha) * average)
     }
 
+    pub fn state(&self) -> SteeringStateSnapshot {
+        SteeringStateSnapshot {
+            control_state: self.control_state.state,
+            enabled: self.control_state.state == ControlState::Enabled,
+            operator_override: self.control_state.state == ControlState::OverrideActive,
+            dtcs: self.control_state.dtcs,
+        }
+    }
+
     pub fn supply_steering_report(&mut self) -> &OsccSteeringReport {
-        self.steering_report.enabled = self.control_state.enabled;
-        self.steering_report.operator_override = self.control_state.operator_override;
-        self.steering_report.dtcs = self.control_state.dtcs;
+        let state = self.state();
+        self.steering_report.enabled = state.enabled;
+        self.steering_report.operator_override = state.operator_override;
+        self.steering_report.dtcs = state.dtcs;
         &self.steering_report
     }
 
+    /// The acknowledgement of the last enable or disable frame, once
+    pub fn supply_control_ack(&mut self) -> Option<OsccControlAck> {
+        self.control_ack.take()
+    }
+
+    // queues the acknowledgement of an enable or disable frame, carrying the
+    // resulting state
+    fn acknowledge(&mut self, request: ControlRequest) {
+        self.control_ack = Some(OsccControlAck {
+            module_origin_id: FAULT_ORIGIN_STEERING,
+            request,
+            enabled: self.control_state.state == ControlState::Enabled,
+            dtcs: self.control_state.dtcs,
+        });
+    }
+
+    /// The module info, once after each module info request
+    pub fn supply_module_info(&mut self) -> Option<&OsccModuleInfo> {
+        if self.module_info_requested {
+            self.module_info_requested = false;
+            Some(&self.module_info)
+        } else {
+            None
+        }
+    }
+
+    /// The answer to the last parameter write or read, once
+    pub fn supply_param_value(&mut self) -> Option<OsccParamValue> {
+        self.param_value.take()
+    }
+
+    /// The current fault report, DTCs cleared since the last detection
+    /// included
+    pub fn supply_fault_report(&mut self) -> &OsccFaultReport {
+        &self.fault_report
+    }
+
+    // answers a parameter write (`raw` set) or read, parameters owned by
+    // another module are ignored
+    fn process_param_request(
+        &mut self,
+        param_id: u8,
+        raw: Option<u16>,
+        debug_console: &mut DebugConsole,
+    ) {
+        if self.param(param_id).is_none() {
+            return;
+        }
+
+        let status = match raw {
+            Some(_) if self.control_state.state == ControlState::Enabled => {
+                ParamStatus::RefusedWhileEnabled
+            }
+            Some(raw) => match (OsccParamWrite { param_id, raw }).value() {
+                Some(value) => self.write_param(param_id, value),
+                None => ParamStatus::InvalidValue,
+            },
+            None => ParamStatus::Ok,
+        };
+
+        // a refused write answers with the value still in effect
+        if let Some(current) = self.param(param_id) {
+            if let Some(raw) = raw {
+                if status == ParamStatus::Ok {
+                    log!(
+                        debug_console,
+                        Module::Steering,
+                        Level::Info,
+                        "Steering parameter 0x{:02X} set to {}",
+                        param_id,
+                        current
+                    );
+                } else {
+                    log!(
+                        debug_console,
+                        Module::Steering,
+                        Level::Warn,
+                        "Steering parameter 0x{:02X} write of raw value {} refused: {:?}",
+                        param_id,
+                        raw,
+                        status
+                    );
+                }
+            }
+
+            self.param_value = Some(OsccParamValue {
+                param_id,
+                status,
+                value: current,
+            });
+        }
+    }
+
+    // the value of one of this module's parameters, None for parameters
+    // owned by another module
+    fn param(&self, param_id: u8) -> Option<f32> {
+        match param_id {
+            OSCC_PARAM_STEERING_MAX_SPOOF_DELTA => Some(f32::from(self.max_spoof_delta())),
+            _ => None,
+        }
+    }
+
+    // applies a written parameter, the caller checks this module owns it and
+    // `value` is within the parameter's range. Integer parameters have one
+    // raw step per unit so their value is whole.
+    fn write_param(&mut self, param_id: u8, value: f32) -> ParamStatus {
+        match param_id {
+            OSCC_PARAM_STEERING_MAX_SPOOF_DELTA => self.set_max_spoof_delta(value as u16),
+            _ => return ParamStatus::InvalidValue,
+        }
+
+        ParamStatus::Ok
+    }
+
+    /// Handles the OSCC frames at IDs shifted by `can_id_offset`, see
+    /// `config::CanIdOffset`
+    pub fn set_can_id_offset(&mut self, can_id_offset: CanIdOffset) {
+        self.can_id_offset = can_id_offset;
+    }
+
+    /// `rx_timestamp_ms` is the system clock time the frame was received
     pub fn process_rx_frame(
         &mut self,
         can_frame: &CanFrame,
+        rx_timestamp_ms: u32,
         debug_console: &mut DebugConsole,
     ) -> Result<(), OxccError> {
         if let CanFrame::DataFrame(ref frame) = can_frame {
-            let id: u32 = frame.id().into();
+            // compared against the standard OSCC IDs from here on
+            let id = match self.can_id_offset.base_id(frame.id().into()) {
+                Some(id) => id,
+                None => return Ok(()),
+            };
             let data = frame.data();
 
-            if (data[0] == OSCC_MAGIC_BYTE_0) && (data[1] == OSCC_MAGIC_BYTE_1) {
+            if has_oscc_magic(data) {
                 if id == OSCC_STEERING_ENABLE_CAN_ID.into() {
-                    self.enable_control(debug_console)?;
+                    let result = self.enable_control(debug_console);
+                    self.acknowledge(ControlRequest::Enable);
+                    result?;
                 } else if id == OSCC_STEERING_DISABLE_CAN_ID.into() {
-                    self.disable_control(debug_console)?;
+                    let result = self.disable_control(debug_console);
+                    self.acknowledge(ControlRequest::Disable);
+                    result?;
+                } else if id == OSCC_ALL_DISABLE_CAN_ID.into() {
+                    self.emergency_stop(debug_console)?;
+                } else if id == OSCC_ALL_DISABLE_CLEAR_CAN_ID.into() {
+                    // would allow control to be enabled again
+                    if oscc_crc::is_crc_valid(data) {
+                        EMERGENCY_STOP_STATE.clear();
+                    } else {
+                        log!(
+                            debug_console,
+                            Module::Steering,
+                            Level::Warn,
+                            "Dropped emergency stop clear with invalid CRC"
+                        );
+                    }
                 } else if id == OSCC_STEERING_COMMAND_CAN_ID.into() {
-                    self.process_steering_command(&OsccSteeringCommand::from(frame))?;
+                    match OsccSteeringCommand::from_frame(frame) {
+                        Ok(ref command) => {
+                            self.process_steering_command(command, rx_timestamp_ms, debug_console)?
+                        }
+                        Err(e) => {
+                            log!(
+                                debug_console,
+                                Module::Steering,
+                                Level::Warn,
+                                "Dropped malformed steering command: {:?}",
+                                e
+                            );
+                        }
+                    }
                 } else if id == OSCC_FAULT_REPORT_CAN_ID.into() {
-                    self.process_fault_report(&OsccFaultReport::from(frame), debug_console)?;
+                    match OsccFaultReport::from_frame(frame) {
+                        Ok(ref fault_report) => {
+                            self.process_fault_report(fault_report, debug_console)?
+                        }
+                        Err(e) => {
+                            log!(
+                                debug_console,
+                                Module::Steering,
+                                Level::Warn,
+                                "Dropped malformed fault report: {:?}",
+                                e
+                            );
+                        }
+                    }
+                } else if id == OSCC_MODULE_INFO_REQUEST_CAN_ID.into() {
+                    self.module_info_requested = true;
+                } else if id == OSCC_PARAM_WRITE_CAN_ID.into() {
+                    match OsccParamWrite::from_frame(frame) {
+                        Ok(write) => self.process_param_request(
+                            write.param_id,
+                            Some(write.raw),
+                            debug_console,
+                        ),
+                        Err(e) => {
+                            log!(
+                                debug_console,
+                                Module::Steering,
+                                Level::Warn,
+                                "Dropped parameter write: {:?}",
+                                e
+                            );
+                        }
+                    }
+                } else if id == OSCC_PARAM_READ_CAN_ID.into() {
+                    if let Some(read) = OsccParamRead::from_frame(frame) {
+                        self.process_param_request(read.param_id, None, debug_console);
+                    }
                 }
             }
         }
@@ -255,21 +957,121 @@ impl SteeringModule {
         Ok(())
     }
 
+    /// Disables control regardless of the current state and latches the
+    /// emergency stop, blocking re-enable until the e-stop clear frame
+    fn emergency_stop(&mut self, debug_console: &mut DebugConsole) -> Result<(), OxccError> {
+        EMERGENCY_STOP_STATE.latch();
+
+        log!(
+            debug_console,
+            Module::Steering,
+            Level::Warn,
+            "Emergency stop received"
+        );
+
+        self.disable_control(debug_console)
+    }
+
     fn process_fault_report(
         &mut self,
         fault_report: &OsccFaultReport,
         debug_console: &mut DebugConsole,
     ) -> Result<(), OxccError> {
-        writeln!(
+        log!(
             debug_console,
+            Module::Steering,
+            Level::Warn,
             "Fault report received from: {} DTCs: {}",
-            fault_report.fault_origin_id, fault_report.dtcs
+            fault_origin_name(fault_report.fault_origin_id),
+            fault_report.dtcs
         );
 
+        let dtcs = describe_dtc(fault_report.fault_origin_id, fault_report.dtcs);
+        let extended_dtcs =
+            describe_extended_dtc(fault_report.fault_origin_id, fault_report.extended_dtcs);
+
+        for dtc in dtcs.chain(extended_dtcs) {
+            log!(debug_console, Module::Steering, Level::Warn, "  {}", dtc);
+        }
+
+        if fault_report.is_hard_fault() {
+            SYSTEM_FAULT_STATE.latch();
+        }
+
         self.disable_control(debug_console)
     }
 
-    fn process_steering_command(&mut self, command: &OsccSteeringCommand) -> Result<(), OxccError> {
+    /// Counts a reversal whenever the torque request changes sign outside
+    /// STEERING_DIRECTION_REVERSAL_DEADBAND, too many within
+    /// STEERING_DIRECTION_REVERSAL_WINDOW_IN_MSEC engages the interlock.
+    /// Returns true while it is engaged.
+    fn check_direction_reversal(&mut self, torque_request: f32, now_ms: u32) -> bool {
+        let direction = command_direction(torque_request, STEERING_DIRECTION_REVERSAL_DEADBAND);
+
+        let reversed = match (direction, self.control_state.last_direction) {
+            (Some(positive), Some(last_positive)) => positive != last_positive,
+            _ => false,
+        };
+
+        if reversed {
+            if system_clock::is_timeout(
+                self.control_state.reversal_window_start_ms,
+                now_ms,
+                STEERING_DIRECTION_REVERSAL_WINDOW_IN_MSEC,
+            ) {
+                self.control_state.reversal_window_start_ms = now_ms;
+                self.control_state.reversals = 0;
+            }
+
+            self.control_state.reversals = self.control_state.reversals.saturating_add(1);
+
+            if self.control_state.reversals > STEERING_MAX_DIRECTION_REVERSALS {
+                self.control_state.direction_interlock = true;
+            }
+        }
+
+        if direction.is_some() {
+            self.control_state.last_direction = direction;
+        }
+
+        self.control_state.direction_interlock
+    }
+
+    /// An oscillating command engages the direction interlock, which
+    /// disables control and sets the direction reversal DTC. Dropped
+    /// commands don't count as received for the command timeout.
+    fn process_steering_command(
+        &mut self,
+        command: &OsccSteeringCommand,
+        rx_timestamp_ms: u32,
+        debug_console: &mut DebugConsole,
+    ) -> Result<(), OxccError> {
+        // commands received while disabled are not applied, so they are not
+        // counted either
+        if (self.control_state.state == ControlState::Enabled)
+            && self.check_direction_reversal(command.torque_request, rx_timestamp_ms)
+        {
+            self.disable_control(debug_console)?;
+
+            self.control_state
+                .dtcs
+                .set(OSCC_STEERING_DTC_DIRECTION_REVERSAL);
+            self.control_state.direction_reversal_fault = true;
+
+            self.update_fault_report();
+
+            log!(
+                debug_console,
+                Module::Steering,
+                Level::Error,
+                "Steering command reversing direction too fast"
+            );
+
+            return Ok(());
+        }
+
+        self.control_state.last_command_ms = rx_timestamp_ms;
+
         let clamped_torque = num::clamp(
             command.torque_request * MAXIMUM_TORQUE_COMMAND,
             MINIMUM_TORQUE_COMMAND,
@@ -288,9 +1090,273 @@ impl SteeringModule {
             STEERING_SPOOF_HIGH_SIGNAL_VOLTAGE_MAX,
         );
 
-        let spoof_value_low = (STEPS_PER_VOLT * spoof_voltage_low) as u16;
-        let spoof_value_high = (STEPS_PER_VOLT * spoof_voltage_high) as u16;
+        let steps_per_volt = self.steering_dac.steps_per_volt();
+        let spoof_value_low = *volts_to_dac_steps(spoof_voltage_low, steps_per_volt).val();
+        let spoof_value_high = *volts_to_dac_steps(spoof_voltage_high, steps_per_volt).val();
 
         self.update_steering(spoof_value_high, spoof_value_low)
     }
 }
+
+impl ControlModule for SteeringModule {
+    fn disable_control(&mut self, debug_console: &mut DebugConsole) -> Result<(), OxccError> {
+        SteeringModule::disable_control(self, debug_console)
+    }
+
+    fn check_for_faults(
+        &mut self,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, OxccError> {
+        SteeringModule::check_for_faults(self, debug_console)
+    }
+
+    fn check_can_health(
+        &mut self,
+        can_health: &CanHealth,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, OxccError> {
+        SteeringModule::check_can_health(self, can_health, debug_console)
+    }
+
+    fn check_spoof_enable(
+        &mut self,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, OxccError> {
+        SteeringModule::check_spoof_enable(self, debug_console)
+    }
+
+    fn check_dac_output(
+        &mut self,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, OxccError> {
+        SteeringModule::check_dac_output(self, debug_console)
+    }
+
+    fn set_can_id_offset(&mut self, can_id_offset: CanIdOffset) {
+        SteeringModule::set_can_id_offset(self, can_id_offset)
+    }
+
+    fn process_rx_frame(
+        &mut self,
+        can_frame: &CanFrame,
+        rx_timestamp_ms: u32,
+        debug_console: &mut DebugConsole,
+    ) -> Result<(), OxccError> {
+        SteeringModule::process_rx_frame(self, can_frame, rx_timestamp_ms, debug_console)
+    }
+
+    fn step_range_learning(&mut self) {
+        SteeringModule::step_range_learning(self)
+    }
+
+    fn publish_reports(&mut self, publisher: &mut ReportPublisher) -> Result<(), CanError> {
+        publisher.publish_steering_report(self.supply_steering_report())
+    }
+
+    fn supply_control_ack(&mut self) -> Option<OsccControlAck> {
+        SteeringModule::supply_control_ack(self)
+    }
+
+    fn supply_module_info(&mut self) -> Option<&OsccModuleInfo> {
+        SteeringModule::supply_module_info(self)
+    }
+
+    fn supply_param_value(&mut self) -> Option<OsccParamValue> {
+        SteeringModule::supply_param_value(self)
+    }
+
+    fn supply_fault_report(&mut self) -> &OsccFaultReport {
+        SteeringModule::supply_fault_report(self)
+    }
+}
+
+/// (high, low) spoof values for zero steering torque
+fn centered_spoof_values(steps_per_volt: f32) -> (u16, u16) {
+    let centered_volts_high = num::clamp(
+        steering_torque_to_volts_high(0.0),
+        STEERING_SPOOF_HIGH_SIGNAL_VOLTAGE_MIN,
+        STEERING_SPOOF_HIGH_SIGNAL_VOLTAGE_MAX,
+    );
+    let centered_volts_low = num::clamp(
+        steering_torque_to_volts_low(0.0),
+        STEERING_SPOOF_LOW_SIGNAL_VOLTAGE_MIN,
+        STEERING_SPOOF_LOW_SIGNAL_VOLTAGE_MAX,
+    );
+
+    (
+        *volts_to_dac_steps(centered_volts_high, steps_per_volt).val(),
+        *volts_to_dac_steps(centered_volts_low, steps_per_volt).val(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sim_board::{oscc_frame, SimBoard, SimConsole};
+    use system_clock::TestClock;
+
+    // wheel at rest, both channels near the expected average
+    const CENTERED: (u16, u16) = (2000, 1940);
+
+    // the clock is held for the whole test, it also keeps the tests sharing
+    // the fault latches from running at once
+    fn steering_module() -> (TestClock, SteeringModule, SimConsole) {
+        let clock = TestClock::take();
+        SYSTEM_FAULT_STATE.clear();
+        EMERGENCY_STOP_STATE.clear();
+
+        let board = SimBoard::new();
+        board.torque_sensor.set(CENTERED.0, CENTERED.1);
+        let mut console = board.debug_console;
+
+        let steering = UnpreparedSteeringModule::new(
+            board.torque_sensor,
+            board.steering_dac,
+            board.steering_pins,
+        )
+        .prepare_module(&mut console);
+
+        (clock, steering, console)
+    }
+
+    fn command_frame(torque_request: f32) -> CanFrame {
+        let bits = torque_request.to_bits();
+        oscc_frame(
+            OSCC_STEERING_COMMAND_CAN_ID,
+            &[
+                bits as u8,
+                (bits >> 8) as u8,
+                (bits >> 16) as u8,
+                (bits >> 24) as u8,
+            ],
+        )
+    }
+
+    fn receive(steering: &mut SteeringModule, frame: &CanFrame, console: &mut SimConsole) {
+        steering
+            .process_rx_frame(frame, system_clock::get_timestamp_ms(), console)
+            .unwrap();
+    }
+
+    #[test]
+    fn enable_is_refused_while_the_system_fault_is_latched() {
+        let (_clock, mut steering, mut console) = steering_module();
+        SYSTEM_FAULT_STATE.latch();
+
+        let enable = oscc_frame(OSCC_STEERING_ENABLE_CAN_ID, &[]);
+        assert_eq!(
+            steering.process_rx_frame(&enable, 0, &mut console),
+            Err(OxccError::NotReady)
+        );
+        assert_eq!(steering.control_state(), ControlState::Disabled);
+        assert!(!steering.steering_pins.spoof_enable.is_high());
+    }
+
+    #[test]
+    fn grounded_sensor_faults_until_it_reads_again() {
+        let (clock, mut steering, mut console) = steering_module();
+        let enable = oscc_frame(OSCC_STEERING_ENABLE_CAN_ID, &[]);
+        receive(&mut steering, &enable, &mut console);
+        assert_eq!(steering.control_state(), ControlState::Enabled);
+
+        steering.steering_torque.reader().set(0, 0);
+        steering.check_for_faults(&mut console).unwrap();
+        clock.advance_ms(SENSOR_FAULT_HYSTERESIS);
+        assert!(steering.check_for_faults(&mut console).unwrap().is_some());
+        assert_eq!(steering.control_state(), ControlState::Faulted);
+        assert!(!steering.steering_pins.spoof_enable.is_high());
+        assert_eq!(
+            steering.process_rx_frame(&enable, 0, &mut console),
+            Err(OxccError::NotReady)
+        );
+
+        steering
+            .steering_torque
+            .reader()
+            .set(CENTERED.0, CENTERED.1);
+        assert!(steering.check_for_faults(&mut console).unwrap().is_none());
+        assert_eq!(steering.control_state(), ControlState::Disabled);
+        receive(&mut steering, &enable, &mut console);
+        assert_eq!(steering.control_state(), ControlState::Enabled);
+    }
+
+    #[test]
+    fn sustained_override_publishes_one_fault_report() {
+        let (_clock, mut steering, mut console) = steering_module();
+        receive(
+            &mut steering,
+            &oscc_frame(OSCC_STEERING_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+
+        // same average, a difference well past the override threshold
+        steering.steering_torque.reader().set(3000, 940);
+        let reports = (0..100)
+            .filter(|_| steering.check_for_faults(&mut console).unwrap().is_some())
+            .count();
+
+        assert_eq!(reports, 1);
+        assert_eq!(steering.control_state(), ControlState::OverrideActive);
+        assert!(steering
+            .state()
+            .dtcs
+            .check(OSCC_STEERING_DTC_OPERATOR_OVERRIDE));
+    }
+
+    // (high, low) spoof values `torque_request` settles at
+    fn settled_spoof(steering: &SteeringModule, torque_request: f32) -> (u16, u16) {
+        let torque = num::clamp(
+            torque_request * MAXIMUM_TORQUE_COMMAND,
+            MINIMUM_TORQUE_COMMAND,
+            MAXIMUM_TORQUE_COMMAND,
+        );
+        let steps_per_volt = steering.steering_dac.steps_per_volt();
+        let high = num::clamp(
+            steering_torque_to_volts_high(torque),
+            STEERING_SPOOF_HIGH_SIGNAL_VOLTAGE_MIN,
+            STEERING_SPOOF_HIGH_SIGNAL_VOLTAGE_MAX,
+        );
+        let low = num::clamp(
+            steering_torque_to_volts_low(torque),
+            STEERING_SPOOF_LOW_SIGNAL_VOLTAGE_MIN,
+            STEERING_SPOOF_LOW_SIGNAL_VOLTAGE_MAX,
+        );
+        (
+            *SteeringSpoofHighSignal::clamp(*volts_to_dac_steps(high, steps_per_volt).val()).val(),
+            *SteeringSpoofLowSignal::clamp(*volts_to_dac_steps(low, steps_per_volt).val()).val(),
+        )
+    }
+
+    #[test]
+    fn large_command_is_rate_limited_and_small_one_passes_through() {
+        let (_clock, mut steering, mut console) = steering_module();
+        receive(
+            &mut steering,
+            &oscc_frame(OSCC_STEERING_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+        let max_delta = i32::from(steering.max_spoof_delta());
+
+        let target = settled_spoof(&steering, 1.0);
+        let mut steps = 0;
+        while steering.control_state.last_spoof != Some(target) {
+            let (last_high, last_low) = steering.control_state.last_spoof.unwrap();
+            receive(&mut steering, &command_frame(1.0), &mut console);
+            let (high, low) = steering.control_state.last_spoof.unwrap();
+
+            assert!((i32::from(high) - i32::from(last_high)).abs() <= max_delta);
+            assert!((i32::from(low) - i32::from(last_low)).abs() <= max_delta);
+            steps += 1;
+            assert!(steps < 1000);
+        }
+        assert!(steps > 1);
+
+        // a step within the limit is written straight away
+        let small_target = settled_spoof(&steering, 0.99);
+        assert_ne!(small_target, target);
+        assert!((i32::from(small_target.0) - i32::from(target.0)).abs() <= max_delta);
+        assert!((i32::from(small_target.1) - i32::from(target.1)).abs() <= max_delta);
+        receive(&mut steering, &command_frame(0.99), &mut console);
+        assert_eq!(steering.control_state.last_spoof, Some(small_target));
+    }
+}
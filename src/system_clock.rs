@@ -0,0 +1,170 @@
+// https://github.com/jonlamb-gh/oscc/blob/master/firmware/common/libs/timer/oscc_timer.h
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use cortex_m::peripheral::syst::SystClkSource;
+use cortex_m::peripheral::SYST;
+use nucleo_f767zi::hal::rcc::Clocks;
+
+pub const SYSTEM_CLOCK_TICK_HZ: u32 = 1000;
+
+static MILLISECONDS: AtomicUsize = AtomicUsize::new(0);
+
+/// Monotonic millisecond clock driven by the SysTick exception
+pub struct SystemClock {
+    _syst: SYST,
+}
+
+impl SystemClock {
+    pub fn new(mut syst: SYST, clocks: Clocks) -> Self {
+        syst.set_clock_source(SystClkSource::Core);
+        syst.set_reload((clocks.sysclk().0 / SYSTEM_CLOCK_TICK_HZ) - 1);
+        syst.clear_current();
+        syst.enable_counter();
+        syst.enable_interrupt();
+
+        SystemClock { _syst: syst }
+    }
+}
+
+/// Called from the SysTick exception handler
+pub fn tick() {
+    MILLISECONDS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Milliseconds elapsed since the clock was started, wraps at u32::MAX
+pub fn get_timestamp_ms() -> u32 {
+    #[cfg(test)]
+    debug_assert!(
+        TEST_CLOCK_TAKEN.load(Ordering::Acquire),
+        "tests that read the clock must hold a TestClock"
+    );
+
+    MILLISECONDS.load(Ordering::Relaxed) as u32
+}
+
+/// Microseconds elapsed since the clock was started, for timing work
+/// shorter than a tick. Wraps at u32::MAX, about every 71 minutes.
+pub fn get_timestamp_us() -> u32 {
+    loop {
+        let ms = get_timestamp_ms();
+        let current = SYST::get_current();
+
+        // retry if a tick landed between the two reads
+        if get_timestamp_ms() == ms {
+            // counts down from the reload value once per tick
+            let reload = SYST::get_reload();
+            let tick_us = (reload - current) * 1000 / (reload + 1);
+            return ms.wrapping_mul(1000).wrapping_add(tick_us);
+        }
+    }
+}
+
+/// Wrapping-safe check of whether `timeout_ms` has elapsed since `start_ms`
+pub fn is_timeout(start_ms: u32, now_ms: u32, timeout_ms: u32) -> bool {
+    now_ms.wrapping_sub(start_ms) >= timeout_ms
+}
+
+/// A window from the clock starting, over for good once it has passed
+pub struct StartupGrace {
+    duration_ms: u32,
+    over: bool,
+}
+
+impl StartupGrace {
+    pub const fn new(duration_ms: u32) -> Self {
+        StartupGrace {
+            duration_ms,
+            over: false,
+        }
+    }
+
+    /// True until `duration_ms` after the clock started
+    pub fn is_active(&mut self) -> bool {
+        if !self.over {
+            self.over = is_timeout(0, get_timestamp_ms(), self.duration_ms);
+        }
+
+        !self.over
+    }
+}
+
+// the clock is global, tests that depend on it take turns
+#[cfg(test)]
+static TEST_CLOCK_TAKEN: ::core::sync::atomic::AtomicBool =
+    ::core::sync::atomic::AtomicBool::new(false);
+
+/// Exclusive control of the millisecond clock for a test, released when
+/// dropped. Only `take` can make one, so holding one means holding the lock.
+#[cfg(test)]
+pub struct TestClock {
+    _taken: (),
+}
+
+#[cfg(test)]
+impl TestClock {
+    /// Waits for other tests to drop theirs, the clock starts at zero
+    pub fn take() -> Self {
+        while TEST_CLOCK_TAKEN.compare_and_swap(false, true, Ordering::Acquire) {
+            ::std::thread::yield_now();
+        }
+
+        let clock = TestClock { _taken: () };
+        clock.set_ms(0);
+        clock
+    }
+
+    pub fn set_ms(&self, ms: u32) {
+        debug_assert!(TEST_CLOCK_TAKEN.load(Ordering::Acquire));
+        MILLISECONDS.store(ms as usize, Ordering::Relaxed);
+    }
+
+    pub fn advance_ms(&self, ms: u32) {
+        self.set_ms(get_timestamp_ms().wrapping_add(ms));
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestClock {
+    fn drop(&mut self) {
+        TEST_CLOCK_TAKEN.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeout_elapses_at_the_duration() {
+        assert!(!is_timeout(100, 149, 50));
+        assert!(is_timeout(100, 150, 50));
+    }
+
+    #[test]
+    fn timeout_survives_wrapping() {
+        let start = u32::max_value() - 10;
+        assert!(!is_timeout(start, 5, 50));
+        assert!(is_timeout(start, 39, 50));
+    }
+
+    #[test]
+    fn startup_grace_ends_at_its_duration_for_good() {
+        let clock = TestClock::take();
+        let mut grace = StartupGrace::new(500);
+        clock.set_ms(499);
+        assert!(grace.is_active());
+        clock.set_ms(500);
+        assert!(!grace.is_active());
+
+        // not active again when the clock wraps
+        clock.set_ms(0);
+        assert!(!grace.is_active());
+    }
+
+    #[test]
+    fn test_clock_advances() {
+        let clock = TestClock::take();
+        clock.advance_ms(25);
+        assert_eq!(get_timestamp_ms(), 25);
+    }
+}
@@ -1,27 +1,51 @@
 // https://github.com/jonlamb-gh/oscc/tree/devel/firmware/throttle
 
-use board::AcceleratorPositionSensor;
-use core::fmt::Write;
+use board::{AcceleratorPositionSensor, SupplyVoltage};
+use can_health::CanHealth;
+use config::CanIdOffset;
+use control_ack_can_protocol::*;
+use control_module::{ControlModule, ControlState, ReportPublisher};
+use core::cmp;
 use dac_mcp4922::DacOutput;
 use dtc::DtcBitfield;
 use dual_signal::DualSignal;
+use emergency_stop_can_protocol::*;
 use fault_can_protocol::*;
-use fault_condition::FaultCondition;
-use nucleo_f767zi::debug_console::DebugConsole;
-use nucleo_f767zi::hal::can::CanFrame;
+use fault_condition::{self, FaultCondition};
+use log::{Level, Module};
+use module_info_can_protocol::*;
+use nucleo_f767zi::hal::can::{CanError, CanFrame};
 use nucleo_f767zi::hal::prelude::*;
 use num;
+use oscc_crc;
 use oscc_magic_byte::*;
 use oxcc_error::OxccError;
-use ranges;
+use param_can_protocol::*;
+use signal_range::SignalRange;
+use spoof_enable_sense::EnableReadback;
+use spoof_signal_pair::{ramp_toward, volts_to_dac_steps};
+use system_clock;
 use throttle_can_protocol::*;
 use types::*;
 use vehicle::*;
 
 struct ThrottleControlState<DTCS: DtcBitfield> {
-    enabled: bool,
-    operator_override: bool,
+    /// `Faulted` is left once the accelerator position sensor reads a
+    /// plausible value again
+    state: ControlState,
     dtcs: DTCS,
+    last_command_ms: u32,
+    /// Last (high, low) spoof values written, used to ramp limit commands
+    last_spoof: Option<(u16, u16)>,
+    /// Last commanded position, echoed in the throttle report
+    last_commanded_position: f32,
+    /// Accelerator position learned at rest, None until calibrated
+    idle_baseline: Option<u32>,
+    /// Highest override threshold, the idle calibration can only lower it
+    override_threshold_limit: u32,
+    /// The spoof enable relay sense input disagrees with the enable pin,
+    /// control is not enabled while it does
+    spoof_enable_mismatch: bool,
 }
 
 impl<DTCS> ThrottleControlState<DTCS>
@@ -30,20 +54,43 @@ where
 {
     pub const fn new(dtcs: DTCS) -> Self {
         ThrottleControlState {
-            enabled: false,
-            operator_override: false,
+            state: ControlState::Disabled,
             dtcs,
+            last_command_ms: 0,
+            last_spoof: None,
+            last_commanded_position: 0.0,
+            idle_baseline: None,
+            override_threshold_limit: ACCELERATOR_OVERRIDE_THRESHOLD,
+            spoof_enable_mismatch: false,
         }
     }
 }
 
+/// Read-only copy of the throttle control state
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ThrottleStateSnapshot {
+    pub control_state: ControlState,
+    pub enabled: bool,
+    pub operator_override: bool,
+    pub dtcs: u8,
+    pub commanded_position: f32,
+    /// Last (high, low) spoof values written, None until the DAC is initialized
+    pub spoof_output: Option<(u16, u16)>,
+}
+
 pub struct ThrottleModule {
     accelerator_position: DualSignal<AcceleratorPositionSensor>,
     control_state: ThrottleControlState<u8>,
-    grounded_fault_state: FaultCondition<ThrottleGroundedFaultTimer>,
-    operator_override_state: FaultCondition<ThrottleOverrideFaultTimer>,
+    grounded_fault_state: FaultCondition,
+    operator_override_state: FaultCondition,
+    override_release_state: FaultCondition,
     throttle_report: OsccThrottleReport,
     fault_report: OsccFaultReport,
+    module_info: OsccModuleInfo,
+    module_info_requested: bool,
+    control_ack: Option<OsccControlAck>,
+    param_value: Option<OsccParamValue>,
+    can_id_offset: CanIdOffset,
     throttle_dac: ThrottleDac,
     throttle_pins: ThrottlePins,
 }
@@ -57,102 +104,351 @@ impl UnpreparedThrottleModule {
         accelerator_position_sensor: AcceleratorPositionSensor,
         throttle_dac: ThrottleDac,
         throttle_pins: ThrottlePins,
-        grounded_fault_timer: ThrottleGroundedFaultTimer,
-        override_timer: ThrottleOverrideFaultTimer,
     ) -> UnpreparedThrottleModule {
         UnpreparedThrottleModule {
             throttle_module: ThrottleModule {
                 accelerator_position: DualSignal::new(0, 0, accelerator_position_sensor),
                 control_state: ThrottleControlState::new(u8::default()),
-                grounded_fault_state: FaultCondition::new(grounded_fault_timer),
-                operator_override_state: FaultCondition::new(override_timer),
+                grounded_fault_state: FaultCondition::with_hysteresis(SENSOR_FAULT_HYSTERESIS),
+                operator_override_state: FaultCondition::with_hysteresis(
+                    OPERATOR_OVERRIDE_HYSTERESIS,
+                ),
+                override_release_state: FaultCondition::with_hysteresis(
+                    OPERATOR_OVERRIDE_RELEASE_DEBOUNCE,
+                ),
                 throttle_report: OsccThrottleReport::new(),
                 fault_report: OsccFaultReport {
                     fault_origin_id: FAULT_ORIGIN_THROTTLE,
                     dtcs: 0,
+                    extended_dtcs: 0,
                 },
+                module_info: OsccModuleInfo::new(FAULT_ORIGIN_THROTTLE),
+                module_info_requested: false,
+                control_ack: None,
+                param_value: None,
+                can_id_offset: CanIdOffset::none(),
                 throttle_dac,
                 throttle_pins,
             },
         }
     }
 
-    pub fn prepare_module(self) -> ThrottleModule {
+    /// Releases the spoof output and sets the DAC to the idle spoof values
+    pub fn prepare_module(self, debug_console: &mut DebugConsole) -> ThrottleModule {
         let mut throttle_module = self.throttle_module;
         throttle_module.throttle_pins.spoof_enable.set_low();
+
+        let (idle_high, idle_low) =
+            idle_spoof_values(throttle_module.throttle_dac.steps_per_volt());
+        match throttle_module
+            .throttle_dac
+            .init(DacOutput::clamp(idle_high), DacOutput::clamp(idle_low))
+        {
+            Ok(()) => throttle_module.control_state.last_spoof = Some((idle_high, idle_low)),
+            Err(e) => {
+                log!(
+                    debug_console,
+                    Module::Throttle,
+                    Level::Error,
+                    "Throttle DAC initialization failed: {:?}",
+                    e
+                );
+            }
+        }
+
         throttle_module
     }
 }
 
 impl ThrottleModule {
+    /// An active override or fault is kept
     pub fn disable_control(&mut self, debug_console: &mut DebugConsole) -> Result<(), OxccError> {
-        if self.control_state.enabled {
-            self.accelerator_position.prevent_signal_discontinuity();
+        if self.control_state.state == ControlState::Enabled {
+            self.transition_to(ControlState::Disabled, debug_console)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn control_state(&self) -> ControlState {
+        self.control_state.state
+    }
 
-            let result = self.throttle_dac.output_ab(
-                DacOutput::clamp(self.accelerator_position.low()),
-                DacOutput::clamp(self.accelerator_position.high()),
+    /// Moves the control state machine to `next`, all enable and disable
+    /// side effects happen here. Returns false, leaving the state unchanged,
+    /// if `next` can't be reached from the current state.
+    ///
+    /// Leaving `Enabled` hands the output back to the pedal, entering it
+    /// expects the DAC to already match the sensor.
+    fn transition_to(
+        &mut self,
+        next: ControlState,
+        debug_console: &mut DebugConsole,
+    ) -> Result<bool, OxccError> {
+        let previous = self.control_state.state;
+
+        if previous == next {
+            return Ok(true);
+        }
+
+        if !previous.can_transition_to(next) {
+            log!(
+                debug_console,
+                Module::Throttle,
+                Level::Warn,
+                "Rejected throttle control transition {:?} -> {:?}",
+                previous,
+                next
             );
+            return Ok(false);
+        }
+
+        self.control_state.state = next;
+
+        log!(
+            debug_console,
+            Module::Throttle,
+            Level::Info,
+            "Throttle control {:?} -> {:?}",
+            previous,
+            next
+        );
+
+        if next == ControlState::Enabled {
+            self.throttle_pins.spoof_enable.set_high();
+        } else if previous == ControlState::Enabled {
+            self.accelerator_position.prevent_signal_discontinuity();
+
+            let high = self.accelerator_position.high();
+            let low = self.accelerator_position.low();
+            let result = self.write_spoof(high, low);
 
             // even if we've encountered an error, we can still disable
             self.throttle_pins.spoof_enable.set_low();
-            self.control_state.enabled = false;
-            writeln!(debug_console, "Throttle control disabled");
 
-            return if let Err(e) = result {
-                Err(OxccError::from(e))
-            } else {
-                Ok(())
-            };
+            result?;
         }
 
-        Ok(())
+        Ok(true)
+    }
+
+    /// Time since the last throttle command frame was received [ms]
+    pub fn time_since_last_command_ms(&self) -> u32 {
+        system_clock::get_timestamp_ms().wrapping_sub(self.control_state.last_command_ms)
     }
 
     fn enable_control(&mut self, debug_console: &mut DebugConsole) -> Result<(), OxccError> {
-        if !self.control_state.enabled && !self.control_state.operator_override {
-            self.accelerator_position.prevent_signal_discontinuity();
+        if SYSTEM_FAULT_STATE.is_latched() {
+            log!(
+                debug_console,
+                Module::Throttle,
+                Level::Warn,
+                "Throttle control not enabled, system fault latched"
+            );
+            return Err(OxccError::NotReady);
+        }
 
-            let result = self.throttle_dac.output_ab(
-                DacOutput::clamp(self.accelerator_position.low()),
-                DacOutput::clamp(self.accelerator_position.high()),
+        if EMERGENCY_STOP_STATE.is_latched() {
+            log!(
+                debug_console,
+                Module::Throttle,
+                Level::Warn,
+                "Throttle control not enabled, emergency stop latched"
             );
+            return Err(OxccError::NotReady);
+        }
 
-            return if let Err(e) = result {
-                Err(OxccError::from(e))
-            } else {
-                self.throttle_pins.spoof_enable.set_high();
-                self.control_state.enabled = true;
-                writeln!(debug_console, "Throttle control enabled");
-                Ok(())
-            };
+        if self.control_state.spoof_enable_mismatch {
+            log!(
+                debug_console,
+                Module::Throttle,
+                Level::Warn,
+                "Throttle control not enabled, spoof enable relay stuck"
+            );
+            return Err(OxccError::NotReady);
+        }
+
+        if self.control_state.state == ControlState::Enabled {
+            return Ok(());
         }
 
+        if !self
+            .control_state
+            .state
+            .can_transition_to(ControlState::Enabled)
+        {
+            log!(
+                debug_console,
+                Module::Throttle,
+                Level::Warn,
+                "Throttle control not enabled, {:?}",
+                self.control_state.state
+            );
+            return Err(OxccError::NotReady);
+        }
+
+        if let Err(dtc) = self.self_test() {
+            self.control_state.dtcs.set(dtc);
+            self.update_fault_report();
+            log!(
+                debug_console,
+                Module::Throttle,
+                Level::Warn,
+                "Throttle self test failed (DTC {}), control not enabled",
+                dtc
+            );
+            return Err(OxccError::NotReady);
+        }
+
+        self.accelerator_position.prevent_signal_discontinuity();
+
+        // the ramp starts from where the pedal currently is
+        let high = self.accelerator_position.high();
+        let low = self.accelerator_position.low();
+        self.write_spoof(high, low)?;
+
+        self.transition_to(ControlState::Enabled, debug_console)?;
+
         Ok(())
     }
 
+    /// Checks that the accelerator position sensor reads a plausible idle
+    /// value, returning the DTC describing the failure otherwise
+    pub fn self_test(&mut self) -> Result<(), u8> {
+        self.accelerator_position.prevent_signal_discontinuity();
+
+        // a value of zero indicates a disconnected sensor
+        if fault_condition::is_voltage_grounded(&self.accelerator_position) {
+            return Err(OSCC_THROTTLE_DTC_INVALID_SENSOR_VAL);
+        }
+
+        // the pedal is pressed, or the sensor is shorted high
+        if self.accelerator_position.average() >= self.override_threshold() {
+            return Err(OSCC_THROTTLE_DTC_OPERATOR_OVERRIDE);
+        }
+
+        Ok(())
+    }
+
+    /// Learns the accelerator position at rest by averaging `samples`
+    /// readings, the baseline then becomes the reference for override
+    /// detection
+    ///
+    /// Rejected with the DTC describing the failure if any reading is
+    /// grounded or outside the idle band, the previous baseline is kept.
+    pub fn calibrate_idle(&mut self, samples: u8) -> Result<u32, u8> {
+        let samples = if samples == 0 { 1 } else { samples };
+        let mut sum: u32 = 0;
+
+        for _ in 0..samples {
+            self.accelerator_position.update();
+
+            if fault_condition::is_voltage_grounded(&self.accelerator_position) {
+                return Err(OSCC_THROTTLE_DTC_INVALID_SENSOR_VAL);
+            }
+
+            let average = self.accelerator_position.average();
+
+            // the pedal is not at rest
+            if average > ACCELERATOR_IDLE_BAND_MAX {
+                return Err(OSCC_THROTTLE_DTC_OPERATOR_OVERRIDE);
+            }
+
+            sum += average;
+        }
+
+        let baseline = sum / u32::from(samples);
+        self.control_state.idle_baseline = Some(baseline);
+
+        Ok(baseline)
+    }
+
+    pub fn idle_baseline(&self) -> Option<u32> {
+        self.control_state.idle_baseline
+    }
+
+    fn override_threshold(&self) -> u32 {
+        let limit = self.control_state.override_threshold_limit;
+
+        match self.control_state.idle_baseline {
+            Some(baseline) => cmp::min(baseline + ACCELERATOR_OVERRIDE_MARGIN, limit),
+            None => limit,
+        }
+    }
+
+    /// Accelerator position average at or above which the operator is
+    /// considered to be overriding when not lowered by the idle calibration,
+    /// defaults to ACCELERATOR_OVERRIDE_THRESHOLD
+    fn set_override_threshold(&mut self, threshold: u32) {
+        self.control_state.override_threshold_limit = threshold;
+    }
+
     fn update_throttle(
         &mut self,
         spoof_command_high: u16,
         spoof_command_low: u16,
     ) -> Result<(), OxccError> {
-        if self.control_state.enabled {
-            // TODO - revisit this, enforce high->A, low->B
-            self.throttle_dac.output_ab(
-                ranges::coerce(ThrottleSpoofHighSignal::clamp(spoof_command_high)),
-                ranges::coerce(ThrottleSpoofHighSignal::clamp(spoof_command_low)),
-            )?;
+        if self.control_state.state == ControlState::Enabled {
+            let target_high = *ThrottleSpoofHighSignal::clamp(spoof_command_high).val();
+            let target_low = *ThrottleSpoofLowSignal::clamp(spoof_command_low).val();
+
+            let (spoof_high, spoof_low) = if VEHICLE_STATE.is_braking() {
+                // brake always wins, drop straight to idle without ramping
+                idle_spoof_values(self.throttle_dac.steps_per_volt())
+            } else {
+                match self.control_state.last_spoof {
+                    Some((last_high, last_low)) => (
+                        ramp_toward(last_high, target_high, THROTTLE_MAX_SPOOF_DELTA),
+                        ramp_toward(last_low, target_low, THROTTLE_MAX_SPOOF_DELTA),
+                    ),
+                    None => (target_high, target_low),
+                }
+            };
+
+            self.write_spoof(spoof_high, spoof_low)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the high spoof value to DAC channel A and the low one to
+    /// channel B, recording them as the ramp limiter's starting point
+    fn write_spoof(&mut self, spoof_high: u16, spoof_low: u16) -> Result<(), OxccError> {
+        self.throttle_dac
+            .output_ab(DacOutput::clamp(spoof_high), DacOutput::clamp(spoof_low))?;
+
+        self.control_state.last_spoof = Some((spoof_high, spoof_low));
+
+        Ok(())
+    }
+
+    /// Drops the spoof output straight to idle once the brake is applied,
+    /// without waiting for the next throttle command
+    fn hold_idle_while_braking(&mut self) -> Result<(), OxccError> {
+        if (self.control_state.state != ControlState::Enabled) || !VEHICLE_STATE.is_braking() {
+            return Ok(());
+        }
+
+        let (idle_high, idle_low) = idle_spoof_values(self.throttle_dac.steps_per_volt());
+
+        if self.control_state.last_spoof != Some((idle_high, idle_low)) {
+            self.write_spoof(idle_high, idle_low)?;
         }
 
         Ok(())
     }
 
-    /// Checks for any fresh (previously undetected or unhandled) faults
+    /// Checks for any fresh (previously undetected or unhandled) faults, and
+    /// holds the output at idle while the brake is applied
     pub fn check_for_faults(
         &mut self,
         debug_console: &mut DebugConsole,
     ) -> Result<Option<&OsccFaultReport>, OxccError> {
-        if !self.control_state.enabled && !self.control_state.dtcs.are_any_set() {
+        self.hold_idle_while_braking()?;
+
+        if (self.control_state.state != ControlState::Enabled)
+            && !self.control_state.dtcs.are_any_set()
+        {
             // Assumes this module already went through the proper transition into a faulted
             // and disabled state, and we do not want to double-report a possible duplicate
             // fault.
@@ -162,10 +458,15 @@ impl ThrottleModule {
         self.accelerator_position.update();
 
         let accelerator_position_average = self.accelerator_position.average();
+        let override_threshold = self.override_threshold();
 
-        let operator_overridden: bool = self.operator_override_state.condition_exceeded_duration(
-            accelerator_position_average >= ACCELERATOR_OVERRIDE_THRESHOLD,
-        );
+        let operator_overridden: bool = self
+            .operator_override_state
+            .condition_exceeded_duration(accelerator_position_average >= override_threshold);
+
+        let override_released: bool = self
+            .override_release_state
+            .condition_exceeded_duration(accelerator_position_average < override_threshold);
 
         let inputs_grounded: bool = self
             .grounded_fault_state
@@ -173,7 +474,7 @@ impl ThrottleModule {
 
         // sensor pins tied to ground - a value of zero indicates disconnection
         if inputs_grounded {
-            self.disable_control(debug_console)?;
+            self.transition_to(ControlState::Faulted, debug_console)?;
 
             self.control_state
                 .dtcs
@@ -181,14 +482,22 @@ impl ThrottleModule {
 
             self.update_fault_report();
 
-            writeln!(
+            log!(
                 debug_console,
+                Module::Throttle,
+                Level::Error,
                 "Bad value read from accelerator position sensor"
             );
 
             Ok(Some(&self.fault_report))
-        } else if operator_overridden && !self.control_state.operator_override {
-            self.disable_control(debug_console)?;
+        } else if operator_overridden
+            && self
+                .control_state
+                .state
+                .can_transition_to(ControlState::OverrideActive)
+            && (self.control_state.state != ControlState::OverrideActive)
+        {
+            self.transition_to(ControlState::OverrideActive, debug_console)?;
 
             self.control_state
                 .dtcs
@@ -196,47 +505,495 @@ impl ThrottleModule {
 
             self.update_fault_report();
 
-            self.control_state.operator_override = true;
-
-            writeln!(debug_console, "Throttle operator override");
+            log!(
+                debug_console,
+                Module::Throttle,
+                Level::Warn,
+                "Throttle operator override"
+            );
 
             Ok(Some(&self.fault_report))
+        } else if (self.control_state.state == ControlState::OverrideActive) && !override_released {
+            // held until the pedal has stayed below the threshold for the
+            // release debounce
+            Ok(None)
         } else {
-            self.control_state.dtcs.clear_all();
-            self.control_state.operator_override = false;
+            // the CAN bus, supply, relay and DAC DTCs are cleared by their
+            // own checks
+            self.control_state
+                .dtcs
+                .clear(OSCC_THROTTLE_DTC_INVALID_SENSOR_VAL);
+            self.control_state
+                .dtcs
+                .clear(OSCC_THROTTLE_DTC_OPERATOR_OVERRIDE);
+            if self.control_state.state != ControlState::Enabled {
+                self.transition_to(ControlState::Disabled, debug_console)?;
+            }
+            self.update_fault_report();
             Ok(None)
         }
     }
 
+    /// Disables control and sets the spoof enable DTC when the spoof enable relay sense
+    /// input disagrees with the enable pin, see
+    /// `config::SPOOF_ENABLE_SENSE_INSTALLED`. The DTC clears once they agree
+    /// again.
+    pub fn check_spoof_enable(
+        &mut self,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, OxccError> {
+        let commanded = self.control_state.state == ControlState::Enabled;
+        let mismatch = self.throttle_pins.spoof_enable_sense.is_stuck(commanded);
+        self.control_state.spoof_enable_mismatch = mismatch;
+
+        let dtc_set = self
+            .control_state
+            .dtcs
+            .check(OSCC_THROTTLE_DTC_SPOOF_ENABLE_MISMATCH);
+
+        if !mismatch {
+            if dtc_set {
+                self.control_state
+                    .dtcs
+                    .clear(OSCC_THROTTLE_DTC_SPOOF_ENABLE_MISMATCH);
+                self.update_fault_report();
+            }
+
+            return Ok(None);
+        }
+
+        if dtc_set {
+            return Ok(None);
+        }
+
+        self.disable_control(debug_console)?;
+
+        self.control_state
+            .dtcs
+            .set(OSCC_THROTTLE_DTC_SPOOF_ENABLE_MISMATCH);
+
+        self.update_fault_report();
+
+        log!(
+            debug_console,
+            Module::Throttle,
+            Level::Error,
+            "Throttle spoof enable relay stuck {}",
+            if commanded { "open" } else { "closed" }
+        );
+
+        Ok(Some(&self.fault_report))
+    }
+
+    /// Disables control and sets the DAC output mismatch DTC once the
+    /// verified throttle DAC writes keep reading back wrong, see
+    /// `Mcp4922::set_output_verifier`
+    pub fn check_dac_output(
+        &mut self,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, OxccError> {
+        if !self.throttle_dac.has_verification_fault()
+            || self
+                .control_state
+                .dtcs
+                .check(OSCC_THROTTLE_DTC_DAC_OUTPUT_MISMATCH)
+        {
+            return Ok(None);
+        }
+
+        self.disable_control(debug_console)?;
+
+        self.control_state
+            .dtcs
+            .set(OSCC_THROTTLE_DTC_DAC_OUTPUT_MISMATCH);
+
+        self.update_fault_report();
+
+        log!(
+            debug_console,
+            Module::Throttle,
+            Level::Error,
+            "Throttle DAC output mismatch, {} verified writes read back wrong",
+            self.throttle_dac.verification_failures()
+        );
+
+        Ok(Some(&self.fault_report))
+    }
+
+    /// Disables control and sets the CAN bus DTC when the control CAN bus is
+    /// bus-off, the DTC clears once the bus is error-active again
+    ///
+    /// Error-passive alone is not a fault, a node with no other node on the
+    /// bus goes error-passive from missing acknowledgments.
+    pub fn check_can_health(
+        &mut self,
+        can_health: &CanHealth,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, OxccError> {
+        if can_health.is_error_active() {
+            if self
+                .control_state
+                .dtcs
+                .check(OSCC_THROTTLE_DTC_CAN_BUS_FAULT)
+            {
+                self.control_state
+                    .dtcs
+                    .clear(OSCC_THROTTLE_DTC_CAN_BUS_FAULT);
+                self.update_fault_report();
+            }
+
+            return Ok(None);
+        }
+
+        if !can_health.bus_off
+            || self
+                .control_state
+                .dtcs
+                .check(OSCC_THROTTLE_DTC_CAN_BUS_FAULT)
+        {
+            return Ok(None);
+        }
+
+        self.disable_control(debug_console)?;
+
+        self.control_state.dtcs.set(OSCC_THROTTLE_DTC_CAN_BUS_FAULT);
+
+        self.update_fault_report();
+
+        log!(
+            debug_console,
+            Module::Throttle,
+            Level::Error,
+            "Throttle control CAN bus-off, TEC {} REC {}",
+            can_health.transmit_error_count,
+            can_health.receive_error_count
+        );
+
+        Ok(Some(&self.fault_report))
+    }
+
+    /// Disables control and sets the supply voltage DTC when VDDA, measured
+    /// by the brake module, is out of tolerance, the DTC clears once VDDA is
+    /// back in tolerance
+    pub fn check_supply_voltage(
+        &mut self,
+        supply_voltage: &SupplyVoltage,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, OxccError> {
+        if !supply_voltage.out_of_tolerance {
+            if self
+                .control_state
+                .dtcs
+                .check(OSCC_THROTTLE_DTC_SUPPLY_VOLTAGE)
+            {
+                self.control_state
+                    .dtcs
+                    .clear(OSCC_THROTTLE_DTC_SUPPLY_VOLTAGE);
+                self.update_fault_report();
+            }
+
+            return Ok(None);
+        }
+
+        if self
+            .control_state
+            .dtcs
+            .check(OSCC_THROTTLE_DTC_SUPPLY_VOLTAGE)
+        {
+            return Ok(None);
+        }
+
+        self.disable_control(debug_console)?;
+
+        self.control_state
+            .dtcs
+            .set(OSCC_THROTTLE_DTC_SUPPLY_VOLTAGE);
+
+        self.update_fault_report();
+
+        log!(
+            debug_console,
+            Module::Throttle,
+            Level::Error,
+            "Throttle supply voltage fault, VDDA {} V",
+            supply_voltage.vdda
+        );
+
+        Ok(Some(&self.fault_report))
+    }
+
+    /// Starts learning the accelerator position sensor operating range for a
+    /// guided calibration, the pedal is swept end to end until
+    /// `finish_range_learning`. Returns false while control is enabled.
+    pub fn start_range_learning(&mut self) -> bool {
+        if self.control_state.state == ControlState::Enabled {
+            return false;
+        }
+
+        self.accelerator_position.start_range_learning();
+        true
+    }
+
+    /// Samples the sensor while learning, called every FAULT_CHECK_PERIOD_MS
+    pub fn step_range_learning(&mut self) {
+        if self.accelerator_position.is_learning_range()
+            && (self.control_state.state != ControlState::Enabled)
+        {
+            self.accelerator_position.update();
+        }
+    }
+
+    /// Makes the learned range the operating range used by the sensor
+    /// fault checks, see `DualSignal::finish_range_learning`
+    pub fn finish_range_learning(&mut self) -> Option<SignalRange> {
+        self.accelerator_position.finish_range_learning()
+    }
+
+    /// Sets a previously learned operating range, None disables the range
+    /// check
+    pub fn set_operating_range(&mut self, range: Option<SignalRange>) {
+        self.accelerator_position.set_operating_range(range);
+    }
+
     fn update_fault_report(&mut self) {
         self.fault_report.dtcs = self.control_state.dtcs;
     }
 
+    pub fn state(&self) -> ThrottleStateSnapshot {
+        ThrottleStateSnapshot {
+            control_state: self.control_state.state,
+            enabled: self.control_state.state == ControlState::Enabled,
+            operator_override: self.control_state.state == ControlState::OverrideActive,
+            dtcs: self.control_state.dtcs,
+            commanded_position: self.control_state.last_commanded_position,
+            spoof_output: self.control_state.last_spoof,
+        }
+    }
+
     pub fn supply_throttle_report(&mut self) -> &OsccThrottleReport {
-        self.throttle_report.enabled = self.control_state.enabled;
-        self.throttle_report.operator_override = self.control_state.operator_override;
-        self.throttle_report.dtcs = self.control_state.dtcs;
+        let state = self.state();
+        self.throttle_report.enabled = state.enabled;
+        self.throttle_report.operator_override = state.operator_override;
+        self.throttle_report.dtcs = state.dtcs;
+        self.throttle_report.commanded_position = state.commanded_position;
+        self.throttle_report.spoof_output_high = match state.spoof_output {
+            Some((spoof_high, _)) => spoof_high,
+            None => 0,
+        };
         &self.throttle_report
     }
 
+    /// The acknowledgement of the last enable or disable frame, once
+    pub fn supply_control_ack(&mut self) -> Option<OsccControlAck> {
+        self.control_ack.take()
+    }
+
+    // queues the acknowledgement of an enable or disable frame, carrying the
+    // resulting state
+    fn acknowledge(&mut self, request: ControlRequest) {
+        self.control_ack = Some(OsccControlAck {
+            module_origin_id: FAULT_ORIGIN_THROTTLE,
+            request,
+            enabled: self.control_state.state == ControlState::Enabled,
+            dtcs: self.control_state.dtcs,
+        });
+    }
+
+    /// The module info, once after each module info request
+    pub fn supply_module_info(&mut self) -> Option<&OsccModuleInfo> {
+        if self.module_info_requested {
+            self.module_info_requested = false;
+            Some(&self.module_info)
+        } else {
+            None
+        }
+    }
+
+    /// The answer to the last parameter write or read, once
+    pub fn supply_param_value(&mut self) -> Option<OsccParamValue> {
+        self.param_value.take()
+    }
+
+    /// The current fault report, DTCs cleared since the last detection
+    /// included
+    pub fn supply_fault_report(&mut self) -> &OsccFaultReport {
+        &self.fault_report
+    }
+
+    // answers a parameter write (`raw` set) or read, parameters owned by
+    // another module are ignored
+    fn process_param_request(
+        &mut self,
+        param_id: u8,
+        raw: Option<u16>,
+        debug_console: &mut DebugConsole,
+    ) {
+        if self.param(param_id).is_none() {
+            return;
+        }
+
+        let status = match raw {
+            Some(_) if self.control_state.state == ControlState::Enabled => {
+                ParamStatus::RefusedWhileEnabled
+            }
+            Some(raw) => match (OsccParamWrite { param_id, raw }).value() {
+                Some(value) => self.write_param(param_id, value),
+                None => ParamStatus::InvalidValue,
+            },
+            None => ParamStatus::Ok,
+        };
+
+        // a refused write answers with the value still in effect
+        if let Some(current) = self.param(param_id) {
+            if let Some(raw) = raw {
+                if status == ParamStatus::Ok {
+                    log!(
+                        debug_console,
+                        Module::Throttle,
+                        Level::Info,
+                        "Throttle parameter 0x{:02X} set to {}",
+                        param_id,
+                        current
+                    );
+                } else {
+                    log!(
+                        debug_console,
+                        Module::Throttle,
+                        Level::Warn,
+                        "Throttle parameter 0x{:02X} write of raw value {} refused: {:?}",
+                        param_id,
+                        raw,
+                        status
+                    );
+                }
+            }
+
+            self.param_value = Some(OsccParamValue {
+                param_id,
+                status,
+                value: current,
+            });
+        }
+    }
+
+    // the value of one of this module's parameters, None for parameters
+    // owned by another module
+    fn param(&self, param_id: u8) -> Option<f32> {
+        match param_id {
+            OSCC_PARAM_THROTTLE_OVERRIDE_THRESHOLD => Some(self.override_threshold() as f32),
+            _ => None,
+        }
+    }
+
+    // applies a written parameter, the caller checks this module owns it and
+    // `value` is within the parameter's range
+    fn write_param(&mut self, param_id: u8, value: f32) -> ParamStatus {
+        match param_id {
+            OSCC_PARAM_THROTTLE_OVERRIDE_THRESHOLD => self.set_override_threshold(value as u32),
+            _ => return ParamStatus::InvalidValue,
+        }
+
+        ParamStatus::Ok
+    }
+
+    /// Handles the OSCC frames at IDs shifted by `can_id_offset`, see
+    /// `config::CanIdOffset`
+    pub fn set_can_id_offset(&mut self, can_id_offset: CanIdOffset) {
+        self.can_id_offset = can_id_offset;
+    }
+
+    /// `rx_timestamp_ms` is the system clock time the frame was received
     pub fn process_rx_frame(
         &mut self,
         can_frame: &CanFrame,
+        rx_timestamp_ms: u32,
         debug_console: &mut DebugConsole,
     ) -> Result<(), OxccError> {
         if let CanFrame::DataFrame(ref frame) = can_frame {
-            let id: u32 = frame.id().into();
+            // compared against the standard OSCC IDs from here on
+            let id = match self.can_id_offset.base_id(frame.id().into()) {
+                Some(id) => id,
+                None => return Ok(()),
+            };
             let data = frame.data();
 
-            if (data[0] == OSCC_MAGIC_BYTE_0) && (data[1] == OSCC_MAGIC_BYTE_1) {
+            if has_oscc_magic(data) {
                 if id == OSCC_THROTTLE_ENABLE_CAN_ID.into() {
-                    self.enable_control(debug_console)?;
+                    let result = self.enable_control(debug_console);
+                    self.acknowledge(ControlRequest::Enable);
+                    result?;
                 } else if id == OSCC_THROTTLE_DISABLE_CAN_ID.into() {
-                    self.disable_control(debug_console)?;
+                    let result = self.disable_control(debug_console);
+                    self.acknowledge(ControlRequest::Disable);
+                    result?;
+                } else if id == OSCC_ALL_DISABLE_CAN_ID.into() {
+                    self.emergency_stop(debug_console)?;
+                } else if id == OSCC_ALL_DISABLE_CLEAR_CAN_ID.into() {
+                    // would allow control to be enabled again
+                    if oscc_crc::is_crc_valid(data) {
+                        EMERGENCY_STOP_STATE.clear();
+                    } else {
+                        log!(
+                            debug_console,
+                            Module::Throttle,
+                            Level::Warn,
+                            "Dropped emergency stop clear with invalid CRC"
+                        );
+                    }
                 } else if id == OSCC_THROTTLE_COMMAND_CAN_ID.into() {
-                    self.process_throttle_command(&OsccThrottleCommand::from(frame))?;
+                    match OsccThrottleCommand::from_frame(frame) {
+                        Ok(ref command) => {
+                            self.control_state.last_command_ms = rx_timestamp_ms;
+                            self.process_throttle_command(command)?;
+                        }
+                        Err(e) => {
+                            log!(
+                                debug_console,
+                                Module::Throttle,
+                                Level::Warn,
+                                "Dropped malformed throttle command: {:?}",
+                                e
+                            );
+                        }
+                    }
                 } else if id == OSCC_FAULT_REPORT_CAN_ID.into() {
-                    self.process_fault_report(&OsccFaultReport::from(frame), debug_console)?;
+                    match OsccFaultReport::from_frame(frame) {
+                        Ok(ref fault_report) => {
+                            self.process_fault_report(fault_report, debug_console)?
+                        }
+                        Err(e) => {
+                            log!(
+                                debug_console,
+                                Module::Throttle,
+                                Level::Warn,
+                                "Dropped malformed fault report: {:?}",
+                                e
+                            );
+                        }
+                    }
+                } else if id == OSCC_MODULE_INFO_REQUEST_CAN_ID.into() {
+                    self.module_info_requested = true;
+                } else if id == OSCC_PARAM_WRITE_CAN_ID.into() {
+                    match OsccParamWrite::from_frame(frame) {
+                        Ok(write) => self.process_param_request(
+                            write.param_id,
+                            Some(write.raw),
+                            debug_console,
+                        ),
+                        Err(e) => {
+                            log!(
+                                debug_console,
+                                Module::Throttle,
+                                Level::Warn,
+                                "Dropped parameter write: {:?}",
+                                e
+                            );
+                        }
+                    }
+                } else if id == OSCC_PARAM_READ_CAN_ID.into() {
+                    if let Some(read) = OsccParamRead::from_frame(frame) {
+                        self.process_param_request(read.param_id, None, debug_console);
+                    }
                 }
             }
         }
@@ -244,27 +1001,63 @@ impl ThrottleModule {
         Ok(())
     }
 
+    /// Disables control regardless of the current state and latches the
+    /// emergency stop, blocking re-enable until the e-stop clear frame
+    fn emergency_stop(&mut self, debug_console: &mut DebugConsole) -> Result<(), OxccError> {
+        EMERGENCY_STOP_STATE.latch();
+
+        log!(
+            debug_console,
+            Module::Throttle,
+            Level::Warn,
+            "Emergency stop received"
+        );
+
+        self.disable_control(debug_console)
+    }
+
     fn process_fault_report(
         &mut self,
         fault_report: &OsccFaultReport,
         debug_console: &mut DebugConsole,
     ) -> Result<(), OxccError> {
-        writeln!(
+        log!(
             debug_console,
+            Module::Throttle,
+            Level::Warn,
             "Fault report received from: {} DTCs: {}",
-            fault_report.fault_origin_id, fault_report.dtcs
+            fault_origin_name(fault_report.fault_origin_id),
+            fault_report.dtcs
         );
 
+        let dtcs = describe_dtc(fault_report.fault_origin_id, fault_report.dtcs);
+        let extended_dtcs =
+            describe_extended_dtc(fault_report.fault_origin_id, fault_report.extended_dtcs);
+
+        for dtc in dtcs.chain(extended_dtcs) {
+            log!(debug_console, Module::Throttle, Level::Warn, "  {}", dtc);
+        }
+
+        if fault_report.is_hard_fault() {
+            SYSTEM_FAULT_STATE.latch();
+        }
+
         self.disable_control(debug_console)
     }
 
     fn process_throttle_command(&mut self, command: &OsccThrottleCommand) -> Result<(), OxccError> {
-        let clamped_position = num::clamp(
-            command.torque_request,
+        let clamped_position = apply_command_deadband(
+            num::clamp(
+                command.torque_request,
+                MINIMUM_THROTTLE_COMMAND,
+                MAXIMUM_THROTTLE_COMMAND,
+            ),
             MINIMUM_THROTTLE_COMMAND,
-            MAXIMUM_THROTTLE_COMMAND,
+            THROTTLE_COMMAND_DEADBAND,
         );
 
+        self.control_state.last_commanded_position = clamped_position;
+
         let spoof_voltage_low: f32 = num::clamp(
             throttle_position_to_volts_low(clamped_position),
             THROTTLE_SPOOF_LOW_SIGNAL_VOLTAGE_MIN,
@@ -277,9 +1070,468 @@ impl ThrottleModule {
             THROTTLE_SPOOF_HIGH_SIGNAL_VOLTAGE_MAX,
         );
 
-        let spoof_value_low = (STEPS_PER_VOLT * spoof_voltage_low) as u16;
-        let spoof_value_high = (STEPS_PER_VOLT * spoof_voltage_high) as u16;
+        let steps_per_volt = self.throttle_dac.steps_per_volt();
+        let spoof_value_low = *volts_to_dac_steps(spoof_voltage_low, steps_per_volt).val();
+        let spoof_value_high = *volts_to_dac_steps(spoof_voltage_high, steps_per_volt).val();
 
         self.update_throttle(spoof_value_high, spoof_value_low)
     }
 }
+
+impl ControlModule for ThrottleModule {
+    fn disable_control(&mut self, debug_console: &mut DebugConsole) -> Result<(), OxccError> {
+        ThrottleModule::disable_control(self, debug_console)
+    }
+
+    fn check_for_faults(
+        &mut self,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, OxccError> {
+        ThrottleModule::check_for_faults(self, debug_console)
+    }
+
+    fn check_can_health(
+        &mut self,
+        can_health: &CanHealth,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, OxccError> {
+        ThrottleModule::check_can_health(self, can_health, debug_console)
+    }
+
+    fn check_spoof_enable(
+        &mut self,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, OxccError> {
+        ThrottleModule::check_spoof_enable(self, debug_console)
+    }
+
+    fn check_dac_output(
+        &mut self,
+        debug_console: &mut DebugConsole,
+    ) -> Result<Option<&OsccFaultReport>, OxccError> {
+        ThrottleModule::check_dac_output(self, debug_console)
+    }
+
+    fn set_can_id_offset(&mut self, can_id_offset: CanIdOffset) {
+        ThrottleModule::set_can_id_offset(self, can_id_offset)
+    }
+
+    fn process_rx_frame(
+        &mut self,
+        can_frame: &CanFrame,
+        rx_timestamp_ms: u32,
+        debug_console: &mut DebugConsole,
+    ) -> Result<(), OxccError> {
+        ThrottleModule::process_rx_frame(self, can_frame, rx_timestamp_ms, debug_console)
+    }
+
+    fn step_range_learning(&mut self) {
+        ThrottleModule::step_range_learning(self)
+    }
+
+    fn publish_reports(&mut self, publisher: &mut ReportPublisher) -> Result<(), CanError> {
+        publisher.publish_throttle_report(self.supply_throttle_report())
+    }
+
+    fn supply_control_ack(&mut self) -> Option<OsccControlAck> {
+        ThrottleModule::supply_control_ack(self)
+    }
+
+    fn supply_module_info(&mut self) -> Option<&OsccModuleInfo> {
+        ThrottleModule::supply_module_info(self)
+    }
+
+    fn supply_param_value(&mut self) -> Option<OsccParamValue> {
+        ThrottleModule::supply_param_value(self)
+    }
+
+    fn supply_fault_report(&mut self) -> &OsccFaultReport {
+        ThrottleModule::supply_fault_report(self)
+    }
+}
+
+/// (high, low) spoof values for the minimum throttle command
+fn idle_spoof_values(steps_per_volt: f32) -> (u16, u16) {
+    let idle_volts_high = throttle_position_to_volts_high(MINIMUM_THROTTLE_COMMAND);
+    let idle_volts_low = throttle_position_to_volts_low(MINIMUM_THROTTLE_COMMAND);
+
+    let idle_high =
+        ThrottleSpoofHighSignal::clamp(*volts_to_dac_steps(idle_volts_high, steps_per_volt).val());
+    let idle_low =
+        ThrottleSpoofLowSignal::clamp(*volts_to_dac_steps(idle_volts_low, steps_per_volt).val());
+
+    (*idle_high.val(), *idle_low.val())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oscc_protocol_version::OSCC_PROTOCOL_VERSION;
+    use sim_board::{oscc_frame, SimBoard, SimConsole};
+    use system_clock::TestClock;
+
+    // pedal at rest, well inside the idle band
+    const IDLE: (u16, u16) = (400, 200);
+
+    // the clock is held for the whole test, it also keeps the tests sharing
+    // the fault latches and the vehicle state from running at once
+    fn throttle_module() -> (TestClock, ThrottleModule, SimConsole) {
+        let clock = TestClock::take();
+        SYSTEM_FAULT_STATE.clear();
+        EMERGENCY_STOP_STATE.clear();
+        VEHICLE_STATE.set_brake_operator_override(false);
+        VEHICLE_STATE.set_brake_applied(false);
+        VEHICLE_STATE.set_brake_pedal_pressed(false);
+
+        let board = SimBoard::new();
+        board.accelerator_position.set(IDLE.0, IDLE.1);
+        let mut console = board.debug_console;
+
+        let throttle = UnpreparedThrottleModule::new(
+            board.accelerator_position,
+            board.throttle_dac,
+            board.throttle_pins,
+        )
+        .prepare_module(&mut console);
+
+        (clock, throttle, console)
+    }
+
+    fn command_frame(torque_request: f32) -> CanFrame {
+        let bits = torque_request.to_bits();
+        oscc_frame(
+            OSCC_THROTTLE_COMMAND_CAN_ID,
+            &[
+                bits as u8,
+                (bits >> 8) as u8,
+                (bits >> 16) as u8,
+                (bits >> 24) as u8,
+            ],
+        )
+    }
+
+    fn param_write_frame(param_id: u8, raw: u16) -> CanFrame {
+        oscc_frame(
+            OSCC_PARAM_WRITE_CAN_ID,
+            &[
+                param_id,
+                0,
+                raw as u8,
+                (raw >> 8) as u8,
+                OSCC_PROTOCOL_VERSION,
+            ],
+        )
+    }
+
+    // the threshold answered to a read, with the answer's status
+    fn read_override_threshold(
+        throttle: &mut ThrottleModule,
+        console: &mut SimConsole,
+    ) -> (ParamStatus, f32) {
+        receive(
+            throttle,
+            &oscc_frame(
+                OSCC_PARAM_READ_CAN_ID,
+                &[OSCC_PARAM_THROTTLE_OVERRIDE_THRESHOLD],
+            ),
+            console,
+        );
+        let param_value = throttle.supply_param_value().unwrap();
+        (param_value.status, param_value.value)
+    }
+
+    fn receive(throttle: &mut ThrottleModule, frame: &CanFrame, console: &mut SimConsole) {
+        throttle
+            .process_rx_frame(frame, system_clock::get_timestamp_ms(), console)
+            .unwrap();
+    }
+
+    fn enabled_throttle_module() -> (TestClock, ThrottleModule, SimConsole) {
+        let (clock, mut throttle, mut console) = throttle_module();
+        receive(
+            &mut throttle,
+            &oscc_frame(OSCC_THROTTLE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+        assert!(throttle.state().enabled);
+        (clock, throttle, console)
+    }
+
+    fn idle_spoof(throttle: &ThrottleModule) -> (u16, u16) {
+        idle_spoof_values(throttle.throttle_dac.steps_per_volt())
+    }
+
+    #[test]
+    fn braking_drops_the_output_to_idle_without_a_command() {
+        let (_clock, mut throttle, mut console) = enabled_throttle_module();
+        for _ in 0..10 {
+            receive(&mut throttle, &command_frame(0.8), &mut console);
+        }
+        let idle = idle_spoof(&throttle);
+        assert!(throttle.state().spoof_output.unwrap().0 > idle.0);
+
+        VEHICLE_STATE.set_brake_pedal_pressed(true);
+        throttle.check_for_faults(&mut console).unwrap();
+
+        assert_eq!(throttle.state().spoof_output, Some(idle));
+        assert!(throttle.state().enabled);
+    }
+
+    #[test]
+    fn commands_are_held_at_idle_while_braking() {
+        let (_clock, mut throttle, mut console) = enabled_throttle_module();
+        VEHICLE_STATE.set_brake_operator_override(true);
+
+        receive(&mut throttle, &command_frame(0.8), &mut console);
+
+        assert_eq!(throttle.state().spoof_output, Some(idle_spoof(&throttle)));
+    }
+
+    #[test]
+    fn enable_is_refused_while_the_emergency_stop_is_latched() {
+        let (_clock, mut throttle, mut console) = throttle_module();
+        EMERGENCY_STOP_STATE.latch();
+
+        let enable = oscc_frame(OSCC_THROTTLE_ENABLE_CAN_ID, &[]);
+        assert_eq!(
+            throttle.process_rx_frame(&enable, 0, &mut console),
+            Err(OxccError::NotReady)
+        );
+        assert_eq!(throttle.control_state(), ControlState::Disabled);
+        assert!(!throttle.throttle_pins.spoof_enable.is_high());
+    }
+
+    #[test]
+    fn override_is_held_until_the_pedal_is_released() {
+        let (clock, mut throttle, mut console) = enabled_throttle_module();
+
+        throttle.accelerator_position.reader().set(1600, 800);
+        assert!(throttle.check_for_faults(&mut console).unwrap().is_none());
+        clock.advance_ms(OPERATOR_OVERRIDE_HYSTERESIS);
+        assert!(throttle.check_for_faults(&mut console).unwrap().is_some());
+        assert_eq!(throttle.control_state(), ControlState::OverrideActive);
+        assert!(!throttle.throttle_pins.spoof_enable.is_high());
+
+        let enable = oscc_frame(OSCC_THROTTLE_ENABLE_CAN_ID, &[]);
+        assert_eq!(
+            throttle.process_rx_frame(&enable, 0, &mut console),
+            Err(OxccError::NotReady)
+        );
+        assert_eq!(throttle.control_state(), ControlState::OverrideActive);
+
+        throttle.accelerator_position.reader().set(IDLE.0, IDLE.1);
+        throttle.check_for_faults(&mut console).unwrap();
+        clock.advance_ms(OPERATOR_OVERRIDE_RELEASE_DEBOUNCE);
+        throttle.check_for_faults(&mut console).unwrap();
+        assert_eq!(throttle.control_state(), ControlState::Disabled);
+        assert_eq!(throttle.state().dtcs, 0);
+    }
+
+    #[test]
+    fn grounded_sensor_faults_until_it_reads_again() {
+        let (clock, mut throttle, mut console) = enabled_throttle_module();
+
+        throttle.accelerator_position.reader().set(0, 0);
+        throttle.check_for_faults(&mut console).unwrap();
+        clock.advance_ms(SENSOR_FAULT_HYSTERESIS);
+        assert!(throttle.check_for_faults(&mut console).unwrap().is_some());
+        assert_eq!(throttle.control_state(), ControlState::Faulted);
+
+        let enable = oscc_frame(OSCC_THROTTLE_ENABLE_CAN_ID, &[]);
+        assert_eq!(
+            throttle.process_rx_frame(&enable, 0, &mut console),
+            Err(OxccError::NotReady)
+        );
+
+        throttle.accelerator_position.reader().set(IDLE.0, IDLE.1);
+        assert!(throttle.check_for_faults(&mut console).unwrap().is_none());
+        assert_eq!(throttle.control_state(), ControlState::Disabled);
+        receive(&mut throttle, &enable, &mut console);
+        assert_eq!(throttle.control_state(), ControlState::Enabled);
+    }
+
+    #[test]
+    fn self_test_refuses_a_grounded_sensor_and_passes_at_idle() {
+        let (_clock, mut throttle, mut console) = throttle_module();
+        let enable = oscc_frame(OSCC_THROTTLE_ENABLE_CAN_ID, &[]);
+
+        throttle.accelerator_position.reader().set(0, 0);
+        assert_eq!(
+            throttle.process_rx_frame(&enable, 0, &mut console),
+            Err(OxccError::NotReady)
+        );
+        assert_eq!(throttle.control_state(), ControlState::Disabled);
+        assert!(throttle
+            .state()
+            .dtcs
+            .check(OSCC_THROTTLE_DTC_INVALID_SENSOR_VAL));
+
+        throttle.accelerator_position.reader().set(IDLE.0, IDLE.1);
+        assert_eq!(throttle.self_test(), Ok(()));
+        receive(&mut throttle, &enable, &mut console);
+        assert_eq!(throttle.control_state(), ControlState::Enabled);
+        assert!(throttle.throttle_pins.spoof_enable.is_high());
+    }
+
+    #[test]
+    fn report_echoes_the_command_and_the_spoof_output() {
+        let (_clock, mut throttle, mut console) = enabled_throttle_module();
+        let command = 0.3;
+
+        // past the ramp limit
+        for _ in 0..50 {
+            receive(&mut throttle, &command_frame(command), &mut console);
+        }
+
+        let steps_per_volt = throttle.throttle_dac.steps_per_volt();
+        let expected_high = *volts_to_dac_steps(
+            num::clamp(
+                throttle_position_to_volts_high(command),
+                THROTTLE_SPOOF_HIGH_SIGNAL_VOLTAGE_MIN,
+                THROTTLE_SPOOF_HIGH_SIGNAL_VOLTAGE_MAX,
+            ),
+            steps_per_volt,
+        )
+        .val();
+
+        let report = throttle.supply_throttle_report();
+        assert_eq!(report.commanded_position, command);
+        assert_eq!(report.spoof_output_high, expected_high);
+    }
+
+    #[test]
+    fn idle_calibration_learns_the_resting_pedal() {
+        let (_clock, mut throttle, _console) = throttle_module();
+
+        assert_eq!(throttle.calibrate_idle(8), Ok(300));
+        assert_eq!(throttle.idle_baseline(), Some(300));
+        assert_eq!(
+            throttle.override_threshold(),
+            cmp::min(
+                300 + ACCELERATOR_OVERRIDE_MARGIN,
+                ACCELERATOR_OVERRIDE_THRESHOLD
+            )
+        );
+    }
+
+    #[test]
+    fn idle_calibration_is_rejected_while_the_pedal_is_pressed() {
+        let (_clock, mut throttle, _console) = throttle_module();
+        throttle.calibrate_idle(8).unwrap();
+
+        throttle.accelerator_position.reader().set(1600, 800);
+        assert_eq!(
+            throttle.calibrate_idle(8),
+            Err(OSCC_THROTTLE_DTC_OPERATOR_OVERRIDE)
+        );
+        throttle.accelerator_position.reader().set(0, 0);
+        assert_eq!(
+            throttle.calibrate_idle(8),
+            Err(OSCC_THROTTLE_DTC_INVALID_SENSOR_VAL)
+        );
+
+        // the earlier baseline is kept
+        assert_eq!(throttle.idle_baseline(), Some(300));
+    }
+
+    #[test]
+    fn remapped_module_responds_only_to_the_shifted_enable() {
+        let (_clock, mut throttle, mut console) = throttle_module();
+        throttle.set_can_id_offset(CanIdOffset::new(0x100).unwrap());
+
+        receive(
+            &mut throttle,
+            &oscc_frame(OSCC_THROTTLE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+        assert!(throttle.supply_control_ack().is_none());
+        assert_eq!(throttle.control_state(), ControlState::Disabled);
+
+        receive(
+            &mut throttle,
+            &oscc_frame(OSCC_THROTTLE_ENABLE_CAN_ID + 0x100, &[]),
+            &mut console,
+        );
+        assert!(throttle.supply_control_ack().unwrap().accepted());
+        assert_eq!(throttle.control_state(), ControlState::Enabled);
+    }
+
+    #[test]
+    fn override_threshold_is_written_over_can_and_read_back() {
+        let (_clock, mut throttle, mut console) = throttle_module();
+        assert_eq!(
+            read_override_threshold(&mut throttle, &mut console),
+            (ParamStatus::Ok, ACCELERATOR_OVERRIDE_THRESHOLD as f32)
+        );
+
+        receive(
+            &mut throttle,
+            &param_write_frame(OSCC_PARAM_THROTTLE_OVERRIDE_THRESHOLD, 600),
+            &mut console,
+        );
+        let param_value = throttle.supply_param_value().unwrap();
+        assert_eq!(param_value.status, ParamStatus::Ok);
+        assert_eq!(param_value.value, 600.0);
+        assert_eq!(
+            read_override_threshold(&mut throttle, &mut console),
+            (ParamStatus::Ok, 600.0)
+        );
+
+        // a pedal between the two thresholds now overrides
+        throttle.accelerator_position.reader().set(700, 650);
+        assert_eq!(
+            throttle.self_test(),
+            Err(OSCC_THROTTLE_DTC_OPERATOR_OVERRIDE)
+        );
+    }
+
+    #[test]
+    fn rejected_override_threshold_writes_keep_the_threshold() {
+        let (_clock, mut throttle, mut console) = throttle_module();
+        let default_threshold = (ParamStatus::Ok, ACCELERATOR_OVERRIDE_THRESHOLD as f32);
+
+        // low enough to read a pedal at rest as an override
+        receive(
+            &mut throttle,
+            &param_write_frame(OSCC_PARAM_THROTTLE_OVERRIDE_THRESHOLD, 50),
+            &mut console,
+        );
+        let param_value = throttle.supply_param_value().unwrap();
+        assert_eq!(param_value.status, ParamStatus::InvalidValue);
+        assert_eq!(param_value.value, ACCELERATOR_OVERRIDE_THRESHOLD as f32);
+
+        // high enough to never see an override
+        receive(
+            &mut throttle,
+            &param_write_frame(OSCC_PARAM_THROTTLE_OVERRIDE_THRESHOLD, 4095),
+            &mut console,
+        );
+        assert_eq!(
+            throttle.supply_param_value().unwrap().status,
+            ParamStatus::InvalidValue
+        );
+        assert_eq!(
+            read_override_threshold(&mut throttle, &mut console),
+            default_threshold
+        );
+
+        receive(
+            &mut throttle,
+            &oscc_frame(OSCC_THROTTLE_ENABLE_CAN_ID, &[]),
+            &mut console,
+        );
+        receive(
+            &mut throttle,
+            &param_write_frame(OSCC_PARAM_THROTTLE_OVERRIDE_THRESHOLD, 600),
+            &mut console,
+        );
+        assert_eq!(
+            throttle.supply_param_value().unwrap().status,
+            ParamStatus::RefusedWhileEnabled
+        );
+        assert_eq!(
+            read_override_threshold(&mut throttle, &mut console),
+            default_threshold
+        );
+    }
+}
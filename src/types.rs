@@ -1,83 +1,150 @@
 use dac_mcp4922::Mcp4922;
 use nucleo_f767zi::hal::can::Can;
-use nucleo_f767zi::hal::gpio::gpioa::{PA15, PA4, PA5, PA6, PA7};
-use nucleo_f767zi::hal::gpio::gpiob::{PB10, PB12, PB13, PB15, PB4};
+use nucleo_f767zi::hal::gpio::gpioa::{PA5, PA6, PA7};
+use nucleo_f767zi::hal::gpio::gpiob::{PB10, PB12, PB13, PB15};
 use nucleo_f767zi::hal::gpio::gpioc::{PC10, PC11, PC12, PC2};
-use nucleo_f767zi::hal::gpio::gpiod::{PD0, PD1, PD10, PD11, PD12, PD13};
-use nucleo_f767zi::hal::gpio::{Output, PushPull, AF5, AF9};
-use nucleo_f767zi::hal::spi::Spi;
-use nucleo_f767zi::hal::stm32f7x7::{
-    CAN1, CAN2, SPI1, SPI2, SPI3, TIM2, TIM3, TIM4, TIM5, TIM6, TIM7,
-};
-use nucleo_f767zi::hal::timer::Timer;
-use nucleo_f767zi::{
-    AnalogInput0Pin, AnalogInput1Pin, AnalogInput2Pin, AnalogInput4Pin, AnalogInput5Pin,
-    AnalogInput6Pin,
-};
-
-pub type CanPublishTimer = Timer<TIM2>;
-pub type BrakeGroundedFaultTimer = Timer<TIM3>;
-pub type BrakeOverrideFaultTimer = Timer<TIM4>;
-pub type ThrottleGroundedFaultTimer = Timer<TIM5>;
-pub type ThrottleOverrideFaultTimer = Timer<TIM6>;
-pub type SteeringGroundedFaultTimer = Timer<TIM7>;
+use nucleo_f767zi::hal::gpio::gpiod::{PD0, PD1};
+use nucleo_f767zi::hal::gpio::{AF5, AF9};
+use nucleo_f767zi::hal::stm32f7x7::{CAN1, CAN2};
+use override_source::OverrideSwitch;
+use spoof_enable_sense::SpoofEnableSense;
+
+pub use self::module_peripherals::*;
+
+/// The peripherals the control modules own
+#[cfg(not(any(test, feature = "sim")))]
+mod module_peripherals {
+    #[cfg(feature = "brake-pressure-loop")]
+    use nucleo_f767zi::hal::gpio::gpioa::PA0;
+    use nucleo_f767zi::hal::gpio::gpioa::{PA15, PA4, PA5, PA6, PA7};
+    use nucleo_f767zi::hal::gpio::gpiob::{PB10, PB15, PB4};
+    use nucleo_f767zi::hal::gpio::gpioc::{PC10, PC11, PC12, PC2};
+    use nucleo_f767zi::hal::gpio::gpiod::{PD10, PD11, PD12, PD13, PD14};
+    use nucleo_f767zi::hal::gpio::gpioe::{PE2, PE4, PE5};
+    #[cfg(feature = "brake-pressure-loop")]
+    use nucleo_f767zi::hal::gpio::Analog;
+    use nucleo_f767zi::hal::gpio::{Input, Output, PullDown, PullUp, PushPull, AF5};
+    use nucleo_f767zi::hal::spi::Spi;
+    use nucleo_f767zi::hal::stm32f7x7::{SPI1, SPI2, SPI3};
+    use nucleo_f767zi::{
+        AnalogInput0Pin, AnalogInput1Pin, AnalogInput2Pin, AnalogInput4Pin, AnalogInput5Pin,
+        AnalogInput6Pin,
+    };
+
+    pub use nucleo_f767zi::debug_console::DebugConsole;
+
+    pub type BrakeSpi = Spi<SPI1, (PA5<AF5>, PA6<AF5>, PA7<AF5>)>;
+    pub type ThrottleSpi = Spi<SPI2, (PB10<AF5>, PC2<AF5>, PB15<AF5>)>;
+    pub type SteeringSpi = Spi<SPI3, (PC10<AF5>, PC11<AF5>, PC12<AF5>)>;
+
+    pub type BrakeSpoofEnablePin = PD12<Output<PushPull>>;
+    pub type BrakeSpoofEnableSensePin = PE2<Input<PullDown>>;
+    pub type BrakeLightEnablePin = PD13<Output<PushPull>>;
+    pub type BrakeOverrideSwitchPin = PD14<Input<PullUp>>;
+    // AIN pins chosen to allow brake module to own ADC1
+    pub type BrakePedalPositionSensorHighPin = AnalogInput0Pin;
+    pub type BrakePedalPositionSensorLowPin = AnalogInput1Pin;
+    // ADC123_IN0, also read by ADC1
+    #[cfg(feature = "brake-pressure-loop")]
+    pub type BrakePressureSensorPin = PA0<Analog>;
+    pub type BrakeSpiNssPin = PA4<Output<PushPull>>;
+
+    pub type ThrottleSpoofEnablePin = PD10<Output<PushPull>>;
+    pub type ThrottleSpoofEnableSensePin = PE4<Input<PullDown>>;
+    // AIN pins chosen to allow throttle module to own ADC2
+    pub type AcceleratorPositionSensorHighPin = AnalogInput2Pin;
+    pub type AcceleratorPositionSensorLowPin = AnalogInput6Pin;
+    pub type ThrottleSpiNssPin = PB4<Output<PushPull>>;
+
+    pub type SteeringSpoofEnablePin = PD11<Output<PushPull>>;
+    pub type SteeringSpoofEnableSensePin = PE5<Input<PullDown>>;
+    // AIN pins chosen to allow steering module to own ADC3
+    pub type TorqueSensorHighPin = AnalogInput4Pin;
+    pub type TorqueSensorLowPin = AnalogInput5Pin;
+    pub type SteeringSpiNssPin = PA15<Output<PushPull>>;
+}
+
+/// The in-memory counterparts from `sim_board`, the host tests and sim
+/// builds run the control modules on them
+#[cfg(any(test, feature = "sim"))]
+mod module_peripherals {
+    use sim_board::{SimAnalogPin, SimInputPin, SimPin, SimSpi};
+
+    pub use sim_board::SimConsole as DebugConsole;
+
+    pub type BrakeSpi = SimSpi;
+    pub type ThrottleSpi = SimSpi;
+    pub type SteeringSpi = SimSpi;
+
+    pub type BrakeSpoofEnablePin = SimPin;
+    pub type BrakeSpoofEnableSensePin = SimInputPin;
+    pub type BrakeLightEnablePin = SimPin;
+    pub type BrakeOverrideSwitchPin = SimInputPin;
+    pub type BrakePedalPositionSensorHighPin = SimAnalogPin;
+    pub type BrakePedalPositionSensorLowPin = SimAnalogPin;
+    #[cfg(feature = "brake-pressure-loop")]
+    pub type BrakePressureSensorPin = SimAnalogPin;
+    pub type BrakeSpiNssPin = SimPin;
+
+    pub type ThrottleSpoofEnablePin = SimPin;
+    pub type ThrottleSpoofEnableSensePin = SimInputPin;
+    pub type AcceleratorPositionSensorHighPin = SimAnalogPin;
+    pub type AcceleratorPositionSensorLowPin = SimAnalogPin;
+    pub type ThrottleSpiNssPin = SimPin;
+
+    pub type SteeringSpoofEnablePin = SimPin;
+    pub type SteeringSpoofEnableSensePin = SimInputPin;
+    pub type TorqueSensorHighPin = SimAnalogPin;
+    pub type TorqueSensorLowPin = SimAnalogPin;
+    pub type SteeringSpiNssPin = SimPin;
+}
 
 pub type ControlCan = Can<CAN1, (PD1<AF9>, PD0<AF9>)>;
 pub type ObdCan = Can<CAN2, (PB13<AF9>, PB12<AF9>)>;
 
-pub type BrakeSpi = Spi<SPI1, (PA5<AF5>, PA6<AF5>, PA7<AF5>)>;
-pub type ThrottleSpi = Spi<SPI2, (PB10<AF5>, PC2<AF5>, PB15<AF5>)>;
-pub type SteeringSpi = Spi<SPI3, (PC10<AF5>, PC11<AF5>, PC12<AF5>)>;
-
-pub type BrakeSpoofEnablePin = PD12<Output<PushPull>>;
-pub type BrakeLightEnablePin = PD13<Output<PushPull>>;
-// AIN pins chosen to allow brake module to own ADC1
-pub type BrakePedalPositionSensorHighPin = AnalogInput0Pin;
-pub type BrakePedalPositionSensorLowPin = AnalogInput1Pin;
 pub type BrakeSpiSckPin = PA5<AF5>;
 pub type BrakeSpiMisoPin = PA6<AF5>;
 pub type BrakeSpiMosiPin = PA7<AF5>;
-pub type BrakeSpiNssPin = PA4<Output<PushPull>>;
 
 pub type BrakeDac = Mcp4922<BrakeSpi, BrakeSpiNssPin>;
 
-pub type ThrottleSpoofEnablePin = PD10<Output<PushPull>>;
-// AIN pins chosen to allow throttle module to own ADC2
-pub type AcceleratorPositionSensorHighPin = AnalogInput2Pin;
-pub type AcceleratorPositionSensorLowPin = AnalogInput6Pin;
 pub type ThrottleSpiSckPin = PB10<AF5>;
 pub type ThrottleSpiMisoPin = PC2<AF5>;
 pub type ThrottleSpiMosiPin = PB15<AF5>;
-pub type ThrottleSpiNssPin = PB4<Output<PushPull>>;
 
 pub type ThrottleDac = Mcp4922<ThrottleSpi, ThrottleSpiNssPin>;
 
-pub type SteeringSpoofEnablePin = PD11<Output<PushPull>>;
-// AIN pins chosen to allow steering module to own ADC3
-pub type TorqueSensorHighPin = AnalogInput4Pin;
-pub type TorqueSensorLowPin = AnalogInput5Pin;
 pub type SteeringSpiSckPin = PC10<AF5>;
 pub type SteeringSpiMisoPin = PC11<AF5>;
 pub type SteeringSpiMosiPin = PC12<AF5>;
-pub type SteeringSpiNssPin = PA15<Output<PushPull>>;
 
 pub type SteeringDac = Mcp4922<SteeringSpi, SteeringSpiNssPin>;
 
 pub struct BrakePins {
     pub spoof_enable: BrakeSpoofEnablePin,
+    /// Optional read-back of the spoof enable relay
+    pub spoof_enable_sense: Option<SpoofEnableSense<BrakeSpoofEnableSensePin>>,
     pub brake_light_enable: BrakeLightEnablePin,
     pub pedal_pos_sensor_high: BrakePedalPositionSensorHighPin,
     pub pedal_pos_sensor_low: BrakePedalPositionSensorLowPin,
+    /// Optional operator override switch, OR-ed with the pedal override
+    pub override_switch: Option<OverrideSwitch<BrakeOverrideSwitchPin>>,
+    #[cfg(feature = "brake-pressure-loop")]
+    pub pressure_sensor: BrakePressureSensorPin,
 }
 
 pub struct ThrottlePins {
     pub spoof_enable: ThrottleSpoofEnablePin,
+    /// Optional read-back of the spoof enable relay
+    pub spoof_enable_sense: Option<SpoofEnableSense<ThrottleSpoofEnableSensePin>>,
     pub accel_pos_sensor_high: AcceleratorPositionSensorHighPin,
     pub accel_pos_sensor_low: AcceleratorPositionSensorLowPin,
 }
 
 pub struct SteeringPins {
     pub spoof_enable: SteeringSpoofEnablePin,
+    /// Optional read-back of the spoof enable relay
+    pub spoof_enable_sense: Option<SpoofEnableSense<SteeringSpoofEnableSensePin>>,
     pub torque_sensor_high: TorqueSensorHighPin,
     pub torque_sensor_low: TorqueSensorLowPin,
 }
@@ -0,0 +1,94 @@
+//! Typed units for the position, voltage and DAC step conversions
+//!
+//! Keeps the steps per volt conversion in one place so a voltage can't be
+//! passed where DAC steps are expected. The `From` conversions use the
+//! vehicle's `STEPS_PER_VOLT`, a DAC with another reference voltage passes
+//! its own `steps_per_volt`.
+
+use vehicle::STEPS_PER_VOLT;
+
+/// Signal voltage [V]
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub struct Volts(pub f32);
+
+/// DAC output value [steps]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DacSteps(pub u16);
+
+impl DacSteps {
+    /// Rounds to the nearest step and saturates at the `u16` limits
+    ///
+    /// A raw `as u16` cast of an out of range float is not well defined, so
+    /// negative (and NaN) voltages become 0 and large ones become the maximum.
+    pub fn from_volts(volts: Volts, steps_per_volt: f32) -> Self {
+        let steps = (steps_per_volt * volts.0) + 0.5;
+
+        if steps > 0.0 {
+            if steps < f32::from(u16::max_value()) {
+                DacSteps(steps as u16)
+            } else {
+                DacSteps(u16::max_value())
+            }
+        } else {
+            DacSteps(0)
+        }
+    }
+}
+
+impl From<Volts> for DacSteps {
+    fn from(volts: Volts) -> Self {
+        DacSteps::from_volts(volts, STEPS_PER_VOLT)
+    }
+}
+
+impl Volts {
+    pub fn from_steps(steps: DacSteps, steps_per_volt: f32) -> Self {
+        Volts(f32::from(steps.0) / steps_per_volt)
+    }
+}
+
+impl From<DacSteps> for Volts {
+    fn from(steps: DacSteps) -> Self {
+        Volts::from_steps(steps, STEPS_PER_VOLT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a 12 bit DAC at a 5 V reference
+    const STEPS_PER_VOLT_5V: f32 = 819.2;
+
+    #[test]
+    fn volts_round_to_the_nearest_step() {
+        assert_eq!(
+            DacSteps::from_volts(Volts(0.333), STEPS_PER_VOLT_5V),
+            DacSteps(273)
+        );
+        assert_eq!(
+            DacSteps::from_volts(Volts(0.698), STEPS_PER_VOLT_5V),
+            DacSteps(572)
+        );
+        assert_eq!(
+            DacSteps::from_volts(Volts(1.0), STEPS_PER_VOLT_5V),
+            DacSteps(819)
+        );
+    }
+
+    #[test]
+    fn volts_saturate_at_the_step_limits() {
+        assert_eq!(
+            DacSteps::from_volts(Volts(-1.0), STEPS_PER_VOLT_5V),
+            DacSteps(0)
+        );
+        assert_eq!(
+            DacSteps::from_volts(Volts(::core::f32::NAN), STEPS_PER_VOLT_5V),
+            DacSteps(0)
+        );
+        assert_eq!(
+            DacSteps::from_volts(Volts(100.0), STEPS_PER_VOLT_5V),
+            DacSteps(u16::max_value())
+        );
+    }
+}
@@ -4,3 +4,357 @@ pub use kial_niro::*;
 pub use kial_soul_ev::*;
 #[cfg(feature = "kia-soul-petrol")]
 pub use kial_soul_petrol::*;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(any(feature = "kia-soul-ev", feature = "kia-niro"))]
+use spoof_signal_pair::SpoofSignalCalibration;
+
+/// Period of the module fault checks [ms]
+pub const FAULT_CHECK_PERIOD_MS: u32 = 5;
+
+/// Period of the report publishing [ms], 50 Hz
+pub const REPORT_PUBLISH_PERIOD_MS: u32 = 20;
+
+/// A task run every `period_ms` by the control loop
+pub struct PeriodicTask {
+    period_ms: u32,
+    next_run_ms: u32,
+}
+
+impl PeriodicTask {
+    /// The first run is due immediately
+    pub const fn new(period_ms: u32, now_ms: u32) -> Self {
+        PeriodicTask {
+            period_ms,
+            next_run_ms: now_ms,
+        }
+    }
+
+    /// Returns true at most once per period
+    ///
+    /// Runs are scheduled on a fixed grid so the cadence doesn't drift with
+    /// loop jitter. If the loop fell more than a period behind, the missed
+    /// runs are skipped rather than run back to back.
+    pub fn is_due(&mut self, now_ms: u32) -> bool {
+        // wrapping-safe check that now is at or past the next run
+        let late_ms = now_ms.wrapping_sub(self.next_run_ms);
+        if late_ms > (u32::max_value() / 2) {
+            return false;
+        }
+
+        self.next_run_ms = if late_ms >= self.period_ms {
+            now_ms.wrapping_add(self.period_ms)
+        } else {
+            self.next_run_ms.wrapping_add(self.period_ms)
+        };
+
+        true
+    }
+
+    pub fn period(&self) -> u32 {
+        self.period_ms
+    }
+}
+
+/// The control loop tasks that are due on a given poll
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DueTasks {
+    pub check_faults: bool,
+    pub publish_reports: bool,
+}
+
+/// Cooperative scheduler for the control loop, timed by the system clock
+pub struct ControlLoopScheduler {
+    fault_check: PeriodicTask,
+    report_publish: PeriodicTask,
+}
+
+impl ControlLoopScheduler {
+    pub fn new(now_ms: u32) -> Self {
+        ControlLoopScheduler {
+            fault_check: PeriodicTask::new(FAULT_CHECK_PERIOD_MS, now_ms),
+            report_publish: PeriodicTask::new(REPORT_PUBLISH_PERIOD_MS, now_ms),
+        }
+    }
+
+    pub fn poll(&mut self, now_ms: u32) -> DueTasks {
+        DueTasks {
+            check_faults: self.fault_check.is_due(now_ms),
+            publish_reports: self.report_publish.is_due(now_ms),
+        }
+    }
+}
+
+/// System-wide safe state, latched when any module reports a hard fault and
+/// checked by every module before it enables control
+///
+/// Operator overrides and command timeouts are not hard faults, see
+/// `OsccFaultReport::is_hard_fault`.
+pub struct SystemFaultState {
+    latched: AtomicBool,
+}
+
+impl SystemFaultState {
+    pub const fn new() -> Self {
+        SystemFaultState {
+            latched: AtomicBool::new(false),
+        }
+    }
+
+    pub fn latch(&self) {
+        self.latched.store(true, Ordering::SeqCst);
+    }
+
+    pub fn clear(&self) {
+        self.latched.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_latched(&self) -> bool {
+        self.latched.load(Ordering::SeqCst)
+    }
+}
+
+pub static SYSTEM_FAULT_STATE: SystemFaultState = SystemFaultState::new();
+
+/// Latched by the all-disable (e-stop) frame, only the e-stop clear frame
+/// releases it
+pub static EMERGENCY_STOP_STATE: SystemFaultState = SystemFaultState::new();
+
+/// Cross-module state shared between the control modules
+///
+/// Brake always wins: while the brake is applied, by the operator or by a
+/// brake command, the throttle module holds its spoof output at idle.
+pub struct VehicleState {
+    brake_operator_override: AtomicBool,
+    brake_applied: AtomicBool,
+    brake_pedal_pressed: AtomicBool,
+}
+
+impl VehicleState {
+    pub const fn new() -> Self {
+        VehicleState {
+            brake_operator_override: AtomicBool::new(false),
+            brake_applied: AtomicBool::new(false),
+            brake_pedal_pressed: AtomicBool::new(false),
+        }
+    }
+
+    pub fn set_brake_operator_override(&self, operator_override: bool) {
+        self.brake_operator_override
+            .store(operator_override, Ordering::SeqCst);
+    }
+
+    /// Set while the brake module is spoofing a brake command above the
+    /// brake light thresholds
+    pub fn set_brake_applied(&self, applied: bool) {
+        self.brake_applied.store(applied, Ordering::SeqCst);
+    }
+
+    /// Set while the brake pedal reads at or above the override threshold,
+    /// whether or not brake control is enabled
+    pub fn set_brake_pedal_pressed(&self, pressed: bool) {
+        self.brake_pedal_pressed.store(pressed, Ordering::SeqCst);
+    }
+
+    pub fn is_braking(&self) -> bool {
+        self.brake_operator_override.load(Ordering::SeqCst)
+            || self.brake_applied.load(Ordering::SeqCst)
+            || self.brake_pedal_pressed.load(Ordering::SeqCst)
+    }
+}
+
+pub static VEHICLE_STATE: VehicleState = VehicleState::new();
+
+/// Treats a `command` less than `deadband` above `minimum` as `minimum`
+pub fn apply_command_deadband(command: f32, minimum: f32, deadband: f32) -> f32 {
+    if command < (minimum + deadband) {
+        minimum
+    } else {
+        command
+    }
+}
+
+/// The direction of a signed `command`, true if positive, None within
+/// `deadband` of zero
+pub fn command_direction(command: f32, deadband: f32) -> Option<bool> {
+    if command > deadband {
+        Some(true)
+    } else if command < -deadband {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Splits a brake `command` into the (friction, regen) requests
+///
+/// Commands up to `crossover` are met by regen alone, scaled to 0.0..1.0 of
+/// the full regen request. Above it regen stays at the full request and the
+/// excess is stretched over the whole friction command range, so the
+/// friction brake engages from released. A crossover outside the command
+/// limits sends every command to the friction brake.
+pub fn blend_regen_braking(command: f32, minimum: f32, maximum: f32, crossover: f32) -> (f32, f32) {
+    if (crossover <= minimum) || (crossover >= maximum) {
+        return (command, 0.0);
+    }
+
+    if command <= crossover {
+        let regen = (command - minimum) / (crossover - minimum);
+        (minimum, regen.max(0.0))
+    } else {
+        let excess = (command - crossover) / (maximum - crossover);
+        (minimum + (excess * (maximum - minimum)), 1.0)
+    }
+}
+
+/// True for a command no working stack would send, NaN, infinite or more
+/// than `margin` outside the command limits
+pub fn is_command_absurd(command: f32, minimum: f32, maximum: f32, margin: f32) -> bool {
+    !command.is_finite() || (command < (minimum - margin)) || (command > (maximum + margin))
+}
+
+/// Vehicle specific brake constants and conversions
+///
+/// The brake module is generic over this, supporting another vehicle means
+/// implementing it rather than editing the module internals.
+#[cfg(any(feature = "kia-soul-ev", feature = "kia-niro"))]
+pub trait Vehicle {
+    const FAULT_HYSTERESIS: u32;
+    const SENSOR_FAULT_HYSTERESIS: u32;
+    const OPERATOR_OVERRIDE_HYSTERESIS: u32;
+    const OPERATOR_OVERRIDE_RELEASE_DEBOUNCE: u32;
+    const MINIMUM_BRAKE_COMMAND: f32;
+    const MAXIMUM_BRAKE_COMMAND: f32;
+    const BRAKE_COMMAND_DEADBAND: f32;
+    const BRAKE_SPOOF_SIGNAL_CALIBRATION: SpoofSignalCalibration;
+    const BRAKE_PEDAL_OVERRIDE_THRESHOLD: u16;
+    const BRAKE_PEDAL_POSITION_SENSOR_HIGH_LOW_RATIO: f32;
+    const BRAKE_PEDAL_POSITION_SENSOR_MISMATCH_TOLERANCE: u16;
+    const BRAKE_PEDAL_POSITION_SENSOR_SHORT_HIGH_THRESHOLD: u16;
+    const BRAKE_LIGHT_SPOOF_LOW_THRESHOLD: u16;
+    const BRAKE_LIGHT_SPOOF_HIGH_THRESHOLD: u16;
+    const BRAKE_LIGHT_SPOOF_HYSTERESIS: u16;
+    const BRAKE_COMMAND_TIMEOUT_IN_MSEC: u32;
+    const BRAKE_MIN_CONTROL_DWELL_IN_MSEC: u32;
+    const BRAKE_DISABLE_RAMP_MAX_DELTA: u16;
+    const BRAKE_PRESSURE_AT_MAXIMUM_COMMAND: f32;
+    const BRAKE_PRESSURE_LOOP_KP: f32;
+    const BRAKE_PRESSURE_LOOP_KI: f32;
+    const BRAKE_PRESSURE_SENSOR_MIN: u16;
+    const BRAKE_PRESSURE_SENSOR_MAX: u16;
+    const BRAKE_PRESSURE_SENSOR_MAX_DELTA: u16;
+    const BRAKE_REGEN_CROSSOVER: f32;
+    const BRAKE_REGEN_STATUS_TIMEOUT_IN_MSEC: u32;
+
+    fn brake_position_to_volts_low(position: f32) -> f32;
+    fn brake_position_to_volts_high(position: f32) -> f32;
+}
+
+/// The Kia Soul EV and Niro, constants come from the selected vehicle file
+#[cfg(any(feature = "kia-soul-ev", feature = "kia-niro"))]
+pub struct KiaSoulEvNiro;
+
+#[cfg(any(feature = "kia-soul-ev", feature = "kia-niro"))]
+impl Vehicle for KiaSoulEvNiro {
+    const FAULT_HYSTERESIS: u32 = FAULT_HYSTERESIS;
+    const SENSOR_FAULT_HYSTERESIS: u32 = SENSOR_FAULT_HYSTERESIS;
+    const OPERATOR_OVERRIDE_HYSTERESIS: u32 = OPERATOR_OVERRIDE_HYSTERESIS;
+    const OPERATOR_OVERRIDE_RELEASE_DEBOUNCE: u32 = OPERATOR_OVERRIDE_RELEASE_DEBOUNCE;
+    const MINIMUM_BRAKE_COMMAND: f32 = MINIMUM_BRAKE_COMMAND;
+    const MAXIMUM_BRAKE_COMMAND: f32 = MAXIMUM_BRAKE_COMMAND;
+    const BRAKE_COMMAND_DEADBAND: f32 = BRAKE_COMMAND_DEADBAND;
+    const BRAKE_SPOOF_SIGNAL_CALIBRATION: SpoofSignalCalibration = SpoofSignalCalibration {
+        high_voltage_min: BRAKE_SPOOF_HIGH_SIGNAL_VOLTAGE_MIN,
+        high_voltage_max: BRAKE_SPOOF_HIGH_SIGNAL_VOLTAGE_MAX,
+        low_voltage_min: BRAKE_SPOOF_LOW_SIGNAL_VOLTAGE_MIN,
+        low_voltage_max: BRAKE_SPOOF_LOW_SIGNAL_VOLTAGE_MAX,
+        high_range_min: BRAKE_SPOOF_HIGH_SIGNAL_RANGE_MIN,
+        high_range_max: BRAKE_SPOOF_HIGH_SIGNAL_RANGE_MAX,
+        low_range_min: BRAKE_SPOOF_LOW_SIGNAL_RANGE_MIN,
+        low_range_max: BRAKE_SPOOF_LOW_SIGNAL_RANGE_MAX,
+    };
+    const BRAKE_PEDAL_OVERRIDE_THRESHOLD: u16 = BRAKE_PEDAL_OVERRIDE_THRESHOLD;
+    const BRAKE_PEDAL_POSITION_SENSOR_HIGH_LOW_RATIO: f32 =
+        BRAKE_PEDAL_POSITION_SENSOR_HIGH_LOW_RATIO;
+    const BRAKE_PEDAL_POSITION_SENSOR_MISMATCH_TOLERANCE: u16 =
+        BRAKE_PEDAL_POSITION_SENSOR_MISMATCH_TOLERANCE;
+    const BRAKE_PEDAL_POSITION_SENSOR_SHORT_HIGH_THRESHOLD: u16 =
+        BRAKE_PEDAL_POSITION_SENSOR_SHORT_HIGH_THRESHOLD;
+    const BRAKE_LIGHT_SPOOF_LOW_THRESHOLD: u16 = BRAKE_LIGHT_SPOOF_LOW_THRESHOLD;
+    const BRAKE_LIGHT_SPOOF_HIGH_THRESHOLD: u16 = BRAKE_LIGHT_SPOOF_HIGH_THRESHOLD;
+    const BRAKE_LIGHT_SPOOF_HYSTERESIS: u16 = BRAKE_LIGHT_SPOOF_HYSTERESIS;
+    const BRAKE_COMMAND_TIMEOUT_IN_MSEC: u32 = BRAKE_COMMAND_TIMEOUT_IN_MSEC;
+    const BRAKE_MIN_CONTROL_DWELL_IN_MSEC: u32 = BRAKE_MIN_CONTROL_DWELL_IN_MSEC;
+    const BRAKE_DISABLE_RAMP_MAX_DELTA: u16 = BRAKE_DISABLE_RAMP_MAX_DELTA;
+    const BRAKE_PRESSURE_AT_MAXIMUM_COMMAND: f32 = BRAKE_PRESSURE_AT_MAXIMUM_COMMAND;
+    const BRAKE_PRESSURE_LOOP_KP: f32 = BRAKE_PRESSURE_LOOP_KP;
+    const BRAKE_PRESSURE_LOOP_KI: f32 = BRAKE_PRESSURE_LOOP_KI;
+    const BRAKE_PRESSURE_SENSOR_MIN: u16 = BRAKE_PRESSURE_SENSOR_MIN;
+    const BRAKE_PRESSURE_SENSOR_MAX: u16 = BRAKE_PRESSURE_SENSOR_MAX;
+    const BRAKE_PRESSURE_SENSOR_MAX_DELTA: u16 = BRAKE_PRESSURE_SENSOR_MAX_DELTA;
+    const BRAKE_REGEN_CROSSOVER: f32 = BRAKE_REGEN_CROSSOVER;
+    const BRAKE_REGEN_STATUS_TIMEOUT_IN_MSEC: u32 = BRAKE_REGEN_STATUS_TIMEOUT_IN_MSEC;
+
+    fn brake_position_to_volts_low(position: f32) -> f32 {
+        brake_position_to_volts_low(position)
+    }
+
+    fn brake_position_to_volts_high(position: f32) -> f32 {
+        brake_position_to_volts_high(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deadband_snaps_small_commands_to_minimum() {
+        assert_eq!(apply_command_deadband(0.01, 0.0, 0.05), 0.0);
+        assert_eq!(apply_command_deadband(0.2, 0.0, 0.05), 0.2);
+    }
+
+    #[test]
+    fn command_direction_ignores_the_deadband() {
+        assert_eq!(command_direction(0.2, 0.05), Some(true));
+        assert_eq!(command_direction(-0.2, 0.05), Some(false));
+        assert_eq!(command_direction(0.05, 0.05), None);
+        assert_eq!(command_direction(-0.01, 0.05), None);
+        assert_eq!(command_direction(::core::f32::NAN, 0.05), None);
+    }
+
+    #[test]
+    fn regen_blend_splits_at_the_crossover() {
+        let (friction, regen) = blend_regen_braking(0.1, 0.0, 1.0, 0.2);
+        assert_eq!(friction, 0.0);
+        assert!((regen - 0.5).abs() < 1e-6);
+
+        let (friction, regen) = blend_regen_braking(1.0, 0.0, 1.0, 0.2);
+        assert!((friction - 1.0).abs() < 1e-6);
+        assert_eq!(regen, 1.0);
+    }
+
+    #[test]
+    fn regen_blend_off_for_a_crossover_outside_the_limits() {
+        assert_eq!(blend_regen_braking(0.1, 0.0, 1.0, 0.0), (0.1, 0.0));
+        assert_eq!(blend_regen_braking(0.1, 0.0, 1.0, 1.0), (0.1, 0.0));
+    }
+
+    #[test]
+    fn absurd_commands() {
+        assert!(is_command_absurd(::core::f32::NAN, 0.0, 1.0, 1.0));
+        assert!(is_command_absurd(::core::f32::INFINITY, 0.0, 1.0, 1.0));
+        assert!(is_command_absurd(2.5, 0.0, 1.0, 1.0));
+        assert!(!is_command_absurd(1.5, 0.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn periodic_task_is_due_once_per_period() {
+        let mut task = PeriodicTask::new(5, 0);
+        assert!(task.is_due(0));
+        assert!(!task.is_due(4));
+        assert!(task.is_due(5));
+        assert!(!task.is_due(6));
+        assert!(task.is_due(10));
+    }
+}
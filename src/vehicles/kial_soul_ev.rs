@@ -75,6 +75,29 @@ pub const STEPS_PER_VOLT: f32 = 819.2;
 //
 pub const FAULT_HYSTERESIS: u32 = 100;
 
+/*
+ * @brief Length of time in ms a sensor must read grounded, shorted or out of
+ * range before it is faulted. Shorter than FAULT_HYSTERESIS, losing the
+ * sensor leaves the module blind. */
+//
+//
+pub const SENSOR_FAULT_HYSTERESIS: u32 = 50;
+
+/*
+ * @brief Length of time in ms the operator must hold an override before
+ * control is disabled. */
+//
+//
+pub const OPERATOR_OVERRIDE_HYSTERESIS: u32 = 100;
+
+/*
+ * @brief Length of time in ms the operator input must stay released before
+ * an override is cleared, so modulating the input does not re-enable
+ * control. */
+//
+//
+pub const OPERATOR_OVERRIDE_RELEASE_DEBOUNCE: u32 = 250;
+
 // ****************************************************************************
 // BRAKE MODULE
 // ****************************************************************************
@@ -91,6 +114,13 @@ pub const MINIMUM_BRAKE_COMMAND: f32 = 0.0;
 //
 pub const MAXIMUM_BRAKE_COMMAND: f32 = 1.0;
 
+/*
+ * @brief Commands less than this above the minimum are treated as the
+ *        minimum, so command noise doesn't creep the brakes on. */
+//
+//
+pub const BRAKE_COMMAND_DEADBAND: f32 = 0.0;
+
 /*
  * @brief Minimum allowed voltage for the low spoof signal voltage. [volts] */
 //
@@ -172,6 +202,28 @@ pub const fn brake_position_to_volts_high(position: f32) -> f32 {
 //
 pub const BRAKE_PEDAL_OVERRIDE_THRESHOLD: u16 = 130 << 2;
 
+/*
+ * @brief Expected ratio of the high brake pedal position sensor reading to
+ * the low reading. */
+//
+// Derived from the spoof signal voltage ranges, the high signal is roughly twice the low signal.
+//
+pub const BRAKE_PEDAL_POSITION_SENSOR_HIGH_LOW_RATIO: f32 = 2.05;
+
+/*
+ * @brief Maximum difference between the high brake pedal position sensor
+ * reading and the value expected from the low reading. [steps] */
+//
+//
+pub const BRAKE_PEDAL_POSITION_SENSOR_MISMATCH_TOLERANCE: u16 = 250;
+
+/*
+ * @brief Brake pedal position sensor reading above which both channels
+ * indicate a short to the supply rail. [steps] */
+//
+//
+pub const BRAKE_PEDAL_POSITION_SENSOR_SHORT_HIGH_THRESHOLD: u16 = 4000;
+
 /*
  * @brief Minimum value of the low spoof signal that activates the brake
  * lights. [steps] */
@@ -186,6 +238,93 @@ pub const BRAKE_LIGHT_SPOOF_LOW_THRESHOLD: u16 = 300;
 //
 pub const BRAKE_LIGHT_SPOOF_HIGH_THRESHOLD: u16 = 600;
 
+/*
+ * @brief Amount the spoof signals must drop below the brake light thresholds
+ * before the brake lights turn off again. [steps] */
+//
+// Keeps the brake lights from chattering when a spoof value hovers at a
+// threshold.
+//
+pub const BRAKE_LIGHT_SPOOF_HYSTERESIS: u16 = 20;
+
+/*
+ * @brief Amount of time without a brake command before control is
+ * disabled. [milliseconds] */
+//
+//
+pub const BRAKE_COMMAND_TIMEOUT_IN_MSEC: u32 = 200;
+
+/*
+ * @brief Minimum amount of time control stays disabled before an enable
+ * request is accepted. [milliseconds] */
+//
+// An enable inside the dwell is refused and NACKed. Operator overrides,
+// hard faults, disable commands and timeouts are never delayed.
+//
+pub const BRAKE_MIN_CONTROL_DWELL_IN_MSEC: u32 = 100;
+
+/*
+ * @brief Maximum change in a brake spoof output per loop while ramping back
+ * to the sensor values on disable. [steps] */
+//
+//
+pub const BRAKE_DISABLE_RAMP_MAX_DELTA: u16 = 100;
+
+/*
+ * @brief Brake pressure sensor reading at the maximum brake command, used by
+ * the closed-loop option to turn a command into a pressure setpoint. [steps] */
+//
+//
+pub const BRAKE_PRESSURE_AT_MAXIMUM_COMMAND: f32 = 3000.0;
+
+/*
+ * @brief Proportional gain of the brake pressure loop. [position / step] */
+//
+//
+pub const BRAKE_PRESSURE_LOOP_KP: f32 = 0.0002;
+
+/*
+ * @brief Integral gain of the brake pressure loop. [position / (step * s)] */
+//
+//
+pub const BRAKE_PRESSURE_LOOP_KI: f32 = 0.001;
+
+/*
+ * @brief Lowest plausible brake pressure sensor reading, anything below is
+ * taken as an open or grounded sensor. [steps] */
+//
+//
+pub const BRAKE_PRESSURE_SENSOR_MIN: u16 = 100;
+
+/*
+ * @brief Highest plausible brake pressure sensor reading, anything above is
+ * taken as a sensor shorted high. [steps] */
+//
+//
+pub const BRAKE_PRESSURE_SENSOR_MAX: u16 = 3995;
+
+/*
+ * @brief Largest plausible change in the brake pressure sensor reading
+ * between two pressure loop steps. [steps] */
+//
+//
+pub const BRAKE_PRESSURE_SENSOR_MAX_DELTA: u16 = 400;
+
+/*
+ * @brief Brake command up to which braking is requested from regen alone,
+ * the friction brake takes the commands above it. Used by the regen blending
+ * option, a crossover at the minimum brake command disables blending. */
+//
+//
+pub const BRAKE_REGEN_CROSSOVER: f32 = 0.15;
+
+/*
+ * @brief Time after the last regen status frame reporting regen available
+ * before brake commands go to the friction brake alone. [milliseconds] */
+//
+//
+pub const BRAKE_REGEN_STATUS_TIMEOUT_IN_MSEC: u32 = 100;
+
 // ****************************************************************************
 // STEERING MODULE
 // ****************************************************************************
@@ -258,6 +397,35 @@ pub const STEERING_SPOOF_HIGH_SIGNAL_RANGE_MAX: u16 = 3440;
 
 pub type SteeringSpoofHighSignal = ranges::Bounded<u16, U738, U3440>;
 
+/*
+ * @brief Maximum change in a steering spoof signal value per update. [steps] */
+//
+// Large command steps are ramped towards over several updates, a sudden
+// steering torque step is the most dangerous actuator output.
+//
+pub const STEERING_MAX_SPOOF_DELTA: u16 = 25;
+
+/*
+ * @brief Direction reversals of the steering command allowed within \ref
+ * STEERING_DIRECTION_REVERSAL_WINDOW_IN_MSEC. */
+//
+// A command flipping sign faster than this is treated as an oscillating
+// controller, the interlock faults and disables control.
+//
+pub const STEERING_MAX_DIRECTION_REVERSALS: u8 = 4;
+
+/*
+ * @brief Window the steering command direction reversals are counted over.
+ * [milliseconds] */
+pub const STEERING_DIRECTION_REVERSAL_WINDOW_IN_MSEC: u32 = 500;
+
+/*
+ * @brief Torque request magnitude at or below which the steering command has
+ * no direction, so noise around zero torque is not counted as a reversal. */
+//
+//
+pub const STEERING_DIRECTION_REVERSAL_DEADBAND: f32 = 0.05;
+
 /*
  * @brief Scalar value for the low spoof signal taken from a calibration
  * curve. */
@@ -311,6 +479,21 @@ pub const fn steering_torque_to_volts_high(torque: f32) -> f32 {
 //
 pub const TORQUE_DIFFERENCE_OVERRIDE_THRESHOLD: u16 = 1600;
 
+/*
+ * @brief Expected average of the high and low torque sensor readings. [steps] */
+//
+// The channels move in opposite directions with applied torque, so their
+// average stays near the zero torque value of the calibration curves.
+//
+pub const TORQUE_SENSOR_EXPECTED_AVERAGE: u32 = 1970;
+
+/*
+ * @brief Maximum deviation of the torque sensor average from
+ * \ref TORQUE_SENSOR_EXPECTED_AVERAGE. [steps] */
+//
+//
+pub const TORQUE_SENSOR_AVERAGE_TOLERANCE: u32 = 400;
+
 // ****************************************************************************
 // THROTTLE MODULE
 // ****************************************************************************
@@ -327,6 +510,13 @@ pub const MINIMUM_THROTTLE_COMMAND: f32 = 0.0;
 //
 pub const MAXIMUM_THROTTLE_COMMAND: f32 = 1.0;
 
+/*
+ * @brief Commands less than this above the minimum are treated as the
+ *        minimum, so command noise doesn't creep the throttle open. */
+//
+//
+pub const THROTTLE_COMMAND_DEADBAND: f32 = 0.0;
+
 /*
  * @brief Minimum allowed voltage for the low spoof signal voltage. [volts] */
 //
@@ -406,3 +596,25 @@ pub const fn throttle_position_to_volts_high(position: f32) -> f32 {
 //
 //
 pub const ACCELERATOR_OVERRIDE_THRESHOLD: u32 = 185 << 2;
+
+/*
+ * @brief Maximum accelerator position accepted as the pedal resting during
+ * idle calibration. [steps] */
+//
+//
+pub const ACCELERATOR_IDLE_BAND_MAX: u32 = 150 << 2;
+
+/*
+ * @brief Accelerator position above the calibrated idle baseline that
+ * indicates operator override. [steps] */
+//
+// The resulting threshold never exceeds ACCELERATOR_OVERRIDE_THRESHOLD.
+//
+pub const ACCELERATOR_OVERRIDE_MARGIN: u32 = 35 << 2;
+
+/*
+ * @brief Maximum change in a throttle spoof signal value per update. [steps] */
+//
+// Large command steps are ramped towards over several updates.
+//
+pub const THROTTLE_MAX_SPOOF_DELTA: u16 = 50;
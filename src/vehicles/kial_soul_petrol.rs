@@ -69,6 +69,29 @@ pub const STEPS_PER_VOLT: f32 = 819.2;
 //
 pub const FAULT_HYSTERESIS: u32 = 100;
 
+/*
+ * @brief Length of time in ms a sensor must read grounded, shorted or out of
+ * range before it is faulted. Shorter than FAULT_HYSTERESIS, losing the
+ * sensor leaves the module blind. */
+//
+//
+pub const SENSOR_FAULT_HYSTERESIS: u32 = 50;
+
+/*
+ * @brief Length of time in ms the operator must hold an override before
+ * control is disabled. */
+//
+//
+pub const OPERATOR_OVERRIDE_HYSTERESIS: u32 = 100;
+
+/*
+ * @brief Length of time in ms the operator input must stay released before
+ * an override is cleared, so modulating the input does not re-enable
+ * control. */
+//
+//
+pub const OPERATOR_OVERRIDE_RELEASE_DEBOUNCE: u32 = 250;
+
 // ****************************************************************************
 // BRAKE MODULE
 // ****************************************************************************
@@ -316,6 +339,35 @@ pub const STEERING_SPOOF_HIGH_SIGNAL_RANGE_MAX: u16 = 3440;
 
 pub type SteeringSpoofHighSignal = ranges::Bounded<u16, U738, U3440>;
 
+/*
+ * @brief Maximum change in a steering spoof signal value per update. [steps] */
+//
+// Large command steps are ramped towards over several updates, a sudden
+// steering torque step is the most dangerous actuator output.
+//
+pub const STEERING_MAX_SPOOF_DELTA: u16 = 25;
+
+/*
+ * @brief Direction reversals of the steering command allowed within \ref
+ * STEERING_DIRECTION_REVERSAL_WINDOW_IN_MSEC. */
+//
+// A command flipping sign faster than this is treated as an oscillating
+// controller, the interlock faults and disables control.
+//
+pub const STEERING_MAX_DIRECTION_REVERSALS: u8 = 4;
+
+/*
+ * @brief Window the steering command direction reversals are counted over.
+ * [milliseconds] */
+pub const STEERING_DIRECTION_REVERSAL_WINDOW_IN_MSEC: u32 = 500;
+
+/*
+ * @brief Torque request magnitude at or below which the steering command has
+ * no direction, so noise around zero torque is not counted as a reversal. */
+//
+//
+pub const STEERING_DIRECTION_REVERSAL_DEADBAND: f32 = 0.05;
+
 /*
  * @brief Scalar value for the low spoof signal taken from a calibration
  * curve. */
@@ -369,6 +421,21 @@ pub const fn steering_torque_to_volts_low(torque: f32) -> f32 {
 //
 pub const TORQUE_DIFFERENCE_OVERRIDE_THRESHOLD: u16 = 1600;
 
+/*
+ * @brief Expected average of the high and low torque sensor readings. [steps] */
+//
+// The channels move in opposite directions with applied torque, so their
+// average stays near the zero torque value of the calibration curves.
+//
+pub const TORQUE_SENSOR_EXPECTED_AVERAGE: u32 = 1970;
+
+/*
+ * @brief Maximum deviation of the torque sensor average from
+ * \ref TORQUE_SENSOR_EXPECTED_AVERAGE. [steps] */
+//
+//
+pub const TORQUE_SENSOR_AVERAGE_TOLERANCE: u32 = 400;
+
 // ****************************************************************************
 // THROTTLE MODULE
 // ****************************************************************************
@@ -385,6 +452,13 @@ pub const MINIMUM_THROTTLE_COMMAND: f32 = 0.0;
 //
 pub const MAXIMUM_THROTTLE_COMMAND: f32 = 1.0;
 
+/*
+ * @brief Commands less than this above the minimum are treated as the
+ *        minimum, so command noise doesn't creep the throttle open. */
+//
+//
+pub const THROTTLE_COMMAND_DEADBAND: f32 = 0.0;
+
 /*
  * @brief Minimum allowed voltage for the low spoof signal voltage. [volts] */
 //
@@ -465,3 +539,25 @@ pub const fn throttle_position_to_volts_high(position: f32) -> f32 {
 //
 //
 pub const ACCELERATOR_OVERRIDE_THRESHOLD: u32 = 185 << 2;
+
+/*
+ * @brief Maximum accelerator position accepted as the pedal resting during
+ * idle calibration. [steps] */
+//
+//
+pub const ACCELERATOR_IDLE_BAND_MAX: u32 = 150 << 2;
+
+/*
+ * @brief Accelerator position above the calibrated idle baseline that
+ * indicates operator override. [steps] */
+//
+// The resulting threshold never exceeds ACCELERATOR_OVERRIDE_THRESHOLD.
+//
+pub const ACCELERATOR_OVERRIDE_MARGIN: u32 = 35 << 2;
+
+/*
+ * @brief Maximum change in a throttle spoof signal value per update. [steps] */
+//
+// Large command steps are ramped towards over several updates.
+//
+pub const THROTTLE_MAX_SPOOF_DELTA: u16 = 50;